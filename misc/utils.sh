@@ -49,7 +49,7 @@ if [ -z "${CARGO_TARGET_DIR}" ]; then
 	export CARGO_TARGET_DIR="$target_dir"
 fi
 
-plugins="auth_basic auth_none motion tflite thumb_scale"
+plugins="auth_basic auth_none motion mqtt tflite thumb_scale"
 packages="-p sentryshot"
 for plugin in $plugins; do
 	packages="$packages -p $plugin"
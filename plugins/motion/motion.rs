@@ -258,7 +258,8 @@ impl MotionPlugin {
             };
 
             let detections: Vec<_>;
-            (detections, state) = self
+            let event_detections: Vec<_>;
+            (detections, event_detections, state) = self
                 .rt_handle
                 .spawn_blocking(move || -> Result<_, RunError> {
                     convert_frame(&mut state.raw_frame, &frame?)?;
@@ -267,7 +268,8 @@ impl MotionPlugin {
                         &state.prev_raw_frame,
                         &mut state.raw_frame_diff,
                     );
-                    Ok((detections, state))
+                    let event_detections = state.zones.event_detections(&detections);
+                    Ok((detections, event_detections, state))
                 })
                 .await
                 .expect("join")?;
@@ -280,23 +282,28 @@ impl MotionPlugin {
                 continue;
             }
 
+            // Continue if there are no detections.
+            if detections.is_empty() {
+                continue;
+            }
+
             for (zone, score) in detections {
                 msg_logger.log(
                     LogLevel::Debug,
                     &format!("detection: zone:{zone} score:{score:.2}"),
                 );
-
-                let time = UnixNano::now();
-                //t := time.Now().Add(-d.config.timestampOffset)
-                monitor
-                    .send_event(Event {
-                        time,
-                        duration: *config.feed_rate,
-                        rec_duration: *config.duration,
-                        detections: Vec::new(),
-                    })
-                    .await;
             }
+
+            let time = UnixNano::now();
+            //t := time.Now().Add(-d.config.timestampOffset)
+            monitor
+                .send_event(Event {
+                    time,
+                    duration: *config.feed_rate,
+                    rec_duration: *config.duration,
+                    detections: event_detections,
+                })
+                .await;
         }
     }
 }
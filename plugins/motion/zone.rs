@@ -1,7 +1,10 @@
 // SPDX-License-Identifier: GPL-2.0-or-later
 
 use crate::config::ZoneConfig;
-use common::recording::{create_inverted_mask, denormalize_polygon};
+use common::{
+    recording::{create_inverted_mask, denormalize_polygon},
+    Detection, Label, PolygonNormalized, Region,
+};
 
 #[derive(Debug, PartialEq)]
 pub struct Zones(pub Vec<Zone>);
@@ -19,6 +22,26 @@ impl Zones {
         }
         detections
     }
+
+    // Converts the active zones into event detections
+    // with the zone area as the detection region.
+    pub fn event_detections(&self, detections: &[(usize, f32)]) -> common::Detections {
+        detections
+            .iter()
+            .map(|(i, score)| Detection {
+                label: motion_label(),
+                score: *score,
+                region: Region {
+                    rectangle: None,
+                    polygon: Some(self.0[*i].area.clone()),
+                },
+            })
+            .collect()
+    }
+}
+
+fn motion_label() -> Label {
+    "motion".to_owned().try_into().expect("label should be valid")
 }
 
 pub type Detections = Vec<(usize, f32)>;
@@ -32,6 +55,7 @@ fn diff_frames(frame1: &[u8], frame2: &[u8], diff: &mut [u8]) {
 #[allow(clippy::struct_field_names)]
 #[derive(Debug, PartialEq)]
 pub struct Zone {
+    area: PolygonNormalized,
     mask: Vec<bool>,
 
     zone_size: u64,
@@ -54,6 +78,7 @@ impl Zone {
         let (mask, zone_size) = parse_mask_image(&mask_image);
 
         Zone {
+            area: config.area.clone(),
             mask,
             zone_size,
             frame_size: i64::from(width) * i64::from(height),
@@ -236,6 +261,29 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_event_detections() {
+        let area = vec![p(0, 0), p(50, 0), p(50, 100), p(0, 100)];
+        let config = ZoneConfig {
+            enable: true,
+            sensitivity: 8.0,
+            threshold_min: 10.0,
+            threshold_max: 100.0,
+            area: area.clone(),
+        };
+        let zones = Zones(vec![Zone::new(2, 2, &config)]);
+
+        let want = vec![Detection {
+            label: "motion".to_owned().try_into().unwrap(),
+            score: 50.0,
+            region: Region {
+                rectangle: None,
+                polygon: Some(area),
+            },
+        }];
+        assert_eq!(want, zones.event_detections(&[(0, 50.0)]));
+    }
+
     /*func BenchmarkDetector(b *testing.B) {
         width := 500
         height := 500
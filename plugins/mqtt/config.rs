@@ -0,0 +1,323 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+use common::{monitor::MonitorConfig, DynMsgLogger, LogLevel, MonitorId};
+use serde::Deserialize;
+use serde_json::Value;
+use std::path::Path;
+use thiserror::Error;
+
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+pub(crate) struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub client_id: String,
+    pub topic_prefix: String,
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum LoadConfigError {
+    #[error("write config: {0}")]
+    WriteConfig(std::io::Error),
+
+    #[error("read config: {0}")]
+    ReadConfig(std::io::Error),
+
+    #[error("deserialize config: {0}")]
+    DeserializeConfig(#[from] toml::de::Error),
+
+    #[error("username and password must both be set")]
+    PartialCredentials,
+
+    #[error("topic prefix cannot be empty or contain wildcards")]
+    InvalidTopicPrefix,
+}
+
+const DEFAULT_CONFIG: &str = include_str!("./default_config.toml");
+
+impl MqttConfig {
+    pub(crate) fn load(logger: &DynMsgLogger, config_dir: &Path) -> Result<Self, LoadConfigError> {
+        use LoadConfigError::*;
+        let config_path = config_dir.join("mqtt.toml");
+        if !config_path.exists() {
+            logger.log(
+                LogLevel::Info,
+                &format!("generating {}", config_path.to_string_lossy()),
+            );
+            std::fs::write(&config_path, DEFAULT_CONFIG).map_err(WriteConfig)?;
+        }
+        let raw = std::fs::read_to_string(config_path).map_err(ReadConfig)?;
+        Self::parse(&raw)
+    }
+
+    fn parse(raw: &str) -> Result<Self, LoadConfigError> {
+        use LoadConfigError::*;
+        let config: Self = toml::from_str(raw)?;
+        if config.username.is_some() != config.password.is_some() {
+            return Err(PartialCredentials);
+        }
+        if config.topic_prefix.is_empty()
+            || config.topic_prefix.contains(['+', '#'])
+            || config.topic_prefix.ends_with('/')
+        {
+            return Err(InvalidTopicPrefix);
+        }
+        Ok(config)
+    }
+}
+
+pub(crate) struct Topics {
+    prefix: String,
+}
+
+impl Topics {
+    pub(crate) fn new(prefix: String) -> Self {
+        Self { prefix }
+    }
+
+    // Retained "online" or "offline", also used as the last will.
+    pub(crate) fn status(&self) -> String {
+        format!("{}/status", self.prefix)
+    }
+
+    // Retained "online" or "offline".
+    pub(crate) fn monitor_state(&self, monitor_id: &MonitorId) -> String {
+        format!("{}/{monitor_id}/state", self.prefix)
+    }
+
+    pub(crate) fn event(&self, monitor_id: &MonitorId) -> String {
+        format!("{}/{monitor_id}/event", self.prefix)
+    }
+
+    pub(crate) fn detection(&self, monitor_id: &MonitorId, label: &str) -> String {
+        format!("{}/{monitor_id}/detection/{label}", self.prefix)
+    }
+
+    pub(crate) fn recording(&self, monitor_id: &MonitorId) -> String {
+        format!("{}/{monitor_id}/recording", self.prefix)
+    }
+
+    // Subscription filter for all command topics.
+    pub(crate) fn commands(&self) -> String {
+        format!("{}/+/+/set", self.prefix)
+    }
+
+    // Parses "<prefix>/<monitor_id>/<detector>/set".
+    pub(crate) fn parse_command(&self, topic: &str, payload: &[u8]) -> Option<Command> {
+        let topic = topic.strip_prefix(&self.prefix)?.strip_prefix('/')?;
+        let mut parts = topic.split('/');
+        let (Some(monitor_id), Some(detector), Some("set"), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return None;
+        };
+        let monitor_id = MonitorId::try_from(monitor_id.to_owned()).ok()?;
+        let detector = match detector {
+            "motion" => Detector::Motion,
+            "tflite" => Detector::Tflite,
+            _ => return None,
+        };
+        let enable = parse_enable_payload(payload)?;
+        Some(Command {
+            monitor_id,
+            detector,
+            enable,
+        })
+    }
+}
+
+fn parse_enable_payload(payload: &[u8]) -> Option<bool> {
+    let payload = std::str::from_utf8(payload).ok()?.trim();
+    for v in ["enable", "on", "true", "1"] {
+        if payload.eq_ignore_ascii_case(v) {
+            return Some(true);
+        }
+    }
+    for v in ["disable", "off", "false", "0"] {
+        if payload.eq_ignore_ascii_case(v) {
+            return Some(false);
+        }
+    }
+    None
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Command {
+    pub monitor_id: MonitorId,
+    pub detector: Detector,
+    pub enable: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Detector {
+    Motion,
+    Tflite,
+}
+
+impl Detector {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Detector::Motion => "motion",
+            Detector::Tflite => "tflite",
+        }
+    }
+}
+
+// Returns None if the monitor doesn't have the detector configured.
+pub(crate) fn set_enable(
+    config: &MonitorConfig,
+    detector: Detector,
+    value: bool,
+) -> Option<MonitorConfig> {
+    let mut raw = config.raw().clone();
+    let Value::Object(root) = &mut raw else {
+        return None;
+    };
+    let Value::Object(detector) = root.get_mut(detector.as_str())? else {
+        return None;
+    };
+    let Value::Bool(enable) = detector.get_mut("enable")? else {
+        return None;
+    };
+    *enable = value;
+
+    serde_json::from_value(raw).expect("config should still be valid after toggling")
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+    use test_case::test_case;
+
+    #[test]
+    fn test_parse_default_config() {
+        let want = MqttConfig {
+            host: "127.0.0.1".to_owned(),
+            port: 1883,
+            username: None,
+            password: None,
+            client_id: "sentryshot".to_owned(),
+            topic_prefix: "sentryshot".to_owned(),
+        };
+        assert_eq!(want, MqttConfig::parse(DEFAULT_CONFIG).unwrap());
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let raw = "
+            host = \"broker\"
+            port = 8883
+            username = \"a\"
+            password = \"b\"
+            client_id = \"c\"
+            topic_prefix = \"home/nvr\"";
+        let want = MqttConfig {
+            host: "broker".to_owned(),
+            port: 8883,
+            username: Some("a".to_owned()),
+            password: Some("b".to_owned()),
+            client_id: "c".to_owned(),
+            topic_prefix: "home/nvr".to_owned(),
+        };
+        assert_eq!(want, MqttConfig::parse(raw).unwrap());
+    }
+
+    #[test_case("username = \"a\"", "topic_prefix = \"x\""; "partial credentials")]
+    #[test_case("", "topic_prefix = \"\""; "empty prefix")]
+    #[test_case("", "topic_prefix = \"a/#\""; "wildcard prefix")]
+    #[test_case("", "topic_prefix = \"a/\""; "trailing slash")]
+    fn test_parse_config_invalid(credentials: &str, prefix: &str) {
+        let raw = format!("host = \"x\"\nport = 1\nclient_id = \"x\"\n{credentials}\n{prefix}");
+        assert!(MqttConfig::parse(&raw).is_err());
+    }
+
+    fn m_id(s: &str) -> MonitorId {
+        s.to_owned().try_into().unwrap()
+    }
+
+    #[test_case("sentryshot/1/motion/set", "enable", Detector::Motion, true; "enable")]
+    #[test_case("sentryshot/1/motion/set", "OFF", Detector::Motion, false; "off")]
+    #[test_case("sentryshot/1/tflite/set", " true\n", Detector::Tflite, true; "trim")]
+    #[test_case("sentryshot/1/tflite/set", "0", Detector::Tflite, false; "zero")]
+    fn test_parse_command(topic: &str, payload: &str, detector: Detector, enable: bool) {
+        let topics = Topics::new("sentryshot".to_owned());
+        let want = Command {
+            monitor_id: m_id("1"),
+            detector,
+            enable,
+        };
+        assert_eq!(Some(want), topics.parse_command(topic, payload.as_bytes()));
+    }
+
+    #[test_case("sentryshot/1/motion/set", "x"; "invalid payload")]
+    #[test_case("sentryshot/1/motion/set/x", "on"; "too long")]
+    #[test_case("sentryshot/1/motion", "on"; "too short")]
+    #[test_case("sentryshot/1/foo/set", "on"; "unknown detector")]
+    #[test_case("sentryshotx/1/motion/set", "on"; "wrong prefix")]
+    #[test_case("sentryshot//motion/set", "on"; "empty id")]
+    fn test_parse_command_invalid(topic: &str, payload: &str) {
+        let topics = Topics::new("sentryshot".to_owned());
+        assert_eq!(None, topics.parse_command(topic, payload.as_bytes()));
+    }
+
+    #[test]
+    fn test_topics() {
+        let topics = Topics::new("a/b".to_owned());
+        assert_eq!("a/b/status", topics.status());
+        assert_eq!("a/b/1/state", topics.monitor_state(&m_id("1")));
+        assert_eq!("a/b/1/event", topics.event(&m_id("1")));
+        assert_eq!("a/b/1/detection/car", topics.detection(&m_id("1"), "car"));
+        assert_eq!("a/b/1/recording", topics.recording(&m_id("1")));
+        assert_eq!("a/b/+/+/set", topics.commands());
+    }
+
+    fn test_config(raw: serde_json::Value) -> MonitorConfig {
+        serde_json::from_value(raw).unwrap()
+    }
+
+    fn raw_config(detectors: &serde_json::Value) -> serde_json::Value {
+        let mut raw = json!({
+            "id": "1",
+            "name": "x",
+            "enable": true,
+            "source": "rtsp",
+            "sourcertsp": {
+                "protocol": "tcp",
+                "mainStream": "rtsp://x"
+            },
+            "alwaysRecord": false,
+            "videoLength": 15,
+        });
+        for (k, v) in detectors.as_object().unwrap() {
+            raw[k] = v.clone();
+        }
+        raw
+    }
+
+    #[test]
+    fn test_set_enable() {
+        let config = test_config(raw_config(&json!({
+            "motion": { "enable": false },
+            "tflite": { "enable": false },
+        })));
+        let config = set_enable(&config, Detector::Tflite, true).unwrap();
+        assert_eq!(
+            &json!({ "enable": false }),
+            config.raw().get("motion").unwrap()
+        );
+        assert_eq!(
+            &json!({ "enable": true }),
+            config.raw().get("tflite").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_set_enable_missing() {
+        let config = test_config(raw_config(&json!({})));
+        assert!(set_enable(&config, Detector::Motion, true).is_none());
+    }
+}
@@ -0,0 +1,581 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+mod config;
+
+use crate::config::{set_enable, Command, MqttConfig, Topics};
+use async_trait::async_trait;
+use common::{
+    monitor::MonitorConfig, recording::RecordingId, DynLogger, DynMsgLogger, Event, LogEntry,
+    LogLevel, LogSource, MonitorId, MsgLogger,
+};
+use monitor::{MonitorManager, MonitorSetAndRestartError};
+use pin_project::pin_project;
+use plugin::{Application, Plugin, PreLoadPlugin};
+use rumqttc::{AsyncClient, EventLoop, LastWill, MqttOptions, Packet, QoS};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+use thiserror::Error;
+use tokio::{
+    runtime::Handle,
+    sync::{mpsc, watch},
+};
+use tokio_util::sync::CancellationToken;
+
+#[no_mangle]
+pub extern "Rust" fn version() -> String {
+    plugin::get_version()
+}
+
+#[no_mangle]
+pub extern "Rust" fn pre_load() -> Box<dyn PreLoadPlugin> {
+    Box::new(PreLoadMqtt)
+}
+struct PreLoadMqtt;
+impl PreLoadPlugin for PreLoadMqtt {
+    fn add_log_source(&self) -> Option<LogSource> {
+        #[allow(clippy::unwrap_used)]
+        Some("mqtt".try_into().unwrap())
+    }
+}
+
+#[no_mangle]
+pub extern "Rust" fn load(app: &dyn Application) -> Arc<dyn Plugin> {
+    Arc::new(MqttPlugin::new(
+        &app.rt_handle(),
+        app.token(),
+        app.shutdown_complete_tx(),
+        app.logger(),
+        app.env().config_dir(),
+        app.monitor_manager(),
+    ))
+}
+
+// Size of the outgoing request buffer.
+const CHANNEL_CAPACITY: usize = 100;
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const STATE_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+pub struct MqttPlugin {
+    _shutdown_complete_tx: mpsc::Sender<()>,
+    logger: DynMsgLogger,
+    client: AsyncClient,
+    topics: Arc<Topics>,
+}
+
+impl MqttPlugin {
+    fn new(
+        rt_handle: &Handle,
+        token: CancellationToken,
+        shutdown_complete_tx: mpsc::Sender<()>,
+        logger: DynLogger,
+        config_dir: &std::path::Path,
+        monitor_manager: MonitorManager,
+    ) -> Self {
+        let mqtt_logger: DynMsgLogger = Arc::new(MqttLogger {
+            logger: logger.clone(),
+            monitor_id: None,
+        });
+        let config = match MqttConfig::load(&mqtt_logger, config_dir) {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("Failed to load mqtt config: {e}");
+                std::process::exit(1);
+            }
+        };
+        let topics = Arc::new(Topics::new(config.topic_prefix.clone()));
+
+        let mut opts = MqttOptions::new(config.client_id, config.host, config.port);
+        opts.set_keep_alive(KEEP_ALIVE);
+        opts.set_last_will(LastWill::new(
+            topics.status(),
+            "offline",
+            QoS::AtLeastOnce,
+            true,
+        ));
+        if let (Some(username), Some(password)) = (config.username, config.password) {
+            opts.set_credentials(username, password);
+        }
+        let (client, eventloop) = AsyncClient::new(opts, CHANNEL_CAPACITY);
+
+        // Cleared on every new connection so that the monitor
+        // states are republished after a broker restart.
+        let (connected_tx, connected_rx) = watch::channel(());
+
+        let state = EventLoopState {
+            rt_handle: rt_handle.clone(),
+            logger,
+            mqtt_logger: mqtt_logger.clone(),
+            client: client.clone(),
+            monitor_manager: monitor_manager.clone(),
+            topics: topics.clone(),
+        };
+        rt_handle.spawn(WithRuntime::new(
+            rt_handle.clone(),
+            state.run(token.clone(), eventloop, connected_tx),
+        ));
+        rt_handle.spawn(WithRuntime::new(
+            rt_handle.clone(),
+            publish_monitor_states(
+                token,
+                mqtt_logger.clone(),
+                client.clone(),
+                monitor_manager,
+                topics.clone(),
+                connected_rx,
+            ),
+        ));
+
+        Self {
+            _shutdown_complete_tx: shutdown_complete_tx,
+            logger: mqtt_logger,
+            client,
+            topics,
+        }
+    }
+
+    // Queues the message without waiting, messages are
+    // dropped if the broker has been unreachable for a while.
+    fn publish(&self, topic: String, payload: Vec<u8>) {
+        if let Err(e) = self
+            .client
+            .try_publish(topic, QoS::AtLeastOnce, false, payload)
+        {
+            self.logger.log(LogLevel::Debug, &format!("publish: {e}"));
+        }
+    }
+
+    fn publish_recording(&self, monitor_id: &MonitorId, state: &str, recording_id: &RecordingId) {
+        let payload = serde_json::to_vec(&RecordingMessage {
+            state,
+            recording_id,
+        })
+        .expect("infallible");
+        self.publish(self.topics.recording(monitor_id), payload);
+    }
+}
+
+#[derive(Serialize)]
+struct RecordingMessage<'a> {
+    state: &'a str,
+    #[serde(rename = "recordingId")]
+    recording_id: &'a RecordingId,
+}
+
+#[async_trait]
+impl Plugin for MqttPlugin {
+    fn on_event(&self, config: &MonitorConfig, event: &Event) {
+        let monitor_id = config.id();
+        let payload = serde_json::to_vec(event).expect("infallible");
+        self.publish(self.topics.event(monitor_id), payload);
+
+        for d in &event.detections {
+            let payload = serde_json::to_vec(d).expect("infallible");
+            self.publish(self.topics.detection(monitor_id, &d.label), payload);
+        }
+    }
+
+    fn on_recording_start(&self, config: &MonitorConfig, recording_id: &RecordingId) {
+        self.publish_recording(config.id(), "start", recording_id);
+    }
+
+    fn on_recording_stop(&self, config: &MonitorConfig, recording_id: &RecordingId) {
+        self.publish_recording(config.id(), "stop", recording_id);
+    }
+}
+
+struct EventLoopState {
+    rt_handle: Handle,
+    logger: DynLogger,
+    mqtt_logger: DynMsgLogger,
+    client: AsyncClient,
+    monitor_manager: MonitorManager,
+    topics: Arc<Topics>,
+}
+
+impl EventLoopState {
+    async fn run(
+        self,
+        token: CancellationToken,
+        mut eventloop: EventLoop,
+        connected_tx: watch::Sender<()>,
+    ) {
+        loop {
+            let event = tokio::select! {
+                () = token.cancelled() => return,
+                v = eventloop.poll() => v,
+            };
+            let event = match event {
+                Ok(v) => v,
+                Err(e) => {
+                    self.mqtt_logger
+                        .log(LogLevel::Error, &format!("connection: {e}"));
+                    tokio::select! {
+                        () = token.cancelled() => return,
+                        () = tokio::time::sleep(RECONNECT_DELAY) => continue,
+                    }
+                }
+            };
+            let rumqttc::Event::Incoming(packet) = event else {
+                continue;
+            };
+            match packet {
+                Packet::ConnAck(_) => self.on_connect(&connected_tx),
+                Packet::Publish(p) => {
+                    let Some(cmd) = self.topics.parse_command(&p.topic, &p.payload) else {
+                        self.mqtt_logger.log(
+                            LogLevel::Warning,
+                            &format!("invalid command: topic:{}", p.topic),
+                        );
+                        continue;
+                    };
+                    // Restarting the monitor is slow, the
+                    // event loop must keep polling meanwhile.
+                    let logger = self.logger.clone();
+                    let monitor_manager = self.monitor_manager.clone();
+                    self.rt_handle.spawn(WithRuntime::new(
+                        self.rt_handle.clone(),
+                        handle_command(logger, monitor_manager, cmd),
+                    ));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn on_connect(&self, connected_tx: &watch::Sender<()>) {
+        self.mqtt_logger.log(LogLevel::Info, "connected");
+        // Subscriptions are lost if the session isn't persistent.
+        if let Err(e) = self
+            .client
+            .try_subscribe(self.topics.commands(), QoS::AtLeastOnce)
+        {
+            self.mqtt_logger
+                .log(LogLevel::Error, &format!("subscribe: {e}"));
+        }
+        if let Err(e) =
+            self.client
+                .try_publish(self.topics.status(), QoS::AtLeastOnce, true, "online")
+        {
+            self.mqtt_logger
+                .log(LogLevel::Error, &format!("publish status: {e}"));
+        }
+        connected_tx.send_replace(());
+    }
+}
+
+#[derive(Debug, Error)]
+enum HandleCommandError {
+    #[error("monitor does not exist")]
+    MonitorNotExist,
+
+    #[error("failed to find enable field")]
+    NoEnableField,
+
+    #[error("set and restart monitor: {0}")]
+    SetAndRestart(#[from] MonitorSetAndRestartError),
+}
+
+async fn handle_command(logger: DynLogger, monitor_manager: MonitorManager, cmd: Command) {
+    let msg_logger = MqttLogger {
+        logger,
+        monitor_id: Some(cmd.monitor_id.clone()),
+    };
+    let detector = cmd.detector.as_str();
+    match try_handle_command(&monitor_manager, &cmd).await {
+        Ok(()) => {
+            let state = if cmd.enable { "enabled" } else { "disabled" };
+            msg_logger.log(LogLevel::Info, &format!("{detector} detector {state}"));
+        }
+        Err(e) => {
+            msg_logger.log(LogLevel::Error, &format!("{detector} command: {e}"));
+        }
+    }
+}
+
+async fn try_handle_command(
+    monitor_manager: &MonitorManager,
+    cmd: &Command,
+) -> Result<(), HandleCommandError> {
+    use HandleCommandError::*;
+    let Some(old_config) = monitor_manager.monitor_config(cmd.monitor_id.clone()).await else {
+        return Err(MonitorNotExist);
+    };
+    let new_config = match set_enable(&old_config, cmd.detector, cmd.enable) {
+        Some(v) => v,
+        // A detector without config is already disabled.
+        None if !cmd.enable => return Ok(()),
+        None => return Err(NoEnableField),
+    };
+    monitor_manager.monitor_set_and_restart(new_config).await?;
+    Ok(())
+}
+
+// Publishes the retained online/offline state of each monitor when it changes.
+async fn publish_monitor_states(
+    token: CancellationToken,
+    logger: DynMsgLogger,
+    client: AsyncClient,
+    monitor_manager: MonitorManager,
+    topics: Arc<Topics>,
+    mut connected_rx: watch::Receiver<()>,
+) {
+    let mut prev_states: HashMap<MonitorId, bool> = HashMap::new();
+    loop {
+        if connected_rx.has_changed().unwrap_or(false) {
+            connected_rx.borrow_and_update();
+            prev_states.clear();
+        }
+
+        let mut states = HashMap::new();
+        for (id, info) in monitor_manager.monitors_info().await {
+            let online = info.enable() && monitor_manager.monitor_is_running(id.clone()).await;
+            states.insert(id, online);
+        }
+
+        for (id, online) in &states {
+            if prev_states.get(id) != Some(online) {
+                publish_state(&logger, &client, &topics, id, *online);
+            }
+        }
+        // Deleted monitors.
+        for id in prev_states.keys() {
+            if !states.contains_key(id) {
+                publish_state(&logger, &client, &topics, id, false);
+            }
+        }
+        prev_states = states;
+
+        tokio::select! {
+            () = token.cancelled() => return,
+            () = tokio::time::sleep(STATE_POLL_INTERVAL) => {}
+        }
+    }
+}
+
+fn publish_state(
+    logger: &DynMsgLogger,
+    client: &AsyncClient,
+    topics: &Topics,
+    monitor_id: &MonitorId,
+    online: bool,
+) {
+    let payload = if online { "online" } else { "offline" };
+    if let Err(e) = client.try_publish(
+        topics.monitor_state(monitor_id),
+        QoS::AtLeastOnce,
+        true,
+        payload,
+    ) {
+        logger.log(LogLevel::Debug, &format!("publish state: {e}"));
+    }
+}
+
+// Tokio can't access the runtime from the main binary without
+// entering it first, rumqttc needs it for every poll.
+#[pin_project]
+struct WithRuntime<F> {
+    rt_handle: Handle,
+    #[pin]
+    inner: F,
+}
+
+impl<F> WithRuntime<F> {
+    fn new(rt_handle: Handle, inner: F) -> Self {
+        Self { rt_handle, inner }
+    }
+}
+
+impl<F: Future> Future for WithRuntime<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let _enter = this.rt_handle.enter();
+        this.inner.poll(cx)
+    }
+}
+
+struct MqttLogger {
+    logger: DynLogger,
+    monitor_id: Option<MonitorId>,
+}
+
+impl MsgLogger for MqttLogger {
+    fn log(&self, level: LogLevel, msg: &str) {
+        self.logger.log(LogEntry::new(
+            level,
+            "mqtt",
+            self.monitor_id.clone(),
+            msg.to_owned(),
+        ));
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use common::{
+        time::{Duration as NanoDuration, UnixNano},
+        Detection, DummyLogger, Region,
+    };
+    use pretty_assertions::assert_eq;
+    use rumqttc::Request;
+    use serde_json::json;
+
+    fn new_test_plugin() -> (MqttPlugin, flume::Receiver<Request>) {
+        let (request_tx, request_rx) = flume::bounded(CHANNEL_CAPACITY);
+        let (shutdown_complete_tx, _) = mpsc::channel(1);
+        let plugin = MqttPlugin {
+            _shutdown_complete_tx: shutdown_complete_tx,
+            logger: Arc::new(MqttLogger {
+                logger: DummyLogger::new(),
+                monitor_id: None,
+            }),
+            client: AsyncClient::from_senders(request_tx),
+            topics: Arc::new(Topics::new("a".to_owned())),
+        };
+        (plugin, request_rx)
+    }
+
+    // Returns the topics and payloads of the published messages.
+    fn published(request_rx: &flume::Receiver<Request>) -> Vec<(String, serde_json::Value)> {
+        request_rx
+            .try_iter()
+            .filter_map(|req| match req {
+                Request::Publish(p) => {
+                    assert!(!p.retain);
+                    Some((p.topic, serde_json::from_slice(&p.payload).unwrap()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn test_config() -> MonitorConfig {
+        serde_json::from_value(json!({
+            "id": "1",
+            "name": "x",
+            "enable": true,
+            "source": "rtsp",
+            "sourcertsp": {
+                "protocol": "tcp",
+                "mainStream": "rtsp://x"
+            },
+            "alwaysRecord": false,
+            "videoLength": 15,
+        }))
+        .unwrap()
+    }
+
+    fn detection(label: &str) -> Detection {
+        Detection {
+            label: label.to_owned().try_into().unwrap(),
+            score: 50.0,
+            region: Region {
+                rectangle: None,
+                polygon: None,
+            },
+        }
+    }
+
+    #[test]
+    fn test_on_event() {
+        let (plugin, request_rx) = new_test_plugin();
+        let event = Event {
+            time: UnixNano::new(1),
+            duration: NanoDuration::new(2),
+            rec_duration: NanoDuration::new(3),
+            detections: vec![detection("person"), detection("car")],
+        };
+        plugin.on_event(&test_config(), &event);
+
+        let detection_payload = |label| {
+            json!({
+                "label": label,
+                "score": 50.0,
+                "region": { "rectangle": null, "polygon": null },
+            })
+        };
+        let want = vec![
+            (
+                "a/1/event".to_owned(),
+                json!({
+                    "time": 1,
+                    "duration": 2,
+                    "detections": [detection_payload("person"), detection_payload("car")],
+                }),
+            ),
+            (
+                "a/1/detection/person".to_owned(),
+                detection_payload("person"),
+            ),
+            ("a/1/detection/car".to_owned(), detection_payload("car")),
+        ];
+        assert_eq!(want, published(&request_rx));
+    }
+
+    #[test]
+    fn test_on_recording() {
+        let (plugin, request_rx) = new_test_plugin();
+        let rec_id = RecordingId::try_from("2000-01-01_00-00-00_1".to_owned()).unwrap();
+        plugin.on_recording_start(&test_config(), &rec_id);
+        plugin.on_recording_stop(&test_config(), &rec_id);
+
+        let want = vec![
+            (
+                "a/1/recording".to_owned(),
+                json!({ "state": "start", "recordingId": "2000-01-01_00-00-00_1" }),
+            ),
+            (
+                "a/1/recording".to_owned(),
+                json!({ "state": "stop", "recordingId": "2000-01-01_00-00-00_1" }),
+            ),
+        ];
+        assert_eq!(want, published(&request_rx));
+    }
+
+    #[test]
+    fn test_publish_state() {
+        let (plugin, request_rx) = new_test_plugin();
+        let monitor_id = "1".to_owned().try_into().unwrap();
+        publish_state(
+            &plugin.logger,
+            &plugin.client,
+            &plugin.topics,
+            &monitor_id,
+            true,
+        );
+        publish_state(
+            &plugin.logger,
+            &plugin.client,
+            &plugin.topics,
+            &monitor_id,
+            false,
+        );
+
+        let got: Vec<_> = request_rx
+            .try_iter()
+            .map(|req| {
+                let Request::Publish(p) = req else {
+                    unreachable!("only publish requests are sent");
+                };
+                assert!(p.retain);
+                (p.topic, p.payload.to_vec())
+            })
+            .collect();
+        let want = vec![
+            ("a/1/state".to_owned(), b"online".to_vec()),
+            ("a/1/state".to_owned(), b"offline".to_vec()),
+        ];
+        assert_eq!(want, got);
+    }
+}
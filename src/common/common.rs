@@ -14,8 +14,16 @@ use http::{HeaderMap, HeaderValue};
 pub use sentryshot_padded_bytes::PaddedBytes;
 use serde::{Deserialize, Serialize};
 use std::{
-    borrow::Cow, collections::HashMap, convert::TryFrom, fmt, io::Cursor, ops::Deref, path::Path,
-    str::FromStr, sync::Arc, task::Poll,
+    borrow::Cow,
+    collections::HashMap,
+    convert::TryFrom,
+    fmt,
+    io::Cursor,
+    ops::Deref,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    task::Poll,
 };
 use thiserror::Error;
 use time::{DtsOffset, DurationH264, UnixH264};
@@ -49,6 +57,148 @@ pub trait EnvConfig {
     fn plugin_dir(&self) -> &Path;
     fn max_disk_usage(&self) -> ByteSize;
     fn plugins(&self) -> &Option<Vec<EnvPlugin>>;
+
+    // Recordings are encrypted at rest if set.
+    fn encryption_key(&self) -> Option<&EncryptionKey>;
+
+    // Older recordings are moved to the archive if set.
+    fn archive(&self) -> Option<&ArchiveConfig>;
+
+    // Recordings are uploaded to a S3-compatible bucket if set.
+    fn replication(&self) -> Option<&ReplicationConfig>;
+}
+
+/// Second storage tier for older recordings.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ArchiveConfig {
+    dir: PathBuf,
+    recordings_dir: PathBuf,
+    max_disk_usage: ByteSize,
+    archive_after: time::Duration,
+}
+
+impl ArchiveConfig {
+    #[must_use]
+    pub fn new(
+        dir: PathBuf,
+        recordings_dir: PathBuf,
+        max_disk_usage: ByteSize,
+        archive_after: time::Duration,
+    ) -> Self {
+        Self {
+            dir,
+            recordings_dir,
+            max_disk_usage,
+            archive_after,
+        }
+    }
+
+    #[must_use]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    #[must_use]
+    pub fn recordings_dir(&self) -> &Path {
+        &self.recordings_dir
+    }
+
+    #[must_use]
+    pub fn max_disk_usage(&self) -> ByteSize {
+        self.max_disk_usage
+    }
+
+    // Recordings older than this are moved to the archive.
+    #[must_use]
+    pub fn archive_after(&self) -> time::Duration {
+        self.archive_after
+    }
+}
+
+/// Off-site replication to a S3-compatible bucket.
+#[derive(Clone, PartialEq, Eq, Deserialize)]
+pub struct ReplicationConfig {
+    // Path-style requests are used, `<endpoint>/<bucket>/<key>`.
+    endpoint: url::Url,
+    bucket: NonEmptyString,
+
+    #[serde(default = "default_region")]
+    region: NonEmptyString,
+
+    access_key: NonEmptyString,
+    secret_key: NonEmptyString,
+
+    // Prepended to the object keys.
+    #[serde(default)]
+    prefix: String,
+
+    // Upload speed limit in kilobytes per second.
+    bandwidth_limit: Option<std::num::NonZeroU64>,
+
+    // Only replicate recordings with events.
+    #[serde(default)]
+    events_only: bool,
+}
+
+fn default_region() -> NonEmptyString {
+    NonEmptyString("us-east-1".to_owned())
+}
+
+impl ReplicationConfig {
+    #[must_use]
+    pub fn endpoint(&self) -> &url::Url {
+        &self.endpoint
+    }
+
+    #[must_use]
+    pub fn bucket(&self) -> &str {
+        &self.bucket
+    }
+
+    #[must_use]
+    pub fn region(&self) -> &str {
+        &self.region
+    }
+
+    #[must_use]
+    pub fn access_key(&self) -> &str {
+        &self.access_key
+    }
+
+    #[must_use]
+    pub fn secret_key(&self) -> &str {
+        &self.secret_key
+    }
+
+    #[must_use]
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    // Bytes per second.
+    #[must_use]
+    pub fn bandwidth_limit(&self) -> Option<u64> {
+        self.bandwidth_limit.map(|v| v.get().saturating_mul(1000))
+    }
+
+    #[must_use]
+    pub fn events_only(&self) -> bool {
+        self.events_only
+    }
+}
+
+// The secret key should never end up in logs.
+impl fmt::Debug for ReplicationConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReplicationConfig")
+            .field("endpoint", &self.endpoint)
+            .field("bucket", &self.bucket)
+            .field("region", &self.region)
+            .field("prefix", &self.prefix)
+            .field("bandwidth_limit", &self.bandwidth_limit)
+            .field("events_only", &self.events_only)
+            .finish_non_exhaustive()
+    }
 }
 
 impl NonZeroGb {
@@ -94,6 +244,68 @@ impl<'de> Deserialize<'de> for NonZeroGb {
     }
 }
 
+/// 256 bit key used to encrypt recordings at rest.
+#[derive(Clone, PartialEq, Eq)]
+pub struct EncryptionKey([u8; 32]);
+
+impl EncryptionKey {
+    #[must_use]
+    pub fn new(key: [u8; 32]) -> Self {
+        Self(key)
+    }
+
+    #[must_use]
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+// The key should never end up in logs.
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "EncryptionKey(..)")
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ParseEncryptionKeyError {
+    #[error("expected 64 hex characters, got {0}")]
+    InvalidLength(usize),
+
+    #[error("invalid hex character")]
+    InvalidHex,
+}
+
+impl FromStr for EncryptionKey {
+    type Err = ParseEncryptionKeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use ParseEncryptionKeyError::*;
+        if s.len() != 64 {
+            return Err(InvalidLength(s.len()));
+        }
+        if !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(InvalidHex);
+        }
+        let mut key = [0; 32];
+        for (i, b) in key.iter_mut().enumerate() {
+            let hex = s.get(i * 2..i * 2 + 2).ok_or(InvalidHex)?;
+            *b = u8::from_str_radix(hex, 16).map_err(|_| InvalidHex)?;
+        }
+        Ok(Self(key))
+    }
+}
+
+impl<'de> Deserialize<'de> for EncryptionKey {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        FromStr::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
+
 /// Thread safe dyn '`ILogger`'.
 pub type DynLogger = Arc<dyn ILogger + Send + Sync>;
 
@@ -609,12 +821,21 @@ impl std::fmt::Debug for StreamType {
     }
 }
 
+#[derive(Clone, Debug, Default)]
+pub struct AudioSample {
+    pub pts: UnixH264, // Relative presentation timestamp.
+    pub data: Bytes,
+
+    pub duration: DurationH264,
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct PartFinalized {
     pub id: u64,
 
     pub is_independent: bool,
     pub video_samples: Arc<Vec<VideoSample>>,
+    pub audio_samples: Arc<Vec<AudioSample>>,
     pub rendered_content: Option<Bytes>,
     pub rendered_duration: DurationH264,
 }
@@ -784,8 +1005,84 @@ pub trait HlsMuxer {
 pub struct TrackParameters {
     pub width: u16,
     pub height: u16,
-    pub codec: String,
+    pub video_codec: VideoCodec,
+    pub codec: String, // RFC 6381 codec string.
     pub extra_data: Vec<u8>,
+    pub audio: Option<AudioParameters>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AudioParameters {
+    pub codec: String, // RFC 6381 codec string.
+    pub sample_entry: AudioSampleEntry,
+}
+
+// ISO/IEC 14496-12 audio sample entry, e.g. 'mp4a' including its 'esds' box.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AudioSampleEntry {
+    pub box_type: [u8; 4],
+    pub data: Vec<u8>, // Box content without the header.
+}
+
+impl AudioSampleEntry {
+    // Parses a complete box including the 8 byte header.
+    #[must_use]
+    pub fn from_box(b: &[u8]) -> Option<Self> {
+        if b.len() < 8 {
+            return None;
+        }
+        let size = u32::from_be_bytes([b[0], b[1], b[2], b[3]]);
+        if usize::try_from(size).ok()? != b.len() {
+            return None;
+        }
+        Some(Self {
+            box_type: [b[4], b[5], b[6], b[7]],
+            data: b[8..].to_vec(),
+        })
+    }
+
+    // Returns the complete box including the header.
+    pub fn to_box(&self) -> Result<Vec<u8>, std::num::TryFromIntError> {
+        let size = u32::try_from(self.data.len() + 8)?;
+        let mut out = Vec::with_capacity(self.data.len() + 8);
+        out.extend_from_slice(&size.to_be_bytes());
+        out.extend_from_slice(&self.box_type);
+        out.extend_from_slice(&self.data);
+        Ok(out)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VideoCodec {
+    #[default]
+    H264,
+    H265,
+}
+
+impl VideoCodec {
+    // Parses the RTP encoding name.
+    #[must_use]
+    pub fn from_encoding_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "h264" => Some(Self::H264),
+            "h265" => Some(Self::H265),
+            _ => None,
+        }
+    }
+
+    #[must_use]
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::H264 => "h264",
+            Self::H265 => "h265",
+        }
+    }
+}
+
+impl fmt::Display for VideoCodec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
 }
 
 #[derive(Clone, Debug, Default)]
@@ -806,6 +1103,12 @@ impl std::fmt::Display for H264Data {
     }
 }
 
+#[derive(Clone, Debug, Default)]
+pub struct AudioData {
+    pub pts: UnixH264, // Absolute presentation timestamp on the same timeline as the video.
+    pub data: Bytes,
+}
+
 pub type DynMsgLogger = Arc<dyn MsgLogger + Send + Sync>;
 
 pub trait MsgLogger {
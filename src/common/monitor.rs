@@ -1,11 +1,12 @@
 // SPDX-License-Identifier: GPL-2.0-or-later
 
 use crate::{
-    time::{Duration, MINUTE},
+    time::{Duration, DAY, MINUTE, SECOND},
     MonitorId, NonEmptyString,
 };
+use bytesize::GB;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use std::{collections::HashMap, ops::Deref, str::FromStr};
+use std::{collections::HashMap, ops::Deref, path::PathBuf, str::FromStr};
 use thiserror::Error;
 use url::Url;
 
@@ -73,6 +74,8 @@ impl MonitorConfig {
     pub fn has_sub_stream(&self) -> bool {
         match &self.source {
             SourceConfig::Rtsp(v) => v.sub_stream.is_some(),
+            SourceConfig::File(_) => false,
+            SourceConfig::Http(v) => v.sub_stream.is_some(),
         }
     }
 
@@ -93,6 +96,24 @@ impl MonitorConfig {
         Duration::from_f64(self.config.video_length * (MINUTE as f64))
     }
 
+    // Draw the detections on the thumbnail.
+    #[must_use]
+    pub fn thumbnail_detections(&self) -> bool {
+        self.config.thumbnail_detections
+    }
+
+    #[must_use]
+    pub fn retention(&self) -> &RetentionConfig {
+        &self.config.retention
+    }
+
+    // Footage to include before the event that triggered the recording.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss, clippy::as_conversions)]
+    pub fn pre_roll(&self) -> Duration {
+        Duration::from_f64(self.config.pre_roll * (SECOND as f64))
+    }
+
     /*
         // TimestampOffset returns the timestamp offset.
         func (c Config) TimestampOffset() string {
@@ -118,6 +139,72 @@ pub struct Config {
 
     #[serde(rename = "videoLength")]
     pub video_length: f64,
+
+    // Seconds.
+    #[serde(rename = "preRoll", default, deserialize_with = "deserialize_pre_roll")]
+    pub pre_roll: f64,
+
+    #[serde(rename = "thumbnailDetections", default)]
+    pub thumbnail_detections: bool,
+
+    #[serde(default)]
+    pub retention: RetentionConfig,
+}
+
+// The segments are buffered in memory.
+const MAX_PRE_ROLL_SECS: f64 = 30.0;
+
+fn deserialize_pre_roll<'de, D>(deserializer: D) -> Result<f64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let v = f64::deserialize(deserializer)?;
+    if !(0.0..=MAX_PRE_ROLL_SECS).contains(&v) {
+        return Err(serde::de::Error::custom(format!(
+            "pre-roll must be between 0 and {MAX_PRE_ROLL_SECS} seconds"
+        )));
+    }
+    Ok(v)
+}
+
+// Per-monitor recording retention, all limits are optional.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct RetentionConfig {
+    // Days to keep recordings without events.
+    #[serde(rename = "maxAge")]
+    pub max_age: Option<f64>,
+
+    // Days to keep recordings with events, defaults to `maxAge`.
+    #[serde(rename = "eventMaxAge")]
+    pub event_max_age: Option<f64>,
+
+    // Maximum storage used by the monitor in GB.
+    #[serde(rename = "maxSize")]
+    pub max_size: Option<f64>,
+}
+
+impl RetentionConfig {
+    #[must_use]
+    #[allow(clippy::cast_precision_loss, clippy::as_conversions)]
+    pub fn max_age(&self, has_events: bool) -> Option<Duration> {
+        let days = if has_events {
+            self.event_max_age.or(self.max_age)?
+        } else {
+            self.max_age?
+        };
+        Some(Duration::from_f64(days * (DAY as f64)))
+    }
+
+    #[must_use]
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::as_conversions
+    )]
+    pub fn max_size(&self) -> Option<u64> {
+        Some((self.max_size? * (GB as f64)) as u64)
+    }
 }
 
 impl Serialize for MonitorConfig {
@@ -143,6 +230,8 @@ impl<'de> Deserialize<'de> for MonitorConfig {
 
         let source = match config.source {
             SelectedSource::Rtsp => SourceConfig::Rtsp(sources.source_rtsp.map_err(Error::custom)?),
+            SelectedSource::File => SourceConfig::File(sources.source_file.map_err(Error::custom)?),
+            SelectedSource::Http => SourceConfig::Http(sources.source_http.map_err(Error::custom)?),
         };
 
         Ok(Self {
@@ -157,12 +246,41 @@ impl<'de> Deserialize<'de> for MonitorConfig {
 pub enum SelectedSource {
     #[serde(rename = "rtsp")]
     Rtsp,
+
+    #[serde(rename = "file")]
+    File,
+
+    #[serde(rename = "http")]
+    Http,
 }
 
+// Only the selected source has to be valid.
 #[derive(Deserialize)]
 pub struct SourcesDeserializer {
-    #[serde(rename = "sourcertsp", deserialize_with = "try_deserialize")]
+    #[serde(
+        rename = "sourcertsp",
+        deserialize_with = "try_deserialize",
+        default = "missing_source"
+    )]
     pub source_rtsp: Result<SourceRtspConfig, serde_json::Error>,
+
+    #[serde(
+        rename = "sourcefile",
+        deserialize_with = "try_deserialize",
+        default = "missing_source"
+    )]
+    pub source_file: Result<SourceFileConfig, serde_json::Error>,
+
+    #[serde(
+        rename = "sourcehttp",
+        deserialize_with = "try_deserialize",
+        default = "missing_source"
+    )]
+    pub source_http: Result<SourceHttpConfig, serde_json::Error>,
+}
+
+fn missing_source<T>() -> Result<T, serde_json::Error> {
+    Err(serde::de::Error::custom("missing source config"))
 }
 
 fn try_deserialize<'de, D, T>(deserializer: D) -> Result<Result<T, serde_json::Error>, D::Error>
@@ -177,6 +295,8 @@ where
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum SourceConfig {
     Rtsp(SourceRtspConfig),
+    File(SourceFileConfig),
+    Http(SourceHttpConfig),
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
@@ -193,6 +313,27 @@ pub struct SourceRtspConfig {
     // but for now I only need it for PTZ, so sticking with what already exists
     #[serde(rename = "onvifUrl")]
     pub onvif_url: Option<Url>,
+
+    // Record audio from the main stream.
+    #[serde(default)]
+    pub audio: bool,
+}
+
+// Loops a local recording or MP4 file.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct SourceFileConfig {
+    // Path to a '.mp4' file or the '.meta' file of a recording.
+    pub path: PathBuf,
+}
+
+// Pulls a HLS stream with fMP4 segments.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct SourceHttpConfig {
+    #[serde(rename = "mainStream")]
+    pub main_stream: HttpUrl,
+
+    #[serde(rename = "subStream")]
+    pub sub_stream: Option<HttpUrl>,
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
@@ -253,3 +394,53 @@ impl Deref for RtspUrl {
         &self.0
     }
 }
+
+#[derive(Debug, Error)]
+pub enum ParseHttpUrl {
+    #[error("{0}")]
+    ParseUrl(#[from] url::ParseError),
+
+    #[error("bad url '{0}' only schemes http and https supported")]
+    BadScheme(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpUrl(Url);
+
+impl TryFrom<Url> for HttpUrl {
+    type Error = ParseHttpUrl;
+
+    fn try_from(url: Url) -> Result<Self, Self::Error> {
+        if url.scheme() != "http" && url.scheme() != "https" {
+            return Err(ParseHttpUrl::BadScheme(url.to_string()));
+        }
+        Ok(Self(url))
+    }
+}
+
+impl FromStr for HttpUrl {
+    type Err = ParseHttpUrl;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let url = Url::try_from(value)?;
+        HttpUrl::try_from(url)
+    }
+}
+
+impl<'de> Deserialize<'de> for HttpUrl {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        FromStr::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+impl Deref for HttpUrl {
+    type Target = Url;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
@@ -146,6 +146,27 @@ impl RecordingId {
         &self.raw[20..]
     }
 
+    #[must_use]
+    pub fn monitor(&self) -> &MonitorId {
+        &self.monitor_id
+    }
+
+    // Start time of the recording with second precision.
+    #[must_use]
+    pub fn as_nanos(&self) -> Option<UnixNano> {
+        let time = chrono::NaiveDate::from_ymd_opt(
+            i32::from(self.year),
+            u32::from(self.month),
+            u32::from(self.day),
+        )?
+        .and_hms_opt(
+            u32::from(self.hour),
+            u32::from(self.minute),
+            u32::from(self.second),
+        )?;
+        Some(UnixNano::new(time.timestamp_nanos_opt()?))
+    }
+
     #[must_use]
     pub fn as_str(&self) -> &str {
         &self.raw
@@ -559,6 +580,15 @@ mod tests {
         assert_eq!(want, got);
     }
 
+    #[test_case("1970-01-01_00-00-00_x", Some(0))]
+    #[test_case("1970-01-01_00-00-02_x", Some(2*SECOND))]
+    #[test_case("2001-02-03_04-05-06_x", Some(981_173_106 * SECOND))]
+    #[test_case("0000-00-00_00-00-00_x", None)]
+    fn test_recording_id_as_nanos(input: &str, want: Option<i64>) {
+        let id: RecordingId = input.to_owned().try_into().unwrap();
+        assert_eq!(want.map(UnixNano::new), id.as_nanos());
+    }
+
     #[test_case(640, 1, 1_562)]
     #[test_case(640, 64, 100_000)]
     #[test_case(640, 100, 156_250)]
@@ -14,6 +14,7 @@ pub const MILLISECOND: i64 = MICROSECOND * 1000;
 pub const SECOND: i64 = MILLISECOND * 1000;
 pub const MINUTE: i64 = SECOND * 60;
 pub const HOUR: i64 = MINUTE * 60;
+pub const DAY: i64 = HOUR * 24;
 
 // Nanoseconds since the Unix epoch.
 #[repr(transparent)]
@@ -1,12 +1,16 @@
 // SPDX-License-Identifier: GPL-2.0-or-later
 
 use bytesize::ByteSize;
-use common::{EnvConfig, EnvPlugin, NonZeroGb};
+use common::{
+    time::{Duration, DAY},
+    ArchiveConfig, EncryptionKey, EnvConfig, EnvPlugin, NonZeroGb, ReplicationConfig,
+};
 use serde::Deserialize;
 use std::{
     collections::HashMap,
     fs::{self, File},
     io::Write,
+    num::NonZeroU32,
     path::{Path, PathBuf},
 };
 use thiserror::Error;
@@ -20,6 +24,9 @@ pub struct EnvConf {
     config_dir: PathBuf,
     plugin_dir: PathBuf,
     max_disk_usage: NonZeroGb,
+    encryption_key: Option<EncryptionKey>,
+    archive: Option<ArchiveConfig>,
+    replication: Option<ReplicationConfig>,
     plugin: Option<Vec<EnvPlugin>>,
 }
 
@@ -30,9 +37,19 @@ pub struct RawEnvConf {
     config_dir: PathBuf,
     plugin_dir: PathBuf,
     max_disk_usage: NonZeroGb,
+    encryption_key: Option<EncryptionKey>,
+    archive: Option<RawArchiveConfig>,
+    replication: Option<ReplicationConfig>,
     plugin: Option<Vec<EnvPlugin>>,
 }
 
+#[derive(Debug, Deserialize)]
+struct RawArchiveConfig {
+    dir: PathBuf,
+    max_disk_usage: NonZeroGb,
+    archive_after_days: NonZeroU32,
+}
+
 impl EnvConf {
     pub fn new(config_path: &PathBuf) -> Result<EnvConf, EnvConfigNewError> {
         use EnvConfigNewError::*;
@@ -78,6 +95,18 @@ impl EnvConfig for EnvConf {
     fn plugins(&self) -> &Option<Vec<EnvPlugin>> {
         &self.plugin
     }
+
+    fn encryption_key(&self) -> Option<&EncryptionKey> {
+        self.encryption_key.as_ref()
+    }
+
+    fn archive(&self) -> Option<&ArchiveConfig> {
+        self.archive.as_ref()
+    }
+
+    fn replication(&self) -> Option<&ReplicationConfig> {
+        self.replication.as_ref()
+    }
 }
 
 #[derive(Debug, Error)]
@@ -160,6 +189,33 @@ plugin_dir = \"{{ cwd }}/plugins\"
 # Recordings are delete automatically before this limit is exceeded.
 max_disk_usage = 100
 
+# Optional key used to encrypt recordings and thumbnails at rest.
+# 64 hex characters, generate with `openssl rand -hex 32`.
+# Existing unencrypted recordings remain readable. Recordings
+# written with a key can't be read without it, keep a backup.
+#encryption_key = \"\"
+
+# Optional second storage tier, usually a larger and slower disk.
+# Recordings older than `archive_after_days` are moved here
+# and are pruned separately based on the archive's `max_disk_usage`.
+#[archive]
+#dir = \"/mnt/archive\"
+#max_disk_usage = 1000
+#archive_after_days = 7
+
+# Optional off-site replication to a S3-compatible bucket.
+# Finalized recordings are uploaded in the background, failed
+# uploads are retried. `bandwidth_limit` is in kilobytes per second.
+#[replication]
+#endpoint = \"https://s3.example.com\"
+#bucket = \"sentryshot\"
+#region = \"us-east-1\"
+#access_key = \"\"
+#secret_key = \"\"
+#prefix = \"\"
+#bandwidth_limit = 1000
+#events_only = false
+
 
 
 # PLUGINS
@@ -184,6 +240,13 @@ enable = false
 name = \"motion\"
 enable = false
 
+# MQTT publisher.
+# Enabling will generate a `mqtt.toml` file.
+# Documentation ./plugins/mqtt/README.md
+[[plugin]]
+name = \"mqtt\"
+enable = false
+
 # TFlite object detection.
 # Enabling will generate a `tflite.toml` file.
 [[plugin]]
@@ -211,6 +274,9 @@ pub enum ParseEnvConfigError {
     #[error("create recordings dir: {0} {1}")]
     CreateRecDir(PathBuf, std::io::Error),
 
+    #[error("create archive dir: {0} {1}")]
+    CreateArchiveDir(PathBuf, std::io::Error),
+
     #[error("canonicalize path: {0:?} {1}")]
     Canonicalize(PathBuf, std::io::Error),
 }
@@ -253,6 +319,11 @@ fn parse_config(env_toml: &str) -> Result<EnvConf, ParseEnvConfigError> {
         .canonicalize()
         .map_err(|e| Canonicalize(raw.plugin_dir, e))?;
 
+    let archive = match raw.archive {
+        Some(archive) => Some(parse_archive_config(archive)?),
+        None => None,
+    };
+
     Ok(EnvConf {
         port: raw.port,
         storage_dir,
@@ -260,10 +331,39 @@ fn parse_config(env_toml: &str) -> Result<EnvConf, ParseEnvConfigError> {
         config_dir,
         plugin_dir,
         max_disk_usage: raw.max_disk_usage,
+        encryption_key: raw.encryption_key,
+        archive,
+        replication: raw.replication,
         plugin: raw.plugin,
     })
 }
 
+fn parse_archive_config(raw: RawArchiveConfig) -> Result<ArchiveConfig, ParseEnvConfigError> {
+    use ParseEnvConfigError::*;
+    if !raw.dir.is_absolute() {
+        return Err(PathNotAbsolute("archive.dir".to_owned(), raw.dir));
+    }
+
+    let recordings_dir = raw.dir.join("recordings");
+    std::fs::create_dir_all(&recordings_dir)
+        .map_err(|e| CreateArchiveDir(recordings_dir.clone(), e))?;
+    let recordings_dir = recordings_dir
+        .canonicalize()
+        .map_err(|e| Canonicalize(recordings_dir, e))?;
+    let dir = raw
+        .dir
+        .canonicalize()
+        .map_err(|e| Canonicalize(raw.dir, e))?;
+
+    let archive_after = Duration::from_nanos(i64::from(raw.archive_after_days.get()) * DAY);
+    Ok(ArchiveConfig::new(
+        dir,
+        recordings_dir,
+        *raw.max_disk_usage,
+        archive_after,
+    ))
+}
+
 #[allow(clippy::unwrap_used)]
 #[cfg(test)]
 mod tests {
@@ -313,6 +413,9 @@ mod tests {
             config_dir: config_dir.parse().unwrap(),
             plugin_dir: plugin_dir.parse().unwrap(),
             max_disk_usage: NonZeroGb::new(ByteSize(GB)).unwrap(),
+            encryption_key: None,
+            archive: None,
+            replication: None,
             plugin: None,
         };
         let got = parse_config(&config).unwrap();
@@ -370,4 +473,115 @@ mod tests {
             Err(ParseEnvConfigError::PathNotAbsolute(..))
         ));
     }
+    #[test]
+    fn test_parse_config_encryption_key() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage_dir = temp_dir.path().join("storage");
+        let storage_dir = storage_dir.to_str().unwrap();
+        let dir = temp_dir.path().to_str().unwrap();
+        let key = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+        let config = format!(
+            "
+            port = 2020
+            storage_dir = \"{storage_dir}\"
+            config_dir = \"{dir}\"
+            plugin_dir = \"{dir}\"
+            max_disk_usage = 1
+            encryption_key = \"{key}\"
+        ",
+        );
+        let got = parse_config(&config).unwrap();
+        let want: [u8; 32] = std::array::from_fn(|i| u8::try_from(i).unwrap());
+        assert_eq!(&want, got.encryption_key().unwrap().as_bytes());
+
+        let config = config.replace(key, "0011");
+        assert!(matches!(
+            parse_config(&config),
+            Err(ParseEnvConfigError::DeserializeToml(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_config_archive() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage_dir = temp_dir.path().join("storage");
+        let storage_dir = storage_dir.to_str().unwrap();
+        let archive_dir = temp_dir.path().join("archive");
+        let archive_dir = archive_dir.to_str().unwrap();
+        let dir = temp_dir.path().to_str().unwrap();
+
+        let config = format!(
+            "
+            port = 2020
+            storage_dir = \"{storage_dir}\"
+            config_dir = \"{dir}\"
+            plugin_dir = \"{dir}\"
+            max_disk_usage = 1
+
+            [archive]
+            dir = \"{archive_dir}\"
+            max_disk_usage = 2
+            archive_after_days = 7
+        ",
+        );
+        let got = parse_config(&config).unwrap();
+        let archive_dir: PathBuf = archive_dir.parse().unwrap();
+        let want = ArchiveConfig::new(
+            archive_dir.clone(),
+            archive_dir.join("recordings"),
+            ByteSize(2 * GB),
+            Duration::from_nanos(7 * DAY),
+        );
+        assert_eq!(Some(&want), got.archive());
+        assert!(archive_dir.join("recordings").exists());
+
+        let config = config.replace(
+            &format!("dir = \"{}\"", archive_dir.display()),
+            "dir = \".\"",
+        );
+        assert!(matches!(
+            parse_config(&config),
+            Err(ParseEnvConfigError::PathNotAbsolute(..))
+        ));
+    }
+
+    #[test]
+    fn test_parse_config_replication() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage_dir = temp_dir.path().join("storage");
+        let storage_dir = storage_dir.to_str().unwrap();
+        let dir = temp_dir.path().to_str().unwrap();
+
+        let config = format!(
+            "
+            port = 2020
+            storage_dir = \"{storage_dir}\"
+            config_dir = \"{dir}\"
+            plugin_dir = \"{dir}\"
+            max_disk_usage = 1
+
+            [replication]
+            endpoint = \"http://127.0.0.1:9000\"
+            bucket = \"a\"
+            access_key = \"b\"
+            secret_key = \"c\"
+            bandwidth_limit = 2
+        ",
+        );
+        let got = parse_config(&config).unwrap();
+        let replication = got.replication().unwrap();
+        assert_eq!("http://127.0.0.1:9000/", replication.endpoint().as_str());
+        assert_eq!("a", replication.bucket());
+        assert_eq!("us-east-1", replication.region());
+        assert_eq!("", replication.prefix());
+        assert_eq!(Some(2000), replication.bandwidth_limit());
+        assert!(!replication.events_only());
+
+        let config = config.replace("secret_key = \"c\"", "secret_key = \"\"");
+        assert!(matches!(
+            parse_config(&config),
+            Err(ParseEnvConfigError::DeserializeToml(_))
+        ));
+    }
 }
@@ -7,7 +7,7 @@ mod test;
 
 use axum::{
     body::Body,
-    extract::{Path, Query, State, WebSocketUpgrade},
+    extract::{Path, Query, RawQuery, State, WebSocketUpgrade},
     http::{header, HeaderMap, Method, StatusCode, Uri},
     response::{IntoResponse, Response},
     Json,
@@ -25,8 +25,11 @@ use log::{
     Logger,
 };
 use monitor::{MonitorDeleteError, MonitorManager, ptz::PtzCapabilities};
-use recdb::{DeleteRecordingError, RecDb, RecDbQuery, RecordingResponse};
-use recording::{new_video_reader, VideoCache};
+use recdb::{
+    DeleteRecordingError, EventQuery, HashChainReport, LockRecordingError, QueryEventsError, RecDb,
+    RecDbQuery, RecordingFilter, RecordingResponse, ReplicationStatus, VerifyHashChainError,
+};
+use recording::{new_video_reader, RecordingFile, VideoCache};
 use rust_embed::EmbeddedFiles;
 use serde::Deserialize;
 use std::{collections::BTreeMap, path::PathBuf, sync::Arc};
@@ -34,7 +37,10 @@ use thiserror::Error;
 use tokio::sync::{broadcast::error::RecvError, Mutex};
 use tokio_util::io::ReaderStream;
 use monitor::ptz::PtzDirection;
-use vod::{CreateVodReaderError, VodCache, VodQuery, VodReader};
+use vod::{
+    CreateExportError, CreateVodReaderError, ExportId, ExportManager, ExportRequest, ExportStatus,
+    VodCache, VodPlaylist, VodQuery, VodReader,
+};
 use web::{serve_mp4_content, Templater};
 
 #[derive(Clone)]
@@ -226,18 +232,18 @@ pub async fn vod_handler(
     headers: HeaderMap,
 ) -> Response {
     use CreateVodReaderError::*;
-    let monitor_id = query.0.monitor_id.clone();
+    let monitor_id = query.0.monitor_ids.first().cloned();
     let reader = match VodReader::new(&state.recdb, &state.cache, query.0).await {
         Ok(Some(v)) => v,
         Ok(None) => return (StatusCode::NOT_FOUND, "no video found").into_response(),
-        Err(e @ (NegativeDuration | MaxDuration)) => {
+        Err(e @ (NoMonitors | NegativeDuration | MaxDuration | MismatchedParams(_))) => {
             return (StatusCode::BAD_REQUEST, e.to_string()).into_response();
         }
         Err(e) => {
             state.logger.log(LogEntry::new(
                 LogLevel::Error,
                 "app",
-                Some(monitor_id),
+                monitor_id,
                 format!("vod handler: {e}"),
             ));
             return (StatusCode::INTERNAL_SERVER_ERROR, "error printed to logs").into_response();
@@ -246,6 +252,54 @@ pub async fn vod_handler(
     serve_mp4_content(&Method::GET, &headers, None, reader.size(), reader).await
 }
 
+// Serves `vod.m3u8`, `init.mp4` and the numbered segments. The query
+// is forwarded to the files referenced by the playlist.
+pub async fn vod_hls_handler(
+    State(state): State<VodHandlerState>,
+    Path(file): Path<String>,
+    RawQuery(raw_query): RawQuery,
+    query: Query<VodQuery>,
+) -> Response {
+    use CreateVodReaderError::*;
+    let monitor_id = query.0.monitor_ids.first().cloned();
+    let log_error = |msg: String| {
+        state.logger.log(LogEntry::new(
+            LogLevel::Error,
+            "app",
+            monitor_id.clone(),
+            format!("vod hls handler: {msg}"),
+        ));
+        (StatusCode::INTERNAL_SERVER_ERROR, "error printed to logs").into_response()
+    };
+    let playlist = match VodPlaylist::new(&state.recdb, &state.cache, query.0).await {
+        Ok(Some(v)) => v,
+        Ok(None) => return (StatusCode::NOT_FOUND, "no video found").into_response(),
+        Err(e @ (NoMonitors | NegativeDuration | MaxDuration | MismatchedParams(_))) => {
+            return (StatusCode::BAD_REQUEST, e.to_string()).into_response();
+        }
+        Err(e) => return log_error(e.to_string()),
+    };
+
+    if file == "vod.m3u8" {
+        let body = playlist.playlist(&raw_query.unwrap_or_default());
+        return ([(header::CONTENT_TYPE, "application/x-mpegURL")], body).into_response();
+    }
+    if file == "init.mp4" {
+        return match playlist.init() {
+            Ok(v) => ([(header::CONTENT_TYPE, "video/mp4")], v).into_response(),
+            Err(e) => log_error(e.to_string()),
+        };
+    }
+    let Some(index) = file.strip_suffix(".mp4").and_then(|v| v.parse().ok()) else {
+        return (StatusCode::NOT_FOUND, "file not found").into_response();
+    };
+    match playlist.segment(index).await {
+        Ok(Some(v)) => ([(header::CONTENT_TYPE, "video/mp4")], v).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "segment not found").into_response(),
+        Err(e) => log_error(e.to_string()),
+    }
+}
+
 const API_HTML: &str = include_str!("./api.html");
 
 pub async fn api_page_handler() -> Response {
@@ -312,8 +366,13 @@ pub struct RecordingQueryHandlerState {
 pub async fn recording_query_handler(
     State(s): State<RecordingQueryHandlerState>,
     query: Query<RecDbQuery>,
+    filter: Query<RecordingFilter>,
 ) -> Result<Json<Vec<RecordingResponse>>, StatusCode> {
-    match s.rec_db.recordings_by_query(&query.0).await {
+    let query = RecDbQuery {
+        filter: filter.0,
+        ..query.0
+    };
+    match s.rec_db.recordings_by_query(&query).await {
         Ok(v) => Ok(Json(v)),
         Err(e) => {
             s.logger.log(LogEntry::new(
@@ -327,6 +386,27 @@ pub async fn recording_query_handler(
     }
 }
 
+pub async fn event_query_handler(
+    State(s): State<RecordingQueryHandlerState>,
+    query: Query<EventQuery>,
+) -> Response {
+    match s.rec_db.events_by_query(query.0).await {
+        Ok(v) => Json(v).into_response(),
+        Err(e @ QueryEventsError::MaxDuration) => {
+            (StatusCode::BAD_REQUEST, e.to_string()).into_response()
+        }
+        Err(e) => {
+            s.logger.log(LogEntry::new(
+                LogLevel::Error,
+                "app",
+                None,
+                format!("could not process event query: {e}"),
+            ));
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct LogFeedHandlerState {
     pub logger: Arc<log::Logger>,
@@ -453,7 +533,7 @@ pub async fn recording_delete_handler(
 ) -> Response {
     match rec_db.delete_recording(rec_id).await {
         Ok(()) => StatusCode::OK.into_response(),
-        Err(e @ DeleteRecordingError::Active) => {
+        Err(e @ DeleteRecordingError::Locked) => {
             (StatusCode::BAD_REQUEST, e.to_string()).into_response()
         }
         Err(DeleteRecordingError::NotExist) => StatusCode::NOT_FOUND.into_response(),
@@ -461,15 +541,160 @@ pub async fn recording_delete_handler(
     }
 }
 
+// POST locks and DELETE unlocks the recording.
+pub async fn recording_lock_handler(
+    method: Method,
+    State(rec_db): State<Arc<RecDb>>,
+    Path(rec_id): Path<RecordingId>,
+) -> Response {
+    let locked = method == Method::POST;
+    match rec_db.set_recording_locked(&rec_id, locked).await {
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(LockRecordingError::NotExist) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+// Verifies the hash chain up to and including the recording.
+pub async fn recording_verify_handler(
+    State(rec_db): State<Arc<RecDb>>,
+    Path(rec_id): Path<RecordingId>,
+) -> Response {
+    let monitor_id = rec_id.monitor().clone();
+    hash_chain_response(rec_db.verify_hash_chain(monitor_id, Some(rec_id)).await)
+}
+
+// Verifies the entire hash chain of the monitor.
+pub async fn monitor_verify_handler(
+    State(rec_db): State<Arc<RecDb>>,
+    Path(monitor_id): Path<MonitorId>,
+) -> Response {
+    hash_chain_response(rec_db.verify_hash_chain(monitor_id, None).await)
+}
+
+fn hash_chain_response(res: Result<HashChainReport, VerifyHashChainError>) -> Response {
+    match res {
+        Ok(report) => Json(report).into_response(),
+        Err(e @ (VerifyHashChainError::NoChain | VerifyHashChainError::NotInChain)) => {
+            (StatusCode::NOT_FOUND, e.to_string()).into_response()
+        }
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+pub async fn replication_status_handler(State(rec_db): State<Arc<RecDb>>) -> Response {
+    match rec_db.replication_status() {
+        Some(status) => Json::<ReplicationStatus>(status).into_response(),
+        None => (StatusCode::NOT_FOUND, "replication is disabled").into_response(),
+    }
+}
+
+pub async fn export_create_handler(
+    State(export_manager): State<Arc<ExportManager>>,
+    Json(req): Json<ExportRequest>,
+) -> Response {
+    match export_manager.create(req) {
+        Ok(status) => (StatusCode::CREATED, Json(status)).into_response(),
+        Err(e @ CreateExportError::TooManyExports) => {
+            (StatusCode::TOO_MANY_REQUESTS, e.to_string()).into_response()
+        }
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+pub async fn exports_handler(
+    State(export_manager): State<Arc<ExportManager>>,
+) -> Json<Vec<ExportStatus>> {
+    Json(export_manager.list())
+}
+
+// GET returns the status and DELETE cancels and removes the export.
+pub async fn export_handler(
+    State(export_manager): State<Arc<ExportManager>>,
+    Path(id): Path<ExportId>,
+) -> Response {
+    match export_manager.status(id) {
+        Some(status) => Json(status).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+pub async fn export_delete_handler(
+    State(export_manager): State<Arc<ExportManager>>,
+    Path(id): Path<ExportId>,
+) -> StatusCode {
+    if export_manager.delete(id) {
+        StatusCode::OK
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+pub async fn export_download_handler(
+    State(export_manager): State<Arc<ExportManager>>,
+    Path(id): Path<ExportId>,
+) -> Response {
+    let Some((path, file_name)) = export_manager.file(id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let file = match tokio::fs::File::open(path).await {
+        Ok(v) => v,
+        Err(e) => {
+            return (StatusCode::INTERNAL_SERVER_ERROR, format!("open file: {e}")).into_response()
+        }
+    };
+
+    let content_type = if file_name.ends_with(".zip") {
+        "application/zip"
+    } else {
+        "video/mp4"
+    };
+    let disposition = format!("attachment; filename=\"{file_name}\"");
+    let body = Body::from_stream(ReaderStream::new(file));
+
+    (
+        [
+            (header::CONTENT_TYPE, content_type.to_owned()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    )
+        .into_response()
+}
+
 pub async fn recording_thumbnail_handler(
     State(rec_db): State<Arc<RecDb>>,
     Path(rec_id): Path<RecordingId>,
 ) -> Response {
-    let Some(path) = rec_db.thumbnail_path(&rec_id).await else {
+    recording_file_response(&rec_db, &rec_id, "jpeg", "image/jpeg").await
+}
+
+pub async fn recording_sprite_handler(
+    State(rec_db): State<Arc<RecDb>>,
+    Path(rec_id): Path<RecordingId>,
+) -> Response {
+    recording_file_response(&rec_db, &rec_id, "sprite", "image/jpeg").await
+}
+
+pub async fn recording_sprite_vtt_handler(
+    State(rec_db): State<Arc<RecDb>>,
+    Path(rec_id): Path<RecordingId>,
+) -> Response {
+    recording_file_response(&rec_db, &rec_id, "vtt", "text/vtt").await
+}
+
+async fn recording_file_response(
+    rec_db: &RecDb,
+    rec_id: &RecordingId,
+    ext: &str,
+    content_type: &'static str,
+) -> Response {
+    let Some(path) = rec_db.recording_file_by_ext(rec_id, ext).await else {
         return (StatusCode::NOT_FOUND).into_response();
     };
 
-    let file = match tokio::fs::OpenOptions::new().read(true).open(path).await {
+    let file = match RecordingFile::open(&path, rec_db.encryption_key()).await {
         Ok(v) => v,
         Err(e) => {
             return (StatusCode::INTERNAL_SERVER_ERROR, format!("open file: {e}")).into_response()
@@ -479,7 +704,7 @@ pub async fn recording_thumbnail_handler(
     let stream = ReaderStream::new(file);
     let body = Body::from_stream(stream);
 
-    (([(header::CONTENT_TYPE, "image/jpeg")]), body).into_response()
+    (([(header::CONTENT_TYPE, content_type)]), body).into_response()
 }
 
 #[derive(Clone)]
@@ -505,7 +730,14 @@ pub async fn recording_video_handler(
         return (StatusCode::NOT_FOUND).into_response();
     };
 
-    let video = match new_video_reader(path, query.cache_id, &Some(state.video_cache)).await {
+    let video = match new_video_reader(
+        path,
+        query.cache_id,
+        &Some(state.video_cache),
+        state.rec_db.encryption_key(),
+    )
+    .await
+    {
         Ok(v) => v,
         Err(e) => {
             state.logger.log(LogEntry::new(
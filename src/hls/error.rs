@@ -120,6 +120,21 @@ pub enum SegmenterWriteH264Error {
     Dts,
 }
 
+#[derive(Debug, Error)]
+pub enum SegmenterWriteAudioError {
+    #[error("reached maximum segment size")]
+    MaximumSegmentSize,
+
+    #[error("{0}")]
+    TryFrom(#[from] std::num::TryFromIntError),
+
+    #[error("sub")]
+    Sub,
+
+    #[error("add")]
+    Add,
+}
+
 #[derive(Debug, Error)]
 pub enum AdjustPartDurationError {
     #[error("error")]
@@ -130,6 +145,9 @@ pub enum AdjustPartDurationError {
 pub enum ParseParamsError {
     #[error("{0}")]
     TryFromInt(#[from] std::num::TryFromIntError),
+
+    #[error("invalid decoder configuration record")]
+    InvalidExtraData,
 }
 
 #[derive(Debug, Error)]
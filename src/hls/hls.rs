@@ -8,20 +8,25 @@ mod segmenter;
 mod types;
 
 use crate::error::PartHlsQueryError;
-pub use crate::error::{CreateSegmenterError, SegmenterWriteH264Error};
+pub use crate::error::{CreateSegmenterError, SegmenterWriteAudioError, SegmenterWriteH264Error};
 use common::{
     time::{DurationH264, H264_MILLISECOND},
     DynLogger, H264Data, TrackParameters,
 };
-pub use error::ParseParamsError;
+pub use error::{GenerateInitError, GeneratePartError, ParseParamsError};
+pub use init::generate_init;
 pub use muxer::{HlsMuxer, NextSegmentGetter};
+pub use part::generate_part;
 pub use segmenter::H264Writer;
 use serde::Deserialize;
 use std::{collections::HashMap, sync::Arc};
 use tokio::sync::{mpsc, oneshot};
 use tokio_util::sync::CancellationToken;
 use types::MuxerIdCounter;
-pub use types::{track_params_from_video_params, VIDEO_TRACK_ID};
+pub use types::{
+    audio_track_params, track_params_from_extra_data, track_params_from_video_params,
+    AUDIO_TRACK_ID, VIDEO_TRACK_ID,
+};
 
 pub struct HlsServer {
     new_muxer_tx: mpsc::Sender<NewMuxerRequest>,
@@ -229,7 +234,7 @@ impl<'de> Deserialize<'de> for HlsQuery {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use common::{DummyLogger, HlsMuxer};
+    use common::{DummyLogger, HlsMuxer, VideoCodec};
     use pretty_assertions::assert_eq;
 
     #[tokio::test]
@@ -240,8 +245,10 @@ mod tests {
         let params = TrackParameters {
             width: 64,
             height: 64,
+            video_codec: VideoCodec::H264,
             codec: "test_codec".to_owned(),
             extra_data: Vec::new(),
+            audio: None,
         };
 
         let first_sample = H264Data {
@@ -314,8 +321,10 @@ seg7.mp4
         let params = TrackParameters {
             width: 64,
             height: 64,
+            video_codec: VideoCodec::H264,
             codec: "test_codec".to_owned(),
             extra_data: Vec::new(),
+            audio: None,
         };
 
         let first_sample = H264Data {
@@ -402,8 +411,10 @@ seg7.mp4
         let params = TrackParameters {
             width: 64,
             height: 64,
+            video_codec: VideoCodec::H264,
             codec: "test_codec".to_owned(),
             extra_data: Vec::new(),
+            audio: None,
         };
 
         let first_sample = H264Data {
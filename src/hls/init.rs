@@ -1,6 +1,9 @@
-use crate::{error::GenerateInitError, types::VIDEO_TRACK_ID};
+use crate::{
+    error::GenerateInitError,
+    types::{AUDIO_TRACK_ID, VIDEO_TRACK_ID},
+};
 use bytes::Bytes;
-use common::{time::H264_TIMESCALE, TrackParameters};
+use common::{time::H264_TIMESCALE, AudioParameters, TrackParameters, VideoCodec};
 use mp4::{ImmutableBox, ImmutableBoxSync};
 
 #[allow(clippy::module_name_repetitions)]
@@ -10,8 +13,10 @@ pub fn generate_init(params: &TrackParameters) -> Result<Bytes, GenerateInitErro
        - moov
          - mvhd
          - trak (video)
+         - trak (audio)
          - mvex
            - trex (video)
+           - trex (audio)
     */
 
     let ftyp = mp4::Boxes::new(
@@ -30,28 +35,44 @@ pub fn generate_init(params: &TrackParameters) -> Result<Bytes, GenerateInitErro
 
     let trak = generate_trak(params);
 
-    let moov = mp4::Boxes::new(mp4::Moov).with_children3(
+    let next_track_id = if params.audio.is_some() {
+        AUDIO_TRACK_ID + 1
+    } else {
+        VIDEO_TRACK_ID + 1
+    };
+
+    let mut mvex = mp4::Boxes::new(mp4::Mvex)
+        // Trex.
+        .with_child(mp4::Boxes::new(mp4::Trex {
+            track_id: VIDEO_TRACK_ID,
+            default_sample_description_index: 1,
+            ..mp4::Trex::default()
+        }));
+
+    let mut moov = mp4::Boxes::new(mp4::Moov).with_children2(
         // Mvhd.
         mp4::Boxes::new(mp4::Mvhd {
             timescale: 1000,
             rate: 65536,
             volume: 256,
             matrix: [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000],
-            next_track_id: 2,
+            next_track_id,
             ..mp4::Mvhd::default()
         }),
         // Trak.
         trak,
-        // Mvex.
-        mp4::Boxes::new(mp4::Mvex)
-            // Trex.
-            .with_child(mp4::Boxes::new(mp4::Trex {
-                track_id: VIDEO_TRACK_ID,
-                default_sample_description_index: 1,
-                ..mp4::Trex::default()
-            })),
     );
 
+    if let Some(audio) = &params.audio {
+        moov.children.push(generate_audio_trak(audio));
+        mvex.children.push(mp4::Boxes::new(mp4::Trex {
+            track_id: AUDIO_TRACK_ID,
+            default_sample_description_index: 1,
+            ..mp4::Trex::default()
+        }));
+    }
+    moov.children.push(mvex);
+
     let size = ftyp.size() + moov.size();
     let mut buf = Vec::with_capacity(size);
 
@@ -76,8 +97,8 @@ fn generate_trak(params: &TrackParameters) -> mp4::Boxes {
                - url
            - stbl
              - stsd
-               - avc1
-                 - avcC
+               - avc1 or hvc1
+                 - avcC or hvcC
                  - btrt
              - stts
              - stsc
@@ -91,33 +112,7 @@ fn generate_trak(params: &TrackParameters) -> mp4::Boxes {
             full_box: mp4::FullBox::default(),
             entry_count: 1,
         })
-        .with_child(
-            // Avc1.
-            mp4::Boxes::new(mp4::Avc1 {
-                sample_entry: mp4::SampleEntry {
-                    reserved: [0, 0, 0, 0, 0, 0],
-                    data_reference_index: 1,
-                },
-                width: params.width,
-                height: params.height,
-                horiz_resolution: 4_718_592,
-                vert_resolution: 4_718_592,
-                frame_count: 1,
-                depth: 24,
-                pre_defined3: -1,
-                ..mp4::Avc1::default()
-            })
-            .with_children2(
-                // AvcC.
-                mp4::Boxes::new(MyAvcC(params.extra_data.clone())),
-                // Btrt.
-                mp4::Boxes::new(mp4::Btrt {
-                    buffer_size_db: 0,
-                    max_bitrate: 1_000_000,
-                    avg_bitrate: 1_000_000,
-                }),
-            ),
-        ),
+        .with_child(generate_sample_entry(params)),
         // Stts.
         mp4::Boxes::new(mp4::Stts::default()),
         // Stsc.
@@ -139,23 +134,7 @@ fn generate_trak(params: &TrackParameters) -> mp4::Boxes {
             opcolor: [0, 0, 0],
         }),
         // Dinf.
-        mp4::Boxes::new(mp4::Dinf).with_child(
-            // Dref.
-            mp4::Boxes::new(mp4::Dref {
-                full_box: mp4::FullBox::default(),
-                entry_count: 1,
-            })
-            .with_child(mp4::Boxes::new(
-                // Url.
-                mp4::Url {
-                    full_box: mp4::FullBox {
-                        version: 0,
-                        flags: [0, 0, 1],
-                    },
-                    location: String::new(),
-                },
-            )),
-        ),
+        generate_dinf(),
         // Stbl.
         stbl,
     );
@@ -191,27 +170,171 @@ fn generate_trak(params: &TrackParameters) -> mp4::Boxes {
     )
 }
 
-struct MyAvcC(Vec<u8>);
+fn generate_audio_trak(params: &AudioParameters) -> mp4::Boxes {
+    /*
+       trak
+       - tkhd
+       - mdia
+         - mdhd
+         - hdlr
+         - minf
+           - smhd
+           - dinf
+             - dref
+               - url
+           - stbl
+             - stsd
+               - mp4a or other sample entry
+             - stts
+             - stsc
+             - stsz
+             - stco
+    */
+
+    let stbl = mp4::Boxes::new(mp4::Stbl).with_children5(
+        // Stds.
+        mp4::Boxes::new(mp4::Stsd {
+            full_box: mp4::FullBox::default(),
+            entry_count: 1,
+        })
+        .with_child(mp4::Boxes::new(MyRawBox {
+            box_type: params.sample_entry.box_type,
+            data: params.sample_entry.data.clone(),
+        })),
+        // Stts.
+        mp4::Boxes::new(mp4::Stts::default()),
+        // Stsc.
+        mp4::Boxes::new(mp4::Stsc::default()),
+        // Stsz.
+        mp4::Boxes::new(mp4::Stsz::default()),
+        // Stco.
+        mp4::Boxes::new(mp4::Stco::default()),
+    );
+
+    let minf = mp4::Boxes::new(mp4::Minf).with_children3(
+        // Smhd.
+        mp4::Boxes::new(mp4::Smhd::default()),
+        // Dinf.
+        generate_dinf(),
+        // Stbl.
+        stbl,
+    );
+
+    // Trak.
+    mp4::Boxes::new(mp4::Trak {}).with_children2(
+        // Tkhd.
+        mp4::Boxes::new(mp4::Tkhd {
+            flags: [0, 0, 3],
+            track_id: AUDIO_TRACK_ID,
+            alternate_group: 1,
+            volume: 256,
+            matrix: [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000],
+            ..mp4::Tkhd::default()
+        }),
+        // Mdia
+        mp4::Boxes::new(mp4::Mdia {}).with_children3(
+            // Mdhd.
+            mp4::Boxes::new(mp4::Mdhd {
+                timescale: H264_TIMESCALE,
+                language: *b"und",
+                ..mp4::Mdhd::default()
+            }),
+            // Hdlr
+            mp4::Boxes::new(mp4::Hdlr {
+                handler_type: *b"soun",
+                name: "SoundHandler".to_owned(),
+                ..mp4::Hdlr::default()
+            }),
+            // Minf.
+            minf,
+        ),
+    )
+}
+
+fn generate_dinf() -> mp4::Boxes {
+    mp4::Boxes::new(mp4::Dinf).with_child(
+        // Dref.
+        mp4::Boxes::new(mp4::Dref {
+            full_box: mp4::FullBox::default(),
+            entry_count: 1,
+        })
+        .with_child(mp4::Boxes::new(
+            // Url.
+            mp4::Url {
+                full_box: mp4::FullBox {
+                    version: 0,
+                    flags: [0, 0, 1],
+                },
+                location: String::new(),
+            },
+        )),
+    )
+}
 
-impl ImmutableBox for MyAvcC {
+fn generate_sample_entry(params: &TrackParameters) -> mp4::Boxes {
+    let visual_sample_entry = mp4::Avc1 {
+        sample_entry: mp4::SampleEntry {
+            reserved: [0, 0, 0, 0, 0, 0],
+            data_reference_index: 1,
+        },
+        width: params.width,
+        height: params.height,
+        horiz_resolution: 4_718_592,
+        vert_resolution: 4_718_592,
+        frame_count: 1,
+        depth: 24,
+        pre_defined3: -1,
+        ..mp4::Avc1::default()
+    };
+    let (sample_entry, config_type) = match params.video_codec {
+        // Avc1.
+        VideoCodec::H264 => (mp4::Boxes::new(visual_sample_entry), mp4::TYPE_AVCC),
+        // Hvc1.
+        VideoCodec::H265 => (
+            mp4::Boxes::new(mp4::Hvc1(visual_sample_entry)),
+            mp4::TYPE_HVCC,
+        ),
+    };
+    sample_entry.with_children2(
+        // AvcC or HvcC.
+        mp4::Boxes::new(MyRawBox {
+            box_type: config_type,
+            data: params.extra_data.clone(),
+        }),
+        // Btrt.
+        mp4::Boxes::new(mp4::Btrt {
+            buffer_size_db: 0,
+            max_bitrate: 1_000_000,
+            avg_bitrate: 1_000_000,
+        }),
+    )
+}
+
+// Box with content from the camera, like the decoder configuration record.
+struct MyRawBox {
+    box_type: mp4::BoxType,
+    data: Vec<u8>,
+}
+
+impl ImmutableBox for MyRawBox {
     fn box_type(&self) -> mp4::BoxType {
-        mp4::TYPE_AVCC
+        self.box_type
     }
 
     fn size(&self) -> usize {
-        self.0.len()
+        self.data.len()
     }
 }
 
-impl ImmutableBoxSync for MyAvcC {
+impl ImmutableBoxSync for MyRawBox {
     fn marshal(&self, w: &mut dyn std::io::Write) -> Result<(), mp4::Mp4Error> {
-        w.write_all(&self.0)?;
+        w.write_all(&self.data)?;
         Ok(())
     }
 }
 
-impl From<MyAvcC> for Box<dyn ImmutableBoxSync> {
-    fn from(value: MyAvcC) -> Self {
+impl From<MyRawBox> for Box<dyn ImmutableBoxSync> {
+    fn from(value: MyRawBox) -> Self {
         Box::new(value)
     }
 }
@@ -234,7 +357,9 @@ mod tests {
                 0xa4, 0x3b, 0xe4, 0x88, 0xc0, 0x44, 0x0, 0x0, 0x3, 0x0, 0x4, 0x0, 0x0, 0x3, 0x0,
                 0x60, 0x3c, 0x58, 0xb6, 0x58, 0x1, 0x0, 0x0,
             ],
+            video_codec: VideoCodec::H264,
             codec: String::new(),
+            audio: None,
         };
 
         let got = generate_init(&params).unwrap();
@@ -394,4 +519,54 @@ mod tests {
             assert_eq!(pretty_hex(&want), pretty_hex(&got));
         }
     }
+
+    #[test]
+    fn test_generate_init_h265() {
+        let params = TrackParameters {
+            width: 650,
+            height: 450,
+            extra_data: vec![1, 2, 3],
+            video_codec: VideoCodec::H265,
+            codec: String::new(),
+            audio: None,
+        };
+
+        let got = generate_init(&params).unwrap();
+
+        let contains = |v: &[u8]| got.windows(v.len()).any(|w| w == v);
+        assert!(contains(&[0, 0, 0, 0x75, b'h', b'v', b'c', b'1']));
+        assert!(contains(&[0, 0, 0, 0x0b, b'h', b'v', b'c', b'C', 1, 2, 3]));
+        assert!(!contains(b"avc1"));
+        assert!(!contains(b"avcC"));
+    }
+
+    #[test]
+    fn test_generate_init_audio() {
+        let params = TrackParameters {
+            width: 650,
+            height: 450,
+            extra_data: vec![1, 2, 3],
+            video_codec: VideoCodec::H264,
+            codec: String::new(),
+            audio: Some(AudioParameters {
+                codec: "mp4a.40.2".to_owned(),
+                sample_entry: common::AudioSampleEntry {
+                    box_type: *b"mp4a",
+                    data: vec![4, 5, 6],
+                },
+            }),
+        };
+
+        let got = generate_init(&params).unwrap();
+
+        let contains = |v: &[u8]| got.windows(v.len()).any(|w| w == v);
+        assert!(contains(&[0, 0, 0, 0x0b, b'm', b'p', b'4', b'a', 4, 5, 6]));
+        assert!(contains(&[0, 0, 0, 0x10, b's', b'm', b'h', b'd']));
+        assert!(contains(b"soun"));
+        assert!(contains(&[
+            0, 0, 0, 0x20, b't', b'r', b'e', b'x', //
+            0, 0, 0, 0, // FullBox.
+            0, 0, 0, 2, // Track ID.
+        ]));
+    }
 }
@@ -75,7 +75,11 @@ impl HlsMuxer {
 
     pub async fn file(&self, name: &str, query: &HlsQuery) -> MuxerFileResponse {
         if name == "index.m3u8" {
-            return primary_playlist(&self.params.codec);
+            let codecs = match &self.params.audio {
+                Some(audio) => format!("{},{}", self.params.codec, audio.codec),
+                None => self.params.codec.clone(),
+            };
+            return primary_playlist(&codecs);
         }
 
         if name == "init.mp4" {
@@ -1,18 +1,21 @@
 use crate::{
     error::{GeneratePartError, GenerateTrafError, PartFinalizeError},
-    types::VIDEO_TRACK_ID,
+    types::{AUDIO_TRACK_ID, VIDEO_TRACK_ID},
 };
 use bytes::Bytes;
 use common::{
     time::{DurationH264, UnixH264},
-    PartFinalized, VideoSample,
+    AudioSample, PartFinalized, VideoSample,
 };
 use mp4::{ImmutableBox, ImmutableBoxSync, TfdtBaseMediaDecodeTime, TrunEntries};
 use std::sync::Arc;
 
-fn generate_part(
+// Generates a moof and mdat pair. The decode times are relative to `muxer_start_time`.
+#[allow(clippy::module_name_repetitions)]
+pub fn generate_part(
     muxer_start_time: UnixH264,
     video_samples: Arc<Vec<VideoSample>>,
+    audio_samples: Arc<Vec<AudioSample>>,
 ) -> Result<Bytes, GeneratePartError> {
     /*
        moof
@@ -21,6 +24,10 @@ fn generate_part(
          - tfhd
          - tfdt
          - trun
+       - traf (audio)
+         - tfhd
+         - tfdt
+         - trun
        mdat
     */
 
@@ -34,14 +41,27 @@ fn generate_part(
 
     let mfhd_offset = 24;
     let video_trun_size = (video_samples.len() * 16) + 20;
-    let mdat_offset = mfhd_offset + video_trun_size + 44;
+    let mut mdat_offset = mfhd_offset + video_trun_size + 44;
+    if !audio_samples.is_empty() {
+        let audio_trun_size = (audio_samples.len() * 8) + 20;
+        mdat_offset += audio_trun_size + 44;
+    }
 
     let video_data_offset = i32::try_from(mdat_offset + 8)?;
     let traf = generate_traf(muxer_start_time, &video_samples, video_data_offset)?;
-
     moof.children.push(traf);
 
-    let mdat = mp4::Boxes::new(MyMdat(video_samples));
+    if !audio_samples.is_empty() {
+        let video_data_size: usize = video_samples.iter().map(|v| v.avcc.len()).sum();
+        let audio_data_offset = video_data_offset + i32::try_from(video_data_size)?;
+        let traf = generate_audio_traf(muxer_start_time, &audio_samples, audio_data_offset)?;
+        moof.children.push(traf);
+    }
+
+    let mdat = mp4::Boxes::new(MyMdat {
+        video_samples,
+        audio_samples,
+    });
 
     let mut buf = Vec::with_capacity(moof.size() + mdat.size());
     moof.marshal(&mut buf)?;
@@ -50,7 +70,11 @@ fn generate_part(
     Ok(Bytes::from(buf))
 }
 
-struct MyMdat(Arc<Vec<VideoSample>>);
+// Video samples followed by audio samples.
+struct MyMdat {
+    video_samples: Arc<Vec<VideoSample>>,
+    audio_samples: Arc<Vec<AudioSample>>,
+}
 
 impl ImmutableBox for MyMdat {
     fn box_type(&self) -> mp4::BoxType {
@@ -58,15 +82,20 @@ impl ImmutableBox for MyMdat {
     }
 
     fn size(&self) -> usize {
-        self.0.iter().map(|sample| sample.avcc.len()).sum()
+        let video_size: usize = self.video_samples.iter().map(|v| v.avcc.len()).sum();
+        let audio_size: usize = self.audio_samples.iter().map(|v| v.data.len()).sum();
+        video_size + audio_size
     }
 }
 
 impl ImmutableBoxSync for MyMdat {
     fn marshal(&self, w: &mut dyn std::io::Write) -> Result<(), mp4::Mp4Error> {
-        for sample in self.0.iter() {
+        for sample in self.video_samples.iter() {
             w.write_all(&sample.avcc)?;
         }
+        for sample in self.audio_samples.iter() {
+            w.write_all(&sample.data)?;
+        }
         Ok(())
     }
 }
@@ -149,6 +178,68 @@ fn generate_traf(
     ))
 }
 
+fn generate_audio_traf(
+    muxer_start_time: UnixH264,
+    audio_samples: &[AudioSample],
+    data_offset: i32,
+) -> Result<mp4::Boxes, GenerateTrafError> {
+    use GenerateTrafError::*;
+    /*
+           traf
+           - tfhd
+           - tfdt
+           - trun
+    */
+
+    let mut trun_entries = Vec::with_capacity(audio_samples.len());
+    for sample in audio_samples {
+        trun_entries.push(mp4::TrunEntryV0 {
+            sample_duration: u32::try_from(*sample.duration)
+                .map_err(|e| TryFromInt("duration".to_owned(), e))?,
+            sample_size: u32::try_from(sample.data.len())
+                .map_err(|e| TryFromInt("sample_size".to_owned(), e))?,
+            sample_flags: 0,
+            sample_composition_time_offset: 0,
+        });
+    }
+
+    let relative_pts = audio_samples[0]
+        .pts
+        .checked_sub(muxer_start_time)
+        .ok_or(Sub)?;
+    let base_media_decode_time_v1 = u64::try_from(*relative_pts)
+        .map_err(|e| TryFromInt(format!("base_media_decode_time: {relative_pts:?}"), e))?;
+
+    // Traf
+    Ok(mp4::Boxes::new(mp4::Traf).with_children3(
+        // Tfhd.
+        mp4::Boxes::new(mp4::Tfhd {
+            full_box: mp4::FullBox {
+                version: 0,
+                flags: [2, 0, 0],
+            },
+            track_id: AUDIO_TRACK_ID,
+            ..mp4::Tfhd::default()
+        }),
+        // Tfdt.
+        mp4::Boxes::new(mp4::Tfdt {
+            flags: [0, 0, 0],
+            base_media_decode_time: TfdtBaseMediaDecodeTime::V1(base_media_decode_time_v1),
+        }),
+        // Trun.
+        mp4::Boxes::new(mp4::Trun {
+            flags: mp4::u32_to_flags(
+                mp4::TRUN_DATA_OFFSET_PRESENT
+                    | mp4::TRUN_SAMPLE_DURATION_PRESENT
+                    | mp4::TRUN_SAMPLE_SIZE_PRESENT,
+            ),
+            data_offset,
+            first_sample_flags: 0,
+            entries: TrunEntries::V0(trun_entries),
+        }),
+    ))
+}
+
 // fmp4 part.
 #[derive(Clone)]
 #[allow(clippy::module_name_repetitions)]
@@ -157,6 +248,7 @@ pub struct MuxerPart {
     pub muxer_start_time: UnixH264,
     pub is_independent: bool,
     pub video_samples: Vec<VideoSample>,
+    pub audio_samples: Vec<AudioSample>,
 }
 
 impl std::fmt::Debug for MuxerPart {
@@ -178,6 +270,7 @@ impl MuxerPart {
             muxer_start_time,
             is_independent: false,
             video_samples: Vec::new(),
+            audio_samples: Vec::new(),
         }
     }
 
@@ -192,16 +285,22 @@ impl MuxerPart {
     pub fn finalize(self) -> Result<PartFinalized, PartFinalizeError> {
         let rendered_duration = self.duration().ok_or(PartFinalizeError::Duration)?;
         let video_samples = Arc::new(self.video_samples);
+        let audio_samples = Arc::new(self.audio_samples);
         let rendered_content = if video_samples.is_empty() {
             None
         } else {
-            Some(generate_part(self.muxer_start_time, video_samples.clone())?)
+            Some(generate_part(
+                self.muxer_start_time,
+                video_samples.clone(),
+                audio_samples.clone(),
+            )?)
         };
 
         Ok(PartFinalized {
             id: self.id,
             is_independent: self.is_independent,
             video_samples: video_samples.clone(),
+            audio_samples,
             rendered_duration,
             rendered_content,
         })
@@ -213,6 +312,10 @@ impl MuxerPart {
         }
         self.video_samples.push(sample);
     }
+
+    pub fn write_audio(&mut self, sample: AudioSample) {
+        self.audio_samples.push(sample);
+    }
 }
 
 #[allow(clippy::unwrap_used)]
@@ -226,7 +329,12 @@ mod tests {
 
     #[test]
     fn test_generate_part_minimal() {
-        let got = generate_part(UnixH264::new(0), Arc::new(vec![VideoSample::default()])).unwrap();
+        let got = generate_part(
+            UnixH264::new(0),
+            Arc::new(vec![VideoSample::default()]),
+            Arc::new(Vec::new()),
+        )
+        .unwrap();
 
         let want = vec![
             0, 0, 0, 0x68, b'm', b'o', b'o', b'f', //
@@ -260,7 +368,7 @@ mod tests {
             ..Default::default()
         }];
 
-        let got = generate_part(UnixH264::new(0), Arc::new(samples)).unwrap();
+        let got = generate_part(UnixH264::new(0), Arc::new(samples), Arc::new(Vec::new())).unwrap();
         let want = vec![
             0, 0, 0, 0x68, b'm', b'o', b'o', b'f', //
             0, 0, 0, 0x10, b'm', b'f', b'h', b'd', //
@@ -287,6 +395,65 @@ mod tests {
         assert_eq!(want, got);
     }
 
+    #[test]
+    fn test_generate_part_audio_sample() {
+        let video_samples = vec![VideoSample {
+            avcc: Arc::new(PaddedBytes::new(b"abcd".to_vec())),
+            random_access_present: true,
+            ..Default::default()
+        }];
+        let audio_samples = vec![AudioSample {
+            pts: UnixH264::new(1),
+            data: Bytes::from_static(b"ef"),
+            duration: DurationH264::new(1920),
+        }];
+
+        let got = generate_part(
+            UnixH264::new(0),
+            Arc::new(video_samples),
+            Arc::new(audio_samples),
+        )
+        .unwrap();
+        let want = vec![
+            0, 0, 0, 0xb0, b'm', b'o', b'o', b'f', //
+            0, 0, 0, 0x10, b'm', b'f', b'h', b'd', //
+            0, 0, 0, 0, // FullBox.
+            0, 0, 0, 0, // Sequence number.
+            0, 0, 0, 0x50, b't', b'r', b'a', b'f', // Video traf.
+            0, 0, 0, 0x10, b't', b'f', b'h', b'd', // Video tfhd.
+            0, 2, 0, 0, // Track id.
+            0, 0, 0, 1, // Sample size.
+            0, 0, 0, 0x14, b't', b'f', b'd', b't', // Video tfdt.
+            1, 0, 0, 0, // Track id.
+            0, 0, 0, 0, 0, 0, 0, 0, // BaseMediaDecodeTime.
+            0, 0, 0, 0x24, b't', b'r', b'u', b'n', // Video trun.
+            1, 0, 0xf, 1, // FullBox.
+            0, 0, 0, 1, // Sample count.
+            0, 0, 0, 0xb8, // Data offset.
+            0, 0, 0, 0, // Entry sample duration.
+            0, 0, 0, 4, // Entry sample size.
+            0, 0, 0, 0, // Entry sample flags.
+            0, 0, 0, 0, // Entry SampleCompositionTimeOffset
+            0, 0, 0, 0x48, b't', b'r', b'a', b'f', // Audio traf.
+            0, 0, 0, 0x10, b't', b'f', b'h', b'd', // Audio tfhd.
+            0, 2, 0, 0, // Track id.
+            0, 0, 0, 2, // Sample size.
+            0, 0, 0, 0x14, b't', b'f', b'd', b't', // Audio tfdt.
+            1, 0, 0, 0, // Track id.
+            0, 0, 0, 0, 0, 0, 0, 1, // BaseMediaDecodeTime.
+            0, 0, 0, 0x1c, b't', b'r', b'u', b'n', // Audio trun.
+            0, 0, 3, 1, // FullBox.
+            0, 0, 0, 1, // Sample count.
+            0, 0, 0, 0xbc, // Data offset.
+            0, 0, 7, 0x80, // Entry sample duration.
+            0, 0, 0, 2, // Entry sample size.
+            0, 0, 0, 0xe, b'm', b'd', b'a', b't', //
+            b'a', b'b', b'c', b'd', // Video Sample
+            b'e', b'f', // Audio Sample
+        ];
+        assert_eq!(pretty_hex(&want), pretty_hex(&got));
+    }
+
     #[test]
     fn test_generate_part_multiple_video_samples() {
         let samples = vec![
@@ -305,7 +472,7 @@ mod tests {
             },
         ];
 
-        let got = generate_part(UnixH264::new(0), Arc::new(samples)).unwrap();
+        let got = generate_part(UnixH264::new(0), Arc::new(samples), Arc::new(Vec::new())).unwrap();
 
         let want = vec![
             0, 0, 0, 0x88, b'm', b'o', b'o', b'f', //
@@ -362,7 +529,7 @@ mod tests {
             },
         ];
 
-        let got = generate_part(start_time, Arc::new(samples)).unwrap();
+        let got = generate_part(start_time, Arc::new(samples), Arc::new(Vec::new())).unwrap();
 
         let want = vec![
             0, 0, 0, 0x78, b'm', b'o', b'o', b'f', //
@@ -1,12 +1,12 @@
 use crate::{
-    error::{PartWriteH264Error, SegmentFinalizeError},
+    error::{PartWriteH264Error, SegmentFinalizeError, SegmenterWriteAudioError},
     part::MuxerPart,
     playlist::Playlist,
     types::IdCounter,
 };
 use common::{
     time::{DurationH264, UnixH264},
-    PartFinalized, SegmentFinalized, VideoSample,
+    AudioSample, PartFinalized, SegmentFinalized, VideoSample,
 };
 use std::{mem, sync::Arc};
 
@@ -86,6 +86,19 @@ impl Segment {
         Ok(())
     }
 
+    // Audio samples are added to the current part, parts are only switched by video.
+    pub fn write_audio(&mut self, sample: AudioSample) -> Result<(), SegmenterWriteAudioError> {
+        let size = u64::try_from(sample.data.len())?;
+
+        if (self.size + size) > self.segment_max_size {
+            return Err(SegmenterWriteAudioError::MaximumSegmentSize);
+        }
+
+        self.current_part.write_audio(sample);
+        self.size += size;
+        Ok(())
+    }
+
     // Retuns None if cancelled.
     pub async fn finalize(
         mut self,
@@ -132,6 +145,7 @@ mod tests {
             id: 0,
             is_independent: false,
             video_samples: Arc::new(Vec::new()),
+            audio_samples: Arc::new(Vec::new()),
             rendered_content: Some(Bytes::from(content)),
             rendered_duration: DurationH264::new(0),
         })
@@ -1,12 +1,15 @@
 use crate::{
-    error::{AdjustPartDurationError, CreateSegmenterError, SegmenterWriteH264Error},
+    error::{
+        AdjustPartDurationError, CreateSegmenterError, SegmenterWriteAudioError,
+        SegmenterWriteH264Error,
+    },
     playlist::Playlist,
     segment::Segment,
     types::IdCounter,
 };
 use common::{
     time::{DurationH264, UnixH264, H264_MILLISECOND, H264_SECOND},
-    H264Data, VideoSample,
+    AudioData, AudioSample, H264Data, VideoSample,
 };
 use std::{collections::HashSet, sync::Arc};
 use tokio_util::sync::DropGuard;
@@ -29,6 +32,10 @@ impl H264Writer {
         self.segmenter.write_h264(data).await
     }
 
+    pub fn write_audio(&mut self, data: AudioData) -> Result<(), SegmenterWriteAudioError> {
+        self.segmenter.write_audio(data)
+    }
+
     #[cfg(test)]
     #[allow(clippy::unwrap_used)]
     pub async fn test_write(&mut self, pts: i64, avcc: Vec<u8>, random_access: bool) {
@@ -60,6 +67,7 @@ pub struct Segmenter {
     segment_id_counter: IdCounter,
     part_id_counter: IdCounter,
     next_sample: VideoSample,
+    next_audio_sample: Option<AudioSample>,
     first_segment_finalized: bool,
     sample_durations: HashSet<DurationH264>,
     adjusted_part_duration: DurationH264,
@@ -105,6 +113,7 @@ impl Segmenter {
             segment_id_counter: IdCounter::new(7), // 7 required by iOS.
             part_id_counter: IdCounter::new(0),
             next_sample,
+            next_audio_sample: None,
             first_segment_finalized: false,
             sample_durations: HashSet::new(),
             adjusted_part_duration: DurationH264::new(0),
@@ -231,6 +240,43 @@ impl Segmenter {
 
         Ok(())
     }
+
+    pub fn write_audio(&mut self, data: AudioData) -> Result<(), SegmenterWriteAudioError> {
+        use crate::error::SegmenterWriteAudioError::*;
+
+        let pts = data
+            .pts
+            .checked_sub(self.first_sample_time.into())
+            .ok_or(Sub)?
+            .checked_add(self.muxer_start_time)
+            .ok_or(Add)?;
+
+        // Drop audio from before the first video sample.
+        if pts < self.muxer_start_time {
+            return Ok(());
+        }
+
+        let sample = AudioSample {
+            pts,
+            data: data.data,
+            duration: DurationH264::new(0),
+        };
+
+        // Put samples in a queue in order to compute sample duration.
+        let Some(mut sample) = self.next_audio_sample.replace(sample) else {
+            return Ok(());
+        };
+        sample.duration = pts.checked_sub(sample.pts).ok_or(Sub)?.into();
+        if *sample.duration < 0 {
+            sample.duration = DurationH264::new(0);
+        }
+
+        // Audio is dropped until the first video segment has started.
+        let Some(current_segment) = &mut self.current_segment else {
+            return Ok(());
+        };
+        current_segment.write_audio(sample)
+    }
 }
 
 /*
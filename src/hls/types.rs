@@ -1,6 +1,6 @@
-use common::TrackParameters;
+use common::{AudioParameters, AudioSampleEntry, TrackParameters, VideoCodec};
 use retina::codec::VideoParameters;
-use std::convert::TryFrom;
+use std::{convert::TryFrom, fmt::Write};
 
 use crate::error::ParseParamsError;
 
@@ -9,6 +9,7 @@ use crate::error::ParseParamsError;
 // over the entire life‐time of this presentation.
 // Track IDs are never re‐used and cannot be zero.
 pub const VIDEO_TRACK_ID: u32 = 1;
+pub const AUDIO_TRACK_ID: u32 = 2;
 
 pub struct IdCounter(u64);
 
@@ -40,16 +41,95 @@ impl MuxerIdCounter {
 
 pub fn track_params_from_video_params(
     params: &VideoParameters,
+    video_codec: VideoCodec,
+    audio: Option<AudioParameters>,
 ) -> Result<TrackParameters, ParseParamsError> {
     let (width, height) = params.pixel_dimensions();
     Ok(TrackParameters {
         width: u16::try_from(width)?,
         height: u16::try_from(height)?,
-        codec: params.rfc6381_codec().to_owned(),
+        video_codec,
+        codec: rfc6381_codec(params.rfc6381_codec(), video_codec),
         extra_data: params.extra_data().to_owned(),
+        audio,
     })
 }
 
+// Creates track parameters from a decoder configuration record,
+// used by sources that demux the stream themselves.
+pub fn track_params_from_extra_data(
+    width: u16,
+    height: u16,
+    video_codec: VideoCodec,
+    extra_data: Vec<u8>,
+) -> Result<TrackParameters, ParseParamsError> {
+    let codec = match video_codec {
+        VideoCodec::H264 => avc1_codec(&extra_data),
+        VideoCodec::H265 => hvc1_codec(&extra_data),
+    }
+    .ok_or(ParseParamsError::InvalidExtraData)?;
+    Ok(TrackParameters {
+        width,
+        height,
+        video_codec,
+        codec,
+        extra_data,
+        audio: None,
+    })
+}
+
+// Codec string from a 'avcC' record, "avc1.PPCCLL".
+fn avc1_codec(avcc: &[u8]) -> Option<String> {
+    let v = avcc.get(1..4)?;
+    Some(format!("avc1.{:02X}{:02X}{:02X}", v[0], v[1], v[2]))
+}
+
+// Codec string from a 'hvcC' record, ISO/IEC 14496-15 Annex E.
+fn hvc1_codec(hvcc: &[u8]) -> Option<String> {
+    let v = hvcc.get(1..13)?;
+    let profile_space = match v[0] >> 6 {
+        0 => "",
+        1 => "A",
+        2 => "B",
+        _ => "C",
+    };
+    let tier = if v[0] & 0b0010_0000 == 0 { "L" } else { "H" };
+    let profile_idc = v[0] & 0b0001_1111;
+    let compatibility_flags = u32::from_be_bytes([v[1], v[2], v[3], v[4]]).reverse_bits();
+    let level_idc = v[11];
+
+    let mut codec =
+        format!("hvc1.{profile_space}{profile_idc}.{compatibility_flags:X}.{tier}{level_idc}");
+    let constraint_flags = &v[5..11];
+    let n = constraint_flags
+        .iter()
+        .rposition(|v| *v != 0)
+        .map_or(0, |i| i + 1);
+    for flag in &constraint_flags[..n] {
+        write!(codec, ".{flag:X}").expect("writing to string should not fail");
+    }
+    Some(codec)
+}
+
+// Returns None if the codec can't be stored in a MP4 container.
+#[must_use]
+pub fn audio_track_params(params: &retina::codec::AudioParameters) -> Option<AudioParameters> {
+    Some(AudioParameters {
+        codec: params.rfc6381_codec()?.to_owned(),
+        sample_entry: AudioSampleEntry::from_box(params.sample_entry()?)?,
+    })
+}
+
+// The init segment always uses the 'hvc1' sample entry
+// for H.265, the codec string has to match it.
+fn rfc6381_codec(codec: &str, video_codec: VideoCodec) -> String {
+    match (video_codec, codec.strip_prefix("hev1")) {
+        (VideoCodec::H265, Some(rest)) => "hvc1".to_owned() + rest,
+        _ => codec.to_owned(),
+    }
+}
+
+#[allow(clippy::unwrap_used)]
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -62,6 +142,42 @@ mod tests {
         assert_eq!(2, counter.next_id());
     }
 
+    #[test]
+    fn test_rfc6381_codec() {
+        assert_eq!(
+            "avc1.640016",
+            rfc6381_codec("avc1.640016", VideoCodec::H264)
+        );
+        assert_eq!(
+            "hvc1.1.6.L93.B0",
+            rfc6381_codec("hev1.1.6.L93.B0", VideoCodec::H265)
+        );
+        assert_eq!(
+            "hvc1.1.6.L93.B0",
+            rfc6381_codec("hvc1.1.6.L93.B0", VideoCodec::H265)
+        );
+    }
+
+    #[test]
+    fn test_track_params_from_extra_data() {
+        let avcc = vec![1, 0x64, 0, 0x16, 3];
+        let params = track_params_from_extra_data(1, 2, VideoCodec::H264, avcc).unwrap();
+        assert_eq!("avc1.640016", params.codec);
+
+        #[rustfmt::skip]
+        let hvcc = vec![
+            1,
+            0x01, // Profile space, tier and profile.
+            0x60, 0, 0, 0, // Compatibility flags.
+            0xb0, 0, 0, 0, 0, 0, // Constraint flags.
+            93, // Level.
+        ];
+        let params = track_params_from_extra_data(1, 2, VideoCodec::H265, hvcc).unwrap();
+        assert_eq!("hvc1.1.6.L93.B0", params.codec);
+
+        assert!(track_params_from_extra_data(1, 2, VideoCodec::H264, vec![1]).is_err());
+    }
+
     #[test]
     fn test_muxer_id_counter() {
         let mut counter = MuxerIdCounter::new();
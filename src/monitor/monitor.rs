@@ -1,17 +1,25 @@
 // SPDX-License-Identifier: GPL-2.0-or-later
 
+mod notifier;
 mod recorder;
 mod source;
+mod source_file;
+mod source_http;
+mod mp4_demuxer;
+mod sprite;
+mod thumbnail;
 pub mod ptz;
 mod onvif;
 
 use recdb::RecDb;
+pub use recorder::{thumbnail_from_avcc, AvccToJpegError};
 pub use source::{DecoderError, Source, SubscribeDecodedError};
 
-use crate::{recorder::new_recorder, source::SourceRtsp};
+use crate::{recorder::new_recorder, source::new_source};
 use async_trait::async_trait;
 use common::{
     monitor::{MonitorConfig, MonitorConfigs, SourceConfig},
+    recording::RecordingId,
     DynLogger, Event, LogEntry, LogLevel, MonitorId, NonEmptyString, StreamType,
 };
 use hls::HlsServer;
@@ -237,20 +245,21 @@ impl MonitorManager {
 
         let (tx, rx) = mpsc::channel(1);
 
+        let state = MonitorManagerState {
+            token: CancellationToken::new(),
+            configs,
+            started_monitors: HashMap::new(),
+            rec_db,
+            logger,
+            hls_server,
+            path: config_path,
+            hooks: None,
+        };
+        state.update_retention_policies();
+
         // This must be an actor in order to be callable from plugins.
         tokio::spawn(async move {
-            MonitorManagerState {
-                token: CancellationToken::new(),
-                configs,
-                started_monitors: HashMap::new(),
-                rec_db,
-                logger,
-                hls_server,
-                path: config_path,
-                hooks: None,
-            }
-            .run(rx)
-            .await;
+            state.run(rx).await;
         });
 
         Ok(Self(tx))
@@ -538,6 +547,7 @@ impl MonitorManagerState {
         }
 
         self.configs.insert(id.to_owned(), config);
+        self.update_retention_policies();
         Ok(created)
     }
 
@@ -567,11 +577,23 @@ impl MonitorManagerState {
             return Err(NotExist(id.to_string()));
         }
 
+        self.update_retention_policies();
+
         tokio::fs::remove_file(self.config_path(id)).await?;
         log_monitor(&self.logger, LogLevel::Info, id, "deleted");
         Ok(())
     }
 
+    // Retention policies are applied without restarting the monitors.
+    fn update_retention_policies(&self) {
+        let policies = self
+            .configs
+            .iter()
+            .map(|(id, config)| (id.to_owned(), config.retention().to_owned()))
+            .collect();
+        self.rec_db.set_retention_policies(policies);
+    }
+
     // Returns common information about the monitors.
     // This will be accessesable by normal users.
     #[must_use]
@@ -612,31 +634,32 @@ impl MonitorManagerState {
         let monitor_token = self.token.child_token();
         let (shutdown_complete_tx, shutdown_complete_rx) = mpsc::channel(1);
 
-        let (source_main, source_sub, onvif_url) = match config.source() {
-            SourceConfig::Rtsp(conf) => {
-                let source_main = SourceRtsp::new(
-                    monitor_token.child_token(),
-                    shutdown_complete_tx.clone(),
-                    self.logger.clone(),
-                    self.hls_server.clone(),
-                    config.id().to_owned(),
-                    conf.to_owned(),
-                    StreamType::Main,
-                )
-                .expect("source main should never be None");
-
-                let source_sub = SourceRtsp::new(
-                    monitor_token.child_token(),
-                    shutdown_complete_tx.clone(),
-                    self.logger.clone(),
-                    self.hls_server.clone(),
-                    config.id().to_owned(),
-                    conf.to_owned(),
-                    StreamType::Sub,
-                );
-
-                (Arc::new(source_main), source_sub.map(Arc::new), conf.onvif_url.clone())
-            }
+        let source_main = new_source(
+            monitor_token.child_token(),
+            shutdown_complete_tx.clone(),
+            self.logger.clone(),
+            self.hls_server.clone(),
+            config.id().to_owned(),
+            config.source(),
+            StreamType::Main,
+        )
+        .expect("source main should never be None");
+        let source_main = Arc::new(source_main);
+
+        let source_sub = new_source(
+            monitor_token.child_token(),
+            shutdown_complete_tx.clone(),
+            self.logger.clone(),
+            self.hls_server.clone(),
+            config.id().to_owned(),
+            config.source(),
+            StreamType::Sub,
+        )
+        .map(Arc::new);
+
+        let onvif_url = match config.source() {
+            SourceConfig::Rtsp(conf) => conf.onvif_url.clone(),
+            SourceConfig::File(_) | SourceConfig::Http(_) => None,
         };
 
         let send_event_tx = new_recorder(
@@ -744,6 +767,23 @@ pub struct MonitorInfo {
     has_sub_stream: bool,
 }
 
+impl MonitorInfo {
+    #[must_use]
+    pub fn id(&self) -> &MonitorId {
+        &self.id
+    }
+
+    #[must_use]
+    pub fn name(&self) -> &NonEmptyString {
+        &self.name
+    }
+
+    #[must_use]
+    pub fn enable(&self) -> bool {
+        self.enable
+    }
+}
+
 pub type DynMonitorHooks = Arc<dyn MonitorHooks + Send + Sync>;
 
 #[async_trait]
@@ -751,6 +791,12 @@ pub trait MonitorHooks {
     async fn on_monitor_start(&self, token: CancellationToken, monitor: Arc<Monitor>);
     // Blocking.
     fn on_thumb_save(&self, config: &MonitorConfig, frame: Frame) -> Frame;
+    // Non-blocking.
+    fn on_event(&self, config: &MonitorConfig, event: &Event);
+    // Non-blocking.
+    fn on_recording_start(&self, config: &MonitorConfig, recording_id: &RecordingId);
+    // Non-blocking.
+    fn on_recording_stop(&self, config: &MonitorConfig, recording_id: &RecordingId);
 }
 
 #[allow(clippy::needless_pass_by_value, clippy::unwrap_used)]
@@ -759,7 +805,9 @@ mod tests {
     use super::*;
     use bytesize::ByteSize;
     use common::{
-        monitor::{Config, Protocol, SelectedSource, SourceConfig, SourceRtspConfig},
+        monitor::{
+            Config, Protocol, RetentionConfig, SelectedSource, SourceConfig, SourceRtspConfig,
+        },
         DummyLogger, ParseMonitorIdError,
     };
     use pretty_assertions::assert_eq;
@@ -824,7 +872,7 @@ mod tests {
 
     fn new_test_recdb(recordings_dir: &Path) -> RecDb {
         let disk = Disk::new(recordings_dir.to_path_buf(), ByteSize(0));
-        RecDb::new(DummyLogger::new(), recordings_dir.to_path_buf(), disk)
+        RecDb::new(DummyLogger::new(), recordings_dir.to_path_buf(), disk, None)
     }
 
     fn new_test_manager() -> (TempDir, PathBuf, MonitorManager) {
@@ -904,12 +952,16 @@ mod tests {
                 source: SelectedSource::Rtsp,
                 always_record: false,
                 video_length: 0.0,
+                pre_roll: 0.0,
+                thumbnail_detections: false,
+                retention: RetentionConfig::default(),
             },
             SourceConfig::Rtsp(SourceRtspConfig {
                 protocol: Protocol::Tcp,
                 main_stream: "rtsp://x1".parse().unwrap(),
                 sub_stream: None,
                 onvif_url: None,
+                audio: false,
             }),
             json!({
                 "id": "new",
@@ -957,12 +1009,16 @@ mod tests {
                 source: SelectedSource::Rtsp,
                 always_record: false,
                 video_length: 0.0,
+                pre_roll: 0.0,
+                thumbnail_detections: false,
+                retention: RetentionConfig::default(),
             },
             SourceConfig::Rtsp(SourceRtspConfig {
                 protocol: Protocol::Tcp,
                 main_stream: "rtsp://x1".parse().unwrap(),
                 sub_stream: None,
                 onvif_url: None,
+                audio: false,
             }),
             json!({
                 "id": "1",
@@ -1089,12 +1145,16 @@ mod tests {
                         source: SelectedSource::Rtsp,
                         always_record: false,
                         video_length: 0.0,
+                        pre_roll: 0.0,
+                        thumbnail_detections: false,
+                        retention: RetentionConfig::default(),
                     },
                     SourceConfig::Rtsp(SourceRtspConfig {
                         protocol: Protocol::Tcp,
                         main_stream: "rtsp://x1".parse().unwrap(),
                         sub_stream: None,
                         onvif_url: None,
+                        audio: false,
                     }),
                     json!({
                         "id": "1",
@@ -1120,12 +1180,16 @@ mod tests {
                         source: SelectedSource::Rtsp,
                         always_record: false,
                         video_length: 0.0,
+                        pre_roll: 0.0,
+                        thumbnail_detections: false,
+                        retention: RetentionConfig::default(),
                     },
                     SourceConfig::Rtsp(SourceRtspConfig {
                         protocol: Protocol::Udp,
                         main_stream: "rtsp://x1".parse().unwrap(),
                         sub_stream: Some("rtsp://x2".parse().unwrap()),
                         onvif_url: None,
+                        audio: false,
                     }),
                     json!({
                         "id": "2",
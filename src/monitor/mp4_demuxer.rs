@@ -0,0 +1,756 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+// Minimal MP4 demuxer that only reads the first video track.
+// Supports regular files and fragmented MP4 used by HLS.
+
+use common::{time::H264_TIMESCALE, VideoCodec};
+use std::num::TryFromIntError;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub(crate) enum DemuxError {
+    #[error("box '{0}' is truncated")]
+    Truncated(String),
+
+    #[error("missing box '{0}'")]
+    MissingBox(&'static str),
+
+    #[error("no video track")]
+    NoVideoTrack,
+
+    #[error("unsupported sample entry '{0}'")]
+    UnsupportedSampleEntry(String),
+
+    #[error("invalid sample table")]
+    InvalidSampleTable,
+
+    #[error("{0}")]
+    TryFromInt(#[from] TryFromIntError),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct VideoTrack {
+    pub track_id: u32,
+    pub timescale: u32,
+    pub video_codec: VideoCodec,
+    pub width: u16,
+    pub height: u16,
+    pub extra_data: Vec<u8>, // 'avcC' or 'hvcC' content.
+    pub defaults: TrackDefaults,
+}
+
+impl VideoTrack {
+    // Converts a timestamp from the track timescale to 90kHz.
+    pub(crate) fn to_h264_timescale(&self, v: i64) -> Option<i64> {
+        let v = i128::from(v) * i128::from(H264_TIMESCALE) / i128::from(self.timescale);
+        i64::try_from(v).ok()
+    }
+}
+
+// Fragment defaults from the 'trex' box.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct TrackDefaults {
+    pub sample_duration: u32,
+    pub sample_size: u32,
+    pub sample_flags: u32,
+}
+
+// Timestamps are in the track timescale.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct DemuxedSample {
+    pub offset: u64, // Position of the sample data in the file or segment.
+    pub size: u32,
+    pub dts: i64,
+    pub cts_offset: i64,
+    pub duration: u32,
+    pub random_access: bool,
+}
+
+// Parses the content of a 'moov' box. The samples are empty for fragmented files.
+pub(crate) fn parse_moov(moov: &[u8]) -> Result<(VideoTrack, Vec<DemuxedSample>), DemuxError> {
+    for b in boxes(moov) {
+        let b = b?;
+        if &b.typ != b"trak" {
+            continue;
+        }
+        if let Some((mut track, samples)) = parse_trak(b.content)? {
+            track.defaults = parse_trex(moov, track.track_id)?;
+            return Ok((track, samples));
+        }
+    }
+    Err(DemuxError::NoVideoTrack)
+}
+
+// Parses a fMP4 init segment.
+pub(crate) fn parse_init(init: &[u8]) -> Result<VideoTrack, DemuxError> {
+    let moov = find(init, "moov")?.ok_or(DemuxError::MissingBox("moov"))?;
+    Ok(parse_moov(moov)?.0)
+}
+
+// Returns the samples from the 'moof' boxes in a fMP4 segment.
+pub(crate) fn parse_fragment(
+    segment: &[u8],
+    track: &VideoTrack,
+) -> Result<Vec<DemuxedSample>, DemuxError> {
+    let mut samples = Vec::new();
+    for b in boxes(segment) {
+        let b = b?;
+        if &b.typ != b"moof" {
+            continue;
+        }
+        for traf in boxes(b.content) {
+            let traf = traf?;
+            if &traf.typ == b"traf" {
+                parse_traf(traf.content, track, u64::try_from(b.start)?, &mut samples)?;
+            }
+        }
+    }
+    Ok(samples)
+}
+
+fn parse_trak(trak: &[u8]) -> Result<Option<(VideoTrack, Vec<DemuxedSample>)>, DemuxError> {
+    let mdia = require(trak, "mdia")?;
+    let mut hdlr = Reader::new("hdlr", require(mdia, "hdlr")?);
+    hdlr.skip(8)?;
+    if &hdlr.array::<4>()? != b"vide" {
+        return Ok(None);
+    }
+
+    let mut tkhd = Reader::new("tkhd", require(trak, "tkhd")?);
+    let version = tkhd.u8()?;
+    tkhd.skip(if version == 1 { 3 + 16 } else { 3 + 8 })?;
+    let track_id = tkhd.u32()?;
+
+    let mut mdhd = Reader::new("mdhd", require(mdia, "mdhd")?);
+    let version = mdhd.u8()?;
+    mdhd.skip(if version == 1 { 3 + 16 } else { 3 + 8 })?;
+    let timescale = mdhd.u32()?;
+    if timescale == 0 {
+        return Err(DemuxError::InvalidSampleTable);
+    }
+
+    let stbl = require(require(mdia, "minf")?, "stbl")?;
+    let stsd = require(stbl, "stsd")?;
+    let entry = boxes(stsd.get(8..).unwrap_or_default())
+        .next()
+        .ok_or(DemuxError::MissingBox("stsd entry"))??;
+    let (video_codec, config_box) = match &entry.typ {
+        b"avc1" | b"avc3" => (VideoCodec::H264, "avcC"),
+        b"hvc1" | b"hev1" => (VideoCodec::H265, "hvcC"),
+        v => {
+            return Err(DemuxError::UnsupportedSampleEntry(
+                String::from_utf8_lossy(v).into_owned(),
+            ))
+        }
+    };
+
+    // ISO/IEC 14496-12 VisualSampleEntry.
+    let mut r = Reader::new("stsd", entry.content);
+    r.skip(24)?;
+    let width = r.u16()?;
+    let height = r.u16()?;
+    let children = entry
+        .content
+        .get(78..)
+        .ok_or_else(|| DemuxError::Truncated("stsd".to_owned()))?;
+    let extra_data = require(children, config_box)?.to_owned();
+
+    let track = VideoTrack {
+        track_id,
+        timescale,
+        video_codec,
+        width,
+        height,
+        extra_data,
+        defaults: TrackDefaults::default(),
+    };
+    Ok(Some((track, parse_stbl(stbl)?)))
+}
+
+fn parse_trex(moov: &[u8], track_id: u32) -> Result<TrackDefaults, DemuxError> {
+    let Some(mvex) = find(moov, "mvex")? else {
+        return Ok(TrackDefaults::default());
+    };
+    for b in boxes(mvex) {
+        let b = b?;
+        if &b.typ != b"trex" {
+            continue;
+        }
+        let mut r = Reader::new("trex", b.content);
+        r.skip(4)?;
+        if r.u32()? != track_id {
+            continue;
+        }
+        r.skip(4)?; // Sample description index.
+        return Ok(TrackDefaults {
+            sample_duration: r.u32()?,
+            sample_size: r.u32()?,
+            sample_flags: r.u32()?,
+        });
+    }
+    Ok(TrackDefaults::default())
+}
+
+fn parse_stbl(stbl: &[u8]) -> Result<Vec<DemuxedSample>, DemuxError> {
+    use DemuxError::*;
+
+    // Sample sizes.
+    let mut stsz = Reader::new("stsz", require(stbl, "stsz")?);
+    stsz.skip(4)?;
+    let sample_size = stsz.u32()?;
+    let sample_count = usize::try_from(stsz.u32()?)?;
+    let mut sizes = Vec::new();
+    for _ in 0..sample_count {
+        sizes.push(if sample_size == 0 {
+            stsz.u32()?
+        } else {
+            sample_size
+        });
+    }
+    if sizes.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // Durations.
+    let mut stts = Reader::new("stts", require(stbl, "stts")?);
+    stts.skip(4)?;
+    let mut durations = Vec::new();
+    for _ in 0..stts.u32()? {
+        let count = stts.u32()?;
+        let delta = stts.u32()?;
+        for _ in 0..count {
+            if durations.len() == sizes.len() {
+                break;
+            }
+            durations.push(delta);
+        }
+    }
+
+    // Composition offsets, optional.
+    let mut cts_offsets = Vec::new();
+    if let Some(ctts) = find(stbl, "ctts")? {
+        let mut ctts = Reader::new("ctts", ctts);
+        ctts.skip(4)?;
+        for _ in 0..ctts.u32()? {
+            let count = ctts.u32()?;
+            // Version 0 is unsigned but negative values are common in the wild.
+            let offset = i64::from(ctts.i32()?);
+            for _ in 0..count {
+                if cts_offsets.len() == sizes.len() {
+                    break;
+                }
+                cts_offsets.push(offset);
+            }
+        }
+    }
+
+    // Sync samples, all samples are sync samples if missing.
+    let sync_samples = match find(stbl, "stss")? {
+        Some(stss) => {
+            let mut stss = Reader::new("stss", stss);
+            stss.skip(4)?;
+            let mut sync_samples = Vec::new();
+            for _ in 0..stss.u32()? {
+                sync_samples.push(stss.u32()?);
+            }
+            Some(sync_samples)
+        }
+        None => None,
+    };
+
+    // Chunk offsets.
+    let mut chunk_offsets = Vec::new();
+    if let Some(stco) = find(stbl, "stco")? {
+        let mut stco = Reader::new("stco", stco);
+        stco.skip(4)?;
+        for _ in 0..stco.u32()? {
+            chunk_offsets.push(u64::from(stco.u32()?));
+        }
+    } else {
+        let mut co64 = Reader::new("co64", require(stbl, "co64")?);
+        co64.skip(4)?;
+        for _ in 0..co64.u32()? {
+            chunk_offsets.push(co64.u64()?);
+        }
+    }
+
+    // Samples per chunk.
+    let mut stsc = Reader::new("stsc", require(stbl, "stsc")?);
+    stsc.skip(4)?;
+    let mut stsc_entries = Vec::new();
+    for _ in 0..stsc.u32()? {
+        let first_chunk = stsc.u32()?;
+        let samples_per_chunk = stsc.u32()?;
+        stsc.skip(4)?; // Sample description index.
+        stsc_entries.push((first_chunk, samples_per_chunk));
+    }
+
+    let mut samples = Vec::with_capacity(sizes.len());
+    let mut dts: i64 = 0;
+    let mut entry_i = 0;
+    for (chunk_i, chunk_offset) in chunk_offsets.iter().enumerate() {
+        let chunk_number = u32::try_from(chunk_i + 1)?;
+        while stsc_entries
+            .get(entry_i + 1)
+            .is_some_and(|(first_chunk, _)| *first_chunk <= chunk_number)
+        {
+            entry_i += 1;
+        }
+        let (_, samples_per_chunk) = stsc_entries.get(entry_i).ok_or(InvalidSampleTable)?;
+
+        let mut offset = *chunk_offset;
+        for _ in 0..*samples_per_chunk {
+            let i = samples.len();
+            let Some(size) = sizes.get(i) else {
+                break;
+            };
+            let duration = *durations.get(i).ok_or(InvalidSampleTable)?;
+            let sample_number = u32::try_from(i + 1)?;
+            samples.push(DemuxedSample {
+                offset,
+                size: *size,
+                dts,
+                cts_offset: cts_offsets.get(i).copied().unwrap_or(0),
+                duration,
+                random_access: sync_samples
+                    .as_ref()
+                    .map_or(true, |v| v.binary_search(&sample_number).is_ok()),
+            });
+            offset += u64::from(*size);
+            dts += i64::from(duration);
+        }
+    }
+    if samples.len() != sizes.len() {
+        return Err(InvalidSampleTable);
+    }
+    Ok(samples)
+}
+
+const TFHD_BASE_DATA_OFFSET: u32 = 0x01;
+const TFHD_SAMPLE_DESCRIPTION_INDEX: u32 = 0x02;
+const TFHD_DEFAULT_SAMPLE_DURATION: u32 = 0x08;
+const TFHD_DEFAULT_SAMPLE_SIZE: u32 = 0x10;
+const TFHD_DEFAULT_SAMPLE_FLAGS: u32 = 0x20;
+
+const TRUN_DATA_OFFSET: u32 = 0x01;
+const TRUN_FIRST_SAMPLE_FLAGS: u32 = 0x04;
+const TRUN_SAMPLE_DURATION: u32 = 0x100;
+const TRUN_SAMPLE_SIZE: u32 = 0x200;
+const TRUN_SAMPLE_FLAGS: u32 = 0x400;
+const TRUN_SAMPLE_CTS: u32 = 0x800;
+
+const SAMPLE_IS_NON_SYNC: u32 = 0x0001_0000;
+
+fn parse_traf(
+    traf: &[u8],
+    track: &VideoTrack,
+    moof_start: u64,
+    samples: &mut Vec<DemuxedSample>,
+) -> Result<(), DemuxError> {
+    let mut tfhd = Reader::new("tfhd", require(traf, "tfhd")?);
+    let flags = tfhd.u32()? & 0x00ff_ffff;
+    if tfhd.u32()? != track.track_id {
+        return Ok(());
+    }
+    let mut base_offset = moof_start;
+    if flags & TFHD_BASE_DATA_OFFSET != 0 {
+        base_offset = tfhd.u64()?;
+    }
+    if flags & TFHD_SAMPLE_DESCRIPTION_INDEX != 0 {
+        tfhd.skip(4)?;
+    }
+    let mut defaults = track.defaults;
+    if flags & TFHD_DEFAULT_SAMPLE_DURATION != 0 {
+        defaults.sample_duration = tfhd.u32()?;
+    }
+    if flags & TFHD_DEFAULT_SAMPLE_SIZE != 0 {
+        defaults.sample_size = tfhd.u32()?;
+    }
+    if flags & TFHD_DEFAULT_SAMPLE_FLAGS != 0 {
+        defaults.sample_flags = tfhd.u32()?;
+    }
+
+    let mut dts = match find(traf, "tfdt")? {
+        Some(tfdt) => {
+            let mut tfdt = Reader::new("tfdt", tfdt);
+            if tfdt.u8()? == 1 {
+                tfdt.skip(3)?;
+                i64::try_from(tfdt.u64()?)?
+            } else {
+                tfdt.skip(3)?;
+                i64::from(tfdt.u32()?)
+            }
+        }
+        None => 0,
+    };
+
+    let mut next_offset = base_offset;
+    for b in boxes(traf) {
+        let b = b?;
+        if &b.typ != b"trun" {
+            continue;
+        }
+        let mut trun = Reader::new("trun", b.content);
+        let version = trun.u8()?;
+        let flags = u32::from_be_bytes([0, trun.u8()?, trun.u8()?, trun.u8()?]);
+        let sample_count = trun.u32()?;
+
+        let mut offset = next_offset;
+        if flags & TRUN_DATA_OFFSET != 0 {
+            let data_offset = i64::from(trun.i32()?);
+            offset = u64::try_from(i64::try_from(base_offset)? + data_offset)?;
+        }
+        let first_sample_flags = if flags & TRUN_FIRST_SAMPLE_FLAGS != 0 {
+            Some(trun.u32()?)
+        } else {
+            None
+        };
+
+        for i in 0..sample_count {
+            let duration = if flags & TRUN_SAMPLE_DURATION != 0 {
+                trun.u32()?
+            } else {
+                defaults.sample_duration
+            };
+            let size = if flags & TRUN_SAMPLE_SIZE != 0 {
+                trun.u32()?
+            } else {
+                defaults.sample_size
+            };
+            let mut sample_flags = if flags & TRUN_SAMPLE_FLAGS != 0 {
+                trun.u32()?
+            } else {
+                defaults.sample_flags
+            };
+            if let (0, Some(v)) = (i, first_sample_flags) {
+                sample_flags = v;
+            }
+            let cts_offset = if flags & TRUN_SAMPLE_CTS == 0 {
+                0
+            } else if version == 0 {
+                i64::from(trun.u32()?)
+            } else {
+                i64::from(trun.i32()?)
+            };
+
+            samples.push(DemuxedSample {
+                offset,
+                size,
+                dts,
+                cts_offset,
+                duration,
+                random_access: sample_flags & SAMPLE_IS_NON_SYNC == 0,
+            });
+            offset += u64::from(size);
+            dts += i64::from(duration);
+        }
+        next_offset = offset;
+    }
+    Ok(())
+}
+
+struct Mp4Box<'a> {
+    typ: [u8; 4],
+    start: usize, // Position of the box header in the parent.
+    content: &'a [u8],
+}
+
+struct Boxes<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+fn boxes(data: &[u8]) -> Boxes<'_> {
+    Boxes { data, pos: 0 }
+}
+
+impl<'a> Iterator for Boxes<'a> {
+    type Item = Result<Mp4Box<'a>, DemuxError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let data = self.data.get(self.pos..)?;
+        if data.is_empty() {
+            return None;
+        }
+        let start = self.pos;
+        // Stop iterating after an error.
+        self.pos = self.data.len();
+
+        let mut r = Reader::new("box", data);
+        let Ok(size) = r.u32() else {
+            return Some(Err(DemuxError::Truncated("box header".to_owned())));
+        };
+        let Ok(typ) = r.array::<4>() else {
+            return Some(Err(DemuxError::Truncated("box header".to_owned())));
+        };
+        let truncated = || DemuxError::Truncated(String::from_utf8_lossy(&typ).into_owned());
+
+        let (size, header_size) = match size {
+            0 => (data.len(), 8),
+            1 => {
+                let Some(size) = r.u64().ok().and_then(|v| usize::try_from(v).ok()) else {
+                    return Some(Err(truncated()));
+                };
+                (size, 16)
+            }
+            _ => {
+                let Ok(size) = usize::try_from(size) else {
+                    return Some(Err(truncated()));
+                };
+                (size, 8)
+            }
+        };
+        let Some(content) = data.get(header_size..size) else {
+            return Some(Err(truncated()));
+        };
+        self.pos = start + size;
+        Some(Ok(Mp4Box {
+            typ,
+            start,
+            content,
+        }))
+    }
+}
+
+// Returns the content of the first child box with the type.
+fn find<'a>(data: &'a [u8], typ: &'static str) -> Result<Option<&'a [u8]>, DemuxError> {
+    for b in boxes(data) {
+        let b = b?;
+        if b.typ == typ.as_bytes() {
+            return Ok(Some(b.content));
+        }
+    }
+    Ok(None)
+}
+
+fn require<'a>(data: &'a [u8], typ: &'static str) -> Result<&'a [u8], DemuxError> {
+    find(data, typ)?.ok_or(DemuxError::MissingBox(typ))
+}
+
+// Big endian reader.
+struct Reader<'a> {
+    name: &'static str,
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(name: &'static str, data: &'a [u8]) -> Self {
+        Self { name, data, pos: 0 }
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], DemuxError> {
+        let v = self
+            .data
+            .get(self.pos..self.pos + N)
+            .ok_or_else(|| DemuxError::Truncated(self.name.to_owned()))?;
+        self.pos += N;
+        Ok(v.try_into().expect("slice should have length N"))
+    }
+
+    fn skip(&mut self, n: usize) -> Result<(), DemuxError> {
+        if self.pos + n > self.data.len() {
+            return Err(DemuxError::Truncated(self.name.to_owned()));
+        }
+        self.pos += n;
+        Ok(())
+    }
+
+    fn u8(&mut self) -> Result<u8, DemuxError> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, DemuxError> {
+        Ok(u16::from_be_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, DemuxError> {
+        Ok(u32::from_be_bytes(self.array()?))
+    }
+
+    fn i32(&mut self) -> Result<i32, DemuxError> {
+        Ok(i32::from_be_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, DemuxError> {
+        Ok(u64::from_be_bytes(self.array()?))
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn mp4_box(typ: &[u8; 4], content: &[&[u8]]) -> Vec<u8> {
+        let content = content.concat();
+        let size = u32::try_from(content.len() + 8).unwrap();
+        [
+            size.to_be_bytes().as_slice(),
+            typ.as_slice(),
+            content.as_slice(),
+        ]
+        .concat()
+    }
+
+    fn full_box(typ: &[u8; 4], flags: u32, content: &[&[u8]]) -> Vec<u8> {
+        mp4_box(typ, &[&flags.to_be_bytes(), &content.concat()])
+    }
+
+    fn u32s(values: &[u32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_be_bytes()).collect()
+    }
+
+    fn test_trak(stbl_tables: &[Vec<u8>]) -> Vec<u8> {
+        let mut avc1 = vec![0; 78];
+        avc1[24..28].copy_from_slice(&[0, 64, 0, 48]);
+        let avc1 = mp4_box(b"avc1", &[&avc1, &mp4_box(b"avcC", &[&[1, 0x64, 0, 0x16]])]);
+        let stsd = full_box(b"stsd", 0, &[&u32s(&[1]), &avc1]);
+        let mut stbl = vec![stsd];
+        stbl.extend_from_slice(stbl_tables);
+        let stbl = mp4_box(b"stbl", &[&stbl.concat()]);
+
+        let tkhd = full_box(b"tkhd", 0, &[&u32s(&[0, 0, 1])]);
+        let mdhd = full_box(b"mdhd", 0, &[&u32s(&[0, 0, 1000])]);
+        let hdlr = full_box(b"hdlr", 0, &[&u32s(&[0]), b"vide"]);
+        let minf = mp4_box(b"minf", &[&stbl]);
+        let mdia = mp4_box(b"mdia", &[&mdhd, &hdlr, &minf]);
+        mp4_box(b"trak", &[&tkhd, &mdia])
+    }
+
+    #[test]
+    fn test_parse_moov() {
+        let stbl_tables = vec![
+            full_box(b"stts", 0, &[&u32s(&[1, 3, 10])]),
+            full_box(b"ctts", 0, &[&u32s(&[2, 1, 20, 1, 0])]),
+            full_box(b"stss", 0, &[&u32s(&[1, 1])]),
+            full_box(b"stsz", 0, &[&u32s(&[0, 3, 5, 6, 7])]),
+            full_box(b"stsc", 0, &[&u32s(&[2, 1, 2, 1, 2, 1, 1])]),
+            full_box(b"stco", 0, &[&u32s(&[2, 100, 200])]),
+        ];
+        let sound_trak = mp4_box(
+            b"trak",
+            &[&mp4_box(
+                b"mdia",
+                &[&full_box(b"hdlr", 0, &[&u32s(&[0]), b"soun"])],
+            )],
+        );
+        let moov = [sound_trak, test_trak(&stbl_tables)].concat();
+
+        let (track, samples) = parse_moov(&moov).unwrap();
+        let want_track = VideoTrack {
+            track_id: 1,
+            timescale: 1000,
+            video_codec: VideoCodec::H264,
+            width: 64,
+            height: 48,
+            extra_data: vec![1, 0x64, 0, 0x16],
+            defaults: TrackDefaults::default(),
+        };
+        assert_eq!(want_track, track);
+
+        let want_samples = vec![
+            DemuxedSample {
+                offset: 100,
+                size: 5,
+                dts: 0,
+                cts_offset: 20,
+                duration: 10,
+                random_access: true,
+            },
+            DemuxedSample {
+                offset: 105,
+                size: 6,
+                dts: 10,
+                cts_offset: 0,
+                duration: 10,
+                random_access: false,
+            },
+            DemuxedSample {
+                offset: 200,
+                size: 7,
+                dts: 20,
+                cts_offset: 0,
+                duration: 10,
+                random_access: false,
+            },
+        ];
+        assert_eq!(want_samples, samples);
+    }
+
+    #[test]
+    fn test_parse_fragment() {
+        let stbl_tables = vec![
+            full_box(b"stts", 0, &[&u32s(&[0])]),
+            full_box(b"stsz", 0, &[&u32s(&[0, 0])]),
+            full_box(b"stsc", 0, &[&u32s(&[0])]),
+            full_box(b"stco", 0, &[&u32s(&[0])]),
+        ];
+        let trex = full_box(b"trex", 0, &[&u32s(&[1, 1, 9, 0, 0x0001_0000])]);
+        let moov = mp4_box(
+            b"moov",
+            &[&test_trak(&stbl_tables), &mp4_box(b"mvex", &[&trex])],
+        );
+        let init = [mp4_box(b"ftyp", &[b"iso4"]), moov].concat();
+
+        let track = parse_init(&init).unwrap();
+        let want_defaults = TrackDefaults {
+            sample_duration: 9,
+            sample_size: 0,
+            sample_flags: 0x0001_0000,
+        };
+        assert_eq!(want_defaults, track.defaults);
+
+        let tfhd = full_box(b"tfhd", 0x0002_0000, &[&u32s(&[1])]);
+        let tfdt = full_box(b"tfdt", 0x0100_0000, &[&[0, 0, 0, 0, 0, 0, 0, 90]]);
+        let trun = full_box(
+            b"trun",
+            TRUN_DATA_OFFSET | TRUN_FIRST_SAMPLE_FLAGS | TRUN_SAMPLE_SIZE,
+            &[&u32s(&[2, 0, 0, 2, 3])],
+        );
+        let mfhd = full_box(b"mfhd", 0, &[&u32s(&[1])]);
+        let traf = mp4_box(b"traf", &[&tfhd, &tfdt, &trun]);
+        let moof = mp4_box(b"moof", &[&mfhd, &traf]);
+
+        // Patch the data offset to point after the mdat header.
+        let data_offset = u32::try_from(moof.len() + 8).unwrap();
+        let moof = moof
+            .windows(4)
+            .position(|v| v == b"trun")
+            .map(|i| {
+                let mut moof = moof.clone();
+                moof[i + 12..i + 16].copy_from_slice(&data_offset.to_be_bytes());
+                moof
+            })
+            .unwrap();
+        let segment = [moof, mp4_box(b"mdat", &[&[1, 2, 3, 4, 5]])].concat();
+
+        let samples = parse_fragment(&segment, &track).unwrap();
+        let want = vec![
+            DemuxedSample {
+                offset: u64::from(data_offset),
+                size: 2,
+                dts: 90,
+                cts_offset: 0,
+                duration: 9,
+                random_access: true,
+            },
+            DemuxedSample {
+                offset: u64::from(data_offset) + 2,
+                size: 3,
+                dts: 99,
+                cts_offset: 0,
+                duration: 9,
+                random_access: false,
+            },
+        ];
+        assert_eq!(want, samples);
+    }
+
+    #[test]
+    fn test_truncated_box() {
+        let data = [0, 0, 0, 10, b'f', b'r', b'e', b'e', 0];
+        assert!(matches!(find(&data, "moov"), Err(DemuxError::Truncated(_))));
+    }
+}
@@ -0,0 +1,678 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+use common::{
+    monitor::MonitorConfig,
+    recording::{DurationSec, RecordingId},
+    time::UnixNano,
+    Detections, DynLogger, DynMsgLogger, Event, Label, LogEntry, LogLevel, MonitorId, MsgLogger,
+    NonEmptyString,
+};
+use serde::{Deserialize, Serialize};
+use std::{process::Stdio, sync::Arc, time::Duration};
+use thiserror::Error;
+use tokio::{
+    io::AsyncWriteExt,
+    sync::{mpsc, watch},
+};
+use tokio_util::sync::CancellationToken;
+use url::Url;
+
+// How long a notification waits for the recording to
+// start before it's sent without a thumbnail.
+const RECORDING_TIMEOUT: Duration = Duration::from_secs(10);
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub(crate) struct NotifierConfig {
+    // Only notify on these labels, empty means all labels.
+    #[serde(default)]
+    labels: Vec<Label>,
+
+    #[serde(rename = "minScore", default)]
+    min_score: f32,
+
+    // Minimum time between two notifications.
+    #[serde(default)]
+    debounce: DurationSec,
+
+    // Used to create absolute thumbnail links.
+    #[serde(rename = "baseUrl")]
+    base_url: Option<Url>,
+
+    #[serde(default)]
+    webhooks: Vec<WebhookConfig>,
+
+    #[serde(default)]
+    commands: Vec<CommandConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+struct WebhookConfig {
+    url: Url,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+struct CommandConfig {
+    command: NonEmptyString,
+
+    #[serde(default)]
+    args: Vec<String>,
+}
+
+impl NotifierConfig {
+    // Returns `None` if unset or disabled.
+    pub(crate) fn parse(raw: serde_json::Value) -> Result<Option<Self>, serde_json::Error> {
+        #[derive(Deserialize)]
+        struct Temp {
+            notifications: serde_json::Value,
+        }
+        let Ok(temp) = serde_json::from_value::<Temp>(raw) else {
+            return Ok(None);
+        };
+        if temp.notifications == serde_json::Value::Object(serde_json::Map::new()) {
+            return Ok(None);
+        }
+
+        #[derive(Deserialize)]
+        struct RawConfig {
+            enable: bool,
+            #[serde(flatten)]
+            config: NotifierConfig,
+        }
+        let raw: RawConfig = serde_json::from_value(temp.notifications)?;
+        if !raw.enable {
+            return Ok(None);
+        }
+        Ok(Some(raw.config))
+    }
+
+    // Returns the detections that passed the filters or
+    // `None` if the event shouldn't trigger a notification.
+    fn filter(&self, detections: &Detections) -> Option<Detections> {
+        if self.labels.is_empty() && self.min_score <= 0.0 {
+            return Some(detections.clone());
+        }
+        let filtered: Detections = detections
+            .iter()
+            .filter(|d| {
+                (self.labels.is_empty() || self.labels.contains(&d.label))
+                    && d.score >= self.min_score
+            })
+            .cloned()
+            .collect();
+        if filtered.is_empty() {
+            None
+        } else {
+            Some(filtered)
+        }
+    }
+}
+
+// Body of the webhook request and stdin of the command.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+struct Notification {
+    #[serde(rename = "monitorId")]
+    monitor_id: MonitorId,
+
+    #[serde(rename = "monitorName")]
+    monitor_name: NonEmptyString,
+
+    time: UnixNano,
+    detections: Detections,
+
+    #[serde(rename = "recordingId")]
+    recording_id: Option<RecordingId>,
+
+    thumbnail: Option<String>,
+}
+
+// Sends notifications to webhooks and local commands when
+// events matching the filters are received.
+pub(crate) struct Notifier {
+    event_tx: mpsc::Sender<Event>,
+    recording_tx: watch::Sender<Option<RecordingId>>,
+}
+
+impl Notifier {
+    // Returns `None` if notifications are unset or disabled.
+    pub(crate) fn new(
+        token: CancellationToken,
+        logger: DynLogger,
+        config: &MonitorConfig,
+    ) -> Option<Self> {
+        let logger: DynMsgLogger =
+            Arc::new(NotifierMsgLogger::new(logger, config.id().to_owned()));
+        let notifier_config = match NotifierConfig::parse(config.raw().clone()) {
+            Ok(Some(v)) => v,
+            Ok(None) => return None,
+            Err(e) => {
+                logger.log(LogLevel::Error, &format!("invalid config: {e}"));
+                return None;
+            }
+        };
+        Some(Self::with_config(
+            token,
+            logger,
+            notifier_config,
+            config.id().to_owned(),
+            config.name().to_owned(),
+        ))
+    }
+
+    fn with_config(
+        token: CancellationToken,
+        logger: DynMsgLogger,
+        config: NotifierConfig,
+        monitor_id: MonitorId,
+        monitor_name: NonEmptyString,
+    ) -> Self {
+        let (event_tx, event_rx) = mpsc::channel(16);
+        let (recording_tx, recording_rx) = watch::channel(None);
+
+        let state = NotifierState {
+            token,
+            logger,
+            config: Arc::new(config),
+            client: reqwest::Client::new(),
+            monitor_id,
+            monitor_name,
+            recording_rx,
+            last_notification: None,
+        };
+        tokio::spawn(state.run(event_rx));
+
+        Self {
+            event_tx,
+            recording_tx,
+        }
+    }
+
+    // Never blocks, events are dropped if the notifier is falling behind.
+    pub(crate) fn notify(&self, event: &Event) {
+        _ = self.event_tx.try_send(event.clone());
+    }
+
+    // Used to attach the thumbnail of the active recording to notifications.
+    pub(crate) fn set_active_recording(&self, id: Option<RecordingId>) {
+        self.recording_tx.send_replace(id);
+    }
+}
+
+struct NotifierState {
+    token: CancellationToken,
+    logger: DynMsgLogger,
+    config: Arc<NotifierConfig>,
+    client: reqwest::Client,
+    monitor_id: MonitorId,
+    monitor_name: NonEmptyString,
+    recording_rx: watch::Receiver<Option<RecordingId>>,
+    last_notification: Option<UnixNano>,
+}
+
+impl NotifierState {
+    async fn run(mut self, mut event_rx: mpsc::Receiver<Event>) {
+        loop {
+            let event = tokio::select! {
+                () = self.token.cancelled() => return,
+                event = event_rx.recv() => {
+                    let Some(event) = event else {
+                        return
+                    };
+                    event
+                }
+            };
+            let Some(detections) = self.filter_and_debounce(&event) else {
+                continue;
+            };
+
+            let mut recording_rx = self.recording_rx.clone();
+            let notification = Notification {
+                monitor_id: self.monitor_id.clone(),
+                monitor_name: self.monitor_name.clone(),
+                time: event.time,
+                detections,
+                recording_id: None,
+                thumbnail: None,
+            };
+            let token = self.token.clone();
+            let logger = self.logger.clone();
+            let config = self.config.clone();
+            let client = self.client.clone();
+            tokio::spawn(async move {
+                // The recording may not have been created yet.
+                tokio::select! {
+                    () = token.cancelled() => return,
+                    _ = tokio::time::timeout(
+                        RECORDING_TIMEOUT,
+                        wait_for_recording(&mut recording_rx),
+                    ) => {}
+                }
+                let recording_id = recording_rx.borrow().clone();
+                let notification =
+                    notification.with_recording(recording_id, config.base_url.as_ref());
+
+                tokio::select! {
+                    () = token.cancelled() => {},
+                    () = send_notification(&logger, &client, &config, &notification) => {},
+                }
+            });
+        }
+    }
+
+    fn filter_and_debounce(&mut self, event: &Event) -> Option<Detections> {
+        let detections = self.config.filter(&event.detections)?;
+
+        if let Some(last) = self.last_notification {
+            let next = last.checked_add(UnixNano::from(*self.config.debounce))?;
+            if event.time.before(next) {
+                return None;
+            }
+        }
+        self.last_notification = Some(event.time);
+        Some(detections)
+    }
+}
+
+async fn wait_for_recording(rx: &mut watch::Receiver<Option<RecordingId>>) {
+    loop {
+        let is_active = rx.borrow_and_update().is_some();
+        if is_active || rx.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+impl Notification {
+    fn with_recording(mut self, recording_id: Option<RecordingId>, base_url: Option<&Url>) -> Self {
+        self.thumbnail = recording_id.as_ref().map(|id| thumbnail_link(id, base_url));
+        self.recording_id = recording_id;
+        self
+    }
+}
+
+fn thumbnail_link(recording_id: &RecordingId, base_url: Option<&Url>) -> String {
+    let path = format!("api/recording/thumbnail/{}", recording_id.as_str());
+    match base_url.and_then(|base_url| base_url.join(&path).ok()) {
+        Some(url) => url.to_string(),
+        None => path,
+    }
+}
+
+async fn send_notification(
+    logger: &DynMsgLogger,
+    client: &reqwest::Client,
+    config: &NotifierConfig,
+    notification: &Notification,
+) {
+    let body = match serde_json::to_vec(notification) {
+        Ok(v) => v,
+        Err(e) => {
+            logger.log(LogLevel::Error, &format!("serialize notification: {e}"));
+            return;
+        }
+    };
+
+    let body = &body;
+    let webhooks = config.webhooks.iter().map(|webhook| async move {
+        if let Err(e) = send_webhook(client, &webhook.url, body.clone()).await {
+            logger.log(LogLevel::Error, &format!("webhook '{}': {e}", webhook.url));
+        }
+    });
+    let commands = config.commands.iter().map(|command| async move {
+        if let Err(e) = run_command(command, body).await {
+            logger.log(LogLevel::Error, &format!("command '{}': {e}", command.command));
+        }
+    });
+    futures::future::join(
+        futures::future::join_all(webhooks),
+        futures::future::join_all(commands),
+    )
+    .await;
+
+    logger.log(
+        LogLevel::Debug,
+        &format!("sent notification: {:?}", notification.recording_id),
+    );
+}
+
+#[derive(Debug, Error)]
+enum SendWebhookError {
+    #[error("send: {0}")]
+    Send(#[from] reqwest::Error),
+
+    #[error("bad status: {0}")]
+    BadStatus(reqwest::StatusCode),
+}
+
+async fn send_webhook(
+    client: &reqwest::Client,
+    url: &Url,
+    body: Vec<u8>,
+) -> Result<(), SendWebhookError> {
+    let res = client
+        .post(url.clone())
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .timeout(WEBHOOK_TIMEOUT)
+        .body(body)
+        .send()
+        .await?;
+    if !res.status().is_success() {
+        return Err(SendWebhookError::BadStatus(res.status()));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+enum RunCommandError {
+    #[error("spawn: {0}")]
+    Spawn(std::io::Error),
+
+    #[error("write stdin: {0}")]
+    WriteStdin(std::io::Error),
+
+    #[error("wait: {0}")]
+    Wait(std::io::Error),
+
+    #[error("timeout")]
+    Timeout,
+
+    #[error("exit status: {0}")]
+    ExitStatus(std::process::ExitStatus),
+}
+
+// The notification is written to stdin as json.
+async fn run_command(config: &CommandConfig, body: &[u8]) -> Result<(), RunCommandError> {
+    use RunCommandError::*;
+    let mut child = tokio::process::Command::new(&*config.command)
+        .args(&config.args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(Spawn)?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(body).await.map_err(WriteStdin)?;
+    }
+
+    let status = tokio::time::timeout(COMMAND_TIMEOUT, child.wait())
+        .await
+        .map_err(|_| Timeout)?
+        .map_err(Wait)?;
+    if !status.success() {
+        return Err(ExitStatus(status));
+    }
+    Ok(())
+}
+
+struct NotifierMsgLogger {
+    logger: DynLogger,
+    monitor_id: MonitorId,
+}
+
+impl NotifierMsgLogger {
+    fn new(logger: DynLogger, monitor_id: MonitorId) -> Self {
+        Self { logger, monitor_id }
+    }
+}
+
+impl MsgLogger for NotifierMsgLogger {
+    fn log(&self, level: LogLevel, msg: &str) {
+        self.logger.log(LogEntry::new(
+            level,
+            "monitor",
+            Some(self.monitor_id.clone()),
+            format!("notifier: {msg}"),
+        ));
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::{new_dummy_msg_logger, time::Duration as NanoDuration, Detection, Region};
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+    use test_case::test_case;
+    use tokio::{io::AsyncReadExt, net::TcpListener, sync::oneshot};
+
+    fn detection(label: &str, score: f32) -> Detection {
+        Detection {
+            label: label.to_owned().try_into().unwrap(),
+            score,
+            region: Region {
+                rectangle: None,
+                polygon: None,
+            },
+        }
+    }
+
+    fn event(time: i64, detections: Detections) -> Event {
+        Event {
+            time: UnixNano::new(time),
+            duration: NanoDuration::new(0),
+            rec_duration: NanoDuration::new(0),
+            detections,
+        }
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let raw = json!({
+            "notifications": {
+                "enable": true,
+                "labels": ["person"],
+                "minScore": 50,
+                "debounce": 30,
+                "baseUrl": "https://example.com/",
+                "webhooks": [{ "url": "http://127.0.0.1/hook" }],
+                "commands": [{ "command": "notify", "args": ["a"] }],
+            }
+        });
+        let want = NotifierConfig {
+            labels: vec!["person".to_owned().try_into().unwrap()],
+            min_score: 50.0,
+            debounce: DurationSec::new(NanoDuration::from_secs(30)),
+            base_url: Some("https://example.com/".parse().unwrap()),
+            webhooks: vec![WebhookConfig {
+                url: "http://127.0.0.1/hook".parse().unwrap(),
+            }],
+            commands: vec![CommandConfig {
+                command: "notify".to_owned().try_into().unwrap(),
+                args: vec!["a".to_owned()],
+            }],
+        };
+        assert_eq!(Some(want), NotifierConfig::parse(raw).unwrap());
+    }
+
+    #[test_case(json!({}); "missing")]
+    #[test_case(json!({"notifications": {}}); "empty")]
+    #[test_case(json!({"notifications": {"enable": false}}); "disabled")]
+    fn test_parse_config_none(raw: serde_json::Value) {
+        assert_eq!(None, NotifierConfig::parse(raw).unwrap());
+    }
+
+    #[test_case(&[], 0.0, &[], true; "no_filter")]
+    #[test_case(&["person"], 0.0, &[("person", 1.0)], true; "label")]
+    #[test_case(&["person"], 0.0, &[("car", 1.0)], false; "wrong_label")]
+    #[test_case(&["person"], 0.0, &[], false; "no_detections")]
+    #[test_case(&[], 50.0, &[("car", 50.0)], true; "score")]
+    #[test_case(&[], 50.0, &[("car", 49.0)], false; "low_score")]
+    #[test_case(&["person"], 50.0, &[("person", 49.0), ("car", 90.0)], false; "both")]
+    fn test_filter(labels: &[&str], min_score: f32, detections: &[(&str, f32)], want: bool) {
+        let config = NotifierConfig {
+            labels: labels
+                .iter()
+                .map(|v| (*v).to_owned().try_into().unwrap())
+                .collect(),
+            min_score,
+            ..Default::default()
+        };
+        let detections: Detections = detections
+            .iter()
+            .map(|(label, score)| detection(label, *score))
+            .collect();
+        assert_eq!(want, config.filter(&detections).is_some());
+    }
+
+    #[test]
+    fn test_filter_removes_unmatched() {
+        let config = NotifierConfig {
+            labels: vec!["person".to_owned().try_into().unwrap()],
+            ..Default::default()
+        };
+        let got = config
+            .filter(&vec![detection("car", 1.0), detection("person", 2.0)])
+            .unwrap();
+        assert_eq!(vec![detection("person", 2.0)], got);
+    }
+
+    #[tokio::test]
+    async fn test_debounce() {
+        let (_, recording_rx) = watch::channel(None);
+        let mut state = NotifierState {
+            token: CancellationToken::new(),
+            logger: new_dummy_msg_logger(),
+            config: Arc::new(NotifierConfig {
+                debounce: DurationSec::new(NanoDuration::from_secs(10)),
+                ..Default::default()
+            }),
+            client: reqwest::Client::new(),
+            monitor_id: "x".to_owned().try_into().unwrap(),
+            monitor_name: "x".to_owned().try_into().unwrap(),
+            recording_rx,
+            last_notification: None,
+        };
+
+        let second = 1_000_000_000;
+        assert!(state.filter_and_debounce(&event(0, Vec::new())).is_some());
+        assert!(state.filter_and_debounce(&event(5 * second, Vec::new())).is_none());
+        assert!(state.filter_and_debounce(&event(9 * second, Vec::new())).is_none());
+        assert!(state.filter_and_debounce(&event(10 * second, Vec::new())).is_some());
+        assert!(state.filter_and_debounce(&event(11 * second, Vec::new())).is_none());
+    }
+
+    #[test_case(None, "api/recording/thumbnail/2000-01-02_03-04-05_x"; "relative")]
+    #[test_case(
+        Some("https://example.com/sentryshot/"),
+        "https://example.com/sentryshot/api/recording/thumbnail/2000-01-02_03-04-05_x";
+        "absolute"
+    )]
+    fn test_thumbnail_link(base_url: Option<&str>, want: &str) {
+        let base_url: Option<Url> = base_url.map(|v| v.parse().unwrap());
+        let id: RecordingId = "2000-01-02_03-04-05_x".to_owned().try_into().unwrap();
+        assert_eq!(want, thumbnail_link(&id, base_url.as_ref()));
+    }
+
+    // Minimal HTTP server that returns the body of the first request.
+    async fn stub_server() -> (Url, oneshot::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (body_tx, body_rx) = oneshot::channel();
+
+        tokio::spawn(async move {
+            let (mut conn, _) = listener.accept().await.unwrap();
+            let mut buf = Vec::new();
+            let body = loop {
+                let mut chunk = [0; 1024];
+                let n = conn.read(&mut chunk).await.unwrap();
+                assert_ne!(0, n, "connection closed");
+                buf.extend_from_slice(&chunk[..n]);
+
+                let request = String::from_utf8_lossy(&buf).to_string();
+                let Some((head, body)) = request.split_once("\r\n\r\n") else {
+                    continue;
+                };
+                let content_length: usize = head
+                    .lines()
+                    .find_map(|line| {
+                        let (key, value) = line.split_once(':')?;
+                        key.eq_ignore_ascii_case("content-length")
+                            .then(|| value.trim().parse().unwrap())
+                    })
+                    .unwrap();
+                if body.len() >= content_length {
+                    break body.to_owned();
+                }
+            };
+            conn.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                .await
+                .unwrap();
+            body_tx.send(body).unwrap();
+        });
+
+        (format!("http://{addr}/hook").parse().unwrap(), body_rx)
+    }
+
+    #[tokio::test]
+    async fn test_webhook() {
+        let (url, body_rx) = stub_server().await;
+        let token = CancellationToken::new();
+
+        let notifier = Notifier::with_config(
+            token.clone(),
+            new_dummy_msg_logger(),
+            NotifierConfig {
+                labels: vec!["person".to_owned().try_into().unwrap()],
+                webhooks: vec![WebhookConfig { url }],
+                ..Default::default()
+            },
+            "x".to_owned().try_into().unwrap(),
+            "y".to_owned().try_into().unwrap(),
+        );
+        notifier.set_active_recording(Some(
+            "2000-01-02_03-04-05_x".to_owned().try_into().unwrap(),
+        ));
+        notifier.notify(&event(1, vec![detection("car", 3.0)]));
+        notifier.notify(&event(2, vec![detection("person", 4.0)]));
+
+        let got: serde_json::Value = serde_json::from_str(&body_rx.await.unwrap()).unwrap();
+        let want = json!({
+            "monitorId": "x",
+            "monitorName": "y",
+            "time": 2,
+            "detections": [{
+                "label": "person",
+                "score": 4.0,
+                "region": {
+                    "rectangle": null,
+                    "polygon": null,
+                },
+            }],
+            "recordingId": "2000-01-02_03-04-05_x",
+            "thumbnail": "api/recording/thumbnail/2000-01-02_03-04-05_x",
+        });
+        assert_eq!(want, got);
+        token.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_command() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("out");
+
+        let config = CommandConfig {
+            command: "sh".to_owned().try_into().unwrap(),
+            args: vec![
+                "-c".to_owned(),
+                "cat > \"$0\"".to_owned(),
+                path.to_string_lossy().to_string(),
+            ],
+        };
+        run_command(&config, b"abc").await.unwrap();
+        assert_eq!("abc", std::fs::read_to_string(path).unwrap());
+
+        let config = CommandConfig {
+            command: "sh".to_owned().try_into().unwrap(),
+            args: vec!["-c".to_owned(), "exit 1".to_owned()],
+        };
+        assert!(matches!(
+            run_command(&config, b"").await,
+            Err(RunCommandError::ExitStatus(_))
+        ));
+    }
+}
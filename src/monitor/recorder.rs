@@ -1,15 +1,23 @@
 // SPDX-License-Identifier: GPL-2.0-or-later
 
-use crate::{source::Source, DynMonitorHooks};
+use crate::{
+    notifier::Notifier,
+    source::Source,
+    sprite::{generate_sprite, GenerateSpriteError, SpriteFrames},
+    thumbnail::{draw_detections, ThumbnailPicker},
+    DynMonitorHooks,
+};
 use common::{
     monitor::MonitorConfig,
     recording::{RecordingData, RecordingId},
     time::{DurationH264, UnixH264, UnixNano},
     DynHlsMuxer, DynLogger, DynMsgLogger, Event, LogEntry, LogLevel, MonitorId, MsgLogger,
-    SegmentFinalized, TrackParameters,
+    SegmentFinalized, TrackParameters, VideoCodec,
 };
 use futures::Future;
-use recdb::{NewRecordingError, OpenFileError, RecDb, RecordingHandle};
+use recdb::{
+    NewRecordingError, OpenFileError, RecDb, RecordingHandle, ReplaceFileError, SaveDataError,
+};
 use recording::{CreateVideoWriterError, MetaHeader, VideoWriter, WriteSampleError};
 use sentryshot_convert::{
     ConvertError, Frame, NewConverterError, PixelFormat, PixelFormatConverter,
@@ -19,7 +27,15 @@ use sentryshot_ffmpeg_h264::{
     SendPacketError,
 };
 use sentryshot_util::ImageCopyToBufferError;
-use std::{pin::Pin, sync::Arc, task::Poll};
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::Poll,
+};
 use thiserror::Error;
 use tokio::{
     io::{AsyncWriteExt, BufWriter},
@@ -40,6 +56,24 @@ pub fn new_recorder(
     rec_db: Arc<RecDb>,
 ) -> mpsc::Sender<Event> {
     let (send_event_tx, mut send_event_rx) = mpsc::channel::<Event>(1);
+    let notifier = Notifier::new(token.clone(), logger.clone(), &config).map(Arc::new);
+
+    let pre_roll_duration = DurationH264::from(config.pre_roll());
+    let pre_roll = if config.always_record() || *pre_roll_duration <= 0 {
+        None
+    } else {
+        let pre_roll = Arc::new(PreRollBuffer::new(pre_roll_duration));
+        let pre_roll2 = pre_roll.clone();
+        let token = token.clone();
+        let shutdown_complete = shutdown_complete.clone();
+        let source_main = source_main.clone();
+        tokio::spawn(async move {
+            let _shutdown_complete = shutdown_complete;
+            pre_roll2.run(token, &source_main).await;
+        });
+        Some(pre_roll)
+    };
+
     let c = RecordingContext {
         hooks,
         logger: Arc::new(RecorderMsgLogger::new(logger, monitor_id)),
@@ -48,6 +82,9 @@ pub fn new_recorder(
         config,
         rec_db,
         event_cache: Arc::new(EventCache::new()),
+        notifier,
+        pre_roll,
+        codec_warned: Arc::new(AtomicBool::new(false)),
     };
 
     // Recorder actor.
@@ -80,11 +117,14 @@ pub fn new_recorder(
                             continue
                         };
                         //r.hooks.Event(r, &event)
+                        c.notify(&event);
 
                         let Some(end) = event.time.checked_add(event.rec_duration.into()) else {
                             continue
                         };
 
+                        c.on_event(&event).await;
+
                         // Update timer if the monitor isn't set to always record.
                         if let Some(timer_end) = session.timer_end {
                             if end.after(timer_end) {
@@ -121,6 +161,7 @@ pub fn new_recorder(
                             return
                         };
                         //r.hooks.Event(r, &event)
+                        c.notify(&event);
 
                         let Some(end) = event.time.checked_add(event.rec_duration.into()) else {
                             continue
@@ -129,6 +170,7 @@ pub fn new_recorder(
                             continue
                         }
 
+                        c.on_event(&event).await;
                         c.event_cache.push(event).await;
                         recording_session = Some(RecordingSession::new(
                             &token,
@@ -224,19 +266,28 @@ async fn run_recording_session(
     restart_sleep: std::time::Duration,
 ) {
     loop {
-        if let Err(e) = run_recording(session_token.clone(), c.clone()).await {
-            c.log(LogLevel::Error, &format!("recording crashed: {e}"));
+        let result = run_recording(session_token.clone(), c.clone()).await;
+        c.set_active_recording(None);
+        let saved = match result {
+            Ok(v) => v,
+            Err(e) => {
+                c.log(LogLevel::Error, &format!("recording crashed: {e}"));
 
-            tokio::select! {
-                () = session_token.cancelled() => return,
-                () = sleep(restart_sleep) => {}
+                tokio::select! {
+                    () = session_token.cancelled() => return,
+                    () = sleep(restart_sleep) => {}
+                }
+                c.log(LogLevel::Debug, "recovering after crash");
+                continue;
             }
-            c.log(LogLevel::Debug, "recovering after crash");
-            continue;
-        }
+        };
         c.log(LogLevel::Debug, "recording finished");
         // Recoding reached videoLength and exited normally. The timer
         // is still active, so continue the loop and start another one.
+        if saved && c.config.always_record() && !session_token.is_cancelled() {
+            // Continue from the previous segment to keep the timeline continuous.
+            continue;
+        }
         tokio::select! {
             () = session_token.cancelled() => return,
             () = sleep(restart_sleep) => {}
@@ -265,26 +316,69 @@ struct RecordingContext {
     config: MonitorConfig,
     rec_db: Arc<RecDb>,
     event_cache: Arc<EventCache>,
+    notifier: Option<Arc<Notifier>>,
+    pre_roll: Option<Arc<PreRollBuffer>>,
+
+    // Set once the unsupported codec warning has been logged.
+    codec_warned: Arc<AtomicBool>,
 }
 
 impl RecordingContext {
     fn log(&self, level: LogLevel, msg: &str) {
         self.logger.log(level, msg);
     }
+
+    // Notifications are sent for every event, even
+    // if it doesn't start or extend a recording.
+    fn notify(&self, event: &Event) {
+        if let Some(notifier) = &self.notifier {
+            notifier.notify(event);
+        }
+    }
+
+    async fn on_event(&self, event: &Event) {
+        self.hooks.on_event(&self.config, event);
+        if let Err(e) = self.rec_db.save_event(self.config.id(), event).await {
+            self.log(LogLevel::Error, &format!("save event: {e}"));
+        }
+    }
+
+    fn set_active_recording(&self, id: Option<RecordingId>) {
+        if let Some(notifier) = &self.notifier {
+            notifier.set_active_recording(id);
+        }
+    }
 }
 
+// Returns false if the source was cancelled before the recording started.
 async fn run_recording(
     token: CancellationToken,
     c: RecordingContext,
-) -> Result<(), RunRecordingError> {
-    let Some(muxer) = c.source_main.muxer().await else {
-        c.log(LogLevel::Debug, "source cancelled");
-        return Ok(());
+) -> Result<bool, RunRecordingError> {
+    let pre_roll = match &c.pre_roll {
+        Some(pre_roll) => pre_roll.segments(c.prev_seg.lock().await.as_deref()).await,
+        None => None,
+    };
+    let (muxer, mut pre_roll_segments) = match pre_roll {
+        Some(v) => v,
+        None => {
+            let Some(muxer) = c.source_main.muxer().await else {
+                c.log(LogLevel::Debug, "source cancelled");
+                return Ok(false);
+            };
+            (muxer, VecDeque::new())
+        }
     };
 
-    let Some(first_segment) = muxer.next_segment(c.prev_seg.lock().await.as_deref()).await else {
-        c.log(LogLevel::Debug, "muxer cancelled");
-        return Ok(());
+    let first_segment = match pre_roll_segments.pop_front() {
+        Some(v) => v,
+        None => {
+            let Some(v) = muxer.next_segment(c.prev_seg.lock().await.as_deref()).await else {
+                c.log(LogLevel::Debug, "muxer cancelled");
+                return Ok(false);
+            };
+            v
+        }
     };
 
     let start_time = first_segment.start_time();
@@ -294,6 +388,7 @@ async fn run_recording(
         .rec_db
         .new_recording(monitor_id.clone(), start_time)
         .await?;
+    c.hooks.on_recording_start(&c.config, recording.id());
 
     let video_length = DurationH264::from(c.config.video_length());
 
@@ -304,38 +399,98 @@ async fn run_recording(
 
     let params = muxer.params();
 
-    let result = generate_thumbnail(
-        c.hooks.clone(),
-        &c.logger,
-        c.config.clone(),
-        &recording,
-        &first_segment,
-        muxer.params().extra_data.clone(),
-    )
-    .await;
-    if let Err(e) = result {
+    // Thumbnails and sprites can only be decoded from H.264.
+    let decodable = params.video_codec == VideoCodec::H264;
+    if !decodable && !c.codec_warned.swap(true, Ordering::Relaxed) {
         c.log(
-            LogLevel::Error,
-            &format!("failed to generate thumbnail: {}", &e),
+            LogLevel::Warning,
+            &format!(
+                "decoding {} is not supported, recordings won't have thumbnails",
+                params.video_codec
+            ),
         );
     }
 
+    if decodable {
+        let result = generate_thumbnail(
+            c.hooks.clone(),
+            &c.logger,
+            c.config.clone(),
+            &recording,
+            &first_segment,
+            params,
+        )
+        .await;
+        if let Err(e) = result {
+            c.log(
+                LogLevel::Error,
+                &format!("failed to generate thumbnail: {}", &e),
+            );
+        }
+    }
+    c.set_active_recording(Some(recording.id().to_owned()));
+
+    let mut frames = RecordingFrames::new(c.event_cache.clone(), UnixNano::from(start_time));
     let (new_prev_seg, end_time) = generate_video(
         token,
         &recording,
         &muxer,
         first_segment,
+        pre_roll_segments,
         params,
         video_length,
+        &mut frames,
     )
     .await?;
     *c.prev_seg.lock().await = Some(new_prev_seg);
 
+    // The recording is being deleted, drop the handle without saving.
+    if recording.is_aborted() {
+        c.log(
+            LogLevel::Info,
+            &format!("recording aborted: {:?}", recording.id()),
+        );
+        return Ok(true);
+    }
+
     c.log(
         LogLevel::Debug,
         &format!("video generated: {:?}", recording.id()),
     );
 
+    if decodable {
+        let result = save_sprite(
+            c.hooks.clone(),
+            c.config.clone(),
+            &recording,
+            frames.sprite,
+            params,
+            UnixNano::from(end_time),
+        )
+        .await;
+        if let Err(e) = result {
+            c.log(
+                LogLevel::Error,
+                &format!("failed to generate sprite: {}", &e),
+            );
+        }
+
+        let result = save_best_thumbnail(
+            c.hooks.clone(),
+            c.config.clone(),
+            &recording,
+            frames.thumbnail,
+            params,
+        )
+        .await;
+        if let Err(e) = result {
+            c.log(
+                LogLevel::Error,
+                &format!("failed to replace thumbnail: {}", &e),
+            );
+        }
+    }
+
     save_recording(
         c.logger.clone(),
         recording.id(),
@@ -345,8 +500,9 @@ async fn run_recording(
         UnixNano::from(end_time),
     )
     .await?;
+    c.hooks.on_recording_stop(&c.config, recording.id());
 
-    Ok(())
+    Ok(true)
 }
 
 #[derive(Debug, Error)]
@@ -367,13 +523,17 @@ enum GenerateVideoError {
     SkippedSegment(u64, u64),
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn generate_video(
     token: CancellationToken,
     recording: &RecordingHandle,
     muxer: &DynHlsMuxer,
     first_segment: Arc<SegmentFinalized>,
+    // Buffered segments that follow the first segment.
+    mut pre_roll_segments: VecDeque<Arc<SegmentFinalized>>,
     params: &TrackParameters,
     max_duration: DurationH264,
+    frames: &mut RecordingFrames,
 ) -> Result<(Arc<SegmentFinalized>, UnixH264), GenerateVideoError> {
     use GenerateVideoError::*;
 
@@ -385,21 +545,24 @@ async fn generate_video(
         .ok_or(GenerateVideoError::Add)?;
 
     let mut meta = recording.new_file("meta").await?;
-    let mut meta = BufWriter::with_capacity(64 * 1024, &mut *meta);
+    let mut meta = BufWriter::with_capacity(64 * 1024, &mut meta);
 
     let mut mdat = recording.new_file("mdat").await?;
-    let mut mdat = BufWriter::with_capacity(64 * 1024, &mut *mdat);
+    let mut mdat = BufWriter::with_capacity(64 * 1024, &mut mdat);
 
     let header = MetaHeader {
         start_time,
         width: params.width,
         height: params.height,
+        video_codec: params.video_codec,
         extra_data: params.extra_data.clone(),
+        audio: params.audio.as_ref().map(|v| v.sample_entry.clone()),
     };
 
     let mut w = VideoWriter::new(&mut meta, &mut mdat, header).await?;
 
     w.write_parts(first_segment.parts()).await?;
+    frames.push_segment(&first_segment).await;
 
     let mut prev_seg = first_segment.clone();
     let mut end_time = first_segment
@@ -408,12 +571,22 @@ async fn generate_video(
         .ok_or(Add)?;
 
     loop {
-        if token.is_cancelled() {
+        if token.is_cancelled() || recording.is_aborted() {
             return Ok((prev_seg, end_time));
         }
 
-        let Some(seg) = muxer.next_segment(Some(&prev_seg)).await else {
-            return Ok((prev_seg, end_time));
+        let seg = match pre_roll_segments.pop_front() {
+            Some(v) => v,
+            None => {
+                let next_segment = tokio::select! {
+                    v = muxer.next_segment(Some(&prev_seg)) => v,
+                    () = recording.aborted() => return Ok((prev_seg, end_time)),
+                };
+                let Some(v) = next_segment else {
+                    return Ok((prev_seg, end_time));
+                };
+                v
+            }
         };
 
         if seg.id() != prev_seg.id() + 1 {
@@ -422,6 +595,7 @@ async fn generate_video(
 
         prev_seg = seg.clone();
         w.write_parts(seg.parts()).await?;
+        frames.push_segment(&seg).await;
         end_time = seg
             .start_time()
             .checked_add(seg.duration().into())
@@ -433,6 +607,40 @@ async fn generate_video(
     }
 }
 
+// Keyframes used to generate the sprite sheet and
+// the thumbnail once the video has been written.
+struct RecordingFrames {
+    event_cache: Arc<EventCache>,
+    start_time: UnixNano,
+    sprite: SpriteFrames,
+    thumbnail: ThumbnailPicker,
+}
+
+impl RecordingFrames {
+    fn new(event_cache: Arc<EventCache>, start_time: UnixNano) -> Self {
+        Self {
+            event_cache,
+            start_time,
+            sprite: SpriteFrames::new(start_time),
+            thumbnail: ThumbnailPicker::new(),
+        }
+    }
+
+    async fn push_segment(&mut self, seg: &SegmentFinalized) {
+        self.sprite.push_segment(seg);
+        self.thumbnail.push_segment(seg);
+
+        let Some(end_time) = seg.start_time().checked_add(seg.duration().into()) else {
+            return;
+        };
+        let events = self
+            .event_cache
+            .query(self.start_time, UnixNano::from(end_time))
+            .await;
+        self.thumbnail.push_events(&events);
+    }
+}
+
 #[derive(Debug, Error)]
 enum GenerateThumbnailError {
     #[error("no part")]
@@ -465,12 +673,14 @@ async fn generate_thumbnail(
     config: MonitorConfig,
     recording: &RecordingHandle,
     first_segment: &Arc<SegmentFinalized>,
-    extradata: Vec<u8>,
+    params: &TrackParameters,
 ) -> Result<(), GenerateThumbnailError> {
     use GenerateThumbnailError::*;
 
     logger.log(LogLevel::Debug, "generating thumbnail");
 
+    let extradata = params.extra_data.clone();
+
     let Some(first_part) = first_segment.parts().first() else {
         return Err(NoPart);
     };
@@ -483,7 +693,9 @@ async fn generate_thumbnail(
 
     let avcc = first_sample.avcc.clone();
     let jpeg_buf = tokio::task::spawn_blocking(move || {
-        avcc_to_jpeg(&hooks, &config, &avcc, PaddedBytes::new(extradata))
+        avcc_to_jpeg(&avcc, PaddedBytes::new(extradata), |frame| {
+            hooks.on_thumb_save(&config, frame)
+        })
     })
     .await
     .expect("join")?;
@@ -501,7 +713,99 @@ async fn generate_thumbnail(
 }
 
 #[derive(Debug, Error)]
-enum AvccToJpegError {
+enum SaveSpriteError {
+    #[error("generate sprite: {0}")]
+    GenerateSprite(#[from] GenerateSpriteError),
+
+    #[error("open file: {0}")]
+    OpenFile(#[from] OpenFileError),
+
+    #[error("write file: {0}")]
+    WriteFile(std::io::Error),
+
+    #[error("flush file: {0}")]
+    FlushFile(std::io::Error),
+}
+
+// Saves a sprite sheet of frames spread over the recording
+// and a WebVTT file that maps time ranges to the tiles.
+async fn save_sprite(
+    hooks: DynMonitorHooks,
+    config: MonitorConfig,
+    recording: &RecordingHandle,
+    sprite: SpriteFrames,
+    params: &TrackParameters,
+    end_time: UnixNano,
+) -> Result<(), SaveSpriteError> {
+    use SaveSpriteError::*;
+
+    if sprite.is_empty() {
+        return Ok(());
+    }
+    let extradata = params.extra_data.clone();
+
+    // The VTT file is served next to the sprite endpoint.
+    let image_url = format!("../sprite/{}", recording.id().as_str());
+    let (jpeg, vtt) = tokio::task::spawn_blocking(move || {
+        generate_sprite(&sprite, &extradata, end_time, &image_url, |frame| {
+            hooks.on_thumb_save(&config, frame)
+        })
+    })
+    .await
+    .expect("join")?;
+
+    for (ext, buf) in [("sprite", jpeg.as_slice()), ("vtt", vtt.as_bytes())] {
+        let mut file = recording.new_file(ext).await?;
+        file.write_all(buf).await.map_err(WriteFile)?;
+        file.flush().await.map_err(FlushFile)?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+enum SaveBestThumbnailError {
+    #[error("decode frame: {0}")]
+    AvccToRgb(#[from] AvccToJpegError),
+
+    #[error("encode jpeg: {0}")]
+    EncodeJpeg(#[from] jpeg_encoder::EncodingError),
+
+    #[error("replace file: {0}")]
+    ReplaceFile(#[from] ReplaceFileError),
+}
+
+// Replaces the thumbnail with the keyframe closest to the highest scoring
+// detection. The first frame is kept if the recording has no detections.
+async fn save_best_thumbnail(
+    hooks: DynMonitorHooks,
+    config: MonitorConfig,
+    recording: &RecordingHandle,
+    thumbnail: ThumbnailPicker,
+    params: &TrackParameters,
+) -> Result<(), SaveBestThumbnailError> {
+    let Some((avcc, detections)) = thumbnail.into_best() else {
+        return Ok(());
+    };
+    let extradata = params.extra_data.clone();
+
+    let jpeg_buf = tokio::task::spawn_blocking(move || {
+        let (mut raw, width, height) = avcc_to_rgb(&avcc, PaddedBytes::new(extradata), |frame| {
+            hooks.on_thumb_save(&config, frame)
+        })?;
+        if config.thumbnail_detections() {
+            draw_detections(&mut raw, width, height, &detections);
+        }
+        Ok::<_, SaveBestThumbnailError>(encode_jpeg(&raw, width, height)?)
+    })
+    .await
+    .expect("join")?;
+
+    recording.replace_file("jpeg", &jpeg_buf).await?;
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum AvccToJpegError {
     #[error("new h264 decoder: {0}")]
     NewH264Decoder(#[from] H264BuilderError),
 
@@ -527,12 +831,30 @@ enum AvccToJpegError {
     EncodeJpeg(#[from] jpeg_encoder::EncodingError),
 }
 
+// Decodes a H.264 IDR sample and encodes it as a jpeg thumbnail.
+pub fn thumbnail_from_avcc(avcc: Vec<u8>, extradata: Vec<u8>) -> Result<Vec<u8>, AvccToJpegError> {
+    avcc_to_jpeg(
+        &PaddedBytes::new(avcc),
+        PaddedBytes::new(extradata),
+        |frame| frame,
+    )
+}
+
 fn avcc_to_jpeg(
-    hooks: &DynMonitorHooks,
-    config: &MonitorConfig,
     avcc: &PaddedBytes,
     extradata: PaddedBytes,
+    on_frame: impl FnOnce(Frame) -> Frame,
 ) -> Result<Vec<u8>, AvccToJpegError> {
+    let (raw_rgb_frame, width, height) = avcc_to_rgb(avcc, extradata, on_frame)?;
+    Ok(encode_jpeg(&raw_rgb_frame, width, height)?)
+}
+
+// Decodes a H.264 IDR sample into a packed RGB24 buffer.
+pub(crate) fn avcc_to_rgb(
+    avcc: &PaddedBytes,
+    extradata: PaddedBytes,
+    on_frame: impl FnOnce(Frame) -> Frame,
+) -> Result<(Vec<u8>, u16, u16), AvccToJpegError> {
     let mut decoder = H264DecoderBuilder::new().avcc(extradata)?;
 
     decoder.send_packet(&Packet::new(avcc))?;
@@ -542,7 +864,7 @@ fn avcc_to_jpeg(
     let mut frame = Frame::new();
     h264_decoder.receive_frame(&mut frame)?;
 
-    let frame = hooks.on_thumb_save(config, frame);
+    let frame = on_frame(frame);
 
     let mut converter = PixelFormatConverter::new(
         frame.width(),
@@ -559,32 +881,28 @@ fn avcc_to_jpeg(
     let mut raw_rgb_frame = Vec::new();
     rgb_frame.copy_to_buffer(&mut raw_rgb_frame, 1)?;
 
-    let mut jpeg_buf = Vec::new();
-    let jpeg_encoder = jpeg_encoder::Encoder::new(&mut jpeg_buf, 75);
-
-    jpeg_encoder.encode(
-        &raw_rgb_frame,
+    Ok((
+        raw_rgb_frame,
         rgb_frame.width().get(),
         rgb_frame.height().get(),
-        jpeg_encoder::ColorType::Rgb,
-    )?;
+    ))
+}
 
+pub(crate) fn encode_jpeg(
+    raw_rgb_frame: &[u8],
+    width: u16,
+    height: u16,
+) -> Result<Vec<u8>, jpeg_encoder::EncodingError> {
+    let mut jpeg_buf = Vec::new();
+    let jpeg_encoder = jpeg_encoder::Encoder::new(&mut jpeg_buf, 75);
+    jpeg_encoder.encode(raw_rgb_frame, width, height, jpeg_encoder::ColorType::Rgb)?;
     Ok(jpeg_buf)
 }
 
 #[derive(Debug, Error)]
 enum SaveRecordingError {
-    #[error("serialize data: {0}")]
-    Serialize(#[from] serde_json::Error),
-
-    #[error("open file: {0}")]
-    OpenFile(#[from] OpenFileError),
-
-    #[error("write data file: {0}")]
-    Write(std::io::Error),
-
-    #[error("flush data file: {0}")]
-    Flush(std::io::Error),
+    #[error("save data: {0}")]
+    SaveData(#[from] SaveDataError),
 }
 
 async fn save_recording(
@@ -595,7 +913,6 @@ async fn save_recording(
     start_time: UnixNano,
     end_time: UnixNano,
 ) -> Result<(), SaveRecordingError> {
-    use SaveRecordingError::*;
     logger.log(LogLevel::Debug, &format!("saving recording: {rec_id:?}"));
 
     let events = event_cache.query_and_prune(start_time, end_time).await;
@@ -606,11 +923,22 @@ async fn save_recording(
         events,
     };
 
-    let json = serde_json::to_vec_pretty(&data)?;
+    recording.save_data(&data).await?;
 
-    let mut data_file = recording.new_file("json").await?;
-    data_file.write_all(&json).await.map_err(Write)?;
-    data_file.flush().await.map_err(Flush)?;
+    // The recording is still usable without the chain entry.
+    if let Err(e) = recording.append_to_hash_chain().await {
+        logger.log(
+            LogLevel::Error,
+            &format!("append to hash chain: {rec_id:?} {e}"),
+        );
+    }
+
+    if let Err(e) = recording.replicate().await {
+        logger.log(
+            LogLevel::Error,
+            &format!("queue replication: {rec_id:?} {e}"),
+        );
+    }
 
     //go r.hooks.RecSaved(r, filePath, data)
 
@@ -619,6 +947,142 @@ async fn save_recording(
     Ok(())
 }
 
+// Follows the segments of the main stream and keeps the most recent
+// ones in memory so triggered recordings can start before the event.
+struct PreRollBuffer {
+    duration: DurationH264,
+    state: Mutex<Option<PreRollState>>,
+}
+
+struct PreRollState {
+    muxer: DynHlsMuxer,
+    segments: PreRollSegments,
+}
+
+impl PreRollBuffer {
+    fn new(duration: DurationH264) -> Self {
+        Self {
+            duration,
+            state: Mutex::new(None),
+        }
+    }
+
+    async fn run(&self, token: CancellationToken, source: &Source) {
+        loop {
+            let muxer = tokio::select! {
+                () = token.cancelled() => return,
+                v = source.muxer() => v,
+            };
+            let Some(muxer) = muxer else {
+                return;
+            };
+            *self.state.lock().await = Some(PreRollState {
+                muxer: muxer.clone(),
+                segments: PreRollSegments::new(self.duration),
+            });
+
+            let mut prev_seg: Option<Arc<SegmentFinalized>> = None;
+            loop {
+                let seg = tokio::select! {
+                    () = token.cancelled() => return,
+                    v = muxer.next_segment(prev_seg.as_deref()) => v,
+                };
+                // Muxer was cancelled, the source is restarting.
+                let Some(seg) = seg else {
+                    break;
+                };
+                if let Some(state) = &mut *self.state.lock().await {
+                    state.segments.push(seg.clone());
+                }
+                prev_seg = Some(seg);
+            }
+
+            *self.state.lock().await = None;
+            tokio::select! {
+                () = token.cancelled() => return,
+                () = sleep(std::time::Duration::from_secs(1)) => {}
+            }
+        }
+    }
+
+    // Returns the muxer and the buffered segments that cover the pre-roll.
+    async fn segments(
+        &self,
+        prev_seg: Option<&SegmentFinalized>,
+    ) -> Option<(DynHlsMuxer, VecDeque<Arc<SegmentFinalized>>)> {
+        let state = self.state.lock().await;
+        let state = state.as_ref()?;
+        let segments = state.segments.since(UnixH264::now(), prev_seg);
+        if segments.is_empty() {
+            return None;
+        }
+        Some((state.muxer.clone(), segments))
+    }
+}
+
+struct PreRollSegments {
+    duration: DurationH264,
+    segments: VecDeque<Arc<SegmentFinalized>>,
+}
+
+impl PreRollSegments {
+    fn new(duration: DurationH264) -> Self {
+        Self {
+            duration,
+            segments: VecDeque::new(),
+        }
+    }
+
+    fn push(&mut self, seg: Arc<SegmentFinalized>) {
+        // Segments must be continuous.
+        if let Some(last) = self.segments.back() {
+            if seg.muxer_id() != last.muxer_id() || seg.id() != last.id() + 1 {
+                self.segments.clear();
+            }
+        }
+
+        let Some(limit) = seg
+            .start_time()
+            .checked_add(seg.duration().into())
+            .and_then(|end| end.checked_sub(self.duration.into()))
+        else {
+            return;
+        };
+        self.segments.push_back(seg);
+
+        // Drop the oldest segment if the next one is enough to cover the duration.
+        while self.segments.len() > 1 && !self.segments[1].start_time().after(limit) {
+            self.segments.pop_front();
+        }
+    }
+
+    // Returns the segments newer than `prev_seg`, starting
+    // with the segment that covers `now - duration`.
+    fn since(
+        &self,
+        now: UnixH264,
+        prev_seg: Option<&SegmentFinalized>,
+    ) -> VecDeque<Arc<SegmentFinalized>> {
+        let Some(since) = now.checked_sub(self.duration.into()) else {
+            return VecDeque::new();
+        };
+        let first = self
+            .segments
+            .iter()
+            .rposition(|seg| !seg.start_time().after(since))
+            .unwrap_or(0);
+        self.segments
+            .iter()
+            .skip(first)
+            .filter(|seg| match prev_seg {
+                Some(prev) if prev.muxer_id() == seg.muxer_id() => seg.id() > prev.id(),
+                _ => true,
+            })
+            .cloned()
+            .collect()
+    }
+}
+
 struct EventCache(Mutex<Vec<Event>>);
 
 impl EventCache {
@@ -630,6 +1094,17 @@ impl EventCache {
         self.0.lock().await.push(event);
     }
 
+    // Returns the events between start and end without removing them.
+    async fn query(&self, start: UnixNano, end: UnixNano) -> Vec<Event> {
+        self.0
+            .lock()
+            .await
+            .iter()
+            .filter(|event| !event.time.before(start) && event.time.before(end))
+            .cloned()
+            .collect()
+    }
+
     async fn query_and_prune(&self, start: UnixNano, end: UnixNano) -> Vec<Event> {
         let mut new_events: Vec<Event> = Vec::new();
         let mut return_events: Vec<Event> = Vec::new();
@@ -984,9 +1459,61 @@ mod tests {
         })
     }*/
 
+    fn new_test_segment(id: u64, muxer_id: u16, start_time: i64) -> Arc<SegmentFinalized> {
+        Arc::new(SegmentFinalized::new(
+            id,
+            muxer_id,
+            UnixH264::new(start_time),
+            String::new(),
+            Vec::new(),
+            DurationH264::new(10),
+        ))
+    }
+
+    fn segment_ids(segments: &VecDeque<Arc<SegmentFinalized>>) -> Vec<u64> {
+        segments.iter().map(|v| v.id()).collect()
+    }
+
+    #[test]
+    fn test_pre_roll_segments() {
+        let mut segments = PreRollSegments::new(DurationH264::new(25));
+        for id in 0..6 {
+            segments.push(new_test_segment(id, 1, i64::try_from(id).unwrap() * 10));
+        }
+        // Segment 3 starts 30 before the end.
+        assert_eq!(vec![3, 4, 5], segment_ids(&segments.segments));
+
+        let now = UnixH264::new(60);
+        assert_eq!(vec![3, 4, 5], segment_ids(&segments.since(now, None)));
+
+        let prev = new_test_segment(3, 1, 30);
+        assert_eq!(vec![4, 5], segment_ids(&segments.since(now, Some(&prev))));
+
+        // Previous segment from another muxer.
+        let prev = new_test_segment(4, 2, 40);
+        assert_eq!(
+            vec![3, 4, 5],
+            segment_ids(&segments.since(now, Some(&prev)))
+        );
+
+        let prev = new_test_segment(5, 1, 50);
+        assert!(segments.since(now, Some(&prev)).is_empty());
+    }
+
+    #[test]
+    fn test_pre_roll_segments_discontinuity() {
+        let mut segments = PreRollSegments::new(DurationH264::new(100));
+        segments.push(new_test_segment(0, 1, 0));
+        segments.push(new_test_segment(1, 1, 10));
+        segments.push(new_test_segment(0, 2, 20));
+        segments.push(new_test_segment(1, 2, 30));
+        segments.push(new_test_segment(3, 2, 40));
+        assert_eq!(vec![3], segment_ids(&segments.segments));
+    }
+
     fn new_test_recdb(recordings_dir: &Path) -> RecDb {
         let disk = Disk::new(recordings_dir.to_path_buf(), ByteSize(0));
-        RecDb::new(DummyLogger::new(), recordings_dir.to_path_buf(), disk)
+        RecDb::new(DummyLogger::new(), recordings_dir.to_path_buf(), disk, None)
     }
 
     #[tokio::test]
@@ -1085,4 +1612,23 @@ mod tests {
 }";
         assert_eq!(want, got);
     }
+
+    #[tokio::test]
+    async fn test_event_cache_query() {
+        let event = |time| Event {
+            time: UnixNano::new(time),
+            duration: Duration::new(0),
+            rec_duration: Duration::new(0),
+            detections: Vec::new(),
+        };
+        let event_cache = EventCache(Mutex::new(vec![event(1), event(2), event(3)]));
+
+        let times = |events: Vec<Event>| -> Vec<i64> { events.iter().map(|v| *v.time).collect() };
+        let got = event_cache.query(UnixNano::new(2), UnixNano::new(3)).await;
+        assert_eq!(vec![2], times(got));
+
+        // Query doesn't prune.
+        let got = event_cache.query(UnixNano::new(0), UnixNano::new(4)).await;
+        assert_eq!(vec![1, 2, 3], times(got));
+    }
 }
@@ -1,21 +1,22 @@
 // SPDX-License-Identifier: GPL-2.0-or-later
 
-use crate::log_monitor;
+use crate::{log_monitor, source_file::SourceFile, source_http::SourceHttp};
+use async_trait::async_trait;
 use common::{
-    monitor::{Protocol, RtspUrl, SourceRtspConfig},
+    monitor::{Protocol, RtspUrl, SourceConfig, SourceRtspConfig},
     recording::{FrameRateLimiter, FrameRateLimiterError},
-    time::{DtsOffset, UnixH264},
-    DynHlsMuxer, DynLogger, DynMsgLogger, H264Data, LogEntry, LogLevel, MonitorId, MsgLogger,
-    StreamType,
+    time::{DtsOffset, DurationH264, UnixH264, H264_TIMESCALE},
+    AudioData, AudioParameters, DynHlsMuxer, DynLogger, DynMsgLogger, H264Data, LogEntry, LogLevel,
+    MonitorId, MsgLogger, StreamType, TrackParameters, VideoCodec,
 };
 use futures::StreamExt;
 use hls::{
-    track_params_from_video_params, CreateSegmenterError, H264Writer, HlsServer, ParseParamsError,
-    SegmenterWriteH264Error,
+    audio_track_params, track_params_from_video_params, CreateSegmenterError, H264Writer,
+    HlsServer, ParseParamsError, SegmenterWriteAudioError, SegmenterWriteH264Error,
 };
 use retina::{
     client::Stream,
-    codec::{ParametersRef, VideoFrame},
+    codec::{AudioFrame, ParametersRef, VideoFrame},
 };
 use sentryshot_convert::Frame;
 use sentryshot_ffmpeg_h264::{
@@ -98,7 +99,13 @@ impl Source {
 
         // We could grab the extradata strait from the source instead.
         let muxer = self.muxer().await?;
-        let extradata = muxer.params().extra_data.clone();
+        let params = muxer.params();
+        if params.video_codec != VideoCodec::H264 {
+            return Some(Err(SubscribeDecodedError::UnsupportedCodec(
+                params.video_codec,
+            )));
+        }
+        let extradata = params.extra_data.clone();
 
         let h264_decoder = match H264DecoderBuilder::new().avcc(PaddedBytes::new(extradata)) {
             Ok(v) => v,
@@ -112,9 +119,12 @@ impl Source {
 pub enum SubscribeDecodedError {
     #[error("new h264 decoder: {0}")]
     NewH264Decoder(#[from] H264BuilderError),
+
+    #[error("decoding {0} is not supported")]
+    UnsupportedCodec(VideoCodec),
 }
 
-struct SourceLogger {
+pub(crate) struct SourceLogger {
     logger: DynLogger,
 
     monitor_id: MonitorId,
@@ -123,7 +133,7 @@ struct SourceLogger {
 }
 
 impl SourceLogger {
-    fn new(
+    pub(crate) fn new(
         logger: DynLogger,
         monitor_id: MonitorId,
         source_name: String,
@@ -154,6 +164,266 @@ impl MsgLogger for SourceLogger {
     }
 }
 
+pub(crate) type StartedTx = mpsc::Sender<(DynHlsMuxer, broadcast::Sender<H264Data>)>;
+
+// Source type that produces a H264 feed and HLS muxer.
+#[async_trait]
+pub(crate) trait SourceRunner: Send + Sync + 'static {
+    type Error: std::fmt::Display + Send;
+
+    fn log(&self, level: LogLevel, msg: &str);
+
+    // Runs until cancelled or an error occurs, restarted with a backoff on errors.
+    // Must send the muxer and feed on `started_tx` once the first sample is received.
+    async fn run(&self, token: CancellationToken, started_tx: StartedTx) -> Result<(), Self::Error>;
+}
+
+// Source registry, new source types are added here.
+// Returns None if the source doesn't have a stream of this type.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn new_source(
+    token: CancellationToken,
+    shutdown_complete: mpsc::Sender<()>,
+    logger: DynLogger,
+    hls_server: Arc<HlsServer>,
+    monitor_id: MonitorId,
+    config: &SourceConfig,
+    stream_type: StreamType,
+) -> Option<Source> {
+    match config {
+        SourceConfig::Rtsp(config) => SourceRtsp::new(
+            token,
+            shutdown_complete,
+            logger,
+            hls_server,
+            monitor_id,
+            config.to_owned(),
+            stream_type,
+        ),
+        SourceConfig::File(config) => SourceFile::new(
+            token,
+            shutdown_complete,
+            logger,
+            hls_server,
+            monitor_id,
+            config.to_owned(),
+            stream_type,
+        ),
+        SourceConfig::Http(config) => SourceHttp::new(
+            token,
+            shutdown_complete,
+            logger,
+            hls_server,
+            monitor_id,
+            config.to_owned(),
+            stream_type,
+        ),
+    }
+}
+
+// Runs the source in a restart loop and serves muxer and feed requests.
+pub(crate) fn spawn_source<T: SourceRunner>(
+    token: CancellationToken,
+    shutdown_complete: mpsc::Sender<()>,
+    source: T,
+    stream_type: StreamType,
+) -> Source {
+    let (started_tx, mut started_rx) = mpsc::channel(1);
+
+    let shutdown_complete2 = shutdown_complete.clone();
+    let token2 = token.clone();
+    tokio::spawn(async move {
+        let _shutdown_complete = shutdown_complete2;
+        let mut monitor_crash_count = 0u32;
+        loop {
+            if token2.is_cancelled() {
+                source.log(LogLevel::Info, "stopped");
+                return;
+            }
+
+            let run_started_at = Instant::now();
+            match source.run(token2.child_token(), started_tx.clone()).await {
+                Ok(()) => source.log(LogLevel::Debug, "cancelled"),
+                Err(e) => {
+                    monitor_crash_count += 1;
+                    source.log(LogLevel::Error, &format!("crashed {monitor_crash_count}-th time: {e}"))
+                },
+            };
+
+            // if monitor ran for long enough, reset backoff
+            if run_started_at.elapsed().as_secs() > 120 {
+                monitor_crash_count = 0;
+            }
+
+            let backoff = std::cmp::max(10, 2u64.pow(std::cmp::min(monitor_crash_count, 9)));
+            tokio::select! {
+                () = token2.cancelled() => {}
+                () = tokio::time::sleep(tokio::time::Duration::from_secs(backoff)) => {}
+            }
+        }
+    });
+
+    let (get_muxer_tx, mut get_muxer_rx) = mpsc::channel::<oneshot::Sender<DynHlsMuxer>>(1);
+    let (subscribe_tx, mut subscribe_rx) = mpsc::channel::<oneshot::Sender<Feed>>(1);
+
+    tokio::spawn(async move {
+        let _shutdown_complete = shutdown_complete;
+        let mut muxer = None;
+        let mut feed_tx = None;
+        let mut get_muxer_requests: Vec<oneshot::Sender<_>> = Vec::new();
+        let mut subscribe_requests: Vec<oneshot::Sender<_>> = Vec::new();
+        loop {
+            tokio::select! {
+                () = token.cancelled() => return,
+               res = started_rx.recv() => {
+                    let Some((m, f)) = res else {
+                        return
+                    };
+                    while let Some(res) = get_muxer_requests.pop() {
+                        _ = res.send(m.clone());
+                    }
+                    while let Some(res) = subscribe_requests.pop() {
+                        _ = res.send(f.subscribe());
+                    }
+                    muxer = Some(m);
+                    feed_tx = Some(f);
+                }
+
+                res = get_muxer_rx.recv() => {
+                    let Some(res) = res else {
+                        return
+                    };
+                    if let Some(muxer) = &muxer {
+                        _ = res.send(muxer.clone());
+                    } else {
+                        get_muxer_requests.push(res);
+                    }
+                }
+                res = subscribe_rx.recv() => {
+                    let Some(res) = res else {
+                        return
+                    };
+                    if let Some(feed_tx) = &feed_tx {
+                        _ = res.send(feed_tx.subscribe());
+                    } else {
+                        subscribe_requests.push(res);
+                    }
+                }
+            }
+        }
+    });
+
+    Source::new(stream_type, get_muxer_tx, subscribe_tx)
+}
+
+pub(crate) fn hls_name(monitor_id: &MonitorId, stream_type: StreamType) -> String {
+    if stream_type.is_main() {
+        monitor_id.to_string()
+    } else {
+        monitor_id.to_string() + "_sub"
+    }
+}
+
+// Writes samples to the HLS muxer and the feed,
+// the muxer is created on the first random access sample.
+pub(crate) struct SampleWriter {
+    token: CancellationToken,
+    hls_server: Arc<HlsServer>,
+    hls_name: String,
+    params: TrackParameters,
+    started_tx: StartedTx,
+    feed_tx: broadcast::Sender<H264Data>,
+    hls_writer: Option<H264Writer>,
+}
+
+impl SampleWriter {
+    pub(crate) fn new(
+        token: CancellationToken,
+        hls_server: Arc<HlsServer>,
+        hls_name: String,
+        params: TrackParameters,
+        started_tx: StartedTx,
+    ) -> Self {
+        // Buffer 10 frame to reduce dropped frames.
+        let (feed_tx, _) = broadcast::channel(10);
+        Self {
+            token,
+            hls_server,
+            hls_name,
+            params,
+            started_tx,
+            feed_tx,
+            hls_writer: None,
+        }
+    }
+
+    // Returns false if cancelled.
+    pub(crate) async fn write(&mut self, data: H264Data) -> Result<bool, WriteSampleError> {
+        if let Some(hls_writer) = &mut self.hls_writer {
+            hls_writer.write_h264(data.clone()).await?;
+            _ = self.feed_tx.send(data);
+            return Ok(true);
+        }
+        if !data.random_access_present {
+            // Wait for IDR.
+            return Ok(true);
+        }
+
+        let result = self
+            .hls_server
+            .new_muxer(
+                self.token.clone(),
+                self.hls_name.clone(),
+                self.params.clone(),
+                data,
+            )
+            .await?;
+        let Some((muxer, hls_writer)) = result else {
+            // Cancelled.
+            return Ok(false);
+        };
+        self.hls_writer = Some(hls_writer);
+        // Notify successful start.
+        _ = self.started_tx.send((muxer, self.feed_tx.clone())).await;
+        Ok(true)
+    }
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum WriteSampleError {
+    #[error("write h264: {0}")]
+    WriteH264(#[from] SegmenterWriteH264Error),
+
+    #[error("create segmenter: {0}")]
+    CreateSegmenter(#[from] CreateSegmenterError),
+}
+
+// Sleeps until each sample is due, for sources that can read faster than real time.
+pub(crate) struct Pacer {
+    start: Option<(tokio::time::Instant, UnixH264)>,
+}
+
+impl Pacer {
+    pub(crate) fn new() -> Self {
+        Self { start: None }
+    }
+
+    pub(crate) async fn wait(&mut self, dts: UnixH264) {
+        let now = tokio::time::Instant::now();
+        let (start, first_dts) = *self.start.get_or_insert((now, dts));
+        let offset = DurationH264::new((*dts).saturating_sub(*first_dts));
+        let deadline =
+            start + std::time::Duration::from_nanos(u64::try_from(offset.as_nanos()).unwrap_or(0));
+
+        // Restart the clock if the source has stalled.
+        if deadline + std::time::Duration::from_secs(5) < now {
+            self.start = Some((now, dts));
+            return;
+        }
+        tokio::time::sleep_until(deadline).await;
+    }
+}
+
 #[allow(clippy::module_name_repetitions)]
 pub struct SourceRtsp {
     msg_logger: DynMsgLogger,
@@ -195,93 +465,56 @@ impl SourceRtsp {
             stream_type,
         };
 
-        let (started_tx, mut started_rx) = mpsc::channel(1);
-
-        let shutdown_complete2 = shutdown_complete.clone();
-        let token2 = token.clone();
-        tokio::spawn(async move {
-            let _shutdown_complete = shutdown_complete2;
-            let mut monitor_crash_count = 0u32;
-            loop {
-                if token2.is_cancelled() {
-                    source.log(LogLevel::Info, "stopped");
-                    return;
-                }
-
-                let run_started_at = Instant::now();
-                match source.run(token2.child_token(), started_tx.clone()).await {
-                    Ok(()) => source.log(LogLevel::Debug, "cancelled"),
-                    Err(e) => {
-                        monitor_crash_count += 1;
-                        source.log(LogLevel::Error, &format!("crashed {monitor_crash_count}-th time: {e}"))
-                    },
-                };
-
-                // if monitor ran for long enough, reset backoff
-                if run_started_at.elapsed().as_secs() > 120 {
-                    monitor_crash_count = 0;
-                }
+        Some(spawn_source(token, shutdown_complete, source, stream_type))
+    }
 
-                let backoff = std::cmp::max(10, 2u64.pow(std::cmp::min(monitor_crash_count, 9)));
-                tokio::select! {
-                    () = token2.cancelled() => {}
-                    () = tokio::time::sleep(tokio::time::Duration::from_secs(backoff)) => {}
-                }
+    // Returns the first audio stream that can be stored in mp4.
+    fn find_audio_stream(&self, streams: &[Stream]) -> Option<(usize, AudioParameters)> {
+        for (i, s) in streams.iter().enumerate() {
+            if s.media() != "audio" {
+                continue;
             }
-        });
-
-        let (get_muxer_tx, mut get_muxer_rx) = mpsc::channel::<oneshot::Sender<DynHlsMuxer>>(1);
-        let (subscribe_tx, mut subscribe_rx) = mpsc::channel::<oneshot::Sender<Feed>>(1);
-
-        tokio::spawn(async move {
-            let _shutdown_complete = shutdown_complete;
-            let mut muxer = None;
-            let mut feed_tx = None;
-            let mut get_muxer_requests: Vec<oneshot::Sender<_>> = Vec::new();
-            let mut subscribe_requests: Vec<oneshot::Sender<_>> = Vec::new();
-            loop {
-                tokio::select! {
-                    () = token.cancelled() => return,
-                   res = started_rx.recv() => {
-                        let Some((m, f)) = res else {
-                            return
-                        };
-                        while let Some(res) = get_muxer_requests.pop() {
-                            _ = res.send(m.clone());
-                        }
-                        while let Some(res) = subscribe_requests.pop() {
-                            _ = res.send(f.subscribe());
-                        }
-                        muxer = Some(m);
-                        feed_tx = Some(f);
-                    }
-
-                    res = get_muxer_rx.recv() => {
-                        let Some(res) = res else {
-                            return
-                        };
-                        if let Some(muxer) = &muxer {
-                            _ = res.send(muxer.clone());
-                        } else {
-                            get_muxer_requests.push(res);
-                        }
-                    }
-                    res = subscribe_rx.recv() => {
-                        let Some(res) = res else {
-                            return
-                        };
-                        if let Some(feed_tx) = &feed_tx {
-                            _ = res.send(feed_tx.subscribe());
-                        } else {
-                            subscribe_requests.push(res);
-                        }
-                    }
-                }
+            let Some(ParametersRef::Audio(params)) = s.parameters() else {
+                continue;
+            };
+            if let Some(params) = audio_track_params(params) {
+                self.log(
+                    LogLevel::Debug,
+                    &format!("using {} audio stream", s.encoding_name()),
+                );
+                return Some((i, params));
             }
-        });
+            self.log(
+                LogLevel::Warning,
+                &format!(
+                    "ignoring {} audio stream because it's unsupported",
+                    s.encoding_name()
+                ),
+            );
+        }
+        self.log(LogLevel::Warning, "no supported audio stream found");
+        None
+    }
+
+    fn hls_name(&self) -> String {
+        hls_name(&self.monitor_id, self.stream_type)
+    }
 
-        Some(Source::new(stream_type, get_muxer_tx, subscribe_tx))
+    fn stream_url(&self) -> &RtspUrl {
+        if self.stream_type.is_main() {
+            &self.config.main_stream
+        } else {
+            self.config
+                .sub_stream
+                .as_ref()
+                .expect("sub_stream to be `Some`")
+        }
     }
+}
+
+#[async_trait]
+impl SourceRunner for SourceRtsp {
+    type Error = SourceRtspRunError;
 
     fn log(&self, level: LogLevel, msg: &str) {
         self.msg_logger.log(level, msg);
@@ -291,7 +524,7 @@ impl SourceRtsp {
     async fn run(
         &self,
         token: CancellationToken,
-        started_tx: mpsc::Sender<(DynHlsMuxer, broadcast::Sender<H264Data>)>,
+        started_tx: StartedTx,
     ) -> Result<(), SourceRtspRunError> {
         use SourceRtspRunError::*;
 
@@ -310,12 +543,12 @@ impl SourceRtsp {
         .await
         .map_err(Describe)?;
 
-        let video_stream_i = {
-            let s = session.streams().iter().position(|s| {
+        let (video_stream_i, video_codec) = {
+            let s = session.streams().iter().enumerate().find_map(|(i, s)| {
                 if s.media() == "video" {
-                    if s.encoding_name() == "h264" {
-                        self.log(LogLevel::Debug, "using h264 video stream");
-                        return true;
+                    if let Some(codec) = VideoCodec::from_encoding_name(s.encoding_name()) {
+                        self.log(LogLevel::Debug, &format!("using {codec} video stream"));
+                        return Some((i, codec));
                     }
 
                     self.log(
@@ -326,7 +559,7 @@ impl SourceRtsp {
                         ),
                     );
                 }
-                false
+                None
             });
             let Some(s) = s else {
                 return Err(NoVideoStreamFound(format_streams(session.streams())));
@@ -346,11 +579,27 @@ impl SourceRtsp {
         session
             .setup(
                 video_stream_i,
-                retina::client::SetupOptions::default().transport(transport),
+                retina::client::SetupOptions::default().transport(transport.clone()),
             )
             .await
             .map_err(SourceRtspRunError::Setup)?;
 
+        let audio = if self.config.audio && self.stream_type.is_main() {
+            self.find_audio_stream(session.streams())
+        } else {
+            None
+        };
+        if let Some((audio_stream_i, _)) = &audio {
+            session
+                .setup(
+                    *audio_stream_i,
+                    retina::client::SetupOptions::default().transport(transport),
+                )
+                .await
+                .map_err(SourceRtspRunError::Setup)?;
+        }
+        let audio_params = audio.map(|(_, params)| params);
+
         let mut session = session
             .play(retina::client::PlayOptions::default())
             .await
@@ -372,6 +621,14 @@ impl SourceRtsp {
                         return Err(Eof);
                     };
                     match pkt {
+                        Ok(retina::codec::CodecItem::AudioFrame(frame)) => {
+                            // Audio is dropped until the first video frame.
+                            if let Some(hls_writer) = &mut hls_writer {
+                                if let Some(data) = frame_to_audio_data(&frame) {
+                                    hls_writer.write_audio(data)?;
+                                }
+                            }
+                        },
                         Ok(retina::codec::CodecItem::VideoFrame(frame)) => {
                             if let Some(hls_writer) = &mut hls_writer {
                                 let data = frame_to_sample(frame);
@@ -389,7 +646,11 @@ impl SourceRtsp {
                                     let result = self.hls_server.new_muxer(
                                         token.clone(),
                                         self.hls_name(),
-                                        track_params_from_video_params(params)?,
+                                        track_params_from_video_params(
+                                            params,
+                                            video_codec,
+                                            audio_params.clone(),
+                                        )?,
                                         frame_to_sample(frame),
                                     ).await?;
                                     let Some((muxer, hls_writer2)) = result else {
@@ -409,25 +670,6 @@ impl SourceRtsp {
             }
         }
     }
-
-    fn hls_name(&self) -> String {
-        if self.stream_type.is_main() {
-            self.monitor_id.to_string()
-        } else {
-            self.monitor_id.to_string() + "_sub"
-        }
-    }
-
-    fn stream_url(&self) -> &RtspUrl {
-        if self.stream_type.is_main() {
-            &self.config.main_stream
-        } else {
-            self.config
-                .sub_stream
-                .as_ref()
-                .expect("sub_stream to be `Some`")
-        }
-    }
 }
 
 #[allow(clippy::similar_names)]
@@ -444,8 +686,19 @@ fn frame_to_sample(frame: VideoFrame) -> H264Data {
     }
 }
 
+// Converts the timestamp to the 90kHz video timescale.
+fn frame_to_audio_data(frame: &AudioFrame) -> Option<AudioData> {
+    let timestamp = frame.timestamp();
+    let clock_rate = i64::from(timestamp.clock_rate().get());
+    let pts = timestamp.elapsed().checked_mul(i64::from(H264_TIMESCALE))? / clock_rate;
+    Some(AudioData {
+        pts: UnixH264::new(pts),
+        data: frame.data().clone(),
+    })
+}
+
 #[derive(Debug, Error)]
-enum SourceRtspRunError {
+pub(crate) enum SourceRtspRunError {
     #[error("end of file")]
     Eof,
 
@@ -473,6 +726,9 @@ enum SourceRtspRunError {
     #[error("write h264: {0}")]
     WriteH264(#[from] SegmenterWriteH264Error),
 
+    #[error("write audio: {0}")]
+    WriteAudio(#[from] SegmenterWriteAudioError),
+
     #[error("convert params: {0}")]
     ConvertTrackParams(#[from] ParseParamsError),
 
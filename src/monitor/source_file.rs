@@ -0,0 +1,308 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+use crate::{
+    mp4_demuxer::{parse_moov, DemuxError, DemuxedSample},
+    source::{
+        hls_name, spawn_source, Pacer, SampleWriter, Source, SourceLogger, SourceRunner, StartedTx,
+        WriteSampleError,
+    },
+};
+use async_trait::async_trait;
+use common::{
+    monitor::SourceFileConfig,
+    time::{DtsOffset, UnixH264},
+    DynLogger, DynMsgLogger, H264Data, LogLevel, MonitorId, PaddedBytes, StreamType,
+    TrackParameters,
+};
+use hls::{track_params_from_extra_data, HlsServer, ParseParamsError};
+use recording::{read_meta, ReadMetaError};
+use std::{
+    io::SeekFrom,
+    num::TryFromIntError,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use thiserror::Error;
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt, BufReader},
+    sync::mpsc,
+};
+use tokio_util::sync::CancellationToken;
+
+// Loops a local recording or MP4 file in real time, audio is ignored.
+pub(crate) struct SourceFile {
+    msg_logger: DynMsgLogger,
+    hls_server: Arc<HlsServer>,
+
+    monitor_id: MonitorId,
+    config: SourceFileConfig,
+}
+
+impl SourceFile {
+    #[allow(clippy::new_ret_no_self)]
+    pub(crate) fn new(
+        token: CancellationToken,
+        shutdown_complete: mpsc::Sender<()>,
+        logger: DynLogger,
+        hls_server: Arc<HlsServer>,
+        monitor_id: MonitorId,
+        config: SourceFileConfig,
+        stream_type: StreamType,
+    ) -> Option<Source> {
+        // Files don't have a sub stream.
+        if stream_type.is_sub() {
+            return None;
+        }
+
+        let msg_logger = Arc::new(SourceLogger::new(
+            logger,
+            monitor_id.clone(),
+            "file".to_owned(),
+            stream_type,
+        ));
+
+        let source = Self {
+            msg_logger,
+            hls_server,
+            monitor_id,
+            config,
+        };
+
+        Some(spawn_source(token, shutdown_complete, source, stream_type))
+    }
+}
+
+#[async_trait]
+impl SourceRunner for SourceFile {
+    type Error = SourceFileRunError;
+
+    fn log(&self, level: LogLevel, msg: &str) {
+        self.msg_logger.log(level, msg);
+    }
+
+    async fn run(
+        &self,
+        token: CancellationToken,
+        started_tx: StartedTx,
+    ) -> Result<(), Self::Error> {
+        use SourceFileRunError::*;
+
+        let path = &self.config.path;
+        let (params, samples, data_path) = match path.extension().and_then(|v| v.to_str()) {
+            Some("meta") => load_meta(path).await?,
+            Some("mp4") => load_mp4(path).await?,
+            _ => return Err(UnsupportedFileType(path.to_owned())),
+        };
+
+        // Skip until first IDR.
+        let first = samples
+            .iter()
+            .position(|v| v.random_access)
+            .ok_or(NoKeyframe)?;
+        let samples = &samples[first..];
+        let first = samples[0];
+        let last = samples.last().expect("samples should not be empty");
+        let loop_duration = last.dts + i64::from(last.duration) - first.dts;
+        if loop_duration <= 0 {
+            return Err(InvalidDuration);
+        }
+
+        self.log(LogLevel::Info, &format!("looping {path:?}"));
+
+        let mut file = File::open(&data_path).await.map_err(Open)?;
+        let mut writer = SampleWriter::new(
+            token.clone(),
+            self.hls_server.clone(),
+            hls_name(&self.monitor_id, StreamType::Main),
+            params,
+            started_tx,
+        );
+        let mut pacer = Pacer::new();
+        let mut loop_offset = 0;
+        loop {
+            for sample in samples {
+                let dts = UnixH264::new(sample.dts - first.dts + loop_offset);
+                // The first sample must have a zero dts offset.
+                let dts_offset = i32::try_from(sample.cts_offset - first.cts_offset)?;
+                tokio::select! {
+                    () = token.cancelled() => return Ok(()),
+                    () = pacer.wait(dts) => {}
+                }
+
+                let mut data = vec![0; usize::try_from(sample.size)?];
+                file.seek(SeekFrom::Start(sample.offset))
+                    .await
+                    .map_err(Read)?;
+                file.read_exact(&mut data).await.map_err(Read)?;
+
+                let data = H264Data {
+                    pts: UnixH264::new(*dts + i64::from(dts_offset)),
+                    dts_offset: DtsOffset::new(dts_offset),
+                    random_access_present: sample.random_access,
+                    avcc: Arc::new(PaddedBytes::new(data)),
+                };
+                if !writer.write(data).await? {
+                    // Cancelled.
+                    return Ok(());
+                }
+            }
+            loop_offset += loop_duration;
+        }
+    }
+}
+
+// Returns the samples in the 90kHz timescale and the path to the sample data.
+async fn load_meta(
+    path: &Path,
+) -> Result<(TrackParameters, Vec<DemuxedSample>, PathBuf), SourceFileRunError> {
+    use SourceFileRunError::*;
+
+    let meta_size = tokio::fs::metadata(path).await.map_err(Open)?.len();
+    let meta = BufReader::new(File::open(path).await.map_err(Open)?);
+    let (header, samples) = read_meta(meta, meta_size).await?;
+
+    let params = track_params_from_extra_data(
+        header.width,
+        header.height,
+        header.video_codec,
+        header.extra_data,
+    )?;
+    let samples = samples
+        .into_iter()
+        .filter(|v| !v.audio)
+        .map(|v| {
+            Ok(DemuxedSample {
+                offset: u64::from(v.data_offset),
+                size: v.data_size,
+                dts: *v.dts().ok_or(InvalidDuration)?,
+                cts_offset: i64::from(*v.dts_offset),
+                duration: v.duration.as_u32()?,
+                random_access: v.random_access_present,
+            })
+        })
+        .collect::<Result<Vec<_>, SourceFileRunError>>()?;
+
+    Ok((params, samples, path.with_extension("mdat")))
+}
+
+// Returns the samples in the 90kHz timescale and the path to the sample data.
+async fn load_mp4(
+    path: &Path,
+) -> Result<(TrackParameters, Vec<DemuxedSample>, PathBuf), SourceFileRunError> {
+    use SourceFileRunError::*;
+
+    let mut file = File::open(path).await.map_err(Open)?;
+    let moov = read_moov(&mut file).await?;
+    let (track, samples) = parse_moov(&moov)?;
+
+    let samples = samples
+        .into_iter()
+        .map(|v| {
+            let duration = track
+                .to_h264_timescale(i64::from(v.duration))
+                .ok_or(InvalidDuration)?;
+            Ok(DemuxedSample {
+                dts: track.to_h264_timescale(v.dts).ok_or(InvalidDuration)?,
+                cts_offset: track
+                    .to_h264_timescale(v.cts_offset)
+                    .ok_or(InvalidDuration)?,
+                duration: u32::try_from(duration)?,
+                ..v
+            })
+        })
+        .collect::<Result<Vec<_>, SourceFileRunError>>()?;
+
+    let params = track_params_from_extra_data(
+        track.width,
+        track.height,
+        track.video_codec,
+        track.extra_data,
+    )?;
+    Ok((params, samples, path.to_owned()))
+}
+
+// Larger boxes are most likely corrupt.
+const MAX_MOOV_SIZE: u64 = 100 * 1024 * 1024;
+
+// Returns the content of the 'moov' box.
+async fn read_moov(file: &mut File) -> Result<Vec<u8>, SourceFileRunError> {
+    use SourceFileRunError::*;
+    loop {
+        let mut header = [0; 8];
+        file.read_exact(&mut header).await.map_err(|e| {
+            if e.kind() == std::io::ErrorKind::UnexpectedEof {
+                Demux(DemuxError::MissingBox("moov"))
+            } else {
+                Read(e)
+            }
+        })?;
+        let size = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        let typ = &header[4..8];
+
+        let (size, header_size) = match size {
+            // Box extends to the end of the file.
+            0 => (u64::MAX, 8),
+            1 => {
+                let mut size = [0; 8];
+                file.read_exact(&mut size).await.map_err(Read)?;
+                (u64::from_be_bytes(size), 16)
+            }
+            _ => (u64::from(size), 8),
+        };
+        let content_size = size
+            .checked_sub(header_size)
+            .ok_or_else(|| Demux(DemuxError::Truncated("moov".to_owned())))?;
+
+        if typ == b"moov" {
+            if content_size > MAX_MOOV_SIZE {
+                return Err(MoovTooLarge(content_size));
+            }
+            let mut moov = vec![0; usize::try_from(content_size)?];
+            file.read_exact(&mut moov).await.map_err(Read)?;
+            return Ok(moov);
+        }
+        if size == u64::MAX {
+            return Err(Demux(DemuxError::MissingBox("moov")));
+        }
+        file.seek(SeekFrom::Current(i64::try_from(content_size)?))
+            .await
+            .map_err(Read)?;
+    }
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum SourceFileRunError {
+    #[error("unsupported file type {0:?}, only '.mp4' and '.meta' files are supported")]
+    UnsupportedFileType(PathBuf),
+
+    #[error("open: {0}")]
+    Open(std::io::Error),
+
+    #[error("read: {0}")]
+    Read(std::io::Error),
+
+    #[error("read meta: {0}")]
+    ReadMeta(#[from] ReadMetaError),
+
+    #[error("demux: {0}")]
+    Demux(#[from] DemuxError),
+
+    #[error("moov box is too large: {0}")]
+    MoovTooLarge(u64),
+
+    #[error("convert params: {0}")]
+    ConvertTrackParams(#[from] ParseParamsError),
+
+    #[error("file doesn't contain a keyframe")]
+    NoKeyframe,
+
+    #[error("invalid duration")]
+    InvalidDuration,
+
+    #[error("{0}")]
+    TryFromInt(#[from] TryFromIntError),
+
+    #[error("{0}")]
+    WriteSample(#[from] WriteSampleError),
+}
@@ -0,0 +1,401 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+use crate::{
+    log_monitor,
+    mp4_demuxer::{parse_fragment, parse_init, DemuxError},
+    source::{
+        hls_name, spawn_source, Pacer, SampleWriter, Source, SourceLogger, SourceRunner, StartedTx,
+        WriteSampleError,
+    },
+};
+use async_trait::async_trait;
+use common::{
+    monitor::{HttpUrl, SourceHttpConfig},
+    time::{DtsOffset, UnixH264},
+    DynLogger, DynMsgLogger, H264Data, LogLevel, MonitorId, PaddedBytes, StreamType,
+};
+use hls::{track_params_from_extra_data, HlsServer, ParseParamsError};
+use std::{num::ParseIntError, num::TryFromIntError, sync::Arc, time::Duration};
+use thiserror::Error;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use url::Url;
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Pulls a HLS stream with fMP4 segments. MPEG-TS segments and MJPEG
+// streams are not supported because they would have to be transcoded.
+pub(crate) struct SourceHttp {
+    msg_logger: DynMsgLogger,
+    hls_server: Arc<HlsServer>,
+
+    monitor_id: MonitorId,
+    config: SourceHttpConfig,
+    stream_type: StreamType,
+}
+
+impl SourceHttp {
+    #[allow(clippy::new_ret_no_self)]
+    pub(crate) fn new(
+        token: CancellationToken,
+        shutdown_complete: mpsc::Sender<()>,
+        logger: DynLogger,
+        hls_server: Arc<HlsServer>,
+        monitor_id: MonitorId,
+        config: SourceHttpConfig,
+        stream_type: StreamType,
+    ) -> Option<Source> {
+        if stream_type.is_sub() && config.sub_stream.is_none() {
+            log_monitor(&logger, LogLevel::Debug, &monitor_id, "no sub stream");
+            return None;
+        }
+
+        let msg_logger = Arc::new(SourceLogger::new(
+            logger,
+            monitor_id.clone(),
+            "http".to_owned(),
+            stream_type,
+        ));
+
+        let source = Self {
+            msg_logger,
+            hls_server,
+            monitor_id,
+            config,
+            stream_type,
+        };
+
+        Some(spawn_source(token, shutdown_complete, source, stream_type))
+    }
+
+    fn stream_url(&self) -> &HttpUrl {
+        if self.stream_type.is_main() {
+            &self.config.main_stream
+        } else {
+            self.config
+                .sub_stream
+                .as_ref()
+                .expect("sub_stream to be `Some`")
+        }
+    }
+
+    async fn pull(
+        &self,
+        token: CancellationToken,
+        started_tx: StartedTx,
+    ) -> Result<(), SourceHttpRunError> {
+        use SourceHttpRunError::*;
+
+        let client = reqwest::Client::new();
+        let (playlist_url, mut playlist) = fetch_media_playlist(&client, self.stream_url()).await?;
+        let init_uri = playlist.init_uri.clone().ok_or(UnsupportedSegments)?;
+        let init = fetch(&client, &playlist_url.join(&init_uri)?).await?;
+        let track = parse_init(&init)?;
+        let params = track_params_from_extra_data(
+            track.width,
+            track.height,
+            track.video_codec,
+            track.extra_data.clone(),
+        )?;
+
+        let mut writer = SampleWriter::new(
+            token,
+            self.hls_server.clone(),
+            hls_name(&self.monitor_id, self.stream_type),
+            params,
+            started_tx,
+        );
+        let mut pacer = Pacer::new();
+        let mut first_cts_offset = None;
+
+        // Start from the last segment.
+        let mut next_seq =
+            playlist.media_sequence + u64::try_from(playlist.segments.len().saturating_sub(1))?;
+        loop {
+            let skip = usize::try_from(next_seq.saturating_sub(playlist.media_sequence))?;
+            for uri in playlist.segments.iter().skip(skip) {
+                let segment = fetch(&client, &playlist_url.join(uri)?).await?;
+                for sample in parse_fragment(&segment, &track)? {
+                    let cts_offset = track
+                        .to_h264_timescale(sample.cts_offset)
+                        .ok_or(InvalidTimestamp)?;
+                    // The first sample must be a IDR with a zero dts offset.
+                    let first_cts_offset = match first_cts_offset {
+                        Some(v) => v,
+                        None if sample.random_access => *first_cts_offset.insert(cts_offset),
+                        None => continue,
+                    };
+                    let dts_offset = i32::try_from(cts_offset - first_cts_offset)?;
+                    let dts = UnixH264::new(
+                        track
+                            .to_h264_timescale(sample.dts)
+                            .ok_or(InvalidTimestamp)?,
+                    );
+
+                    let start = usize::try_from(sample.offset)?;
+                    let end = start + usize::try_from(sample.size)?;
+                    let data = segment
+                        .get(start..end)
+                        .ok_or_else(|| DemuxError::Truncated("mdat".to_owned()))?;
+
+                    pacer.wait(dts).await;
+                    let data = H264Data {
+                        pts: UnixH264::new(*dts + i64::from(dts_offset)),
+                        dts_offset: DtsOffset::new(dts_offset),
+                        random_access_present: sample.random_access,
+                        avcc: Arc::new(PaddedBytes::new(data.to_owned())),
+                    };
+                    if !writer.write(data).await? {
+                        // Cancelled.
+                        return Ok(());
+                    }
+                }
+                next_seq += 1;
+            }
+
+            if playlist.end_list {
+                return Err(EndOfStream);
+            }
+            let interval = std::cmp::max(1, playlist.target_duration / 2);
+            tokio::time::sleep(Duration::from_secs(interval)).await;
+
+            (_, playlist) = fetch_media_playlist(&client, &playlist_url).await?;
+            if next_seq < playlist.media_sequence {
+                self.log(LogLevel::Warning, "fell behind, skipping segments");
+                next_seq = playlist.media_sequence;
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl SourceRunner for SourceHttp {
+    type Error = SourceHttpRunError;
+
+    fn log(&self, level: LogLevel, msg: &str) {
+        self.msg_logger.log(level, msg);
+    }
+
+    async fn run(
+        &self,
+        token: CancellationToken,
+        started_tx: StartedTx,
+    ) -> Result<(), Self::Error> {
+        tokio::select! {
+            () = token.cancelled() => Ok(()),
+            res = self.pull(token.clone(), started_tx) => res,
+        }
+    }
+}
+
+async fn fetch(client: &reqwest::Client, url: &Url) -> Result<Vec<u8>, SourceHttpRunError> {
+    let res = client
+        .get(url.clone())
+        .timeout(FETCH_TIMEOUT)
+        .send()
+        .await?;
+    if !res.status().is_success() {
+        return Err(SourceHttpRunError::BadStatus(res.status()));
+    }
+    Ok(res.bytes().await?.to_vec())
+}
+
+// Follows the first variant if the url points to a master playlist.
+async fn fetch_media_playlist(
+    client: &reqwest::Client,
+    url: &Url,
+) -> Result<(Url, MediaPlaylist), SourceHttpRunError> {
+    let mut url = url.clone();
+    for _ in 0..2 {
+        let raw = fetch(client, &url).await?;
+        match parse_playlist(&String::from_utf8_lossy(&raw))? {
+            Playlist::Media(v) => return Ok((url, v)),
+            Playlist::Master(variants) => url = url.join(&variants[0])?,
+        }
+    }
+    Err(SourceHttpRunError::NestedMasterPlaylist)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Playlist {
+    // Variant stream URIs.
+    Master(Vec<String>),
+    Media(MediaPlaylist),
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct MediaPlaylist {
+    target_duration: u64,
+    media_sequence: u64,
+    init_uri: Option<String>,
+    segments: Vec<String>,
+    end_list: bool,
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum ParsePlaylistError {
+    #[error("missing #EXTM3U header")]
+    MissingHeader,
+
+    #[error("parse int: {0}")]
+    ParseInt(#[from] ParseIntError),
+
+    #[error("#EXT-X-MAP is missing the URI attribute")]
+    MissingMapUri,
+}
+
+fn parse_playlist(raw: &str) -> Result<Playlist, ParsePlaylistError> {
+    use ParsePlaylistError::*;
+
+    let mut lines = raw.lines().map(str::trim).filter(|v| !v.is_empty());
+    if lines.next() != Some("#EXTM3U") {
+        return Err(MissingHeader);
+    }
+
+    let mut variants = Vec::new();
+    let mut media = MediaPlaylist::default();
+    let mut next_is_variant = false;
+    for line in lines {
+        if let Some(v) = line.strip_prefix("#EXT-X-TARGETDURATION:") {
+            media.target_duration = v.parse()?;
+        } else if let Some(v) = line.strip_prefix("#EXT-X-MEDIA-SEQUENCE:") {
+            media.media_sequence = v.parse()?;
+        } else if let Some(v) = line.strip_prefix("#EXT-X-MAP:") {
+            media.init_uri = Some(attribute(v, "URI").ok_or(MissingMapUri)?);
+        } else if line.starts_with("#EXT-X-STREAM-INF:") {
+            next_is_variant = true;
+        } else if line == "#EXT-X-ENDLIST" {
+            media.end_list = true;
+        } else if line.starts_with('#') {
+            // Unused tag or comment.
+        } else if next_is_variant {
+            variants.push(line.to_owned());
+            next_is_variant = false;
+        } else {
+            media.segments.push(line.to_owned());
+        }
+    }
+
+    if variants.is_empty() {
+        Ok(Playlist::Media(media))
+    } else {
+        Ok(Playlist::Master(variants))
+    }
+}
+
+// Returns the value of a attribute from a attribute list without quotes.
+fn attribute(list: &str, name: &str) -> Option<String> {
+    let mut rest = list;
+    loop {
+        let (key, value) = rest.split_once('=')?;
+        let (value, next) = if let Some(value) = value.strip_prefix('"') {
+            let (value, next) = value.split_once('"')?;
+            (value, next.trim_start_matches(','))
+        } else {
+            value.split_once(',').unwrap_or((value, ""))
+        };
+        if key.trim() == name {
+            return Some(value.to_owned());
+        }
+        if next.is_empty() {
+            return None;
+        }
+        rest = next;
+    }
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum SourceHttpRunError {
+    #[error("end of stream")]
+    EndOfStream,
+
+    #[error("fetch: {0}")]
+    Fetch(#[from] reqwest::Error),
+
+    #[error("bad status: {0}")]
+    BadStatus(reqwest::StatusCode),
+
+    #[error("parse playlist: {0}")]
+    ParsePlaylist(#[from] ParsePlaylistError),
+
+    #[error("join url: {0}")]
+    JoinUrl(#[from] url::ParseError),
+
+    #[error("master playlist points to another master playlist")]
+    NestedMasterPlaylist,
+
+    #[error("only fMP4 segments are supported")]
+    UnsupportedSegments,
+
+    #[error("demux: {0}")]
+    Demux(#[from] DemuxError),
+
+    #[error("convert params: {0}")]
+    ConvertTrackParams(#[from] ParseParamsError),
+
+    #[error("invalid timestamp")]
+    InvalidTimestamp,
+
+    #[error("{0}")]
+    TryFromInt(#[from] TryFromIntError),
+
+    #[error("{0}")]
+    WriteSample(#[from] WriteSampleError),
+}
+
+#[allow(clippy::unwrap_used)]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use test_case::test_case;
+
+    #[test]
+    fn test_parse_media_playlist() {
+        let raw = "#EXTM3U
+            #EXT-X-VERSION:9
+            #EXT-X-TARGETDURATION:4
+            #EXT-X-MEDIA-SEQUENCE:7
+            #EXT-X-MAP:URI=\"init.mp4\"
+            #EXTINF:4.00000,
+            seg7.mp4
+            #EXT-X-PART:DURATION=1,URI=\"part1.mp4\"
+            #EXTINF:4.00000,
+            seg8.mp4
+            #EXT-X-ENDLIST";
+        let want = Playlist::Media(MediaPlaylist {
+            target_duration: 4,
+            media_sequence: 7,
+            init_uri: Some("init.mp4".to_owned()),
+            segments: vec!["seg7.mp4".to_owned(), "seg8.mp4".to_owned()],
+            end_list: true,
+        });
+        assert_eq!(want, parse_playlist(raw).unwrap());
+    }
+
+    #[test]
+    fn test_parse_master_playlist() {
+        let raw = "#EXTM3U
+            #EXT-X-STREAM-INF:BANDWIDTH=1000,CODECS=\"avc1.640016\"
+            stream.m3u8";
+        let want = Playlist::Master(vec!["stream.m3u8".to_owned()]);
+        assert_eq!(want, parse_playlist(raw).unwrap());
+    }
+
+    #[test]
+    fn test_parse_playlist_missing_header() {
+        assert!(matches!(
+            parse_playlist("seg.mp4"),
+            Err(ParsePlaylistError::MissingHeader)
+        ));
+    }
+
+    #[test_case("URI=\"a\"", Some("a"); "quoted")]
+    #[test_case("BYTERANGE=\"1@2\",URI=\"a,b\"", Some("a,b"); "quoted comma")]
+    #[test_case("X=1,URI=a", Some("a"); "unquoted")]
+    #[test_case("X=1, URI=a,Y=2", Some("a"); "space")]
+    #[test_case("X=1", None; "missing")]
+    fn test_attribute(list: &str, want: Option<&str>) {
+        assert_eq!(want.map(str::to_owned), attribute(list, "URI"));
+    }
+}
@@ -0,0 +1,296 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+use crate::recorder::{avcc_to_rgb, encode_jpeg, AvccToJpegError};
+use common::{
+    time::{UnixNano, MILLISECOND, SECOND},
+    SegmentFinalized,
+};
+use sentryshot_convert::Frame;
+use sentryshot_ffmpeg_h264::PaddedBytes;
+use std::{fmt::Write, sync::Arc};
+use thiserror::Error;
+
+// Time between the frames in the sprite sheet.
+const SPRITE_INTERVAL: i64 = 10 * SECOND;
+
+// Limits the size of the sprite sheet for long recordings.
+const MAX_TILES: usize = 1000;
+
+const TILE_WIDTH: usize = 160;
+const COLUMNS: usize = 10;
+
+// Keyframes collected while the recording is
+// written, decoded once the recording is done.
+pub(crate) struct SpriteFrames {
+    start: UnixNano,
+    next: UnixNano,
+    frames: Vec<(UnixNano, Arc<PaddedBytes>)>,
+}
+
+impl SpriteFrames {
+    pub(crate) fn new(start: UnixNano) -> Self {
+        Self {
+            start,
+            next: start,
+            frames: Vec::new(),
+        }
+    }
+
+    // Adds the first sample of the segment if it's an IDR and
+    // at least one interval has passed since the previous frame.
+    pub(crate) fn push_segment(&mut self, seg: &SegmentFinalized) {
+        let Some(sample) = seg.parts().first().and_then(|v| v.video_samples.first()) else {
+            return;
+        };
+        if !sample.random_access_present {
+            return;
+        }
+        self.push(UnixNano::from(seg.start_time()), &sample.avcc);
+    }
+
+    fn push(&mut self, time: UnixNano, avcc: &Arc<PaddedBytes>) {
+        if self.frames.len() >= MAX_TILES || time.before(self.next) {
+            return;
+        }
+        self.frames.push((time, avcc.clone()));
+        self.next = time
+            .checked_add(UnixNano::new(SPRITE_INTERVAL))
+            .unwrap_or(UnixNano::new(i64::MAX));
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum GenerateSpriteError {
+    #[error("decode frame: {0}")]
+    Decode(#[from] AvccToJpegError),
+
+    #[error("encode jpeg: {0}")]
+    EncodeJpeg(#[from] jpeg_encoder::EncodingError),
+
+    #[error("no frames")]
+    NoFrames,
+
+    #[error("sprite is too large")]
+    TooLarge,
+}
+
+// Decodes the frames and places them in a grid. Returns the jpeg sprite
+// sheet and a WebVTT index where each cue references its tile in the
+// sprite sheet at `image_url`. `on_frame` is applied to every frame.
+pub(crate) fn generate_sprite(
+    frames: &SpriteFrames,
+    extradata: &[u8],
+    end: UnixNano,
+    image_url: &str,
+    on_frame: impl Fn(Frame) -> Frame,
+) -> Result<(Vec<u8>, String), GenerateSpriteError> {
+    let mut tiles = Vec::with_capacity(frames.frames.len());
+    for (_, avcc) in &frames.frames {
+        let (rgb, width, height) =
+            avcc_to_rgb(avcc, PaddedBytes::new(extradata.to_vec()), &on_frame)?;
+        tiles.push((rgb, usize::from(width), usize::from(height)));
+    }
+    let Some((_, width, height)) = tiles.first() else {
+        return Err(GenerateSpriteError::NoFrames);
+    };
+    let tile_width = TILE_WIDTH;
+    let tile_height = tile_height(*width, *height, tile_width);
+
+    let columns = tiles.len().min(COLUMNS);
+    let rows = tiles.len().div_ceil(COLUMNS);
+    let sprite_width = columns * tile_width;
+    let sprite_height = rows * tile_height;
+    let mut sprite = vec![0; sprite_width * sprite_height * 3];
+    for (i, (rgb, width, height)) in tiles.iter().enumerate() {
+        let tile = resize_rgb(rgb, *width, *height, tile_width, tile_height);
+        let x = (i % COLUMNS) * tile_width;
+        let y = (i / COLUMNS) * tile_height;
+        blit_rgb(&mut sprite, sprite_width, &tile, tile_width, x, y);
+    }
+
+    let to_u16 = |v: usize| u16::try_from(v).map_err(|_| GenerateSpriteError::TooLarge);
+    let jpeg = encode_jpeg(&sprite, to_u16(sprite_width)?, to_u16(sprite_height)?)?;
+
+    let times: Vec<_> = frames.frames.iter().map(|(time, _)| *time).collect();
+    let vtt = format_vtt(
+        frames.start,
+        end,
+        &times,
+        image_url,
+        tile_width,
+        tile_height,
+    );
+    Ok((jpeg, vtt))
+}
+
+// Keeps the aspect ratio, the height is rounded to an even number.
+fn tile_height(width: usize, height: usize, tile_width: usize) -> usize {
+    let tile_height = (height * tile_width).checked_div(width).unwrap_or(0);
+    (tile_height + (tile_height & 1)).max(2)
+}
+
+// Box filter, each destination pixel is the average of the source pixels it covers.
+fn resize_rgb(src: &[u8], src_w: usize, src_h: usize, dst_w: usize, dst_h: usize) -> Vec<u8> {
+    let mut dst = vec![0; dst_w * dst_h * 3];
+    if src_w == 0 || src_h == 0 || src.len() < src_w * src_h * 3 {
+        return dst;
+    }
+    for y in 0..dst_h {
+        let y0 = y * src_h / dst_h;
+        let y1 = ((y + 1) * src_h / dst_h).max(y0 + 1);
+        for x in 0..dst_w {
+            let x0 = x * src_w / dst_w;
+            let x1 = ((x + 1) * src_w / dst_w).max(x0 + 1);
+            let mut sum = [0_usize; 3];
+            for sy in y0..y1 {
+                for sx in x0..x1 {
+                    let i = (sy * src_w + sx) * 3;
+                    for (c, sum) in sum.iter_mut().enumerate() {
+                        *sum += usize::from(src[i + c]);
+                    }
+                }
+            }
+            let n = (y1 - y0) * (x1 - x0);
+            let i = (y * dst_w + x) * 3;
+            for (c, sum) in sum.iter().enumerate() {
+                dst[i + c] = u8::try_from(sum / n).expect("average should fit u8");
+            }
+        }
+    }
+    dst
+}
+
+// Copies the tile into the sprite at x, y.
+fn blit_rgb(sprite: &mut [u8], sprite_w: usize, tile: &[u8], tile_w: usize, x: usize, y: usize) {
+    for (row, line) in tile.chunks_exact(tile_w * 3).enumerate() {
+        let start = ((y + row) * sprite_w + x) * 3;
+        sprite[start..start + line.len()].copy_from_slice(line);
+    }
+}
+
+fn format_vtt(
+    start: UnixNano,
+    end: UnixNano,
+    times: &[UnixNano],
+    image_url: &str,
+    tile_w: usize,
+    tile_h: usize,
+) -> String {
+    let mut vtt = "WEBVTT\n".to_owned();
+    for (i, time) in times.iter().enumerate() {
+        let cue_end = times.get(i + 1).copied().unwrap_or(end);
+        let x = (i % COLUMNS) * tile_w;
+        let y = (i / COLUMNS) * tile_h;
+        write!(
+            vtt,
+            "\n{} --> {}\n{image_url}#xywh={x},{y},{tile_w},{tile_h}\n",
+            vtt_timestamp(*time - start),
+            vtt_timestamp(cue_end - start),
+        )
+        .expect("writing to string should not fail");
+    }
+    vtt
+}
+
+// `hh:mm:ss.ttt`
+fn vtt_timestamp(offset: UnixNano) -> String {
+    let millis = (*offset / MILLISECOND).max(0);
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use test_case::test_case;
+
+    #[test]
+    fn test_sprite_frames_push() {
+        let avcc = Arc::new(PaddedBytes::new(Vec::new()));
+        let mut frames = SpriteFrames::new(UnixNano::new(0));
+        for secs in [0, 4, 8, 12, 16, 21, 25] {
+            frames.push(UnixNano::new(secs * SECOND), &avcc);
+        }
+        let times: Vec<_> = frames
+            .frames
+            .iter()
+            .map(|(time, _)| **time / SECOND)
+            .collect();
+        assert_eq!(vec![0, 12, 25], times);
+    }
+
+    #[test_case(1920, 1080, 90; "16_9")]
+    #[test_case(640, 480, 120; "4_3")]
+    #[test_case(1000, 333, 54; "rounded_to_even")]
+    #[test_case(0, 0, 2; "zero")]
+    fn test_tile_height(width: usize, height: usize, want: usize) {
+        assert_eq!(want, tile_height(width, height, 160));
+    }
+
+    #[test]
+    fn test_resize_rgb() {
+        #[rustfmt::skip]
+        let src = vec![
+            0, 0, 0,   2, 2, 2,     10, 20, 30,  10, 20, 30,
+            4, 4, 4,   6, 6, 6,     10, 20, 30,  10, 20, 30,
+        ];
+        let got = resize_rgb(&src, 4, 2, 2, 1);
+        assert_eq!(vec![3, 3, 3, 10, 20, 30], got);
+
+        let got = resize_rgb(&[1, 2, 3], 1, 1, 2, 1);
+        assert_eq!(vec![1, 2, 3, 1, 2, 3], got);
+    }
+
+    #[test]
+    fn test_blit_rgb() {
+        let mut sprite = vec![0; 3 * 2 * 3];
+        // 1x2 tile.
+        blit_rgb(&mut sprite, 3, &[1, 1, 1, 2, 2, 2], 1, 1, 0);
+        blit_rgb(&mut sprite, 3, &[3, 3, 3], 1, 2, 1);
+        #[rustfmt::skip]
+        let want = vec![
+            0, 0, 0,   1, 1, 1,   0, 0, 0,
+            0, 0, 0,   2, 2, 2,   3, 3, 3,
+        ];
+        assert_eq!(want, sprite);
+    }
+
+    #[test]
+    fn test_format_vtt() {
+        let times: Vec<_> = [0, 10, 25]
+            .into_iter()
+            .map(|v| UnixNano::new(1000 * SECOND + v * SECOND))
+            .collect();
+        let got = format_vtt(
+            UnixNano::new(1000 * SECOND),
+            UnixNano::new(1000 * SECOND + 3723 * SECOND + 5 * MILLISECOND),
+            &times,
+            "../sprite/x",
+            160,
+            90,
+        );
+        let want = "WEBVTT
+
+00:00:00.000 --> 00:00:10.000
+../sprite/x#xywh=0,0,160,90
+
+00:00:10.000 --> 00:00:25.000
+../sprite/x#xywh=160,0,160,90
+
+00:00:25.000 --> 01:02:03.005
+../sprite/x#xywh=320,0,160,90
+";
+        assert_eq!(want, got);
+    }
+}
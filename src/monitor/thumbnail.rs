@@ -0,0 +1,300 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+use common::{
+    recording::denormalize,
+    time::{UnixNano, SECOND},
+    Detection, Event, SegmentFinalized,
+};
+use sentryshot_ffmpeg_h264::PaddedBytes;
+use std::{collections::VecDeque, sync::Arc};
+
+// Detections are reported a few seconds after the frame they were
+// detected in, keyframes older than this can't be picked anymore.
+const RECENT_KEYFRAMES_DURATION: i64 = 30 * SECOND;
+
+const BOX_COLOR: [u8; 3] = [255, 0, 0];
+const BOX_THICKNESS: usize = 2;
+
+#[derive(Clone)]
+struct Keyframe {
+    time: UnixNano,
+    avcc: Arc<PaddedBytes>,
+}
+
+// Picks the keyframe closest to the highest scoring detection in the
+// recording. Only the recent keyframes are kept to limit memory usage.
+pub(crate) struct ThumbnailPicker {
+    recent: VecDeque<Keyframe>,
+
+    // Time of the best detection and the detections from the same event.
+    best_event: Option<(f32, UnixNano, Vec<Detection>)>,
+    best_frame: Option<Keyframe>,
+}
+
+impl ThumbnailPicker {
+    pub(crate) fn new() -> Self {
+        Self {
+            recent: VecDeque::new(),
+            best_event: None,
+            best_frame: None,
+        }
+    }
+
+    pub(crate) fn push_segment(&mut self, seg: &SegmentFinalized) {
+        let Some(sample) = seg.parts().first().and_then(|v| v.video_samples.first()) else {
+            return;
+        };
+        if !sample.random_access_present {
+            return;
+        }
+        self.push_keyframe(UnixNano::from(seg.start_time()), sample.avcc.clone());
+    }
+
+    fn push_keyframe(&mut self, time: UnixNano, avcc: Arc<PaddedBytes>) {
+        self.recent.push_back(Keyframe { time, avcc });
+        while let Some(oldest) = self.recent.front() {
+            if *time - *oldest.time <= RECENT_KEYFRAMES_DURATION {
+                break;
+            }
+            self.recent.pop_front();
+        }
+        self.update_best_frame();
+    }
+
+    // Should be called with the recording's events every time a segment is pushed.
+    pub(crate) fn push_events(&mut self, events: &[Event]) {
+        for event in events {
+            for detection in &event.detections {
+                let is_better = match &self.best_event {
+                    Some((score, ..)) => detection.score > *score,
+                    None => true,
+                };
+                if is_better {
+                    self.best_event = Some((detection.score, event.time, event.detections.clone()));
+                }
+            }
+        }
+        self.update_best_frame();
+    }
+
+    fn update_best_frame(&mut self) {
+        let Some((_, time, _)) = &self.best_event else {
+            return;
+        };
+        let distance = |frame: &Keyframe| (*frame.time - **time).abs();
+        let closest = self
+            .recent
+            .iter()
+            .chain(self.best_frame.iter())
+            .min_by_key(|frame| distance(frame))
+            .cloned();
+        self.best_frame = closest;
+    }
+
+    // Returns the picked keyframe and the detections to draw on it.
+    // Returns None if the recording doesn't have any detections.
+    pub(crate) fn into_best(self) -> Option<(Arc<PaddedBytes>, Vec<Detection>)> {
+        let (_, _, detections) = self.best_event?;
+        Some((self.best_frame?.avcc, detections))
+    }
+}
+
+// Draws the outlines of the detection rectangles onto a packed RGB24 frame.
+// Polygon regions are drawn as their bounding box.
+pub(crate) fn draw_detections(raw: &mut [u8], width: u16, height: u16, detections: &[Detection]) {
+    for d in detections {
+        let (x1, y1, x2, y2) = if let Some(r) = &d.region.rectangle {
+            (r.x, r.y, r.x + r.width.get(), r.y + r.height.get())
+        } else if let Some(polygon) = &d.region.polygon {
+            let (Some(x1), Some(y1), Some(x2), Some(y2)) = (
+                polygon.iter().map(|p| p.x).min(),
+                polygon.iter().map(|p| p.y).min(),
+                polygon.iter().map(|p| p.x).max(),
+                polygon.iter().map(|p| p.y).max(),
+            ) else {
+                continue;
+            };
+            (x1, y1, x2, y2)
+        } else {
+            continue;
+        };
+        draw_rectangle(
+            raw,
+            usize::from(width),
+            usize::from(height),
+            usize::from(denormalize(x1, width)),
+            usize::from(denormalize(y1, height)),
+            usize::from(denormalize(x2, width)),
+            usize::from(denormalize(y2, height)),
+        );
+    }
+}
+
+fn draw_rectangle(
+    raw: &mut [u8],
+    width: usize,
+    height: usize,
+    x1: usize,
+    y1: usize,
+    x2: usize,
+    y2: usize,
+) {
+    if width == 0 || height == 0 || raw.len() < width * height * 3 {
+        return;
+    }
+    let x2 = x2.min(width - 1);
+    let y2 = y2.min(height - 1);
+    if x1 > x2 || y1 > y2 {
+        return;
+    }
+    let mut set_pixel = |x: usize, y: usize| {
+        let i = (y * width + x) * 3;
+        raw[i..i + 3].copy_from_slice(&BOX_COLOR);
+    };
+    for t in 0..BOX_THICKNESS {
+        for x in x1..=x2 {
+            set_pixel(x, (y1 + t).min(y2));
+            set_pixel(x, y2.saturating_sub(t).max(y1));
+        }
+        for y in y1..=y2 {
+            set_pixel((x1 + t).min(x2), y);
+            set_pixel(x2.saturating_sub(t).max(x1), y);
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use common::{time::Duration, Label, PointNormalized, RectangleNormalized, Region};
+    use pretty_assertions::assert_eq;
+
+    fn avcc(v: u8) -> Arc<PaddedBytes> {
+        Arc::new(PaddedBytes::new(vec![v]))
+    }
+
+    fn event(secs: i64, score: f32) -> Event {
+        Event {
+            time: UnixNano::new(secs * SECOND),
+            duration: Duration::new(0),
+            rec_duration: Duration::new(0),
+            detections: vec![Detection {
+                label: Label::try_from("a".to_owned()).unwrap(),
+                score,
+                region: Region {
+                    rectangle: None,
+                    polygon: None,
+                },
+            }],
+        }
+    }
+
+    fn best(picker: ThumbnailPicker) -> Option<u8> {
+        picker.into_best().map(|(avcc, _)| avcc[0])
+    }
+
+    #[test]
+    fn test_thumbnail_picker() {
+        let mut picker = ThumbnailPicker::new();
+        picker.push_keyframe(UnixNano::new(0), avcc(0));
+        picker.push_keyframe(UnixNano::new(4 * SECOND), avcc(4));
+        picker.push_events(&[event(5, 0.5)]);
+        picker.push_keyframe(UnixNano::new(8 * SECOND), avcc(8));
+        picker.push_events(&[event(5, 0.5), event(7, 0.9), event(1, 0.1)]);
+        picker.push_keyframe(UnixNano::new(12 * SECOND), avcc(12));
+        assert_eq!(Some(8), best(picker));
+    }
+
+    #[test]
+    fn test_thumbnail_picker_no_detections() {
+        let mut picker = ThumbnailPicker::new();
+        picker.push_keyframe(UnixNano::new(0), avcc(0));
+        picker.push_events(&[]);
+        assert_eq!(None, best(picker));
+    }
+
+    #[test]
+    fn test_thumbnail_picker_old_keyframes() {
+        let mut picker = ThumbnailPicker::new();
+        picker.push_keyframe(UnixNano::new(0), avcc(0));
+        picker.push_keyframe(UnixNano::new(40 * SECOND), avcc(40));
+        // The keyframe at 0 was dropped.
+        picker.push_events(&[event(1, 0.5)]);
+        assert_eq!(Some(40), best(picker));
+    }
+
+    #[test]
+    fn test_thumbnail_picker_keeps_best_frame() {
+        let mut picker = ThumbnailPicker::new();
+        picker.push_keyframe(UnixNano::new(0), avcc(0));
+        picker.push_events(&[event(0, 0.5)]);
+        picker.push_keyframe(UnixNano::new(40 * SECOND), avcc(40));
+        picker.push_keyframe(UnixNano::new(80 * SECOND), avcc(80));
+        assert_eq!(Some(0), best(picker));
+    }
+
+    fn pixels(raw: &[u8], width: usize) -> Vec<String> {
+        raw.chunks_exact(width * 3)
+            .map(|row| {
+                row.chunks_exact(3)
+                    .map(|p| if p == BOX_COLOR { '#' } else { '.' })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_draw_detections() {
+        let mut raw = vec![0; 6 * 5 * 3];
+        let detection = |region| Detection {
+            label: Label::try_from("a".to_owned()).unwrap(),
+            score: 1.0,
+            region,
+        };
+        let rectangle = detection(Region {
+            // 1,1 to 4,3
+            rectangle: Some(RectangleNormalized {
+                x: 166_666,
+                y: 200_000,
+                width: 500_000.try_into().unwrap(),
+                height: 400_000.try_into().unwrap(),
+            }),
+            polygon: None,
+        });
+        draw_detections(&mut raw, 6, 5, &[rectangle]);
+        #[rustfmt::skip]
+        let want = vec![
+            "......",
+            ".####.",
+            ".####.",
+            ".####.",
+            "......",
+        ];
+        assert_eq!(want, pixels(&raw, 6));
+
+        // Polygons use the bounding box.
+        let mut raw = vec![0; 6 * 5 * 3];
+        let polygon = detection(Region {
+            rectangle: None,
+            polygon: Some(vec![
+                PointNormalized { x: 0, y: 0 },
+                PointNormalized { x: 1_000_000, y: 0 },
+                PointNormalized {
+                    x: 500_000,
+                    y: 1_000_000,
+                },
+            ]),
+        });
+        draw_detections(&mut raw, 6, 5, &[polygon]);
+        #[rustfmt::skip]
+        let want = vec![
+            "######",
+            "######",
+            "##..##",
+            "######",
+            "######",
+        ];
+        assert_eq!(want, pixels(&raw, 6));
+    }
+}
@@ -1659,6 +1659,41 @@ impl ImmutableBoxAsync for Avc1 {
     }
 }
 
+/*********************** hvc1 *************************/
+
+pub const TYPE_HVC1: BoxType = *b"hvc1";
+
+// Hvc1 and avc1 are both visual sample entries with identical fields.
+#[derive(Default)]
+pub struct Hvc1(pub Avc1);
+impl_from!(Hvc1);
+
+impl ImmutableBox for Hvc1 {
+    fn box_type(&self) -> BoxType {
+        TYPE_HVC1
+    }
+
+    fn size(&self) -> usize {
+        self.0.size()
+    }
+}
+
+impl ImmutableBoxSync for Hvc1 {
+    fn marshal(&self, w: &mut dyn Write) -> Result<(), Mp4Error> {
+        ImmutableBoxSync::marshal(&self.0, w)
+    }
+}
+
+#[async_trait]
+impl ImmutableBoxAsync for Hvc1 {
+    async fn marshal(
+        &self,
+        w: &mut (dyn AsyncWrite + Unpin + Send + Sync),
+    ) -> Result<(), Mp4Error> {
+        ImmutableBoxAsync::marshal(&self.0, w).await
+    }
+}
+
 /**************** AVCDecoderConfiguration ****************.*/
 pub const AVC_BASELINE_PROFILE: u8 = 66; // 0x42
 pub const AVC_MAIN_PROFILE: u8 = 77; // 0x4d
@@ -1834,6 +1869,55 @@ impl ImmutableBoxAsync for AvcC {
     }
 }
 
+/*************************** hvcC ****************************/
+
+// The HEVCDecoderConfigurationRecord is passed through from the camera as is.
+pub const TYPE_HVCC: BoxType = *b"hvcC";
+
+/*************************** smhd ****************************/
+
+pub const TYPE_SMHD: BoxType = *b"smhd";
+
+#[derive(Default)]
+pub struct Smhd {
+    pub full_box: FullBox,
+    pub balance: i16, // template=0
+    pub reserved: u16,
+}
+impl_from!(Smhd);
+
+impl ImmutableBox for Smhd {
+    fn box_type(&self) -> BoxType {
+        TYPE_SMHD
+    }
+
+    fn size(&self) -> usize {
+        8
+    }
+}
+
+impl ImmutableBoxSync for Smhd {
+    fn marshal(&self, w: &mut dyn Write) -> Result<(), Mp4Error> {
+        self.full_box.marshal_field(w)?;
+        w.write_all(&self.balance.to_be_bytes())?;
+        w.write_all(&self.reserved.to_be_bytes())?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ImmutableBoxAsync for Smhd {
+    async fn marshal(
+        &self,
+        w: &mut (dyn AsyncWrite + Unpin + Send + Sync),
+    ) -> Result<(), Mp4Error> {
+        self.full_box.marshal_field2(w).await?;
+        w.write_all(&self.balance.to_be_bytes()).await?;
+        w.write_all(&self.reserved.to_be_bytes()).await?;
+        Ok(())
+    }
+}
+
 /*************************** stbl ****************************/
 
 pub const TYPE_STBL: BoxType = *b"stbl";
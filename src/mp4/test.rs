@@ -431,6 +431,34 @@ use test_case::test_case;
             0x03, 0xe9, // pre_defined3
         ]; "Avc1"
     )]
+#[test_case(
+        Hvc1(Avc1{
+            width: 0x0102,
+            height: 0x0103,
+            ..Avc1::default()
+        }),
+        &[
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // reserved
+            0x00, 0x00, // data reference index
+            0x00, 0x00, // pre_defined
+            0x00, 0x00, // reserved
+            0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, // pre_defined2
+            0x01, 0x02, // width
+            0x01, 0x03, // height
+            0x00, 0x00, 0x00, 0x00, // horiz_resolution
+            0x00, 0x00, 0x00, 0x00, // vert_resolution
+            0x00, 0x00, 0x00, 0x00, // reserved2
+            0x00, 0x00, // frame_count
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // compressor_name
+            0x00, 0x00, // depth
+            0x00, 0x00, // pre_defined3
+        ]; "Hvc1"
+    )]
 #[test_case(
         AvcC{
             configuration_version: 0x12,
@@ -583,6 +611,22 @@ use test_case::test_case;
             0x12, 0x34, 0x56, // nalUnit
         ]; "AvcC high profile new spec"
     )]
+#[test_case(
+        Smhd{
+            full_box: FullBox{
+                version: 0,
+                flags:   [0, 0, 0],
+            },
+            balance: 0x0123,
+            reserved: 0,
+        },
+        &[
+            0,                // version
+            0x00, 0x00, 0x00, // flags
+            0x01, 0x23, // balance
+            0x00, 0x00, // reserved
+        ]; "smhd"
+    )]
 #[test_case(Stbl{}, &[]; "stbl")]
 #[test_case(
         Stco{
@@ -5,8 +5,8 @@ pub mod types;
 use async_trait::async_trait;
 use axum::Router;
 use common::{
-    monitor::MonitorConfig, DynAuth, DynEnvConfig, DynLogger, EnvPlugin, LogEntry, LogLevel,
-    LogSource,
+    monitor::MonitorConfig, recording::RecordingId, DynAuth, DynEnvConfig, DynLogger, EnvPlugin,
+    Event, LogEntry, LogLevel, LogSource,
 };
 use libloading::{Library, Symbol};
 use monitor::{Monitor, MonitorHooks, MonitorManager};
@@ -50,6 +50,12 @@ pub trait Plugin {
     fn on_thumb_save(&self, _config: &MonitorConfig, frame: Frame) -> Frame {
         frame
     }
+    // Non-blocking.
+    fn on_event(&self, _config: &MonitorConfig, _event: &Event) {}
+    // Non-blocking.
+    fn on_recording_start(&self, _config: &MonitorConfig, _recording_id: &RecordingId) {}
+    // Non-blocking.
+    fn on_recording_stop(&self, _config: &MonitorConfig, _recording_id: &RecordingId) {}
 }
 
 pub trait Application {
@@ -57,6 +63,8 @@ pub trait Application {
     fn auth(&self) -> DynAuth;
     fn monitor_manager(&self) -> MonitorManager;
     fn shutdown_complete_tx(&self) -> mpsc::Sender<()>;
+    // Cancelled on shutdown.
+    fn token(&self) -> CancellationToken;
     fn logger(&self) -> DynLogger;
     fn env(&self) -> DynEnvConfig;
 }
@@ -240,6 +248,21 @@ impl MonitorHooks for PluginManager {
         }
         frame
     }
+    fn on_event(&self, config: &MonitorConfig, event: &Event) {
+        for plugin in &self.plugins {
+            plugin.on_event(config, event);
+        }
+    }
+    fn on_recording_start(&self, config: &MonitorConfig, recording_id: &RecordingId) {
+        for plugin in &self.plugins {
+            plugin.on_recording_start(config, recording_id);
+        }
+    }
+    fn on_recording_stop(&self, config: &MonitorConfig, recording_id: &RecordingId) {
+        for plugin in &self.plugins {
+            plugin.on_recording_stop(config, recording_id);
+        }
+    }
 }
 
 #[must_use]
@@ -0,0 +1,358 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+use crate::{
+    disk::Disk,
+    events::EVENTS_FILE_NAME,
+    prune::{self, PruneError},
+};
+use common::{recording::RecordingId, time::Duration, time::UnixNano};
+use std::{
+    collections::{BTreeMap, HashSet},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+// Second storage tier, e.g. a large but slow disk. Recordings older than
+// `move_after` are moved from the recordings directory to the archive
+// by the pruner. The archive uses the same directory structure, has its own
+// disk limit and is read transparently. The recording index and the hash
+// chains are kept in the recordings directory.
+pub struct Archive {
+    pub(crate) dir: PathBuf,
+    pub(crate) disk: Disk,
+    pub(crate) move_after: Duration,
+}
+
+impl Archive {
+    #[must_use]
+    pub fn new(dir: PathBuf, disk: Disk, move_after: Duration) -> Self {
+        Self {
+            dir,
+            disk,
+            move_after,
+        }
+    }
+}
+
+// Recording directories in the order they're searched.
+#[derive(Clone, Debug)]
+pub(crate) struct Tiers {
+    hot: PathBuf,
+    archive: Option<PathBuf>,
+}
+
+impl Tiers {
+    pub(crate) fn new(hot: PathBuf, archive: Option<PathBuf>) -> Self {
+        Self { hot, archive }
+    }
+
+    // Recordings directory, new recordings are always stored here.
+    pub(crate) fn hot(&self) -> &Path {
+        &self.hot
+    }
+
+    pub(crate) fn dirs(&self) -> impl Iterator<Item = &Path> {
+        std::iter::once(self.hot.as_path()).chain(self.archive.as_deref())
+    }
+
+    // Returns the path without extension of the recording in the first tier
+    // where its meta file exists. Defaults to the recordings directory.
+    pub(crate) fn recording_path(&self, id: &RecordingId) -> PathBuf {
+        let full_path = id.as_full_path();
+        for dir in self.dirs() {
+            let path = dir.join(&full_path);
+            if path.with_extension("meta").exists() {
+                return path;
+            }
+        }
+        self.hot.join(full_path)
+    }
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum ArchiveError {
+    #[error("list directories: {0}")]
+    ListDirs(std::io::Error),
+
+    #[error("read dir: {0}")]
+    ReadDir(std::io::Error),
+
+    #[error("dir entry: {0}")]
+    DirEntry(std::io::Error),
+
+    #[error("create dir: {0}")]
+    CreateDir(std::io::Error),
+
+    #[error("copy file: {0:?} {1}")]
+    Copy(PathBuf, std::io::Error),
+
+    #[error("remove file: {0:?} {1}")]
+    RemoveFile(PathBuf, std::io::Error),
+
+    #[error("{0}")]
+    Prune(#[from] PruneError),
+}
+
+// Moves the recordings that started before `cutoff` from `hot` to
+// `archive` and returns their ids. Active recordings are skipped.
+// The events file is moved with the last recording of the monitor and day.
+pub(crate) fn archive_recordings(
+    hot: &Path,
+    archive: &Path,
+    cutoff: UnixNano,
+    active: &HashSet<RecordingId>,
+) -> Result<Vec<RecordingId>, ArchiveError> {
+    use ArchiveError::*;
+    let mut archived = Vec::new();
+    for monitor_dir in prune::list_monitor_dirs(hot).map_err(ListDirs)? {
+        let archive_dir = archive.join(
+            monitor_dir
+                .strip_prefix(hot)
+                .expect("monitor dir should be in the recordings dir"),
+        );
+
+        let mut remaining = 0;
+        for (stem, files) in list_recording_files(&monitor_dir)? {
+            let Ok(id) = RecordingId::try_from(stem) else {
+                remaining += 1;
+                continue;
+            };
+            let has_meta = files.iter().any(|v| is_meta(v));
+            let archive_has_meta = archive_dir
+                .join(id.as_path())
+                .with_extension("meta")
+                .exists();
+            if !has_meta && archive_has_meta {
+                // Leftovers from an interrupted move.
+                remove_files(&files)?;
+                continue;
+            }
+            let is_old = id.as_nanos().is_some_and(|v| v.before(cutoff));
+            if !has_meta || !is_old || active.contains(&id) {
+                remaining += 1;
+                continue;
+            }
+            move_recording(&files, &archive_dir)?;
+            archived.push(id);
+        }
+
+        if remaining == 0 {
+            move_events_file(&monitor_dir, &archive_dir)?;
+            prune::remove_monitor_dir_if_empty(hot, &monitor_dir)?;
+        }
+    }
+    prune::remove_empty_dirs(hot)?;
+    Ok(archived)
+}
+
+// Returns the files in the directory grouped by stem, the events file is excluded.
+fn list_recording_files(dir: &Path) -> Result<BTreeMap<String, Vec<PathBuf>>, ArchiveError> {
+    use ArchiveError::*;
+    let mut files: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    for entry in std::fs::read_dir(dir).map_err(ReadDir)? {
+        let entry = entry.map_err(DirEntry)?;
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        if name == EVENTS_FILE_NAME {
+            continue;
+        }
+        let Some((stem, _)) = name.split_once('.') else {
+            continue;
+        };
+        files.entry(stem.to_owned()).or_default().push(entry.path());
+    }
+    Ok(files)
+}
+
+// The files are copied before they're removed in case the archive is on
+// another filesystem. The meta file is copied last and removed first, a
+// recording is read from the archive once the hot meta file is removed.
+fn move_recording(files: &[PathBuf], dst_dir: &Path) -> Result<(), ArchiveError> {
+    use ArchiveError::*;
+    std::fs::create_dir_all(dst_dir).map_err(CreateDir)?;
+
+    let (meta, other): (Vec<_>, Vec<_>) = files.iter().partition(|v| is_meta(v));
+    for path in other.iter().chain(&meta) {
+        let dst = dst_dir.join(path.file_name().expect("file should have a name"));
+        copy_file(path, &dst).map_err(|e| Copy((*path).clone(), e))?;
+    }
+    remove_files(meta.into_iter().chain(other))
+}
+
+fn is_meta(path: &Path) -> bool {
+    path.extension().is_some_and(|v| v == "meta")
+}
+
+fn copy_file(src: &Path, dst: &Path) -> Result<(), std::io::Error> {
+    std::fs::copy(src, dst)?;
+    std::fs::File::open(dst)?.sync_all()
+}
+
+fn remove_files<'a>(files: impl IntoIterator<Item = &'a PathBuf>) -> Result<(), ArchiveError> {
+    for path in files {
+        match std::fs::remove_file(path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(ArchiveError::RemoveFile(path.clone(), e)),
+        }
+    }
+    Ok(())
+}
+
+// Appends the events to the events file in the archive.
+fn move_events_file(src_dir: &Path, dst_dir: &Path) -> Result<(), ArchiveError> {
+    use ArchiveError::*;
+    let src = src_dir.join(EVENTS_FILE_NAME);
+    let raw = match std::fs::read(&src) {
+        Ok(v) => v,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(Copy(src, e)),
+    };
+    std::fs::create_dir_all(dst_dir).map_err(CreateDir)?;
+    let append = || {
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dst_dir.join(EVENTS_FILE_NAME))?;
+        file.write_all(&raw)?;
+        file.sync_all()
+    };
+    append().map_err(|e| Copy(src.clone(), e))?;
+    remove_files([&src])
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use common::time::DAY;
+    use pretty_assertions::assert_eq;
+    use tempfile::TempDir;
+
+    fn write_files(base: &Path, files: &[&str]) {
+        for path in files {
+            let path = base.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "x").unwrap();
+        }
+    }
+
+    fn list_files(base: &Path) -> Vec<String> {
+        let mut list = Vec::new();
+        let mut dirs = vec![base.to_owned()];
+        while let Some(dir) = dirs.pop() {
+            for entry in std::fs::read_dir(&dir).unwrap() {
+                let entry = entry.unwrap();
+                if entry.metadata().unwrap().is_dir() {
+                    dirs.push(entry.path());
+                } else {
+                    let path = entry.path();
+                    list.push(
+                        path.strip_prefix(base)
+                            .unwrap()
+                            .to_string_lossy()
+                            .to_string(),
+                    );
+                }
+            }
+        }
+        list.sort();
+        list
+    }
+
+    fn r_id(s: &str) -> RecordingId {
+        s.to_owned().try_into().unwrap()
+    }
+
+    #[test]
+    fn test_archive_recordings() {
+        let temp_dir = TempDir::new().unwrap();
+        let hot = temp_dir.path().join("hot");
+        let archive = temp_dir.path().join("archive");
+        write_files(
+            &hot,
+            &[
+                "1970/01/01/m1/1970-01-01_00-00-00_m1.meta",
+                "1970/01/01/m1/1970-01-01_00-00-00_m1.mdat",
+                "1970/01/01/m1/1970-01-01_00-00-00_m1.lock",
+                "1970/01/01/m1/events.jsonl",
+                // Active.
+                "1970/01/01/m2/1970-01-01_00-00-00_m2.meta",
+                "1970/01/01/m2/events.jsonl",
+                // Too new.
+                "1970/01/03/m1/1970-01-03_00-00-00_m1.meta",
+                // Leftover from an interrupted move.
+                "1970/01/02/m1/1970-01-02_00-00-00_m1.json",
+            ],
+        );
+        write_files(
+            &archive,
+            &[
+                "1970/01/01/m1/events.jsonl",
+                "1970/01/02/m1/1970-01-02_00-00-00_m1.meta",
+                "1970/01/02/m1/1970-01-02_00-00-00_m1.json",
+            ],
+        );
+
+        let active = HashSet::from([r_id("1970-01-01_00-00-00_m2")]);
+        let archived = archive_recordings(&hot, &archive, UnixNano::new(2 * DAY), &active).unwrap();
+        assert_eq!(vec![r_id("1970-01-01_00-00-00_m1")], archived);
+
+        assert_eq!(
+            vec![
+                "1970/01/01/m2/1970-01-01_00-00-00_m2.meta",
+                "1970/01/01/m2/events.jsonl",
+                "1970/01/03/m1/1970-01-03_00-00-00_m1.meta",
+            ],
+            list_files(&hot)
+        );
+        assert_eq!(
+            vec![
+                "1970/01/01/m1/1970-01-01_00-00-00_m1.lock",
+                "1970/01/01/m1/1970-01-01_00-00-00_m1.mdat",
+                "1970/01/01/m1/1970-01-01_00-00-00_m1.meta",
+                "1970/01/01/m1/events.jsonl",
+                "1970/01/02/m1/1970-01-02_00-00-00_m1.json",
+                "1970/01/02/m1/1970-01-02_00-00-00_m1.meta",
+            ],
+            list_files(&archive)
+        );
+        // Events are appended to the existing file.
+        assert_eq!(
+            "xx",
+            std::fs::read_to_string(archive.join("1970/01/01/m1/events.jsonl")).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_tiers_recording_path() {
+        let temp_dir = TempDir::new().unwrap();
+        let hot = temp_dir.path().join("hot");
+        let archive = temp_dir.path().join("archive");
+        write_files(
+            &hot,
+            &[
+                "2000/01/01/m1/2000-01-01_00-00-00_m1.meta",
+                "2000/01/01/m1/2000-01-01_00-00-01_m1.mdat",
+            ],
+        );
+        write_files(
+            &archive,
+            &[
+                "2000/01/01/m1/2000-01-01_00-00-00_m1.meta",
+                "2000/01/01/m1/2000-01-01_00-00-01_m1.meta",
+            ],
+        );
+        let tiers = Tiers::new(hot.clone(), Some(archive.clone()));
+        let path = |id| tiers.recording_path(&r_id(id));
+
+        let rel_path = "2000/01/01/m1/2000-01-01_00-00-00_m1";
+        assert_eq!(hot.join(rel_path), path("2000-01-01_00-00-00_m1"));
+        let rel_path = "2000/01/01/m1/2000-01-01_00-00-01_m1";
+        assert_eq!(archive.join(rel_path), path("2000-01-01_00-00-01_m1"));
+        let rel_path = "2000/01/01/m1/2000-01-01_00-00-02_m1";
+        assert_eq!(hot.join(rel_path), path("2000-01-01_00-00-02_m1"));
+    }
+}
@@ -3,10 +3,15 @@
 use crate::{
     RecDbQuery, RecordingActive, RecordingFinalized, RecordingIncomplete, RecordingResponse,
 };
-use common::recording::{RecordingData, RecordingId};
+use common::{
+    recording::{RecordingData, RecordingId},
+    EncryptionKey,
+};
 use fs::{DynFs, Entry, FsError, Open};
+use recording::decrypt_file;
 use std::{
     collections::{HashMap, HashSet},
+    io::ErrorKind,
     path::PathBuf,
     str::FromStr,
     vec::IntoIter,
@@ -23,7 +28,8 @@ use thiserror::Error;
 //             ├── YYYY-MM-DD_hh-mm-ss_monitor2.jpeg  // Thumbnail.
 //             ├── YYYY-MM-DD_hh-mm-ss_monitor2.meta  // Video metadata.
 //             ├── YYYY-MM-DD_hh-mm-ss_monitor2.mdat  // Raw video data.
-//             └── YYYY-MM-DD_hh-mm-ss_monitor2.json  // Event data.
+//             ├── YYYY-MM-DD_hh-mm-ss_monitor2.json  // Event data.
+//             └── YYYY-MM-DD_hh-mm-ss_monitor2.lock  // Optional lock marker.
 //
 // Event data is only generated if video was saved successfully.
 // The job of these functions are to on-request find and return recording IDs.
@@ -38,15 +44,36 @@ pub enum CrawlerError {
     UnexpectedDir(PathBuf),
 }
 
-// Crawls through storage looking for recordings.
+// Crawls through storage looking for recordings,
+// used to rebuild the recording index.
 pub struct Crawler {
     fs: DynFs,
+    archive_fs: Option<DynFs>,
+    encryption_key: Option<EncryptionKey>,
 }
 
 impl Crawler {
     #[must_use]
     pub(crate) fn new(fs: DynFs) -> Self {
-        Self { fs }
+        Self {
+            fs,
+            archive_fs: None,
+            encryption_key: None,
+        }
+    }
+
+    // Archive tier that's crawled together with `fs`.
+    #[must_use]
+    pub(crate) fn with_archive(mut self, fs: Option<DynFs>) -> Self {
+        self.archive_fs = fs;
+        self
+    }
+
+    // Key used to decrypt data files.
+    #[must_use]
+    pub(crate) fn with_encryption_key(mut self, key: Option<EncryptionKey>) -> Self {
+        self.encryption_key = key;
+        self
     }
 
     // finds the best matching recording and
@@ -57,9 +84,32 @@ impl Crawler {
         active_recordings: HashSet<RecordingId>,
     ) -> Result<Vec<RecordingResponse>, CrawlerError> {
         let fs = self.fs.clone();
-        tokio::task::spawn_blocking(move || recordings_by_query(&fs, &query, &active_recordings))
-            .await
-            .expect("join")
+        let archive_fs = self.archive_fs.as_ref().map(|fs| (**fs).clone());
+        let key = self.encryption_key.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut recordings =
+                recordings_by_query(&fs, &query, &active_recordings, key.as_ref())?;
+            let Some(archive_fs) = archive_fs else {
+                return Ok(recordings);
+            };
+            // Both tiers are sorted, merge them and keep the first `limit`.
+            recordings.extend(recordings_by_query(
+                &archive_fs,
+                &query,
+                &active_recordings,
+                key.as_ref(),
+            )?);
+            if query.reverse {
+                recordings.sort_by(|a, b| a.id().cmp(b.id()));
+            } else {
+                recordings.sort_by(|a, b| b.id().cmp(a.id()));
+            }
+            recordings.dedup_by(|a, b| a.id() == b.id());
+            recordings.truncate(query.limit.get());
+            Ok(recordings)
+        })
+        .await
+        .expect("join")
     }
 }
 
@@ -67,9 +117,17 @@ fn recordings_by_query(
     fs: &DynFs,
     query: &RecDbQuery,
     active_recordings: &HashSet<RecordingId>,
+    key: Option<&EncryptionKey>,
 ) -> Result<Vec<RecordingResponse>, CrawlerError> {
     let mut recordings = Vec::new();
-    let mut year_iter = DirIterYear::new(fs, query.clone())?;
+    let mut year_iter = match DirIterYear::new(fs, query.clone()) {
+        Ok(v) => v,
+        // The directory is created by the first recording.
+        Err(CrawlerError::Fs(FsError::Io(e))) if e.kind() == ErrorKind::NotFound => {
+            return Ok(recordings)
+        }
+        Err(e) => return Err(e),
+    };
     while recordings.len() < query.limit.get() {
         let mut rec = match year_iter.next() {
             Some(rec) => rec?,
@@ -78,6 +136,7 @@ fn recordings_by_query(
         };
 
         let id = rec.id;
+        let locked = rec.locked;
         if let Some(end) = &query.end {
             if query.reverse && &id > end || !query.reverse && &id < end {
                 break;
@@ -86,33 +145,37 @@ fn recordings_by_query(
 
         let is_active = active_recordings.contains(&id);
         if is_active {
-            recordings.push(RecordingResponse::Active(RecordingActive { id }));
+            recordings.push(RecordingResponse::Active(RecordingActive { id, locked }));
             continue;
         }
 
         let Some(json_file) = rec.json_file.take() else {
-            recordings.push(RecordingResponse::Incomplete(RecordingIncomplete { id }));
+            recordings.push(RecordingResponse::Incomplete(RecordingIncomplete {
+                id,
+                locked,
+            }));
             continue;
         };
 
         let data = if query.include_data {
-            read_data_file(&json_file)
+            read_data_file(&json_file, key)
         } else {
             None
         };
         recordings.push(RecordingResponse::Finalized(RecordingFinalized {
             id,
+            locked,
             data,
         }));
     }
     Ok(recordings)
 }
 
-fn read_data_file(fs: &DynFs) -> Option<RecordingData> {
+fn read_data_file(fs: &DynFs, key: Option<&EncryptionKey>) -> Option<RecordingData> {
     let Ok(Open::File(mut file)) = fs.open(&PathBuf::from(".")) else {
         return None;
     };
-    let raw_data = file.read().ok()?;
+    let raw_data = decrypt_file(file.read().ok()?, key).ok()?;
     serde_json::from_slice::<RecordingData>(&raw_data).ok()
 }
 
@@ -404,6 +467,7 @@ impl DirIterRec {
 
             let mut meta_files = Vec::new();
             let mut json_files = HashMap::new();
+            let mut lock_files = HashSet::new();
 
             let files = montor_fs.read_dir()?;
             for file in &files {
@@ -423,6 +487,8 @@ impl DirIterRec {
                     let name = name.trim_end_matches(".json");
                     let file_fs = montor_fs.sub(file.name())?;
                     json_files.insert(name, file_fs);
+                } else if ext == "lock" {
+                    lock_files.insert(name.trim_end_matches(".lock"));
                 }
             }
 
@@ -433,6 +499,7 @@ impl DirIterRec {
                 all_files.push(DirRec {
                     id,
                     json_file: json_files.remove(name),
+                    locked: lock_files.contains(name),
                 });
             }
         }
@@ -446,6 +513,7 @@ impl DirIterRec {
 struct DirRec {
     id: RecordingId,
     json_file: Option<DynFs>,
+    locked: bool,
 }
 
 fn monitor_selected(monitors: &[String], monitor: &str) -> bool {
@@ -459,7 +527,7 @@ fn monitor_selected(monitors: &[String], monitor: &str) -> bool {
 #[allow(clippy::unwrap_used)]
 mod tests {
     use super::*;
-    use crate::RecDbQuery;
+    use crate::{RecDbQuery, RecordingFilter};
     use fs::{MapEntry, MapFs};
     use pretty_assertions::assert_eq;
     use std::num::NonZeroUsize;
@@ -561,6 +629,7 @@ mod tests {
             reverse: false,
             monitors: Vec::new(),
             include_data: false,
+            filter: RecordingFilter::default(),
         };
         let rec = match Crawler::new(crawler_test_fs())
             .recordings_by_query(query, HashSet::new())
@@ -601,6 +670,7 @@ mod tests {
             reverse: true,
             monitors: Vec::new(),
             include_data: false,
+            filter: RecordingFilter::default(),
         };
         let rec = match Crawler::new(crawler_test_fs())
             .recordings_by_query(query, HashSet::new())
@@ -630,6 +700,7 @@ mod tests {
             reverse: false,
             monitors: Vec::new(),
             include_data: false,
+            filter: RecordingFilter::default(),
         };
         let recordings = c.recordings_by_query(query, HashSet::new()).await.unwrap();
 
@@ -651,6 +722,40 @@ mod tests {
         assert_eq!(want, ids);
     }
 
+    #[test_case(false, &["2004-01-01_01-01-22_m1", "2004-01-01_01-01-11_m1", "2003-06-01_01-01-11_m1"]; "backwards")]
+    #[test_case(true, &["2000-01-01_01-01-01_m1", "2000-01-01_01-01-11_m1", "2000-01-01_01-01-22_m1"]; "forwards")]
+    #[tokio::test]
+    async fn test_recording_by_query_archive(reverse: bool, want: &[&str]) {
+        let archive_fs: DynFs = Box::new(MapFs(
+            [
+                map_fs_item("2000/01/01/m1/2000-01-01_01-01-01_m1"),
+                map_fs_item("2003/06/01/m1/2003-06-01_01-01-11_m1"),
+                // Duplicate from an interrupted move.
+                map_fs_item("2004/01/01/m1/2004-01-01_01-01-22_m1"),
+            ]
+            .into_iter()
+            .flatten()
+            .collect(),
+        ));
+        let c = Crawler::new(crawler_test_fs()).with_archive(Some(archive_fs));
+        let query = RecDbQuery {
+            recording_id: r_id(if reverse {
+                "0000-01-01_01-01-01_x"
+            } else {
+                "2004-01-01_01-01-23_x"
+            }),
+            end: None,
+            limit: NonZeroUsize::new(3).unwrap(),
+            reverse,
+            monitors: Vec::new(),
+            include_data: false,
+            filter: RecordingFilter::default(),
+        };
+        let recordings = c.recordings_by_query(query, HashSet::new()).await.unwrap();
+        let ids: Vec<_> = recordings.iter().map(|v| v.id().as_str()).collect();
+        assert_eq!(want, ids);
+    }
+
     #[tokio::test]
     async fn test_recording_by_query_monitors() {
         let c = Crawler::new(crawler_test_fs());
@@ -661,6 +766,7 @@ mod tests {
             reverse: false,
             monitors: vec!["m1".to_owned()],
             include_data: false,
+            filter: RecordingFilter::default(),
         };
         let rec = c.recordings_by_query(query, HashSet::new()).await.unwrap();
         assert_eq!(1, rec.len());
@@ -680,6 +786,7 @@ mod tests {
             reverse: false,
             monitors: Vec::new(),
             include_data: true,
+            filter: RecordingFilter::default(),
         };
         let rec = c.recordings_by_query(query, HashSet::new()).await.unwrap();
         let RecordingResponse::Finalized(rec) = &rec[0] else {
@@ -701,6 +808,7 @@ mod tests {
             reverse: true,
             monitors: Vec::new(),
             include_data: true,
+            filter: RecordingFilter::default(),
         };
         let rec = c.recordings_by_query(query, HashSet::new()).await.unwrap();
         let RecordingResponse::Finalized(rec) = &rec[0] else {
@@ -709,6 +817,54 @@ mod tests {
         assert!(rec.data.is_none());
     }
 
+    #[tokio::test]
+    async fn test_recording_by_query_locked() {
+        let mut files: HashMap<PathBuf, MapEntry> = [
+            map_fs_item("2000/01/01/m1/2000-01-01_01-01-11_m1"),
+            map_fs_item("2000/01/01/m1/2000-01-01_01-01-22_m1"),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        files.insert(
+            PathBuf::from("2000/01/01/m1/2000-01-01_01-01-11_m1.lock"),
+            MapEntry {
+                is_file: true,
+                ..Default::default()
+            },
+        );
+
+        let query = RecDbQuery {
+            recording_id: r_id("9999-01-01_01-01-01_m1"),
+            end: None,
+            limit: NonZeroUsize::new(2).unwrap(),
+            reverse: false,
+            monitors: Vec::new(),
+            include_data: false,
+            filter: RecordingFilter::default(),
+        };
+        let recs = Crawler::new(Box::new(MapFs(files)))
+            .recordings_by_query(query, HashSet::new())
+            .await
+            .unwrap();
+        let locked: Vec<_> = recs
+            .iter()
+            .map(|rec| {
+                let RecordingResponse::Finalized(rec) = rec else {
+                    panic!("expected finalized")
+                };
+                (rec.id.as_str(), rec.locked)
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                ("2000-01-01_01-01-22_m1", false),
+                ("2000-01-01_01-01-11_m1", true)
+            ],
+            locked
+        );
+    }
+
     #[tokio::test]
     async fn test_inexact_propagation() {
         let dirs = Box::new(MapFs(
@@ -725,6 +881,7 @@ mod tests {
             reverse: false,
             monitors: Vec::new(),
             include_data: false,
+            filter: RecordingFilter::default(),
         };
         let rec = match Crawler::new(dirs)
             .recordings_by_query(query, HashSet::new())
@@ -752,6 +909,7 @@ mod tests {
             reverse: false,
             monitors: Vec::new(),
             include_data: false,
+            filter: RecordingFilter::default(),
         };
         let recordings = c.recordings_by_query(query, HashSet::new()).await.unwrap();
 
@@ -782,6 +940,7 @@ mod tests {
             reverse: true,
             monitors: Vec::new(),
             include_data: false,
+            filter: RecordingFilter::default(),
         };
         let recordings = c.recordings_by_query(query, HashSet::new()).await.unwrap();
 
@@ -831,6 +990,7 @@ mod tests {
             reverse,
             monitors: Vec::new(),
             include_data: false,
+            filter: RecordingFilter::default(),
         };
         let rec = match Crawler::new(dirs)
             .recordings_by_query(query, HashSet::new())
@@ -874,6 +1034,7 @@ mod tests {
             reverse,
             monitors: Vec::new(),
             include_data: false,
+            filter: RecordingFilter::default(),
         };
         let rec = match Crawler::new(dirs)
             .recordings_by_query(query, HashSet::new())
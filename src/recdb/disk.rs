@@ -9,7 +9,8 @@ use tokio::sync::Mutex;
 
 #[async_trait]
 pub(crate) trait DiskBytesUsed {
-    async fn bytes(&self, path: PathBuf) -> Result<u64, UsageBytesError>;
+    // Returns the size of the directory excluding `exclude`.
+    async fn bytes(&self, path: PathBuf, exclude: Option<PathBuf>) -> Result<u64, UsageBytesError>;
 }
 
 // Only used to calculate and cache disk usage.
@@ -19,6 +20,9 @@ pub struct Disk {
     max_disk_usage: ByteSize,
     disk_usage: Box<dyn DiskBytesUsed + Send + Sync>,
 
+    // Subdirectory that doesn't count toward the disk usage.
+    excluded_dir: Option<PathBuf>,
+
     cache: Mutex<Option<DiskCache>>,
     update_lock: Mutex<()>,
 }
@@ -46,10 +50,18 @@ impl Disk {
             max_disk_usage,
             cache: Mutex::new(None),
             disk_usage: Box::new(DiskUsageBytes),
+            excluded_dir: None,
             update_lock: Mutex::new(()),
         }
     }
 
+    // Excludes a subdirectory of the storage directory from the disk usage.
+    #[must_use]
+    pub fn with_excluded_dir(mut self, dir: PathBuf) -> Self {
+        self.excluded_dir = Some(dir);
+        self
+    }
+
     #[must_use]
     #[cfg(test)]
     pub(crate) fn with_disk_usage(
@@ -62,10 +74,15 @@ impl Disk {
             max_disk_usage,
             cache: Mutex::new(None),
             disk_usage,
+            excluded_dir: None,
             update_lock: Mutex::new(()),
         }
     }
 
+    pub(crate) fn max_disk_usage(&self) -> u64 {
+        self.max_disk_usage.as_u64()
+    }
+
     pub(crate) async fn usage(&self, max_age: Duration) -> Result<DiskUsage, UsageError> {
         use UsageError::*;
         let max_time = UnixNano::now().checked_sub(max_age.into()).ok_or(Sub)?;
@@ -111,7 +128,10 @@ impl Disk {
         clippy::as_conversions
     )]
     async fn calculate_disk_usage(&self) -> Result<DiskUsage, UsageBytesError> {
-        let used = self.disk_usage.bytes(self.storage_dir.clone()).await?;
+        let used = self
+            .disk_usage
+            .bytes(self.storage_dir.clone(), self.excluded_dir.clone())
+            .await?;
         let percent = (((used * 100) as f64) / (self.max_disk_usage.as_u64() as f64)) as f32;
         let max = self.max_disk_usage.as_u64() / GB;
         Ok(DiskUsage {
@@ -183,7 +203,7 @@ pub enum UsageBytesError {
 
 #[async_trait]
 impl DiskBytesUsed for DiskUsageBytes {
-    async fn bytes(&self, path: PathBuf) -> Result<u64, UsageBytesError> {
+    async fn bytes(&self, path: PathBuf, exclude: Option<PathBuf>) -> Result<u64, UsageBytesError> {
         tokio::task::spawn_blocking(move || -> Result<u64, UsageBytesError> {
             use UsageBytesError::*;
             let mut total = 0;
@@ -192,6 +212,9 @@ impl DiskBytesUsed for DiskUsageBytes {
             while let Some(dir) = dirs.pop() {
                 for entry in std::fs::read_dir(&dir).map_err(|e| ReadDir(e, dir.clone()))? {
                     let entry = entry.map_err(DirEntry)?;
+                    if exclude.as_ref().is_some_and(|v| *v == entry.path()) {
+                        continue;
+                    }
                     let metadata = entry.metadata().map_err(Metadata)?;
 
                     total += metadata.len();
@@ -221,7 +244,7 @@ pub(crate) struct StubDiskUsageBytes(pub u64);
 
 #[async_trait]
 impl DiskBytesUsed for StubDiskUsageBytes {
-    async fn bytes(&self, _: PathBuf) -> Result<u64, UsageBytesError> {
+    async fn bytes(&self, _: PathBuf, _: Option<PathBuf>) -> Result<u64, UsageBytesError> {
         Ok(self.0)
     }
 }
@@ -274,6 +297,7 @@ mod tests {
             storage_dir: PathBuf::new(),
             max_disk_usage: ByteSize(0),
             disk_usage: Box::new(StubDiskUsageBytes(0)),
+            excluded_dir: None,
             update_lock: Mutex::new(()),
         };
         let (got, age) = d.usage_cached().await.unwrap();
@@ -330,6 +354,24 @@ mod tests {
         assert_eq!(want, got);
     }
 
+    #[tokio::test]
+    async fn test_disk_usage_bytes_excluded_dir() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        std::fs::write(path.join("a"), [0; 10]).unwrap();
+        std::fs::create_dir(path.join("exports")).unwrap();
+        std::fs::write(path.join("exports/b"), [0; 100]).unwrap();
+
+        let total = DiskUsageBytes.bytes(path.clone(), None).await.unwrap();
+        assert!(total >= 110);
+
+        let got = DiskUsageBytes
+            .bytes(path.clone(), Some(path.join("exports")))
+            .await
+            .unwrap();
+        assert_eq!(10, got);
+    }
+
     /*t.Run("CensorLog", func(t *testing.T) {
         cases := map[string]struct {
             env      ConfigEnv
@@ -0,0 +1,357 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+use common::{
+    time::{UnixNano, DAY},
+    EncryptionKey, Event, MonitorId,
+};
+use csv::deserialize_csv_option;
+use recording::{decrypt_file, encrypt_file};
+use serde::{Deserialize, Serialize};
+use std::{
+    io::ErrorKind,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+use tokio::io::AsyncWriteExt;
+
+// Events are indexed independently of the recordings, one
+// file per monitor and day next to the recordings.
+//
+// <Year>
+// └── <Month>
+//     └── <Day>
+//         └── Monitor1
+//             └── events.jsonl
+//
+// Events are pruned together with the last recording of the monitor and day.
+// If encryption is enabled, each line is encrypted separately and base64 encoded.
+pub(crate) const EVENTS_FILE_NAME: &str = "events.jsonl";
+
+// Each day in the range is read from disk.
+const MAX_QUERY_DURATION: i64 = 7 * DAY;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct EventQuery {
+    pub start: UnixNano,
+    pub end: UnixNano,
+
+    #[serde(default)]
+    #[serde(deserialize_with = "deserialize_csv_option")]
+    pub monitors: Vec<String>,
+
+    pub limit: NonZeroUsize,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MonitorEvent {
+    #[serde(rename = "monitorId")]
+    pub monitor_id: MonitorId,
+
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+#[derive(Debug, Error)]
+pub enum SaveEventError {
+    #[error("invalid event time")]
+    InvalidTime,
+
+    #[error("create directory: {0}")]
+    CreateDir(std::io::Error),
+
+    #[error("serialize event: {0}")]
+    Serialize(#[from] serde_json::Error),
+
+    #[error("encrypt event: {0}")]
+    Encrypt(std::io::Error),
+
+    #[error("open file: {0}")]
+    OpenFile(std::io::Error),
+
+    #[error("write file: {0}")]
+    WriteFile(std::io::Error),
+}
+
+#[derive(Debug, Error)]
+pub enum QueryEventsError {
+    #[error("invalid time")]
+    InvalidTime,
+
+    #[error("max duration is 7 days")]
+    MaxDuration,
+
+    #[error("read dir: {0}")]
+    ReadDir(std::io::Error),
+
+    #[error("dir entry: {0}")]
+    DirEntry(std::io::Error),
+
+    #[error("read file: {0}")]
+    ReadFile(std::io::Error),
+}
+
+// Appends event to the index of the day it occurred.
+pub(crate) async fn save_event(
+    recordings_dir: &Path,
+    monitor_id: &MonitorId,
+    event: &Event,
+    key: Option<&EncryptionKey>,
+) -> Result<(), SaveEventError> {
+    use SaveEventError::*;
+    let day = event.time.as_chrono().ok_or(InvalidTime)?;
+    let dir = recordings_dir
+        .join(day.format("%Y/%m/%d").to_string())
+        .join(monitor_id.to_string());
+    tokio::fs::create_dir_all(&dir).await.map_err(CreateDir)?;
+
+    let json = serde_json::to_vec(event)?;
+    let mut line = match key {
+        Some(key) => {
+            let raw = encrypt_file(&json, key).map_err(Encrypt)?;
+            BASE64_STANDARD.encode(raw).into_bytes()
+        }
+        None => json,
+    };
+    line.push(b'\n');
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(EVENTS_FILE_NAME))
+        .await
+        .map_err(OpenFile)?;
+    file.write_all(&line).await.map_err(WriteFile)?;
+    file.flush().await.map_err(WriteFile)?;
+    Ok(())
+}
+
+// Returns events between start and end sorted by time.
+pub(crate) fn query_events(
+    recordings_dir: &Path,
+    query: &EventQuery,
+    key: Option<&EncryptionKey>,
+) -> Result<Vec<MonitorEvent>, QueryEventsError> {
+    use QueryEventsError::*;
+    if query.end.before(query.start) {
+        return Ok(Vec::new());
+    }
+    if query.end - query.start > UnixNano::new(MAX_QUERY_DURATION) {
+        return Err(MaxDuration);
+    }
+
+    let mut day = query.start.as_chrono().ok_or(InvalidTime)?.date();
+    let last_day = query.end.as_chrono().ok_or(InvalidTime)?.date();
+
+    let mut events = Vec::new();
+    while day <= last_day {
+        let day_dir = recordings_dir.join(day.format("%Y/%m/%d").to_string());
+        for (monitor_id, path) in list_event_files(&day_dir, &query.monitors)? {
+            let raw = match std::fs::read_to_string(&path) {
+                Ok(v) => v,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(ReadFile(e)),
+            };
+            for line in raw.lines() {
+                // The last line may be incomplete after a crash.
+                let Some(event) = parse_line(line, key) else {
+                    continue;
+                };
+                if event.time.before(query.start) || !event.time.before(query.end) {
+                    continue;
+                }
+                events.push(MonitorEvent {
+                    monitor_id: monitor_id.clone(),
+                    event,
+                });
+            }
+        }
+        let Some(next_day) = day.succ_opt() else {
+            break;
+        };
+        day = next_day;
+    }
+
+    events.sort_by_key(|v| v.event.time);
+    events.truncate(query.limit.get());
+    Ok(events)
+}
+
+// Events saved before encryption was enabled are plain JSON.
+fn parse_line(line: &str, key: Option<&EncryptionKey>) -> Option<Event> {
+    if line.starts_with('{') {
+        return serde_json::from_str(line).ok();
+    }
+    let raw = decrypt_file(BASE64_STANDARD.decode(line).ok()?, key).ok()?;
+    serde_json::from_slice(&raw).ok()
+}
+
+fn list_event_files(
+    day_dir: &Path,
+    monitors: &[String],
+) -> Result<Vec<(MonitorId, PathBuf)>, QueryEventsError> {
+    use QueryEventsError::*;
+    let entries = match std::fs::read_dir(day_dir) {
+        Ok(v) => v,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(ReadDir(e)),
+    };
+
+    let mut files = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(DirEntry)?;
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        if !monitors.is_empty() && !monitors.contains(&name) {
+            continue;
+        }
+        let Ok(monitor_id) = MonitorId::try_from(name) else {
+            continue;
+        };
+        files.push((monitor_id, entry.path().join(EVENTS_FILE_NAME)));
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use common::time::Duration;
+    use pretty_assertions::assert_eq;
+    use tempfile::TempDir;
+
+    fn m_id(s: &str) -> MonitorId {
+        s.to_owned().try_into().unwrap()
+    }
+
+    fn test_event(time: i64) -> Event {
+        Event {
+            time: UnixNano::new(time),
+            duration: Duration::new(1),
+            rec_duration: Duration::new(0),
+            detections: Vec::new(),
+        }
+    }
+
+    fn test_query(start: i64, end: i64, monitors: &[&str]) -> EventQuery {
+        EventQuery {
+            start: UnixNano::new(start),
+            end: UnixNano::new(end),
+            monitors: monitors.iter().map(|v| (*v).to_owned()).collect(),
+            limit: NonZeroUsize::new(100).unwrap(),
+        }
+    }
+
+    fn times(events: &[MonitorEvent]) -> Vec<(String, i64)> {
+        events
+            .iter()
+            .map(|v| (v.monitor_id.to_string(), *v.event.time))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_save_and_query_events() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+
+        save_event(dir, &m_id("m1"), &test_event(DAY + 3), None)
+            .await
+            .unwrap();
+        save_event(dir, &m_id("m2"), &test_event(DAY + 2), None)
+            .await
+            .unwrap();
+        save_event(dir, &m_id("m1"), &test_event(2 * DAY + 1), None)
+            .await
+            .unwrap();
+        save_event(dir, &m_id("m1"), &test_event(5 * DAY), None)
+            .await
+            .unwrap();
+
+        let events = query_events(dir, &test_query(DAY, 3 * DAY, &[]), None).unwrap();
+        assert_eq!(
+            vec![
+                ("m2".to_owned(), DAY + 2),
+                ("m1".to_owned(), DAY + 3),
+                ("m1".to_owned(), 2 * DAY + 1),
+            ],
+            times(&events)
+        );
+
+        let events = query_events(dir, &test_query(DAY, 3 * DAY, &["m2"]), None).unwrap();
+        assert_eq!(vec![("m2".to_owned(), DAY + 2)], times(&events));
+
+        // End is exclusive.
+        let events = query_events(dir, &test_query(DAY + 3, 5 * DAY, &[]), None).unwrap();
+        assert_eq!(
+            vec![("m1".to_owned(), DAY + 3), ("m1".to_owned(), 2 * DAY + 1)],
+            times(&events)
+        );
+
+        let mut query = test_query(0, 6 * DAY, &[]);
+        query.limit = NonZeroUsize::new(1).unwrap();
+        let events = query_events(dir, &query, None).unwrap();
+        assert_eq!(vec![("m2".to_owned(), DAY + 2)], times(&events));
+    }
+
+    #[tokio::test]
+    async fn test_query_events_partial_line() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+
+        save_event(dir, &m_id("m1"), &test_event(1), None)
+            .await
+            .unwrap();
+        let path = dir.join("1970/01/01/m1").join(EVENTS_FILE_NAME);
+        let mut raw = std::fs::read(&path).unwrap();
+        raw.extend_from_slice(b"{\"time\":");
+        std::fs::write(&path, raw).unwrap();
+
+        let events = query_events(dir, &test_query(0, DAY, &[]), None).unwrap();
+        assert_eq!(vec![("m1".to_owned(), 1)], times(&events));
+    }
+
+    #[tokio::test]
+    async fn test_query_events_encrypted() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        let key = EncryptionKey::new([1; 32]);
+
+        // Saved before encryption was enabled.
+        save_event(dir, &m_id("m1"), &test_event(1), None)
+            .await
+            .unwrap();
+        save_event(dir, &m_id("m1"), &test_event(2), Some(&key))
+            .await
+            .unwrap();
+        let raw =
+            std::fs::read_to_string(dir.join("1970/01/01/m1").join(EVENTS_FILE_NAME)).unwrap();
+        assert!(!raw.lines().nth(1).unwrap().contains("time"));
+
+        let events = query_events(dir, &test_query(0, DAY, &[]), Some(&key)).unwrap();
+        assert_eq!(
+            vec![("m1".to_owned(), 1), ("m1".to_owned(), 2)],
+            times(&events)
+        );
+
+        // Encrypted events can't be read without the key.
+        let events = query_events(dir, &test_query(0, DAY, &[]), None).unwrap();
+        assert_eq!(vec![("m1".to_owned(), 1)], times(&events));
+    }
+
+    #[test]
+    fn test_query_events_max_duration() {
+        let temp_dir = TempDir::new().unwrap();
+        let result = query_events(temp_dir.path(), &test_query(0, 8 * DAY, &[]), None);
+        assert!(matches!(result, Err(QueryEventsError::MaxDuration)));
+    }
+
+    #[test]
+    fn test_query_events_empty() {
+        let temp_dir = TempDir::new().unwrap();
+        let events = query_events(temp_dir.path(), &test_query(0, DAY, &[]), None).unwrap();
+        assert!(events.is_empty());
+    }
+}
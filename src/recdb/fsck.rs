@@ -0,0 +1,443 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+use crate::events::{query_events, EventQuery};
+use common::{recording::RecordingData, time::UnixNano, MonitorId, VideoCodec};
+use recording::{
+    is_encrypted, new_video_reader, read_meta, CreateVideoReaderError, ReadMetaError, Sample,
+};
+use std::{
+    collections::{HashSet, VecDeque},
+    io::ErrorKind,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+// Files that belong to a recording and are orphaned without the meta file.
+const ORPHAN_EXTENSIONS: [&str; 6] = ["mdat", "json", "jpeg", "sprite", "vtt", "lock"];
+
+// Generates a jpeg thumbnail from a H.264 IDR sample and the extradata.
+pub type ThumbnailFn<'a> = &'a (dyn Fn(Vec<u8>, Vec<u8>) -> Result<Vec<u8>, String> + Sync);
+
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Default)]
+pub struct FsckFiles {
+    // Recording paths without extension.
+    pub recordings: Vec<PathBuf>,
+
+    // Recording files without a meta file.
+    pub orphans: Vec<PathBuf>,
+}
+
+#[derive(Debug, Error)]
+pub enum ListFsckFilesError {
+    #[error("read dir: {0}")]
+    ReadDir(std::io::Error),
+
+    #[error("dir entry: {0}")]
+    DirEntry(std::io::Error),
+
+    #[error("file type: {0}")]
+    FileType(std::io::Error),
+}
+
+// Lists all recordings and orphaned files in the recordings directory.
+pub fn list_fsck_files(recordings_dir: &Path) -> Result<FsckFiles, ListFsckFilesError> {
+    use ListFsckFilesError::*;
+    let mut meta_files = HashSet::new();
+    let mut other_files = Vec::new();
+    let mut dirs_to_visit = VecDeque::from([recordings_dir.to_path_buf()]);
+    while let Some(dir) = dirs_to_visit.pop_front() {
+        for entry in std::fs::read_dir(dir).map_err(ReadDir)? {
+            let entry = entry.map_err(DirEntry)?;
+            let path = entry.path();
+            if entry.file_type().map_err(FileType)?.is_dir() {
+                dirs_to_visit.push_back(path);
+                continue;
+            }
+            match path.extension().and_then(|v| v.to_str()) {
+                Some("meta") => {
+                    meta_files.insert(path.with_extension(""));
+                }
+                Some(ext) if ORPHAN_EXTENSIONS.contains(&ext) => other_files.push(path),
+                _ => {}
+            }
+        }
+    }
+
+    let mut orphans: Vec<_> = other_files
+        .into_iter()
+        .filter(|v| !meta_files.contains(&v.with_extension("")))
+        .collect();
+    orphans.sort();
+    let mut recordings: Vec<_> = meta_files.into_iter().collect();
+    recordings.sort();
+    Ok(FsckFiles {
+        recordings,
+        orphans,
+    })
+}
+
+#[derive(Debug, Error)]
+pub enum CheckRecordingError {
+    #[error("missing mdat file")]
+    MissingMdat,
+
+    #[error("encrypted recordings can't be checked")]
+    Encrypted,
+
+    #[error("read file: {0}")]
+    ReadFile(std::io::Error),
+
+    #[error("read meta: {0}")]
+    ReadMeta(#[from] ReadMetaError),
+
+    #[error("no complete samples")]
+    NoSamples,
+
+    #[error("truncate file: {0}")]
+    Truncate(std::io::Error),
+
+    #[error("invalid monitor directory")]
+    InvalidMonitorDir,
+
+    #[error("query events: {0}")]
+    QueryEvents(#[from] crate::QueryEventsError),
+
+    #[error("serialize data: {0}")]
+    Serialize(#[from] serde_json::Error),
+
+    #[error("write file: {0}")]
+    WriteFile(std::io::Error),
+
+    #[error("{0}")]
+    TryFromInt(#[from] std::num::TryFromIntError),
+
+    #[error("create video reader: {0}")]
+    CreateVideoReader(#[from] CreateVideoReaderError),
+}
+
+// Validates the samples against the size of the mdat file and checks that
+// the data file and thumbnail exist. Returns a list of issues, the issues
+// are fixed if `repair` is true. Active recordings must not be checked.
+pub async fn check_recording(
+    recordings_dir: &Path,
+    rec_path: &Path,
+    repair: bool,
+    generate_thumbnail: ThumbnailFn<'_>,
+) -> Result<Vec<String>, CheckRecordingError> {
+    use CheckRecordingError::*;
+    let meta_path = rec_path.with_extension("meta");
+    let mdat_path = rec_path.with_extension("mdat");
+    let mdat_size = match tokio::fs::metadata(&mdat_path).await {
+        Ok(v) => v.len(),
+        Err(e) if e.kind() == ErrorKind::NotFound => return Err(MissingMdat),
+        Err(e) => return Err(ReadFile(e)),
+    };
+
+    let raw_meta = tokio::fs::read(&meta_path).await.map_err(ReadFile)?;
+    if is_encrypted(&raw_meta) {
+        return Err(Encrypted);
+    }
+    let meta_size = u64::try_from(raw_meta.len())?;
+    let (header, mut samples) = read_meta(raw_meta.as_slice(), meta_size).await?;
+
+    // Samples are written in order, everything after
+    // the first sample that is out of bounds is lost.
+    let n_valid = samples
+        .iter()
+        .take_while(|v| u64::from(v.data_offset) + u64::from(v.data_size) <= mdat_size)
+        .count();
+    samples.truncate(n_valid);
+    let Some(last_sample) = samples.last() else {
+        return Err(NoSamples);
+    };
+
+    let mut issues = Vec::new();
+    let valid_meta_size = u64::try_from(header.size() + n_valid * recording::SAMPLE_SIZE)?;
+    if valid_meta_size < meta_size {
+        issues.push(format!(
+            "truncated meta file from {meta_size} to {valid_meta_size} bytes"
+        ));
+        if repair {
+            truncate_file(&meta_path, valid_meta_size).await?;
+        }
+    }
+    let valid_mdat_size = u64::from(last_sample.data_offset) + u64::from(last_sample.data_size);
+    if valid_mdat_size < mdat_size {
+        issues.push(format!(
+            "truncated mdat file from {mdat_size} to {valid_mdat_size} bytes"
+        ));
+        if repair {
+            truncate_file(&mdat_path, valid_mdat_size).await?;
+        }
+    }
+
+    let json_path = rec_path.with_extension("json");
+    if !json_path.exists() {
+        issues.push("missing data file".to_owned());
+        if repair {
+            let data = recover_data(recordings_dir, rec_path, &samples)?;
+            let json = serde_json::to_vec_pretty(&data)?;
+            tokio::fs::write(&json_path, json)
+                .await
+                .map_err(WriteFile)?;
+        }
+    }
+
+    let jpeg_path = rec_path.with_extension("jpeg");
+    if !jpeg_path.exists() {
+        issues.push("missing thumbnail".to_owned());
+        if repair {
+            let jpeg = match header.video_codec {
+                VideoCodec::H264 => match read_first_idr(&mdat_path, &samples).await? {
+                    Some(avcc) => generate_thumbnail(avcc, header.extra_data.clone()),
+                    None => Err("no IDR sample".to_owned()),
+                },
+                VideoCodec::H265 => Err("H.265 is not supported".to_owned()),
+            };
+            match jpeg {
+                Ok(jpeg) => tokio::fs::write(&jpeg_path, jpeg)
+                    .await
+                    .map_err(WriteFile)?,
+                Err(e) => issues.push(format!("failed to generate thumbnail: {e}")),
+            }
+        }
+    }
+
+    // Make sure the repaired recording can be played.
+    if repair && !issues.is_empty() {
+        new_video_reader(rec_path.to_path_buf(), 0, &None, None).await?;
+    }
+    Ok(issues)
+}
+
+async fn truncate_file(path: &Path, size: u64) -> Result<(), CheckRecordingError> {
+    use CheckRecordingError::*;
+    let file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(path)
+        .await
+        .map_err(Truncate)?;
+    file.set_len(size).await.map_err(Truncate)?;
+    file.sync_all().await.map_err(Truncate)
+}
+
+// The events are recovered from the event index.
+fn recover_data(
+    recordings_dir: &Path,
+    rec_path: &Path,
+    samples: &[Sample],
+) -> Result<RecordingData, CheckRecordingError> {
+    use CheckRecordingError::*;
+    let monitor_id = rec_path
+        .parent()
+        .and_then(Path::file_name)
+        .and_then(|v| v.to_str())
+        .and_then(|v| MonitorId::try_from(v.to_owned()).ok())
+        .ok_or(InvalidMonitorDir)?;
+
+    let start = samples.iter().map(|v| v.pts).min().expect("not empty");
+    let end = samples
+        .iter()
+        .filter_map(Sample::end)
+        .max()
+        .unwrap_or(start);
+    let (start, end) = (UnixNano::from(start), UnixNano::from(end));
+
+    let events = query_events(
+        recordings_dir,
+        &EventQuery {
+            start,
+            end,
+            monitors: vec![monitor_id.to_string()],
+            limit: NonZeroUsize::new(10_000).expect("nonzero"),
+        },
+        // Encrypted recordings aren't checked.
+        None,
+    )?;
+    Ok(RecordingData {
+        start,
+        end,
+        events: events.into_iter().map(|v| v.event).collect(),
+    })
+}
+
+async fn read_first_idr(
+    mdat_path: &Path,
+    samples: &[Sample],
+) -> Result<Option<Vec<u8>>, CheckRecordingError> {
+    use CheckRecordingError::*;
+    let Some(sample) = samples.iter().find(|v| !v.audio && v.random_access_present) else {
+        return Ok(None);
+    };
+    let mut file = tokio::fs::File::open(mdat_path).await.map_err(ReadFile)?;
+    file.seek(std::io::SeekFrom::Start(u64::from(sample.data_offset)))
+        .await
+        .map_err(ReadFile)?;
+    let mut buf = vec![0; usize::try_from(sample.data_size)?];
+    file.read_exact(&mut buf).await.map_err(ReadFile)?;
+    Ok(Some(buf))
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use crate::events::save_event;
+    use common::{
+        time::{DtsOffset, Duration, DurationH264, UnixH264, H264_SECOND, SECOND},
+        Event,
+    };
+    use pretty_assertions::assert_eq;
+    use recording::MetaHeader;
+    use tempfile::TempDir;
+
+    fn test_sample(pts: i64, offset: u32, size: u32) -> Sample {
+        Sample {
+            random_access_present: true,
+            audio: false,
+            pts: UnixH264::new(pts),
+            dts_offset: DtsOffset::new(0),
+            duration: DurationH264::new(H264_SECOND),
+            data_offset: offset,
+            data_size: size,
+        }
+    }
+
+    fn write_recording(rec_path: &Path, samples: &[Sample], extra: &[u8], mdat: &[u8]) {
+        std::fs::create_dir_all(rec_path.parent().unwrap()).unwrap();
+        let header = MetaHeader {
+            start_time: UnixH264::new(0),
+            width: 64,
+            height: 64,
+            video_codec: VideoCodec::H264,
+            extra_data: vec![1],
+            audio: None,
+        };
+        let mut meta = header.marshal().unwrap();
+        for sample in samples {
+            meta.extend(sample.encode().unwrap());
+        }
+        meta.extend_from_slice(extra);
+        std::fs::write(rec_path.with_extension("meta"), meta).unwrap();
+        std::fs::write(rec_path.with_extension("mdat"), mdat).unwrap();
+    }
+
+    #[allow(clippy::unnecessary_wraps)]
+    fn thumbnail(avcc: Vec<u8>, extradata: Vec<u8>) -> Result<Vec<u8>, String> {
+        Ok([avcc, extradata].concat())
+    }
+
+    #[test]
+    fn test_list_fsck_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        let rec_dir = dir.join("2000/01/01/m1");
+        std::fs::create_dir_all(&rec_dir).unwrap();
+        for name in [
+            "a.meta",
+            "a.mdat",
+            "a.json",
+            "b.mdat",
+            "b.jpeg",
+            "c.x",
+            "a.mp4",
+            "events.jsonl",
+        ] {
+            std::fs::write(rec_dir.join(name), "").unwrap();
+        }
+        std::fs::write(dir.join("index.jsonl"), "").unwrap();
+
+        let files = list_fsck_files(dir).unwrap();
+        assert_eq!(vec![rec_dir.join("a")], files.recordings);
+        assert_eq!(
+            vec![rec_dir.join("b.jpeg"), rec_dir.join("b.mdat")],
+            files.orphans
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_recording_repair() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        let rec_path = dir.join("1970/01/01/m1/1970-01-01_00-00-00_m1");
+        let samples = [
+            test_sample(0, 0, 2),
+            test_sample(H264_SECOND, 2, 1),
+            // Out of bounds.
+            test_sample(2 * H264_SECOND, 3, 5),
+        ];
+        write_recording(&rec_path, &samples, &[1, 2, 3], &[4, 5, 6, 7]);
+        let event = Event {
+            time: UnixNano::new(SECOND),
+            duration: Duration::new(1),
+            rec_duration: Duration::new(0),
+            detections: Vec::new(),
+        };
+        save_event(dir, &"m1".to_owned().try_into().unwrap(), &event, None)
+            .await
+            .unwrap();
+
+        let want_issues = vec![
+            "truncated meta file from 94 to 66 bytes".to_owned(),
+            "truncated mdat file from 4 to 3 bytes".to_owned(),
+            "missing data file".to_owned(),
+            "missing thumbnail".to_owned(),
+        ];
+
+        // Dry run.
+        let issues = check_recording(dir, &rec_path, false, &thumbnail)
+            .await
+            .unwrap();
+        assert_eq!(want_issues, issues);
+        assert!(!rec_path.with_extension("json").exists());
+
+        let issues = check_recording(dir, &rec_path, true, &thumbnail)
+            .await
+            .unwrap();
+        assert_eq!(want_issues, issues);
+
+        let meta = std::fs::read(rec_path.with_extension("meta")).unwrap();
+        assert_eq!(66, meta.len());
+        let mdat = std::fs::read(rec_path.with_extension("mdat")).unwrap();
+        assert_eq!(vec![4, 5, 6], mdat);
+        let jpeg = std::fs::read(rec_path.with_extension("jpeg")).unwrap();
+        assert_eq!(vec![4, 5, 1], jpeg);
+
+        let json = std::fs::read(rec_path.with_extension("json")).unwrap();
+        let data: RecordingData = serde_json::from_slice(&json).unwrap();
+        let want_data = RecordingData {
+            start: UnixNano::new(0),
+            end: UnixNano::new(2 * SECOND),
+            events: vec![event],
+        };
+        assert_eq!(want_data, data);
+
+        // Nothing left to repair.
+        let issues = check_recording(dir, &rec_path, true, &thumbnail)
+            .await
+            .unwrap();
+        assert!(issues.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_check_recording_errors() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        let rec_path = dir.join("1970/01/01/m1/1970-01-01_00-00-00_m1");
+
+        write_recording(&rec_path, &[test_sample(0, 1, 2)], &[], &[0, 0]);
+        assert!(matches!(
+            check_recording(dir, &rec_path, true, &thumbnail).await,
+            Err(CheckRecordingError::NoSamples)
+        ));
+
+        std::fs::remove_file(rec_path.with_extension("mdat")).unwrap();
+        assert!(matches!(
+            check_recording(dir, &rec_path, true, &thumbnail).await,
+            Err(CheckRecordingError::MissingMdat)
+        ));
+    }
+}
@@ -0,0 +1,687 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+use crate::archive::Tiers;
+use common::{recording::RecordingId, time::UnixNano, EncryptionKey, MonitorId};
+use ring::{digest, hkdf, hmac};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+// Tamper-evident log of saved recordings, one file per monitor.
+//
+// hashchain
+// └── <Monitor>.jsonl
+//
+// Each entry contains the SHA-256 hashes of the recording files and the hash
+// of the previous entry, changing or removing an entry breaks every entry
+// after it. Entries are signed with a key derived from the encryption key
+// if one is configured, the chain can't be rebuilt without it. The chain
+// isn't pruned, deleted recordings are reported by the verifier.
+pub const HASH_CHAIN_DIR: &str = "hashchain";
+
+// Files that are hashed if they exist.
+const HASHED_EXTENSIONS: [&str; 6] = ["meta", "mdat", "json", "jpeg", "sprite", "vtt"];
+
+// Previous hash of the first entry.
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+const HKDF_INFO: &[u8] = b"sentryshot hash chain";
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct ChainEntry {
+    #[serde(flatten)]
+    body: EntryBody,
+
+    // SHA-256 of the serialized body.
+    hash: String,
+
+    // HMAC-SHA256 of the hash.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    signature: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct EntryBody {
+    seq: u64,
+
+    #[serde(rename = "recordingId")]
+    recording_id: RecordingId,
+
+    time: UnixNano,
+
+    // File extension and SHA-256 of the file.
+    files: BTreeMap<String, String>,
+
+    prev: String,
+}
+
+impl ChainEntry {
+    fn new(body: EntryBody, key: Option<&hmac::Key>) -> Self {
+        let hash = body.hash();
+        let signature = key.map(|key| to_hex(hmac::sign(key, hash.as_bytes()).as_ref()));
+        Self {
+            body,
+            hash,
+            signature,
+        }
+    }
+}
+
+impl EntryBody {
+    fn hash(&self) -> String {
+        let raw = serde_json::to_vec(self).expect("body should be serializable");
+        to_hex(digest::digest(&digest::SHA256, &raw).as_ref())
+    }
+}
+
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct HashChainReport {
+    #[serde(rename = "monitorId")]
+    pub monitor_id: MonitorId,
+
+    // Number of entries that were verified.
+    pub verified: usize,
+
+    // Recordings in the chain that no longer exist.
+    #[serde(rename = "deletedRecordings")]
+    pub deleted_recordings: Vec<RecordingId>,
+
+    #[serde(rename = "firstBrokenLink")]
+    pub first_broken_link: Option<BrokenLink>,
+}
+
+impl HashChainReport {
+    #[must_use]
+    pub fn is_ok(&self) -> bool {
+        self.first_broken_link.is_none()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct BrokenLink {
+    // Line number in the chain file, starting at 1.
+    pub line: usize,
+
+    // Missing if the entry couldn't be parsed.
+    #[serde(rename = "recordingId")]
+    pub recording_id: Option<RecordingId>,
+
+    pub reason: String,
+}
+
+#[derive(Debug, Error)]
+pub enum AppendHashChainError {
+    #[error("hash file: {0} {1}")]
+    HashFile(PathBuf, std::io::Error),
+
+    #[error("read chain: {0}")]
+    ReadChain(std::io::Error),
+
+    #[error("last entry is invalid: {0}")]
+    InvalidLastEntry(serde_json::Error),
+
+    #[error("create dir: {0}")]
+    CreateDir(std::io::Error),
+
+    #[error("open chain: {0}")]
+    OpenChain(std::io::Error),
+
+    #[error("write entry: {0}")]
+    Write(std::io::Error),
+
+    #[error("sync chain: {0}")]
+    Sync(std::io::Error),
+}
+
+#[derive(Debug, Error)]
+pub enum VerifyHashChainError {
+    #[error("monitor doesn't have a hash chain")]
+    NoChain,
+
+    #[error("recording isn't in the hash chain")]
+    NotInChain,
+
+    #[error("read chain: {0}")]
+    ReadChain(std::io::Error),
+}
+
+pub(crate) struct HashChains {
+    tiers: Tiers,
+    signing_key: Option<hmac::Key>,
+
+    // Only one entry can be appended at a time.
+    append_lock: tokio::sync::Mutex<()>,
+}
+
+impl HashChains {
+    pub(crate) fn new(tiers: Tiers, key: Option<&EncryptionKey>) -> Self {
+        Self {
+            tiers,
+            signing_key: key.map(signing_key),
+            append_lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    // Hashes the files of a saved recording and appends them to the
+    // chain of the monitor. `rec_path` is the path without extension.
+    pub(crate) async fn append(
+        &self,
+        rec_id: &RecordingId,
+        rec_path: PathBuf,
+    ) -> Result<(), AppendHashChainError> {
+        let _guard = self.append_lock.lock().await;
+        let chain_path = chain_path(self.tiers.hot(), rec_id.monitor());
+        let rec_id = rec_id.clone();
+        let key = self.signing_key.clone();
+        tokio::task::spawn_blocking(move || {
+            append_entry(&chain_path, rec_id, &rec_path, key.as_ref())
+        })
+        .await
+        .expect("join")
+    }
+
+    // Verifies the chain of a monitor up to and including the given
+    // recording, or the whole chain. Only the files of the given
+    // recording are hashed when a recording is specified.
+    pub(crate) async fn verify(
+        &self,
+        monitor_id: MonitorId,
+        rec_id: Option<RecordingId>,
+    ) -> Result<HashChainReport, VerifyHashChainError> {
+        let tiers = self.tiers.clone();
+        let key = self.signing_key.clone();
+        tokio::task::spawn_blocking(move || {
+            verify_chain(&tiers, monitor_id, rec_id.as_ref(), key.as_ref())
+        })
+        .await
+        .expect("join")
+    }
+}
+
+// Verifies the hash chain without a database, used by the CLI.
+#[allow(clippy::module_name_repetitions)]
+pub async fn verify_hash_chain(
+    recordings_dir: PathBuf,
+    archive_dir: Option<PathBuf>,
+    key: Option<&EncryptionKey>,
+    monitor_id: MonitorId,
+    rec_id: Option<RecordingId>,
+) -> Result<HashChainReport, VerifyHashChainError> {
+    HashChains::new(Tiers::new(recordings_dir, archive_dir), key)
+        .verify(monitor_id, rec_id)
+        .await
+}
+
+fn signing_key(key: &EncryptionKey) -> hmac::Key {
+    let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, &[]).extract(key.as_bytes());
+    let okm = prk
+        .expand(&[HKDF_INFO], hmac::HMAC_SHA256)
+        .expect("key length should be valid");
+    hmac::Key::from(okm)
+}
+
+fn chain_path(recordings_dir: &Path, monitor_id: &MonitorId) -> PathBuf {
+    recordings_dir
+        .join(HASH_CHAIN_DIR)
+        .join(format!("{monitor_id}.jsonl"))
+}
+
+fn append_entry(
+    chain_path: &Path,
+    recording_id: RecordingId,
+    rec_path: &Path,
+    key: Option<&hmac::Key>,
+) -> Result<(), AppendHashChainError> {
+    use AppendHashChainError::*;
+    let files = hash_files(rec_path)?;
+
+    let (seq, prev) = match read_last_entry(chain_path)? {
+        Some(last) => (last.body.seq + 1, last.hash),
+        None => (0, GENESIS_HASH.to_owned()),
+    };
+    let body = EntryBody {
+        seq,
+        recording_id,
+        time: UnixNano::now(),
+        files,
+        prev,
+    };
+    let mut line = serde_json::to_vec(&ChainEntry::new(body, key)).expect("serializable");
+    line.push(b'\n');
+
+    if let Some(parent) = chain_path.parent() {
+        std::fs::create_dir_all(parent).map_err(CreateDir)?;
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(chain_path)
+        .map_err(OpenChain)?;
+    file.write_all(&line).map_err(Write)?;
+    file.sync_data().map_err(Sync)
+}
+
+fn read_last_entry(chain_path: &Path) -> Result<Option<ChainEntry>, AppendHashChainError> {
+    use AppendHashChainError::*;
+    let raw = match std::fs::read_to_string(chain_path) {
+        Ok(v) => v,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(ReadChain(e)),
+    };
+    let Some(line) = raw.lines().last() else {
+        return Ok(None);
+    };
+    Ok(Some(serde_json::from_str(line).map_err(InvalidLastEntry)?))
+}
+
+// Returns the extension and hash of each existing recording file.
+fn hash_files(rec_path: &Path) -> Result<BTreeMap<String, String>, AppendHashChainError> {
+    let mut hashes = BTreeMap::new();
+    for ext in HASHED_EXTENSIONS {
+        let path = rec_path.with_extension(ext);
+        match hash_file(&path) {
+            Ok(hash) => {
+                hashes.insert(ext.to_owned(), hash);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(AppendHashChainError::HashFile(path, e)),
+        }
+    }
+    Ok(hashes)
+}
+
+fn hash_file(path: &Path) -> Result<String, std::io::Error> {
+    let mut file = std::fs::File::open(path)?;
+    let mut ctx = digest::Context::new(&digest::SHA256);
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        ctx.update(&buf[..n]);
+    }
+    Ok(to_hex(ctx.finish().as_ref()))
+}
+
+fn verify_chain(
+    tiers: &Tiers,
+    monitor_id: MonitorId,
+    target: Option<&RecordingId>,
+    key: Option<&hmac::Key>,
+) -> Result<HashChainReport, VerifyHashChainError> {
+    use VerifyHashChainError::*;
+    let raw = match std::fs::read_to_string(chain_path(tiers.hot(), &monitor_id)) {
+        Ok(v) => v,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(NoChain),
+        Err(e) => return Err(ReadChain(e)),
+    };
+
+    let mut report = HashChainReport {
+        monitor_id,
+        verified: 0,
+        deleted_recordings: Vec::new(),
+        first_broken_link: None,
+    };
+    let mut prev = GENESIS_HASH.to_owned();
+    let mut found = false;
+    for (i, line) in raw.lines().enumerate() {
+        let broken_link = |recording_id: Option<&RecordingId>, reason: String| BrokenLink {
+            line: i + 1,
+            recording_id: recording_id.cloned(),
+            reason,
+        };
+        let entry = match check_entry(line, i, &prev, &report.monitor_id, key) {
+            Ok(v) => v,
+            Err((recording_id, reason)) => {
+                report.first_broken_link = Some(broken_link(recording_id.as_ref(), reason));
+                break;
+            }
+        };
+        let rec_id = &entry.body.recording_id;
+        let is_target = target.is_some_and(|v| v == rec_id);
+        if target.is_none() || is_target {
+            match check_files(tiers, &entry) {
+                Ok(true) => {}
+                Ok(false) => report.deleted_recordings.push(rec_id.clone()),
+                Err(reason) => {
+                    report.first_broken_link = Some(broken_link(Some(rec_id), reason));
+                    break;
+                }
+            }
+        }
+        report.verified += 1;
+        prev = entry.hash;
+        if is_target {
+            found = true;
+            break;
+        }
+    }
+
+    // A broken link before the recording is reported instead.
+    if target.is_some() && !found && report.first_broken_link.is_none() {
+        return Err(NotInChain);
+    }
+    Ok(report)
+}
+
+// Returns the recording id, if known, and the reason on failure.
+fn check_entry(
+    line: &str,
+    index: usize,
+    prev: &str,
+    monitor_id: &MonitorId,
+    key: Option<&hmac::Key>,
+) -> Result<ChainEntry, (Option<RecordingId>, String)> {
+    let entry: ChainEntry =
+        serde_json::from_str(line).map_err(|e| (None, format!("invalid entry: {e}")))?;
+    let fail = |reason: String| (Some(entry.body.recording_id.clone()), reason);
+
+    let seq = entry.body.seq;
+    if u64::try_from(index) != Ok(seq) {
+        return Err(fail(format!("expected sequence number {index}, got {seq}")));
+    }
+    if entry.body.prev != prev {
+        return Err(fail("previous hash doesn't match".to_owned()));
+    }
+    if entry.body.hash() != entry.hash {
+        return Err(fail("entry hash doesn't match".to_owned()));
+    }
+    if entry.body.recording_id.monitor() != monitor_id {
+        return Err(fail("recording belongs to another monitor".to_owned()));
+    }
+    // Signatures can only be verified with the key.
+    if let Some(key) = key {
+        let Some(signature) = &entry.signature else {
+            return Err(fail("missing signature".to_owned()));
+        };
+        let valid = from_hex(signature)
+            .is_some_and(|v| hmac::verify(key, entry.hash.as_bytes(), &v).is_ok());
+        if !valid {
+            return Err(fail("invalid signature".to_owned()));
+        }
+    }
+    Ok(entry)
+}
+
+// Returns false if the recording has been deleted.
+fn check_files(tiers: &Tiers, entry: &ChainEntry) -> Result<bool, String> {
+    let rec_path = tiers.recording_path(&entry.body.recording_id);
+    let mut n_missing = 0;
+    let mut first_missing = None;
+    for (ext, want) in &entry.body.files {
+        match hash_file(&rec_path.with_extension(ext)) {
+            Ok(got) if &got == want => {}
+            Ok(_) => return Err(format!("{ext} file was modified")),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                n_missing += 1;
+                first_missing.get_or_insert(ext);
+            }
+            Err(e) => return Err(format!("hash {ext} file: {e}")),
+        }
+    }
+    if n_missing != 0 && n_missing == entry.body.files.len() {
+        return Ok(false);
+    }
+    if let Some(ext) = first_missing {
+        return Err(format!("{ext} file is missing"));
+    }
+    Ok(true)
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut s, b| {
+        write!(s, "{b:02x}").expect("writing to string should not fail");
+        s
+    })
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 || !s.is_ascii() {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tempfile::TempDir;
+
+    fn rec_id(s: &str) -> RecordingId {
+        s.to_owned().try_into().unwrap()
+    }
+
+    fn m_id() -> MonitorId {
+        "m1".to_owned().try_into().unwrap()
+    }
+
+    // Creates the recording files and appends them to the chain.
+    async fn save_recording(chains: &HashChains, dir: &Path, id: &RecordingId) -> PathBuf {
+        let rec_path = dir.join(id.as_full_path());
+        std::fs::create_dir_all(rec_path.parent().unwrap()).unwrap();
+        std::fs::write(rec_path.with_extension("meta"), id.as_str()).unwrap();
+        std::fs::write(rec_path.with_extension("mdat"), b"mdat").unwrap();
+        std::fs::write(rec_path.with_extension("json"), b"{}").unwrap();
+        chains.append(id, rec_path.clone()).await.unwrap();
+        rec_path
+    }
+
+    fn chain_lines(dir: &Path) -> Vec<String> {
+        std::fs::read_to_string(chain_path(dir, &m_id()))
+            .unwrap()
+            .lines()
+            .map(ToOwned::to_owned)
+            .collect()
+    }
+
+    fn write_chain_lines(dir: &Path, lines: &[String]) {
+        std::fs::write(chain_path(dir, &m_id()), lines.join("\n") + "\n").unwrap();
+    }
+
+    fn broken_link(line: usize, id: Option<&RecordingId>, reason: &str) -> BrokenLink {
+        BrokenLink {
+            line,
+            recording_id: id.cloned(),
+            reason: reason.to_owned(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hash_chain() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        let chains = HashChains::new(Tiers::new(dir.to_path_buf(), None), None);
+
+        let id1 = rec_id("2000-01-01_01-01-01_m1");
+        let id2 = rec_id("2000-01-01_01-01-02_m1");
+        let id3 = rec_id("2000-01-01_01-01-03_m1");
+        save_recording(&chains, dir, &id1).await;
+        let rec_path2 = save_recording(&chains, dir, &id2).await;
+        save_recording(&chains, dir, &id3).await;
+
+        let lines = chain_lines(dir);
+        assert_eq!(3, lines.len());
+        let entry: ChainEntry = serde_json::from_str(&lines[1]).unwrap();
+        assert_eq!(1, entry.body.seq);
+        assert_eq!(
+            vec!["json", "mdat", "meta"],
+            entry.body.files.keys().collect::<Vec<_>>()
+        );
+        assert_eq!(None, entry.signature);
+
+        let want = HashChainReport {
+            monitor_id: m_id(),
+            verified: 3,
+            deleted_recordings: Vec::new(),
+            first_broken_link: None,
+        };
+        assert_eq!(want, chains.verify(m_id(), None).await.unwrap());
+
+        let report = chains.verify(m_id(), Some(id2.clone())).await.unwrap();
+        assert_eq!(2, report.verified);
+        assert!(report.is_ok());
+
+        // Modified file.
+        std::fs::write(rec_path2.with_extension("mdat"), b"xxxx").unwrap();
+        let report = chains.verify(m_id(), None).await.unwrap();
+        assert_eq!(1, report.verified);
+        assert_eq!(
+            Some(broken_link(2, Some(&id2), "mdat file was modified")),
+            report.first_broken_link
+        );
+
+        // Deleted recording.
+        for ext in ["meta", "mdat", "json"] {
+            std::fs::remove_file(rec_path2.with_extension(ext)).unwrap();
+        }
+        let report = chains.verify(m_id(), None).await.unwrap();
+        assert_eq!(3, report.verified);
+        assert_eq!(vec![id2], report.deleted_recordings);
+        assert!(report.is_ok());
+
+        // Recordings before the deleted recording can still be verified.
+        let report = chains.verify(m_id(), Some(id1)).await.unwrap();
+        assert_eq!(1, report.verified);
+    }
+
+    #[tokio::test]
+    async fn test_hash_chain_tampered() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        let chains = HashChains::new(Tiers::new(dir.to_path_buf(), None), None);
+
+        let id1 = rec_id("2000-01-01_01-01-01_m1");
+        let id2 = rec_id("2000-01-01_01-01-02_m1");
+        let id3 = rec_id("2000-01-01_01-01-03_m1");
+        save_recording(&chains, dir, &id1).await;
+        save_recording(&chains, dir, &id2).await;
+        save_recording(&chains, dir, &id3).await;
+        let lines = chain_lines(dir);
+
+        // Removed entry.
+        write_chain_lines(dir, &[lines[0].clone(), lines[2].clone()]);
+        let report = chains.verify(m_id(), None).await.unwrap();
+        assert_eq!(
+            Some(broken_link(
+                2,
+                Some(&id3),
+                "expected sequence number 1, got 2"
+            )),
+            report.first_broken_link
+        );
+
+        // Modified entry.
+        let mut entry: ChainEntry = serde_json::from_str(&lines[1]).unwrap();
+        entry.body.files.remove("json");
+        let modified = serde_json::to_string(&entry).unwrap();
+        write_chain_lines(dir, &[lines[0].clone(), modified, lines[2].clone()]);
+        let report = chains.verify(m_id(), None).await.unwrap();
+        assert_eq!(
+            Some(broken_link(2, Some(&id2), "entry hash doesn't match")),
+            report.first_broken_link
+        );
+
+        // Rehashed entry.
+        entry.hash = entry.body.hash();
+        let rehashed = serde_json::to_string(&entry).unwrap();
+        write_chain_lines(dir, &[lines[0].clone(), rehashed, lines[2].clone()]);
+        let report = chains.verify(m_id(), None).await.unwrap();
+        assert_eq!(
+            Some(broken_link(3, Some(&id3), "previous hash doesn't match")),
+            report.first_broken_link
+        );
+
+        // Invalid entry.
+        write_chain_lines(dir, &[lines[0].clone(), "x".to_owned()]);
+        let report = chains.verify(m_id(), Some(id3)).await.unwrap();
+        assert_eq!(1, report.verified);
+        assert_eq!(
+            Some(broken_link(
+                2,
+                None,
+                "invalid entry: expected value at line 1 column 1"
+            )),
+            report.first_broken_link
+        );
+    }
+
+    #[tokio::test]
+    async fn test_hash_chain_signed() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        let key = EncryptionKey::new([1; 32]);
+        let chains = HashChains::new(Tiers::new(dir.to_path_buf(), None), Some(&key));
+
+        let id1 = rec_id("2000-01-01_01-01-01_m1");
+        let id2 = rec_id("2000-01-01_01-01-02_m1");
+        save_recording(&chains, dir, &id1).await;
+        save_recording(&chains, dir, &id2).await;
+        assert!(chains.verify(m_id(), None).await.unwrap().is_ok());
+
+        // Chain rebuilt without the key.
+        let lines = chain_lines(dir);
+        let mut entry: ChainEntry = serde_json::from_str(&lines[1]).unwrap();
+        assert!(entry.signature.is_some());
+        entry.signature = None;
+        write_chain_lines(
+            dir,
+            &[lines[0].clone(), serde_json::to_string(&entry).unwrap()],
+        );
+        let report = chains.verify(m_id(), None).await.unwrap();
+        assert_eq!(
+            Some(broken_link(2, Some(&id2), "missing signature")),
+            report.first_broken_link
+        );
+
+        // Chain rebuilt with another key.
+        let other_key = signing_key(&EncryptionKey::new([2; 32]));
+        let entry = ChainEntry::new(entry.body, Some(&other_key));
+        write_chain_lines(
+            dir,
+            &[lines[0].clone(), serde_json::to_string(&entry).unwrap()],
+        );
+        let report = chains.verify(m_id(), None).await.unwrap();
+        assert_eq!(
+            Some(broken_link(2, Some(&id2), "invalid signature")),
+            report.first_broken_link
+        );
+
+        // Signatures are ignored without the key.
+        let report = verify_hash_chain(dir.to_path_buf(), None, None, m_id(), None)
+            .await
+            .unwrap();
+        assert!(report.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_hash_chain_errors() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        let chains = HashChains::new(Tiers::new(dir.to_path_buf(), None), None);
+
+        assert!(matches!(
+            chains.verify(m_id(), None).await,
+            Err(VerifyHashChainError::NoChain)
+        ));
+
+        save_recording(&chains, dir, &rec_id("2000-01-01_01-01-01_m1")).await;
+        assert!(matches!(
+            chains
+                .verify(m_id(), Some(rec_id("2000-01-01_01-01-02_m1")))
+                .await,
+            Err(VerifyHashChainError::NotInChain)
+        ));
+    }
+}
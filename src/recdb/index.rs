@@ -0,0 +1,1035 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+use crate::{
+    archive::Tiers,
+    crawler::{Crawler, CrawlerError},
+    RecDbQuery, RecordingActive, RecordingIncomplete, RecordingResponse,
+};
+use common::{
+    recording::{RecordingData, RecordingId},
+    time::UnixNano,
+    Detection, DynLogger, Label, LogEntry, LogLevel, RectangleNormalized,
+};
+use csv::{deserialize_csv_option, deserialize_csv_option2};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::{File, OpenOptions},
+    io::{BufWriter, ErrorKind, Write},
+    num::{NonZeroU32, NonZeroUsize},
+    ops::Bound,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+// All recordings are indexed in an append-only log next to the recordings.
+// Each line is an operation that is replayed when the index is loaded.
+// The index is rebuilt from the filesystem if the file is missing or
+// inconsistent, deleting the file forces a rebuild.
+//
+// index.jsonl
+// <Year>
+// └── <Month>
+//     └── <Day>
+//         └── Monitor1
+pub const INDEX_FILE_NAME: &str = "index.jsonl";
+const INDEX_VERSION: u32 = 2;
+
+// Files that count towards the size of a recording.
+const RECORDING_FILE_EXTENSIONS: [&str; 6] = ["meta", "mdat", "json", "jpeg", "sprite", "vtt"];
+
+// Recording query filters. Only finalized recordings
+// are returned if any of the filters are set.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct RecordingFilter {
+    // Only include recordings with a detection of one of these labels.
+    #[serde(default)]
+    #[serde(deserialize_with = "deserialize_csv_option")]
+    pub labels: Vec<Label>,
+
+    // Only include recordings with a detection of at least this score.
+    #[serde(rename = "min-score")]
+    pub min_score: Option<f32>,
+
+    // Only include recordings with a detection that overlaps this area.
+    // Format: "x,y,width,height" normalized to 0-1000000.
+    #[serde(default)]
+    #[serde(deserialize_with = "deserialize_zone")]
+    pub zone: Option<RectangleNormalized>,
+
+    // Only include recordings that overlap this time window.
+    #[serde(rename = "time-from")]
+    pub time_from: Option<UnixNano>,
+    #[serde(rename = "time-to")]
+    pub time_to: Option<UnixNano>,
+
+    #[serde(rename = "has-events")]
+    pub has_events: Option<bool>,
+}
+
+fn deserialize_zone<'de, D>(deserializer: D) -> Result<Option<RectangleNormalized>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    use serde::de::Error;
+    let values: Vec<u32> = deserialize_csv_option2(deserializer)?;
+    if values.is_empty() {
+        return Ok(None);
+    }
+    let [x, y, width, height] = values[..] else {
+        return Err(Error::custom(
+            "zone must be formatted as 'x,y,width,height'",
+        ));
+    };
+    let (Some(width), Some(height)) = (NonZeroU32::new(width), NonZeroU32::new(height)) else {
+        return Err(Error::custom("zone width and height must be non-zero"));
+    };
+    Ok(Some(RectangleNormalized {
+        x,
+        y,
+        width,
+        height,
+    }))
+}
+
+impl RecordingFilter {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    fn matches(&self, entry: &IndexEntry) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+        let Some(data) = &entry.data else {
+            return false;
+        };
+        if self.time_from.is_some_and(|v| data.end.before(v)) {
+            return false;
+        }
+        if self.time_to.is_some_and(|v| !data.start.before(v)) {
+            return false;
+        }
+        if self.has_events.is_some_and(|v| v != data.has_events) {
+            return false;
+        }
+        if self.labels.is_empty() && self.min_score.is_none() && self.zone.is_none() {
+            return true;
+        }
+        data.detections.iter().any(|d| self.matches_detection(d))
+    }
+
+    fn matches_detection(&self, d: &IndexDetection) -> bool {
+        if !self.labels.is_empty() && !self.labels.contains(&d.label) {
+            return false;
+        }
+        if self.min_score.is_some_and(|v| d.score < v) {
+            return false;
+        }
+        if let Some(zone) = &self.zone {
+            let Some(bounds) = &d.bounds else {
+                return false;
+            };
+            return bounds.overlaps(&Bounds::from_rect(zone));
+        }
+        true
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct IndexEntry {
+    // Combined size of the recording files in bytes.
+    pub(crate) size: u64,
+
+    // Set once the recording has been finalized.
+    pub(crate) data: Option<DataSummary>,
+
+    pub(crate) locked: bool,
+
+    // Set once the recording has been moved to the archive.
+    pub(crate) archived: bool,
+}
+
+impl IndexEntry {
+    // Incomplete recordings are treated as having no events.
+    pub(crate) fn has_events(&self) -> bool {
+        self.data.as_ref().is_some_and(|v| v.has_events)
+    }
+}
+
+// Summary of the recording data.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct DataSummary {
+    start: UnixNano,
+    end: UnixNano,
+    has_events: bool,
+
+    // Unique detections, only the highest score is kept.
+    detections: Vec<IndexDetection>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct IndexDetection {
+    label: Label,
+    score: f32,
+    bounds: Option<Bounds>,
+}
+
+// Bounding box of the detection region.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Bounds {
+    x1: u32,
+    y1: u32,
+    x2: u32,
+    y2: u32,
+}
+
+impl Bounds {
+    fn from_detection(d: &Detection) -> Option<Self> {
+        if let Some(rect) = &d.region.rectangle {
+            return Some(Self::from_rect(rect));
+        }
+        let polygon = d.region.polygon.as_ref()?;
+        Some(Self {
+            x1: polygon.iter().map(|p| p.x).min()?,
+            y1: polygon.iter().map(|p| p.y).min()?,
+            x2: polygon.iter().map(|p| p.x).max()?,
+            y2: polygon.iter().map(|p| p.y).max()?,
+        })
+    }
+
+    fn from_rect(rect: &RectangleNormalized) -> Self {
+        Self {
+            x1: rect.x,
+            y1: rect.y,
+            x2: rect.x.saturating_add(rect.width.get()),
+            y2: rect.y.saturating_add(rect.height.get()),
+        }
+    }
+
+    fn overlaps(&self, other: &Self) -> bool {
+        self.x1 <= other.x2 && other.x1 <= self.x2 && self.y1 <= other.y2 && other.y1 <= self.y2
+    }
+}
+
+impl DataSummary {
+    pub(crate) fn new(data: &RecordingData) -> Self {
+        let mut detections: Vec<IndexDetection> = Vec::new();
+        for d in data.events.iter().flat_map(|e| &e.detections) {
+            let bounds = Bounds::from_detection(d);
+            if let Some(existing) = detections
+                .iter_mut()
+                .find(|v| v.label == d.label && v.bounds == bounds)
+            {
+                existing.score = existing.score.max(d.score);
+                continue;
+            }
+            detections.push(IndexDetection {
+                label: d.label.clone(),
+                score: d.score,
+                bounds,
+            });
+        }
+        Self {
+            start: data.start,
+            end: data.end,
+            has_events: !data.events.is_empty(),
+            detections,
+        }
+    }
+
+    // Used when the data file exists but can't be read.
+    fn unreadable(id: &RecordingId) -> Self {
+        let time = id.as_nanos().unwrap_or(UnixNano::new(0));
+        Self {
+            start: time,
+            end: time,
+            has_events: false,
+            detections: Vec::new(),
+        }
+    }
+}
+
+// Returns the combined size of the recording files,
+// path is the recording path without extension.
+pub(crate) fn recording_size(path: &Path) -> u64 {
+    RECORDING_FILE_EXTENSIONS
+        .iter()
+        .filter_map(|ext| std::fs::metadata(path.with_extension(ext)).ok())
+        .map(|v| v.len())
+        .sum()
+}
+
+// Each line in the index file is one operation.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "camelCase")]
+pub(crate) enum IndexOp {
+    Insert {
+        id: RecordingId,
+        entry: IndexEntry,
+    },
+    SetData {
+        id: RecordingId,
+        size: u64,
+        data: DataSummary,
+    },
+    SetLocked {
+        id: RecordingId,
+        locked: bool,
+    },
+    SetArchived {
+        id: RecordingId,
+    },
+    Remove {
+        id: RecordingId,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum BuildIndexError {
+    #[error("crawler: {0}")]
+    Crawler(#[from] CrawlerError),
+
+    #[error("write index file: {0}")]
+    WriteFile(std::io::Error),
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct RecordingIndex(BTreeMap<RecordingId, IndexEntry>);
+
+impl RecordingIndex {
+    fn apply(&mut self, op: IndexOp) {
+        match op {
+            // Queued operations may be older than the rebuilt entry.
+            IndexOp::Insert { id, entry } => {
+                self.0.entry(id).or_insert(entry);
+            }
+            IndexOp::SetData { id, size, data } => {
+                let entry = self.0.entry(id).or_default();
+                entry.size = size;
+                entry.data = Some(data);
+            }
+            IndexOp::SetLocked { id, locked } => {
+                if let Some(entry) = self.0.get_mut(&id) {
+                    entry.locked = locked;
+                }
+            }
+            IndexOp::SetArchived { id } => {
+                if let Some(entry) = self.0.get_mut(&id) {
+                    entry.archived = true;
+                }
+            }
+            IndexOp::Remove { id } => {
+                self.0.remove(&id);
+            }
+        }
+    }
+
+    // Returns the recordings matching the query.
+    // Paginated like the crawler, `recording_id` itself is excluded.
+    pub(crate) fn query(&self, query: &RecDbQuery) -> Vec<(RecordingId, IndexEntry)> {
+        let range: Box<dyn Iterator<Item = (&RecordingId, &IndexEntry)>> = if query.reverse {
+            Box::new(
+                self.0
+                    .range((Bound::Excluded(&query.recording_id), Bound::Unbounded)),
+            )
+        } else {
+            Box::new(self.0.range(..&query.recording_id).rev())
+        };
+        range
+            .take_while(|(id, _)| match &query.end {
+                Some(end) if query.reverse => *id <= end,
+                Some(end) => *id >= end,
+                None => true,
+            })
+            .filter(|(id, _)| {
+                query.monitors.is_empty()
+                    || query.monitors.iter().any(|m| m.as_str() == &**id.monitor())
+            })
+            .filter(|(_, entry)| query.filter.matches(entry))
+            .take(query.limit.get())
+            .map(|(id, entry)| (id.to_owned(), entry.to_owned()))
+            .collect()
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct IndexHeader {
+    version: u32,
+}
+
+// The log is compacted when most of the operations are obsolete.
+fn needs_compaction(ops: usize, entries: usize) -> bool {
+    ops > entries.saturating_mul(3).saturating_add(1000)
+}
+
+// Append-only log of index operations.
+struct IndexFile {
+    path: PathBuf,
+    file: File,
+
+    // Number of operations in the file.
+    ops: usize,
+}
+
+impl IndexFile {
+    // Returns None if the file is missing, from another version or corrupt.
+    fn load(path: &Path) -> Result<Option<(RecordingIndex, Self)>, std::io::Error> {
+        let raw = match std::fs::read_to_string(path) {
+            Ok(v) => v,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let mut lines = raw.lines().peekable();
+        let Some(Ok(header)) = lines.next().map(serde_json::from_str::<IndexHeader>) else {
+            return Ok(None);
+        };
+        if header.version != INDEX_VERSION {
+            return Ok(None);
+        }
+
+        let mut index = RecordingIndex::default();
+        let mut ops = 0;
+        let mut truncated = false;
+        while let Some(line) = lines.next() {
+            match serde_json::from_str(line) {
+                Ok(op) => {
+                    index.apply(op);
+                    ops += 1;
+                }
+                // The last line may be incomplete after a crash.
+                Err(_) if lines.peek().is_none() => truncated = true,
+                Err(_) => return Ok(None),
+            }
+        }
+
+        if truncated || needs_compaction(ops, index.0.len()) {
+            let file = Self::create(path, &index)?;
+            return Ok(Some((index, file)));
+        }
+        let file = OpenOptions::new().append(true).open(path)?;
+        Ok(Some((
+            index,
+            Self {
+                path: path.to_owned(),
+                file,
+                ops,
+            },
+        )))
+    }
+
+    // Writes the index to a temporary file that replaces the old file.
+    fn create(path: &Path, index: &RecordingIndex) -> Result<Self, std::io::Error> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let tmp_path = path.with_extension("jsonl.tmp");
+        let mut w = BufWriter::new(File::create(&tmp_path)?);
+        serde_json::to_writer(
+            &mut w,
+            &IndexHeader {
+                version: INDEX_VERSION,
+            },
+        )?;
+        w.write_all(b"\n")?;
+        for (id, entry) in &index.0 {
+            let op = IndexOp::Insert {
+                id: id.clone(),
+                entry: entry.clone(),
+            };
+            serde_json::to_writer(&mut w, &op)?;
+            w.write_all(b"\n")?;
+        }
+        w.into_inner()
+            .map_err(std::io::IntoInnerError::into_error)?
+            .sync_all()?;
+        std::fs::rename(&tmp_path, path)?;
+
+        let file = OpenOptions::new().append(true).open(path)?;
+        Ok(Self {
+            path: path.to_owned(),
+            file,
+            ops: index.0.len(),
+        })
+    }
+
+    fn append(&mut self, op: &IndexOp) -> Result<(), std::io::Error> {
+        let mut line = serde_json::to_vec(op)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.ops += 1;
+        Ok(())
+    }
+}
+
+enum IndexState {
+    // Operations are queued until the index is loaded.
+    Unloaded(Vec<IndexOp>),
+    Loaded(RecordingIndex, IndexFile),
+}
+
+// Persistent index of all recordings, used instead of crawling
+// the directory tree on every query. Loaded on first use.
+pub(crate) struct Index {
+    logger: DynLogger,
+    tiers: Tiers,
+    crawler: Crawler,
+
+    // Prevents concurrent loads.
+    load_lock: tokio::sync::Mutex<()>,
+    state: std::sync::Mutex<IndexState>,
+}
+
+impl Index {
+    pub(crate) fn new(logger: DynLogger, tiers: Tiers, crawler: Crawler) -> Self {
+        Self {
+            logger,
+            tiers,
+            crawler,
+            load_lock: tokio::sync::Mutex::new(()),
+            state: std::sync::Mutex::new(IndexState::Unloaded(Vec::new())),
+        }
+    }
+
+    fn path(&self) -> PathBuf {
+        self.tiers.hot().join(INDEX_FILE_NAME)
+    }
+
+    fn log(&self, level: LogLevel, msg: &str) {
+        self.logger.log(LogEntry::new(
+            level,
+            "app",
+            None,
+            format!("recording index: {msg}"),
+        ));
+    }
+
+    // Writes the operation to the index file. The index
+    // is rebuilt on the next load if the write fails.
+    pub(crate) fn update(&self, op: IndexOp) {
+        let mut state = self.state.lock().expect("not poisoned");
+        let res = match &mut *state {
+            IndexState::Unloaded(pending) => {
+                pending.push(op);
+                return;
+            }
+            IndexState::Loaded(index, file) => file.append(&op).and_then(|()| {
+                index.apply(op);
+                if needs_compaction(file.ops, index.0.len()) {
+                    *file = IndexFile::create(&file.path, index)?;
+                }
+                Ok(())
+            }),
+        };
+        if let Err(e) = res {
+            self.reset(&mut state, &format!("write: {e}"));
+        }
+    }
+
+    // Discards the index, it will be rebuilt on the next load.
+    pub(crate) fn discard(&self, reason: &str) {
+        let mut state = self.state.lock().expect("not poisoned");
+        self.reset(&mut state, reason);
+    }
+
+    fn reset(&self, state: &mut IndexState, reason: &str) {
+        self.log(LogLevel::Warning, &format!("{reason}, rebuilding"));
+        *state = IndexState::Unloaded(Vec::new());
+        if let Err(e) = std::fs::remove_file(self.path()) {
+            if e.kind() != ErrorKind::NotFound {
+                self.log(LogLevel::Error, &format!("remove file: {e}"));
+            }
+        }
+    }
+
+    // Loads the index file or rebuilds the index from the filesystem.
+    pub(crate) async fn load(&self) -> Result<(), BuildIndexError> {
+        use BuildIndexError::*;
+        let _guard = self.load_lock.lock().await;
+        if matches!(
+            *self.state.lock().expect("not poisoned"),
+            IndexState::Loaded(..)
+        ) {
+            return Ok(());
+        }
+
+        let path = self.path();
+        let loaded = {
+            let path = path.clone();
+            tokio::task::spawn_blocking(move || IndexFile::load(&path))
+                .await
+                .expect("join")
+        };
+        let (mut index, mut file) = match loaded {
+            Ok(Some(v)) => v,
+            Ok(None) => {
+                self.log(LogLevel::Info, "building index");
+                self.rebuild(path).await?
+            }
+            Err(e) => {
+                self.log(LogLevel::Warning, &format!("load: {e}, rebuilding"));
+                self.rebuild(path).await?
+            }
+        };
+
+        let mut state = self.state.lock().expect("not poisoned");
+        let pending = match &mut *state {
+            IndexState::Unloaded(pending) => std::mem::take(pending),
+            IndexState::Loaded(..) => Vec::new(),
+        };
+        for op in pending {
+            if let Err(e) = file.append(&op) {
+                self.reset(&mut state, &format!("write: {e}"));
+                return Err(WriteFile(e));
+            }
+            index.apply(op);
+        }
+        *state = IndexState::Loaded(index, file);
+        Ok(())
+    }
+
+    // Crawls the recordings in pages to limit memory usage.
+    async fn rebuild(&self, path: PathBuf) -> Result<(RecordingIndex, IndexFile), BuildIndexError> {
+        use BuildIndexError::*;
+        let mut index = RecordingIndex::default();
+        let mut query = RecDbQuery {
+            recording_id: RecordingId::try_from("9999-12-31_23-59-59_x".to_owned())
+                .expect("recording id should be valid"),
+            end: None,
+            limit: NonZeroUsize::new(1000).expect("non zero"),
+            reverse: false,
+            monitors: Vec::new(),
+            include_data: true,
+            filter: RecordingFilter::default(),
+        };
+        loop {
+            let recordings = self
+                .crawler
+                .recordings_by_query(query.clone(), HashSet::new())
+                .await?;
+            let Some(last) = recordings.last() else {
+                break;
+            };
+            query.recording_id = last.id().clone();
+
+            let tiers = self.tiers.clone();
+            let ops = tokio::task::spawn_blocking(move || {
+                recordings
+                    .into_iter()
+                    .map(|rec| {
+                        let id = rec.id().clone();
+                        let path = tiers.recording_path(&id);
+                        let size = recording_size(&path);
+                        let archived = !path.starts_with(tiers.hot());
+                        let entry = match rec {
+                            RecordingResponse::Finalized(rec) => IndexEntry {
+                                size,
+                                data: Some(rec.data.map_or_else(
+                                    || DataSummary::unreadable(&id),
+                                    |v| DataSummary::new(&v),
+                                )),
+                                locked: rec.locked,
+                                archived,
+                            },
+                            RecordingResponse::Active(RecordingActive { locked, .. })
+                            | RecordingResponse::Incomplete(RecordingIncomplete {
+                                locked, ..
+                            }) => IndexEntry {
+                                size,
+                                data: None,
+                                locked,
+                                archived,
+                            },
+                        };
+                        IndexOp::Insert { id, entry }
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .await
+            .expect("join");
+            for op in ops {
+                index.apply(op);
+            }
+        }
+
+        tokio::task::spawn_blocking(move || {
+            let file = IndexFile::create(&path, &index).map_err(WriteFile)?;
+            Ok((index, file))
+        })
+        .await
+        .expect("join")
+    }
+
+    // Returns the recordings matching the query with their index entries.
+    pub(crate) async fn query(
+        &self,
+        query: &RecDbQuery,
+    ) -> Result<Vec<(RecordingId, IndexEntry)>, BuildIndexError> {
+        loop {
+            self.load().await?;
+            // The index may have been discarded in between.
+            if let IndexState::Loaded(index, _) = &*self.state.lock().expect("not poisoned") {
+                return Ok(index.query(query));
+            }
+        }
+    }
+
+    // Maps all the recordings in the index, oldest first.
+    pub(crate) async fn map_all<T>(
+        &self,
+        f: impl Fn(&RecordingId, &IndexEntry) -> T,
+    ) -> Result<Vec<T>, BuildIndexError> {
+        loop {
+            self.load().await?;
+            // The index may have been discarded in between.
+            if let IndexState::Loaded(index, _) = &*self.state.lock().expect("not poisoned") {
+                return Ok(index.0.iter().map(|(id, entry)| f(id, entry)).collect());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use common::{Event, PointNormalized, Region};
+    use pretty_assertions::assert_eq;
+    use serde::de::value::{Error as ValueError, StrDeserializer};
+    use test_case::test_case;
+
+    fn detection(label: &str, score: f32, rect: Option<[u32; 4]>) -> Detection {
+        Detection {
+            label: label.to_owned().try_into().unwrap(),
+            score,
+            region: Region {
+                rectangle: rect.map(|[x, y, width, height]| RectangleNormalized {
+                    x,
+                    y,
+                    width: NonZeroU32::new(width).unwrap(),
+                    height: NonZeroU32::new(height).unwrap(),
+                }),
+                polygon: None,
+            },
+        }
+    }
+
+    fn test_data(start: i64, end: i64, detections: Vec<Detection>) -> RecordingData {
+        RecordingData {
+            start: UnixNano::new(start),
+            end: UnixNano::new(end),
+            events: vec![Event {
+                time: UnixNano::new(start),
+                duration: common::time::Duration::new(0),
+                rec_duration: common::time::Duration::new(0),
+                detections,
+            }],
+        }
+    }
+
+    fn test_index() -> RecordingIndex {
+        let mut index = RecordingIndex::default();
+        let mut insert = |id: &str, data: RecordingData| {
+            index.apply(IndexOp::Insert {
+                id: id.to_owned().try_into().unwrap(),
+                entry: IndexEntry {
+                    size: 0,
+                    data: Some(DataSummary::new(&data)),
+                    locked: false,
+                    archived: false,
+                },
+            });
+        };
+        insert(
+            "2000-01-01_00-00-00_m1",
+            test_data(
+                10,
+                20,
+                vec![detection("person", 90.0, Some([0, 0, 10, 10]))],
+            ),
+        );
+        insert(
+            "2000-01-01_00-00-01_m1",
+            test_data(20, 30, vec![detection("person", 50.0, None)]),
+        );
+        insert(
+            "2000-01-01_00-00-02_m2",
+            test_data(30, 40, vec![detection("car", 90.0, Some([50, 50, 10, 10]))]),
+        );
+        insert(
+            "2000-01-01_00-00-03_m1",
+            RecordingData {
+                start: UnixNano::new(40),
+                end: UnixNano::new(50),
+                events: Vec::new(),
+            },
+        );
+        index
+    }
+
+    fn test_query(filter: RecordingFilter) -> RecDbQuery {
+        RecDbQuery {
+            recording_id: "9999-01-01_00-00-00_x".to_owned().try_into().unwrap(),
+            end: None,
+            limit: NonZeroUsize::new(10).unwrap(),
+            reverse: false,
+            monitors: Vec::new(),
+            include_data: false,
+            filter,
+        }
+    }
+
+    fn query_ids(index: &RecordingIndex, query: &RecDbQuery) -> Vec<String> {
+        index
+            .query(query)
+            .into_iter()
+            .map(|(id, _)| id.as_str().to_owned())
+            .collect()
+    }
+
+    #[test_case(
+        RecordingFilter::default(),
+        &["2000-01-01_00-00-03_m1", "2000-01-01_00-00-02_m2", "2000-01-01_00-00-01_m1", "2000-01-01_00-00-00_m1"];
+        "no filter"
+    )]
+    #[test_case(
+        RecordingFilter{ labels: vec!["person".to_owned().try_into().unwrap()], ..Default::default() },
+        &["2000-01-01_00-00-01_m1", "2000-01-01_00-00-00_m1"];
+        "labels"
+    )]
+    #[test_case(
+        RecordingFilter{ min_score: Some(80.0), ..Default::default() },
+        &["2000-01-01_00-00-02_m2", "2000-01-01_00-00-00_m1"];
+        "min score"
+    )]
+    #[test_case(
+        RecordingFilter{
+            labels: vec!["person".to_owned().try_into().unwrap()],
+            min_score: Some(80.0),
+            ..Default::default()
+        },
+        &["2000-01-01_00-00-00_m1"];
+        "labels and min score"
+    )]
+    #[test_case(
+        RecordingFilter{
+            zone: Some(RectangleNormalized{
+                x: 55,
+                y: 0,
+                width: NonZeroU32::new(10).unwrap(),
+                height: NonZeroU32::new(100).unwrap(),
+            }),
+            ..Default::default()
+        },
+        &["2000-01-01_00-00-02_m2"];
+        "zone"
+    )]
+    #[test_case(
+        RecordingFilter{ time_from: Some(UnixNano::new(25)), time_to: Some(UnixNano::new(40)), ..Default::default() },
+        &["2000-01-01_00-00-02_m2", "2000-01-01_00-00-01_m1"];
+        "time window"
+    )]
+    #[test_case(
+        RecordingFilter{ has_events: Some(false), ..Default::default() },
+        &["2000-01-01_00-00-03_m1"];
+        "no events"
+    )]
+    fn test_index_query_filter(filter: RecordingFilter, want: &[&str]) {
+        assert_eq!(want, query_ids(&test_index(), &test_query(filter)));
+    }
+
+    #[test]
+    fn test_index_query_paging() {
+        let index = test_index();
+        let mut query = test_query(RecordingFilter {
+            has_events: Some(true),
+            ..Default::default()
+        });
+        query.monitors = vec!["m1".to_owned()];
+        query.limit = NonZeroUsize::new(1).unwrap();
+        assert_eq!(vec!["2000-01-01_00-00-01_m1"], query_ids(&index, &query));
+
+        query.recording_id = "2000-01-01_00-00-01_m1".to_owned().try_into().unwrap();
+        assert_eq!(vec!["2000-01-01_00-00-00_m1"], query_ids(&index, &query));
+
+        query.reverse = true;
+        query.recording_id = "2000-01-01_00-00-00_m1".to_owned().try_into().unwrap();
+        assert_eq!(vec!["2000-01-01_00-00-01_m1"], query_ids(&index, &query));
+    }
+
+    #[test]
+    fn test_index_entry_dedup_detections() {
+        let mut data = test_data(
+            0,
+            0,
+            vec![
+                detection("person", 10.0, None),
+                detection("person", 30.0, None),
+            ],
+        );
+        data.events[0].detections.push(Detection {
+            label: "person".to_owned().try_into().unwrap(),
+            score: 20.0,
+            region: Region {
+                rectangle: None,
+                polygon: Some(vec![
+                    PointNormalized { x: 5, y: 1 },
+                    PointNormalized { x: 1, y: 5 },
+                ]),
+            },
+        });
+        let summary = DataSummary::new(&data);
+        assert_eq!(
+            vec![
+                IndexDetection {
+                    label: "person".to_owned().try_into().unwrap(),
+                    score: 30.0,
+                    bounds: None,
+                },
+                IndexDetection {
+                    label: "person".to_owned().try_into().unwrap(),
+                    score: 20.0,
+                    bounds: Some(Bounds {
+                        x1: 1,
+                        y1: 1,
+                        x2: 5,
+                        y2: 5
+                    }),
+                },
+            ],
+            summary.detections
+        );
+    }
+
+    fn parse_zone(input: &str) -> Result<Option<RectangleNormalized>, ValueError> {
+        deserialize_zone(StrDeserializer::<ValueError>::new(input))
+    }
+
+    #[test_case("", None; "empty")]
+    #[test_case("1,2,3,4", Some([1, 2, 3, 4]); "ok")]
+    fn test_deserialize_zone(input: &str, want: Option<[u32; 4]>) {
+        let want = want.map(|[x, y, width, height]| RectangleNormalized {
+            x,
+            y,
+            width: NonZeroU32::new(width).unwrap(),
+            height: NonZeroU32::new(height).unwrap(),
+        });
+        assert_eq!(want, parse_zone(input).unwrap());
+    }
+
+    #[test_case("1,2,3"; "too few")]
+    #[test_case("1,2,3,4,5"; "too many")]
+    #[test_case("1,2,0,4"; "zero width")]
+    #[test_case("1,2,x,4"; "not a number")]
+    fn test_deserialize_zone_error(input: &str) {
+        assert!(parse_zone(input).is_err());
+    }
+
+    fn r_id(s: &str) -> RecordingId {
+        s.to_owned().try_into().unwrap()
+    }
+
+    fn load_ids(path: &Path) -> Option<Vec<(String, IndexEntry)>> {
+        let (index, _) = IndexFile::load(path).unwrap()?;
+        Some(
+            index
+                .0
+                .into_iter()
+                .map(|(id, entry)| (id.as_str().to_owned(), entry))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_index_file() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join(INDEX_FILE_NAME);
+        assert_eq!(None, load_ids(&path));
+
+        let mut index = RecordingIndex::default();
+        index.apply(IndexOp::Insert {
+            id: r_id("2000-01-01_00-00-00_m1"),
+            entry: IndexEntry::default(),
+        });
+        let mut file = IndexFile::create(&path, &index).unwrap();
+        let ops = [
+            IndexOp::Insert {
+                id: r_id("2000-01-01_00-00-01_m1"),
+                entry: IndexEntry::default(),
+            },
+            IndexOp::SetData {
+                id: r_id("2000-01-01_00-00-01_m1"),
+                size: 5,
+                data: DataSummary::new(&test_data(1, 2, Vec::new())),
+            },
+            IndexOp::SetLocked {
+                id: r_id("2000-01-01_00-00-01_m1"),
+                locked: true,
+            },
+            IndexOp::SetArchived {
+                id: r_id("2000-01-01_00-00-01_m1"),
+            },
+            IndexOp::Remove {
+                id: r_id("2000-01-01_00-00-00_m1"),
+            },
+        ];
+        for op in &ops {
+            file.append(op).unwrap();
+        }
+        drop(file);
+
+        let want = vec![(
+            "2000-01-01_00-00-01_m1".to_owned(),
+            IndexEntry {
+                size: 5,
+                data: Some(DataSummary::new(&test_data(1, 2, Vec::new()))),
+                locked: true,
+                archived: true,
+            },
+        )];
+        assert_eq!(Some(want.clone()), load_ids(&path));
+
+        // Incomplete last line.
+        let mut raw = std::fs::read(&path).unwrap();
+        raw.extend_from_slice(b"{\"op\":\"remove\",");
+        std::fs::write(&path, raw).unwrap();
+        assert_eq!(Some(want.clone()), load_ids(&path));
+
+        // The file was compacted.
+        let raw = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(2, raw.lines().count());
+        assert_eq!(Some(want), load_ids(&path));
+    }
+
+    #[test_case("{\"version\":1}\n"; "version")]
+    #[test_case("{\"version\":2}\nx\n{\"op\":\"remove\",\"id\":\"2000-01-01_00-00-00_m1\"}\n"; "corrupt line")]
+    #[test_case("x\n"; "header")]
+    fn test_index_file_invalid(raw: &str) {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join(INDEX_FILE_NAME);
+        std::fs::write(&path, raw).unwrap();
+        assert_eq!(None, load_ids(&path));
+    }
+
+    #[test]
+    fn test_index_query_unfinalized() {
+        let mut index = test_index();
+        index.apply(IndexOp::Insert {
+            id: r_id("2000-01-01_00-00-04_m1"),
+            entry: IndexEntry::default(),
+        });
+        let ids = query_ids(&index, &test_query(RecordingFilter::default()));
+        assert_eq!("2000-01-01_00-00-04_m1", ids[0]);
+
+        // Recordings without data never match a filter.
+        let ids = query_ids(
+            &index,
+            &test_query(RecordingFilter {
+                has_events: Some(false),
+                ..Default::default()
+            }),
+        );
+        assert_eq!(vec!["2000-01-01_00-00-03_m1"], ids);
+    }
+}
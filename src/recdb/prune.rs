@@ -0,0 +1,627 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+use crate::{disk::UsageError, events::EVENTS_FILE_NAME, index::BuildIndexError};
+use bytesize::ByteSize;
+use common::{
+    monitor::RetentionConfig,
+    recording::{RecordingData, RecordingId},
+    time::UnixNano,
+    EncryptionKey, MonitorId,
+};
+use recording::decrypt_file;
+use std::{
+    collections::{HashMap, HashSet},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub(crate) enum PruneError {
+    #[error("usage: {0}")]
+    Usage(#[from] UsageError),
+
+    #[error("list directories: {0}")]
+    ListDirs(std::io::Error),
+
+    #[error("read dir: {0}")]
+    ReadDir(std::io::Error),
+
+    #[error("dir entry: {0}")]
+    DirEntry(std::io::Error),
+
+    #[error("metadata: {0}")]
+    Metadata(std::io::Error),
+
+    #[error("remove file: {0}")]
+    RemoveFile(std::io::Error),
+
+    #[error("remove dir: {0}")]
+    RemoveDir(std::io::Error),
+
+    #[error("index: {0}")]
+    Index(#[from] BuildIndexError),
+}
+
+// Recording that may be deleted by the pruner.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct PruneCandidate {
+    pub(crate) id: RecordingId,
+
+    // Combined size of all the recording files in bytes.
+    pub(crate) size: u64,
+
+    pub(crate) has_events: bool,
+
+    // Locked recordings are never deleted.
+    pub(crate) locked: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum PruneReason {
+    MaxAge,
+    MaxSize,
+    DiskFull,
+}
+
+impl std::fmt::Display for PruneReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PruneReason::MaxAge => write!(f, "max age"),
+            PruneReason::MaxSize => write!(f, "max size"),
+            PruneReason::DiskFull => write!(f, "disk full"),
+        }
+    }
+}
+
+// Returns the recordings that should be deleted, oldest first.
+// `recordings` must be sorted oldest first. Active and locked
+// recordings are never selected. `disk_excess` is the number of bytes that must
+// be freed to bring the disk usage back under the limit.
+pub(crate) fn select_recordings(
+    recordings: &[PruneCandidate],
+    policies: &HashMap<MonitorId, RetentionConfig>,
+    active: &HashSet<RecordingId>,
+    now: UnixNano,
+    disk_excess: u64,
+) -> Vec<(RecordingId, PruneReason)> {
+    let mut selected: Vec<Option<PruneReason>> = vec![None; recordings.len()];
+    let is_eligible = |selected: &[Option<PruneReason>], i: usize| {
+        selected[i].is_none() && !recordings[i].locked && !active.contains(&recordings[i].id)
+    };
+
+    // Recordings older than the max age of their monitor.
+    for (i, rec) in recordings.iter().enumerate() {
+        if !is_eligible(&selected, i) {
+            continue;
+        }
+        let Some(max_age) = policies
+            .get(rec.id.monitor())
+            .and_then(|v| v.max_age(rec.has_events))
+        else {
+            continue;
+        };
+        let (Some(start), Some(cutoff)) = (rec.id.as_nanos(), now.checked_sub(max_age.into()))
+        else {
+            continue;
+        };
+        if start.before(cutoff) {
+            selected[i] = Some(PruneReason::MaxAge);
+        }
+    }
+
+    // Oldest recordings from monitors that use more than their max size.
+    let mut monitor_sizes: HashMap<&MonitorId, u64> = HashMap::new();
+    for (rec, reason) in recordings.iter().zip(&selected) {
+        if reason.is_none() {
+            *monitor_sizes.entry(rec.id.monitor()).or_default() += rec.size;
+        }
+    }
+    for (i, rec) in recordings.iter().enumerate() {
+        if !is_eligible(&selected, i) {
+            continue;
+        }
+        let Some(max_size) = policies
+            .get(rec.id.monitor())
+            .and_then(RetentionConfig::max_size)
+        else {
+            continue;
+        };
+        let size = monitor_sizes
+            .get_mut(rec.id.monitor())
+            .expect("size should be counted");
+        if *size <= max_size {
+            continue;
+        }
+        *size = size.saturating_sub(rec.size);
+        selected[i] = Some(PruneReason::MaxSize);
+    }
+
+    // Oldest recordings from any monitor until the disk has enough space.
+    let mut freed: u64 = recordings
+        .iter()
+        .zip(&selected)
+        .filter(|(_, reason)| reason.is_some())
+        .map(|(rec, _)| rec.size)
+        .sum();
+    for (i, rec) in recordings.iter().enumerate() {
+        if freed >= disk_excess {
+            break;
+        }
+        if !is_eligible(&selected, i) {
+            continue;
+        }
+        freed += rec.size;
+        selected[i] = Some(PruneReason::DiskFull);
+    }
+
+    recordings
+        .iter()
+        .zip(selected)
+        .filter_map(|(rec, reason)| Some((rec.id.clone(), reason?)))
+        .collect()
+}
+
+// Returns a warning for each monitor where the locked recordings alone
+// exceed the max size, and if all the locked recordings exceed `disk_limit`.
+pub(crate) fn locked_quota_warnings(
+    recordings: &[PruneCandidate],
+    policies: &HashMap<MonitorId, RetentionConfig>,
+    disk_limit: u64,
+) -> Vec<(Option<MonitorId>, String)> {
+    let mut total = 0;
+    let mut monitor_sizes: HashMap<&MonitorId, u64> = HashMap::new();
+    for rec in recordings.iter().filter(|v| v.locked) {
+        total += rec.size;
+        *monitor_sizes.entry(rec.id.monitor()).or_default() += rec.size;
+    }
+
+    let mut warnings = Vec::new();
+    let mut monitor_sizes: Vec<_> = monitor_sizes.into_iter().collect();
+    monitor_sizes.sort_by_key(|(monitor_id, _)| monitor_id.to_string());
+    for (monitor_id, size) in monitor_sizes {
+        let Some(max_size) = policies.get(monitor_id).and_then(RetentionConfig::max_size) else {
+            continue;
+        };
+        if size > max_size {
+            warnings.push((
+                Some(monitor_id.to_owned()),
+                format!(
+                    "locked recordings use {} which exceeds the max size of {}",
+                    ByteSize(size),
+                    ByteSize(max_size)
+                ),
+            ));
+        }
+    }
+    if total > disk_limit {
+        warnings.push((
+            None,
+            format!(
+                "locked recordings use {} which exceeds the max disk usage of {}",
+                ByteSize(total),
+                ByteSize(disk_limit)
+            ),
+        ));
+    }
+    warnings
+}
+
+// Returns all `<Year>/<Month>/<Day>/<Monitor>` directories.
+pub(crate) fn list_monitor_dirs(recordings_dir: &Path) -> Result<Vec<PathBuf>, std::io::Error> {
+    list_dirs_at_depth(recordings_dir, 4)
+}
+
+fn list_dirs_at_depth(dir: &Path, depth: usize) -> Result<Vec<PathBuf>, std::io::Error> {
+    let mut dirs = vec![dir.to_owned()];
+    for _ in 0..depth {
+        let mut children = Vec::new();
+        for dir in dirs {
+            children.extend(list_sub_dirs(&dir)?);
+        }
+        dirs = children;
+    }
+    Ok(dirs)
+}
+
+fn list_sub_dirs(dir: &Path) -> Result<Vec<PathBuf>, std::io::Error> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(v) => v,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut dirs = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            dirs.push(entry.path());
+        }
+    }
+    Ok(dirs)
+}
+
+// Incomplete recordings without a data file are treated as having no events.
+pub(crate) fn read_has_events(path: &Path, key: Option<&EncryptionKey>) -> bool {
+    let Some(raw) = std::fs::read(path)
+        .ok()
+        .and_then(|raw| decrypt_file(raw, key).ok())
+    else {
+        return false;
+    };
+    serde_json::from_slice::<RecordingData>(&raw).is_ok_and(|v| !v.events.is_empty())
+}
+
+// Deletes all the files belonging to the recording.
+pub(crate) fn delete_recording_files(
+    recordings_dir: &Path,
+    rec_id: &RecordingId,
+) -> Result<(), PruneError> {
+    use PruneError::*;
+    let dir = recordings_dir
+        .join(rec_id.as_full_path())
+        .parent()
+        .expect("path should have a parent")
+        .to_owned();
+    for entry in std::fs::read_dir(dir).map_err(ReadDir)? {
+        let entry = entry.map_err(DirEntry)?;
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        if name.split_once('.').is_some_and(|v| v.0 == rec_id.as_str()) {
+            match std::fs::remove_file(entry.path()) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(RemoveFile(e)),
+            }
+        }
+    }
+    Ok(())
+}
+
+// Removes the monitor directory if only the events file is left, the events
+// from that day are removed together with the last recording. Directories
+// with any other files, like a recording that was just started, are kept.
+// Empty parent directories are removed up to the recordings directory.
+pub(crate) fn remove_monitor_dir_if_empty(
+    recordings_dir: &Path,
+    monitor_dir: &Path,
+) -> Result<(), PruneError> {
+    use PruneError::*;
+    for entry in std::fs::read_dir(monitor_dir).map_err(ReadDir)? {
+        let entry = entry.map_err(DirEntry)?;
+        if entry.file_name() != EVENTS_FILE_NAME {
+            return Ok(());
+        }
+    }
+    match std::fs::remove_file(monitor_dir.join(EVENTS_FILE_NAME)) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(RemoveFile(e)),
+    }
+    match std::fs::remove_dir(monitor_dir) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        // A recording was started in between.
+        Err(_) if !dir_is_empty(monitor_dir).map_err(ReadDir)? => return Ok(()),
+        Err(e) => return Err(RemoveDir(e)),
+    }
+    remove_empty_parents(recordings_dir, monitor_dir)
+}
+
+fn dir_is_empty(dir: &Path) -> Result<bool, std::io::Error> {
+    match std::fs::read_dir(dir) {
+        Ok(mut v) => Ok(v.next().is_none()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(true),
+        Err(e) => Err(e),
+    }
+}
+
+fn remove_empty_parents(recordings_dir: &Path, dir: &Path) -> Result<(), PruneError> {
+    use PruneError::*;
+    let mut dir = dir.parent();
+    while let Some(path) = dir {
+        // Don't delete the recordings directory.
+        if path == recordings_dir || !path.starts_with(recordings_dir) {
+            return Ok(());
+        }
+        if !dir_is_empty(path).map_err(ReadDir)? {
+            return Ok(());
+        }
+        match std::fs::remove_dir(path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(RemoveDir(e)),
+        }
+        dir = path.parent();
+    }
+    Ok(())
+}
+
+// Removes empty `<Year>/<Month>/<Day>` directories and the
+// month and year directories that are left empty.
+pub(crate) fn remove_empty_dirs(recordings_dir: &Path) -> Result<(), PruneError> {
+    for depth in (1..=3).rev() {
+        for dir in list_dirs_at_depth(recordings_dir, depth).map_err(PruneError::ListDirs)? {
+            let is_empty = std::fs::read_dir(&dir)
+                .map_err(PruneError::ReadDir)?
+                .next()
+                .is_none();
+            if !is_empty {
+                continue;
+            }
+            match std::fs::remove_dir(&dir) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(PruneError::RemoveDir(e)),
+            }
+        }
+    }
+    Ok(())
+}
+
+// Removes the oldest day directory if it doesn't contain any recordings.
+// Leftover files that don't belong to a recording are otherwise never
+// deleted when the disk is full.
+pub(crate) fn remove_oldest_day_without_recordings(
+    recordings_dir: &Path,
+) -> Result<(), PruneError> {
+    use PruneError::*;
+    let days = list_dirs_at_depth(recordings_dir, 3).map_err(ListDirs)?;
+    let Some(oldest_day) = days.into_iter().min() else {
+        return Ok(());
+    };
+    for monitor_dir in list_sub_dirs(&oldest_day).map_err(ListDirs)? {
+        for entry in std::fs::read_dir(monitor_dir).map_err(ReadDir)? {
+            let entry = entry.map_err(DirEntry)?;
+            if entry.path().extension().is_some_and(|v| v == "meta") {
+                return Ok(());
+            }
+        }
+    }
+    match std::fs::remove_dir_all(oldest_day) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(RemoveDir(e)),
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use common::time::DAY;
+    use pretty_assertions::assert_eq;
+
+    fn candidate(id: &str, size: u64, has_events: bool) -> PruneCandidate {
+        PruneCandidate {
+            id: id.to_owned().try_into().unwrap(),
+            size,
+            has_events,
+            locked: false,
+        }
+    }
+
+    fn locked(id: &str, size: u64) -> PruneCandidate {
+        PruneCandidate {
+            locked: true,
+            ..candidate(id, size, false)
+        }
+    }
+
+    fn policy(max_age: Option<f64>, event_max_age: Option<f64>) -> RetentionConfig {
+        RetentionConfig {
+            max_age,
+            event_max_age,
+            max_size: None,
+        }
+    }
+
+    fn ids(selected: Vec<(RecordingId, PruneReason)>) -> Vec<(String, PruneReason)> {
+        selected
+            .into_iter()
+            .map(|(id, reason)| (id.as_str().to_owned(), reason))
+            .collect()
+    }
+
+    fn now() -> UnixNano {
+        // 1970-01-11
+        UnixNano::new(10 * DAY)
+    }
+
+    #[test]
+    fn test_select_recordings_max_age() {
+        let recordings = vec![
+            candidate("1970-01-01_00-00-00_m1", 1, false),
+            candidate("1970-01-01_00-00-00_m2", 1, false),
+            candidate("1970-01-02_00-00-00_m1", 1, true),
+            candidate("1970-01-08_00-00-00_m1", 1, false),
+            candidate("1970-01-09_00-00-00_m1", 1, false),
+        ];
+        let policies = HashMap::from([(
+            "m1".to_owned().try_into().unwrap(),
+            policy(Some(2.5), Some(20.0)),
+        )]);
+
+        let selected = select_recordings(&recordings, &policies, &HashSet::new(), now(), 0);
+        assert_eq!(
+            vec![
+                ("1970-01-01_00-00-00_m1".to_owned(), PruneReason::MaxAge),
+                ("1970-01-08_00-00-00_m1".to_owned(), PruneReason::MaxAge),
+            ],
+            ids(selected)
+        );
+    }
+
+    #[test]
+    fn test_select_recordings_event_max_age_defaults_to_max_age() {
+        let recordings = vec![candidate("1970-01-01_00-00-00_m1", 1, true)];
+        let policies =
+            HashMap::from([("m1".to_owned().try_into().unwrap(), policy(Some(1.0), None))]);
+
+        let selected = select_recordings(&recordings, &policies, &HashSet::new(), now(), 0);
+        assert_eq!(
+            vec![("1970-01-01_00-00-00_m1".to_owned(), PruneReason::MaxAge)],
+            ids(selected)
+        );
+    }
+
+    #[test]
+    fn test_select_recordings_max_size() {
+        let recordings = vec![
+            candidate("1970-01-01_00-00-00_m1", 400_000_000, false),
+            candidate("1970-01-01_00-00-00_m2", 400_000_000, false),
+            candidate("1970-01-02_00-00-00_m1", 400_000_000, true),
+            candidate("1970-01-03_00-00-00_m1", 400_000_000, false),
+        ];
+        let policies = HashMap::from([(
+            "m1".to_owned().try_into().unwrap(),
+            RetentionConfig {
+                max_age: None,
+                event_max_age: None,
+                max_size: Some(0.5),
+            },
+        )]);
+
+        let selected = select_recordings(&recordings, &policies, &HashSet::new(), now(), 0);
+        assert_eq!(
+            vec![
+                ("1970-01-01_00-00-00_m1".to_owned(), PruneReason::MaxSize),
+                ("1970-01-02_00-00-00_m1".to_owned(), PruneReason::MaxSize),
+            ],
+            ids(selected)
+        );
+    }
+
+    #[test]
+    fn test_select_recordings_disk_full() {
+        let recordings = vec![
+            candidate("1970-01-01_00-00-00_m1", 10, false),
+            candidate("1970-01-01_00-00-00_m2", 10, false),
+            candidate("1970-01-02_00-00-00_m1", 10, false),
+            candidate("1970-01-03_00-00-00_m2", 10, false),
+        ];
+        let policies =
+            HashMap::from([("m2".to_owned().try_into().unwrap(), policy(Some(9.5), None))]);
+
+        // The recording deleted by age also counts towards the freed space.
+        let selected = select_recordings(&recordings, &policies, &HashSet::new(), now(), 15);
+        assert_eq!(
+            vec![
+                ("1970-01-01_00-00-00_m1".to_owned(), PruneReason::DiskFull),
+                ("1970-01-01_00-00-00_m2".to_owned(), PruneReason::MaxAge),
+            ],
+            ids(selected)
+        );
+    }
+
+    #[test]
+    fn test_select_recordings_skip_active() {
+        let recordings = vec![
+            candidate("1970-01-01_00-00-00_m1", 10, false),
+            candidate("1970-01-02_00-00-00_m1", 10, false),
+        ];
+        let policies =
+            HashMap::from([("m1".to_owned().try_into().unwrap(), policy(Some(1.0), None))]);
+        let active = HashSet::from(["1970-01-01_00-00-00_m1".to_owned().try_into().unwrap()]);
+
+        let selected = select_recordings(&recordings, &policies, &active, now(), 100);
+        assert_eq!(
+            vec![("1970-01-02_00-00-00_m1".to_owned(), PruneReason::MaxAge)],
+            ids(selected)
+        );
+    }
+
+    #[test]
+    fn test_select_recordings_skip_locked() {
+        let recordings = vec![
+            locked("1970-01-01_00-00-00_m1", 10),
+            candidate("1970-01-02_00-00-00_m1", 10, false),
+            locked("1970-01-03_00-00-00_m1", 10),
+            candidate("1970-01-04_00-00-00_m1", 10, false),
+        ];
+        let policies =
+            HashMap::from([("m1".to_owned().try_into().unwrap(), policy(Some(8.5), None))]);
+
+        let selected = select_recordings(&recordings, &policies, &HashSet::new(), now(), 15);
+        assert_eq!(
+            vec![
+                ("1970-01-02_00-00-00_m1".to_owned(), PruneReason::MaxAge),
+                ("1970-01-04_00-00-00_m1".to_owned(), PruneReason::DiskFull),
+            ],
+            ids(selected)
+        );
+    }
+
+    #[test]
+    fn test_locked_quota_warnings() {
+        let recordings = vec![
+            locked("1970-01-01_00-00-00_m1", 6_000_000),
+            locked("1970-01-01_00-00-00_m2", 6_000_000),
+            locked("1970-01-02_00-00-00_m1", 6_000_000),
+            candidate("1970-01-02_00-00-00_m2", 6_000_000, false),
+        ];
+        let policies = HashMap::from([
+            (
+                "m1".to_owned().try_into().unwrap(),
+                RetentionConfig {
+                    max_age: None,
+                    event_max_age: None,
+                    max_size: Some(0.01),
+                },
+            ),
+            (
+                "m2".to_owned().try_into().unwrap(),
+                RetentionConfig {
+                    max_age: None,
+                    event_max_age: None,
+                    max_size: Some(0.01),
+                },
+            ),
+        ]);
+
+        let warnings = locked_quota_warnings(&recordings, &policies, 20_000_000);
+        assert_eq!(
+            vec![(
+                Some("m1".to_owned().try_into().unwrap()),
+                "locked recordings use 12.0 MB which exceeds the max size of 10.0 MB".to_owned()
+            )],
+            warnings
+        );
+
+        let warnings = locked_quota_warnings(&recordings, &HashMap::new(), 10_000_000);
+        assert_eq!(
+            vec![(
+                None,
+                "locked recordings use 18.0 MB which exceeds the max disk usage of 10.0 MB"
+                    .to_owned()
+            )],
+            warnings
+        );
+    }
+
+    #[test]
+    fn test_remove_monitor_dir_if_empty() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        let m1 = dir.join("2000/01/01/m1");
+        let m2 = dir.join("2000/01/01/m2");
+        std::fs::create_dir_all(&m1).unwrap();
+        std::fs::create_dir_all(&m2).unwrap();
+        std::fs::write(m1.join("events.jsonl"), [0; 1]).unwrap();
+        std::fs::write(m2.join("events.jsonl"), [0; 1]).unwrap();
+        // Recording that was just started.
+        std::fs::write(m2.join("2000-01-01_00-00-00_m2.mdat"), [0; 1]).unwrap();
+
+        remove_monitor_dir_if_empty(dir, &m2).unwrap();
+        assert!(m2.join("events.jsonl").exists());
+
+        remove_monitor_dir_if_empty(dir, &m1).unwrap();
+        assert!(!m1.exists());
+        assert!(dir.join("2000/01/01").exists());
+
+        std::fs::remove_dir_all(&m2).unwrap();
+        std::fs::create_dir(&m1).unwrap();
+        remove_monitor_dir_if_empty(dir, &m1).unwrap();
+        assert!(!dir.join("2000").exists());
+    }
+}
@@ -1,33 +1,62 @@
 // SPDX-License-Identifier: GPL-2.0-or-later
 
+mod archive;
 mod crawler;
 mod disk;
-
+mod events;
+mod fsck;
+mod hash_chain;
+mod index;
+mod prune;
+mod replication;
+
+pub use archive::Archive;
 pub use crawler::CrawlerError;
 pub use disk::Disk;
+pub use events::{EventQuery, MonitorEvent, QueryEventsError, SaveEventError};
+pub use fsck::{
+    check_recording, list_fsck_files, CheckRecordingError, FsckFiles, ListFsckFilesError,
+    ThumbnailFn,
+};
+pub use hash_chain::{
+    verify_hash_chain, AppendHashChainError, BrokenLink, HashChainReport, VerifyHashChainError,
+    HASH_CHAIN_DIR,
+};
+pub use index::{BuildIndexError, RecordingFilter, INDEX_FILE_NAME};
+pub use replication::{ReplicationStatus, SaveReplicationQueueError, REPLICATION_QUEUE_FILE_NAME};
 
+use archive::{ArchiveError, Tiers};
 use common::recording::{RecordingData, RecordingId, RecordingIdError};
 use common::{
-    time::{Duration, UnixH264},
-    DynLogger, LogEntry, LogLevel, MonitorId,
+    monitor::RetentionConfig,
+    time::{Duration, UnixH264, UnixNano},
+    DynLogger, EncryptionKey, Event, LogEntry, LogLevel, MonitorId, ReplicationConfig,
 };
 use crawler::Crawler;
 use csv::deserialize_csv_option;
-use disk::UsageError;
 use fs::dir_fs;
+use hash_chain::HashChains;
+use index::{recording_size, DataSummary, Index, IndexEntry, IndexOp};
+use prune::{PruneCandidate, PruneError, PruneReason};
+use recording::{decrypt_file, EncryptWriter};
+use replication::Replication;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     num::NonZeroUsize,
-    ops::{Deref, DerefMut},
     path::{Path, PathBuf},
+    pin::Pin,
     sync::Arc,
+    task::{Context, Poll},
 };
 use thiserror::Error;
-use tokio::fs::{File, OpenOptions};
-use tokio_util::sync::CancellationToken;
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf},
+};
+use tokio_util::sync::{CancellationToken, DropGuard};
 
-// Query of recordings for crawler to find.
+// Query of recordings in the recording index.
 #[derive(Clone, Debug, Deserialize)]
 pub struct RecDbQuery {
     #[serde(rename = "recording-id")]
@@ -48,6 +77,11 @@ pub struct RecDbQuery {
     // If event data should be read from file and included.
     #[serde(rename = "include-data")]
     pub include_data: bool,
+
+    // Serde can't flatten numbers from query strings,
+    // the handler deserializes the filter separately.
+    #[serde(skip)]
+    pub filter: RecordingFilter,
 }
 
 // Contains identifier and optionally data.
@@ -83,27 +117,73 @@ impl RecordingResponse {
 #[derive(Debug, Serialize)]
 pub struct RecordingActive {
     id: RecordingId,
+    locked: bool,
 }
 
 #[derive(Debug, Serialize)]
 pub struct RecordingFinalized {
     pub id: RecordingId,
+    locked: bool,
     data: Option<RecordingData>,
 }
 
+impl RecordingFinalized {
+    #[must_use]
+    pub fn data(&self) -> Option<&RecordingData> {
+        self.data.as_ref()
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct RecordingIncomplete {
     id: RecordingId,
+    locked: bool,
 }
 
 pub struct RecDb {
     logger: DynLogger,
     recordings_dir: PathBuf,
-    crawler: Crawler,
     disk: Disk,
 
+    // Optional second storage tier for older recordings.
+    archive: Option<Archive>,
+    tiers: Tiers,
+
     // There should only be one active recording per monitor.
-    active_recordings: Arc<std::sync::Mutex<HashSet<RecordingId>>>,
+    active_recordings: ActiveRecordings,
+
+    // Retention policy of each monitor.
+    retention: std::sync::Mutex<HashMap<MonitorId, RetentionConfig>>,
+
+    // New files are encrypted if set.
+    encryption_key: Option<EncryptionKey>,
+
+    hash_chains: Arc<HashChains>,
+
+    // Finalized recordings are uploaded off-site if set.
+    replication: Option<Arc<Replication>>,
+
+    index: Arc<Index>,
+}
+
+type ActiveRecordings = Arc<std::sync::Mutex<HashMap<RecordingId, ActiveRecording>>>;
+
+#[derive(Clone)]
+struct ActiveRecording {
+    // Tells the recorder to stop without saving the recording.
+    abort: CancellationToken,
+
+    // Cancelled when the recording handle is dropped.
+    stopped: CancellationToken,
+}
+
+// Max time to wait for an aborted recording to stop.
+const ABORT_RECORDING_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+#[derive(Debug, Error)]
+pub enum QueryRecordingsError {
+    #[error("build index: {0}")]
+    BuildIndex(#[from] BuildIndexError),
 }
 
 #[derive(Debug, Error)]
@@ -126,8 +206,11 @@ pub enum NewRecordingError {
 
 #[derive(Debug, Error)]
 pub enum DeleteRecordingError {
-    #[error("deleting active recordings is not implemented")]
-    Active,
+    #[error("timed out waiting for active recording to stop")]
+    AbortTimeout,
+
+    #[error("recording is locked")]
+    Locked,
 
     #[error("recording doesn't exist")]
     NotExist,
@@ -142,43 +225,157 @@ pub enum DeleteRecordingError {
     Delete(std::io::Error),
 }
 
+#[derive(Debug, Error)]
+pub enum LockRecordingError {
+    #[error("recording doesn't exist")]
+    NotExist,
+
+    #[error("create lock file: {0}")]
+    CreateFile(std::io::Error),
+
+    #[error("remove lock file: {0}")]
+    RemoveFile(std::io::Error),
+}
+
 impl RecDb {
     #[must_use]
-    pub fn new(logger: DynLogger, recording_dir: PathBuf, disk: Disk) -> Self {
+    pub fn new(
+        logger: DynLogger,
+        recording_dir: PathBuf,
+        disk: Disk,
+        encryption_key: Option<EncryptionKey>,
+    ) -> Self {
+        Self::new_with_archive(logger, recording_dir, disk, encryption_key, None)
+    }
+
+    // Recordings older than the archive age are moved to the archive by
+    // the pruner. Must be called before the database is used.
+    #[must_use]
+    pub fn with_archive(self, archive: Archive) -> Self {
+        Self {
+            replication: self.replication,
+            ..Self::new_with_archive(
+                self.logger,
+                self.recordings_dir,
+                self.disk,
+                self.encryption_key,
+                Some(archive),
+            )
+        }
+    }
+
+    // Finalized recordings are queued for upload to the bucket,
+    // the uploads are done by `replication_loop`.
+    #[must_use]
+    pub fn with_replication(self, config: ReplicationConfig) -> Self {
+        let replication = Replication::new(self.logger.clone(), config, &self.recordings_dir);
         Self {
+            replication: Some(Arc::new(replication)),
+            ..self
+        }
+    }
+
+    fn new_with_archive(
+        logger: DynLogger,
+        recording_dir: PathBuf,
+        disk: Disk,
+        encryption_key: Option<EncryptionKey>,
+        archive: Option<Archive>,
+    ) -> Self {
+        let archive_dir = archive.as_ref().map(|v| v.dir.clone());
+        let tiers = Tiers::new(recording_dir.clone(), archive_dir.clone());
+        let crawler = Crawler::new(dir_fs(recording_dir.clone()))
+            .with_archive(archive_dir.map(dir_fs))
+            .with_encryption_key(encryption_key.clone());
+        Self {
+            index: Arc::new(Index::new(logger.clone(), tiers.clone(), crawler)),
+            hash_chains: Arc::new(HashChains::new(tiers.clone(), encryption_key.as_ref())),
+            replication: None,
             logger,
-            recordings_dir: recording_dir.clone(),
-            crawler: Crawler::new(dir_fs(recording_dir)),
+            recordings_dir: recording_dir,
             disk,
-            active_recordings: Arc::new(std::sync::Mutex::new(HashSet::new())),
+            archive,
+            tiers,
+            active_recordings: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            retention: std::sync::Mutex::new(HashMap::new()),
+            encryption_key,
         }
     }
 
+    // Key used to decrypt recording files.
+    #[must_use]
+    pub fn encryption_key(&self) -> Option<&EncryptionKey> {
+        self.encryption_key.as_ref()
+    }
+
+    // Replaces the retention policies used by the pruner.
+    pub fn set_retention_policies(&self, policies: HashMap<MonitorId, RetentionConfig>) {
+        *self.retention.lock().expect("not poisoned") = policies;
+    }
+
+    // Loads the recording index, it's otherwise loaded on the first query.
+    pub async fn load_index(&self) -> Result<(), BuildIndexError> {
+        self.index.load().await
+    }
+
     // finds the best matching recording and
     // returns limit number of subsequent recorings.
     pub async fn recordings_by_query(
         &self,
         query: &RecDbQuery,
-    ) -> Result<Vec<RecordingResponse>, CrawlerError> {
-        // Do not hold onto the lock.
-        let active_recordings = self.active_recordings.lock().expect("not poisoned").clone();
-        self.crawler
-            .recordings_by_query(query.clone(), active_recordings)
+    ) -> Result<Vec<RecordingResponse>, QueryRecordingsError> {
+        let mut retried = false;
+        loop {
+            let recordings = self.index.query(query).await?;
+            let active_recordings = self.active_recording_ids();
+            let tiers = self.tiers.clone();
+            let include_data = query.include_data;
+            let key = self.encryption_key.clone();
+            let (responses, missing) = tokio::task::spawn_blocking(move || {
+                index_responses(
+                    &tiers,
+                    recordings,
+                    &active_recordings,
+                    include_data,
+                    key.as_ref(),
+                )
+            })
             .await
+            .expect("join");
+
+            if !missing || retried {
+                return Ok(responses);
+            }
+            // The index is out of sync with the filesystem.
+            self.index.discard("indexed recording is missing");
+            retried = true;
+        }
+    }
+
+    fn active_recording_ids(&self) -> HashSet<RecordingId> {
+        // Do not hold onto the lock.
+        let active_recordings = self.active_recordings.lock().expect("not poisoned");
+        active_recordings.keys().cloned().collect()
     }
 
     // Returns the full path of file tied to recording id by file extension.
+    // The recordings directory is searched before the archive.
     pub async fn recording_file_by_ext(&self, rec_id: &RecordingId, ext: &str) -> Option<PathBuf> {
         let full_relative_path = rec_id.as_full_path();
-        let mut path = self.recordings_dir.join(full_relative_path);
-        path.set_extension(ext);
-        let path = tokio::fs::canonicalize(path).await.ok()?;
+        for dir in self.tiers.dirs() {
+            let mut path = dir.join(&full_relative_path);
+            path.set_extension(ext);
+            let Ok(path) = tokio::fs::canonicalize(path).await else {
+                continue;
+            };
 
-        let is_path_safe = path.starts_with(&self.recordings_dir);
-        if !is_path_safe {
-            return None;
-        };
-        Some(path)
+            let is_path_safe = path.starts_with(dir);
+            if !is_path_safe {
+                return None;
+            };
+            return Some(path);
+        }
+        None
     }
 
     // Returns full path to the thumbnail file for specified recording id.
@@ -214,32 +411,51 @@ impl RecDb {
             .await
             .map_err(CreateDir)?;
 
+        let active = ActiveRecording {
+            abort: CancellationToken::new(),
+            stopped: CancellationToken::new(),
+        };
         {
             let mut active_recordings = self.active_recordings.lock().expect("not poisoned");
-            if active_recordings.contains(&recording_id) {
+            if active_recordings.contains_key(&recording_id) {
                 return Err(AlreadyActive);
             }
             // Function must be infallible after id has been added.
-            active_recordings.insert(recording_id.clone());
+            active_recordings.insert(recording_id.clone(), active.clone());
         }
 
         Ok(RecordingHandle {
             active_recordings: self.active_recordings.clone(),
+            abort: active.abort,
+            _stopped: active.stopped.drop_guard(),
+            index: self.index.clone(),
             id: recording_id,
             path: path.clone(),
             open_files: Arc::new(std::sync::Mutex::new(HashSet::new())),
+            encryption_key: self.encryption_key.clone(),
+            hash_chains: self.hash_chains.clone(),
+            replication: self.replication.clone(),
         })
     }
 
+    // Active recordings are aborted and deleted once the recorder has stopped.
     pub async fn delete_recording(&self, rec_id: RecordingId) -> Result<(), DeleteRecordingError> {
         use DeleteRecordingError::*;
-        if self
+        let active = self
             .active_recordings
             .lock()
             .expect("not poisoned")
-            .contains(&rec_id)
-        {
-            return Err(Active);
+            .get(&rec_id)
+            .cloned();
+
+        if self.recording_file_by_ext(&rec_id, "lock").await.is_some() {
+            return Err(Locked);
+        }
+        if let Some(active) = active {
+            active.abort.cancel();
+            tokio::time::timeout(ABORT_RECORDING_TIMEOUT, active.stopped.cancelled())
+                .await
+                .map_err(|_| AbortTimeout)?;
         }
 
         let Some(path) = self.recording_file_by_ext(&rec_id, "meta").await else {
@@ -250,6 +466,7 @@ impl RecDb {
             .expect("path should have a parent")
             .to_path_buf();
 
+        let index = self.index.clone();
         tokio::task::spawn_blocking(move || {
             let mut res = Ok(());
             for file in dir.read_dir().map_err(ReadDir)? {
@@ -273,12 +490,106 @@ impl RecDb {
                     };
                 }
             }
+            if res.is_ok() {
+                index.update(IndexOp::Remove { id: rec_id });
+            }
             res
         })
         .await
         .expect("join")
     }
 
+    // Locked recordings are never pruned or deleted. The lock is stored
+    // as an empty file next to the recording data, active recordings
+    // can also be locked.
+    pub async fn set_recording_locked(
+        &self,
+        rec_id: &RecordingId,
+        locked: bool,
+    ) -> Result<(), LockRecordingError> {
+        use LockRecordingError::*;
+        let Some(path) = self.recording_file_by_ext(rec_id, "meta").await else {
+            return Err(NotExist);
+        };
+        let path = path.with_extension("lock");
+        if locked {
+            OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(false)
+                .open(path)
+                .await
+                .map_err(CreateFile)?;
+        } else {
+            match tokio::fs::remove_file(path).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(RemoveFile(e)),
+            }
+        }
+        self.index.update(IndexOp::SetLocked {
+            id: rec_id.clone(),
+            locked,
+        });
+        Ok(())
+    }
+
+    // Adds event to the event index.
+    pub async fn save_event(
+        &self,
+        monitor_id: &MonitorId,
+        event: &Event,
+    ) -> Result<(), SaveEventError> {
+        let key = self.encryption_key.as_ref();
+        events::save_event(&self.recordings_dir, monitor_id, event, key).await
+    }
+
+    // Returns indexed events from the selected monitors between
+    // start and end, independent of the recording boundaries.
+    pub async fn events_by_query(
+        &self,
+        query: EventQuery,
+    ) -> Result<Vec<MonitorEvent>, QueryEventsError> {
+        let tiers = self.tiers.clone();
+        let key = self.encryption_key.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut events = Vec::new();
+            for dir in tiers.dirs() {
+                events.extend(events::query_events(dir, &query, key.as_ref())?);
+            }
+            events.sort_by_key(|v| v.event.time);
+            events.truncate(query.limit.get());
+            Ok(events)
+        })
+        .await
+        .expect("join")
+    }
+
+    // Verifies the hash chain of a monitor, or the chain up to a recording.
+    pub async fn verify_hash_chain(
+        &self,
+        monitor_id: MonitorId,
+        rec_id: Option<RecordingId>,
+    ) -> Result<HashChainReport, VerifyHashChainError> {
+        self.hash_chains.verify(monitor_id, rec_id).await
+    }
+
+    // Returns `None` if replication is disabled.
+    #[must_use]
+    pub fn replication_status(&self) -> Option<ReplicationStatus> {
+        self.replication.as_ref().map(|v| v.status())
+    }
+
+    // Uploads queued recordings until the token is canceled.
+    pub async fn replication_loop(&self, token: CancellationToken) {
+        let Some(replication) = &self.replication else {
+            return;
+        };
+        replication
+            .run(token, self.tiers.clone(), self.encryption_key.clone())
+            .await;
+    }
+
     pub async fn test_recording(&self) -> RecordingHandle {
         #[allow(clippy::unwrap_used)]
         self.new_recording("test".to_owned().try_into().unwrap(), UnixH264::new(1))
@@ -296,18 +607,27 @@ impl RecDb {
             reverse: false,
             monitors: Vec::new(),
             include_data: false,
+            filter: RecordingFilter::default(),
         })
         .await
         .unwrap()
         .len()
     }
 
-    // Runs `prune()` on an interval until the token is canceled.
+    // Runs `archive()` and `prune()` on an interval until the token is canceled.
     pub async fn prune_loop(&self, token: CancellationToken, interval: std::time::Duration) {
         loop {
             tokio::select! {
                 () = token.cancelled() => return,
                 () = tokio::time::sleep(interval) => {
+                    if let Err(e) = self.archive().await {
+                        self.logger.log(LogEntry::new(
+                            LogLevel::Error,
+                            "app",
+                            None,
+                            format!("failed to archive recordings: {e}"),
+                        ));
+                    }
                     if let Err(e) = self.prune().await {
                         self.logger.log(LogEntry::new(
                             LogLevel::Error,
@@ -321,94 +641,228 @@ impl RecDb {
         }
     }
 
-    // Checks if disk usage is above 99% and if true deletes all files from the oldest day.
-    #[allow(clippy::items_after_statements)]
-    pub(crate) async fn prune(&self) -> Result<(), PruneError> {
-        use PruneError::*;
-        let usage = self.disk.usage(Duration::from_minutes(10)).await?;
-
-        if usage.percent < 99.0 {
+    // Moves recordings older than the archive age to the archive.
+    // Active recordings are never moved.
+    pub(crate) async fn archive(&self) -> Result<(), ArchiveError> {
+        let Some(archive) = &self.archive else {
             return Ok(());
-        }
+        };
+        let Some(cutoff) = UnixNano::now().checked_sub(archive.move_after.into()) else {
+            return Ok(());
+        };
+        let active_recordings = self.active_recording_ids();
+        let hot = self.recordings_dir.clone();
+        let archive_dir = archive.dir.clone();
 
-        const DAY_DEPTH: u8 = 3;
+        let archived = tokio::task::spawn_blocking(move || {
+            archive::archive_recordings(&hot, &archive_dir, cutoff, &active_recordings)
+        })
+        .await
+        .expect("join")?;
 
-        // Find the oldest day.
-        let mut path = self.recordings_dir.clone();
+        for id in &archived {
+            self.index.update(IndexOp::SetArchived { id: id.clone() });
+        }
+        if !archived.is_empty() {
+            self.logger.log(LogEntry::new(
+                LogLevel::Info,
+                "app",
+                None,
+                format!("archived {} recordings", archived.len()),
+            ));
+        }
+        Ok(())
+    }
 
-        let mut depth = 1;
-        while depth <= DAY_DEPTH {
-            let path2 = path.clone();
-            let entries = tokio::task::spawn_blocking(move || std::fs::read_dir(path2))
-                .await
-                .expect("join")
-                .map_err(ReadDir)?;
+    // Returns all the indexed recordings and if they're archived, oldest first.
+    async fn prune_candidates(&self) -> Result<Vec<(PruneCandidate, bool)>, BuildIndexError> {
+        self.index
+            .map_all(|id, entry| {
+                let candidate = PruneCandidate {
+                    id: id.clone(),
+                    size: entry.size,
+                    has_events: entry.has_events(),
+                    locked: entry.locked,
+                };
+                (candidate, entry.archived)
+            })
+            .await
+    }
 
-            let mut list = Vec::new();
-            for entry in entries {
-                list.push(entry.map_err(DirEntry)?);
-            }
+    // Deletes recordings that are older or use more space than allowed by
+    // the retention policy of their monitor. If the disk usage of a tier is
+    // above 99%, the oldest recordings in that tier are deleted until it's
+    // below 98%. Active and locked recordings are never deleted.
+    // The recordings are listed from the index.
+    pub(crate) async fn prune(&self) -> Result<(), PruneError> {
+        let mut tiers = vec![(self.recordings_dir.clone(), &self.disk)];
+        if let Some(archive) = &self.archive {
+            tiers.push((archive.dir.clone(), &archive.disk));
+        }
+        let mut tier_limits = Vec::new();
+        for (dir, disk) in tiers {
+            let usage = disk.usage(Duration::from_minutes(10)).await?;
+            let disk_limit = disk.max_disk_usage() / 100 * 98;
+            let disk_excess = if usage.percent < 99.0 {
+                0
+            } else {
+                usage.used.saturating_sub(disk_limit)
+            };
+            tier_limits.push((dir, disk_limit, disk_excess));
+        }
 
-            if list.is_empty() {
-                // Don't delete the recordings directory.
-                if depth == 1 {
-                    return Ok(());
-                }
+        let candidates = self.prune_candidates().await?;
 
-                // Remove empty directory.
-                tokio::fs::remove_dir_all(&path)
-                    .await
-                    .map_err(RemoveDirAll)?;
+        let policies = self.retention.lock().expect("not poisoned").clone();
+        let active_recordings = self.active_recording_ids();
+        let logger = self.logger.clone();
+        let index = self.index.clone();
 
-                path = self.recordings_dir.clone();
-                depth = 1;
-                continue;
+        tokio::task::spawn_blocking(move || {
+            // Retention policies apply to the recordings in all tiers.
+            let mut recordings = Vec::new();
+            let mut tier_recordings = vec![Vec::new(); tier_limits.len()];
+            for (rec, archived) in candidates {
+                // The archive may have been disabled.
+                let Some(tier) = tier_recordings.get_mut(usize::from(archived)) else {
+                    continue;
+                };
+                tier.push(rec.clone());
+                recordings.push(rec);
+            }
+            let now = UnixNano::now();
+            let selected: HashMap<RecordingId, PruneReason> =
+                prune::select_recordings(&recordings, &policies, &active_recordings, now, 0)
+                    .into_iter()
+                    .collect();
+            let mut warnings = prune::locked_quota_warnings(&recordings, &policies, u64::MAX);
+
+            // Disk usage is limited per tier.
+            let mut tier_selected = Vec::new();
+            for ((_, disk_limit, disk_excess), recordings) in
+                tier_limits.iter().zip(tier_recordings)
+            {
+                warnings.extend(prune::locked_quota_warnings(
+                    &recordings,
+                    &HashMap::new(),
+                    *disk_limit,
+                ));
+                let (by_policy, remaining): (Vec<_>, Vec<_>) = recordings
+                    .into_iter()
+                    .partition(|v| selected.contains_key(&v.id));
+                let freed: u64 = by_policy.iter().map(|v| v.size).sum();
+                let mut to_delete: Vec<_> = by_policy
+                    .into_iter()
+                    .map(|v| {
+                        let reason = selected[&v.id];
+                        (v.id, reason)
+                    })
+                    .collect();
+                to_delete.extend(prune::select_recordings(
+                    &remaining,
+                    &HashMap::new(),
+                    &active_recordings,
+                    now,
+                    disk_excess.saturating_sub(freed),
+                ));
+                to_delete.sort_by(|a, b| a.0.cmp(&b.0));
+                tier_selected.push(to_delete);
+            }
+            for (monitor_id, msg) in warnings {
+                logger.log(LogEntry::new(LogLevel::Warning, "app", monitor_id, msg));
             }
 
-            list.sort_by_key(std::fs::DirEntry::path);
-            let first_file = list[0].file_name();
-            path = path.join(first_file);
-
-            depth += 1;
-        }
-
-        self.logger.log(LogEntry::new(
-            LogLevel::Info,
-            "app",
-            None,
-            format!("pruning storage: deleting {path:?}"),
-        ));
-
-        // Delete all files from that day
-        tokio::fs::remove_dir_all(&path)
-            .await
-            .map_err(RemoveDirAll)?;
-
-        Ok(())
+            for ((dir, _, disk_excess), to_delete) in tier_limits.iter().zip(tier_selected) {
+                let mut monitor_dirs = HashSet::new();
+                for (rec_id, reason) in to_delete {
+                    logger.log(LogEntry::new(
+                        LogLevel::Info,
+                        "app",
+                        Some(rec_id.monitor().clone()),
+                        format!("pruning storage: deleting {rec_id:?}, reason: {reason}"),
+                    ));
+                    prune::delete_recording_files(dir, &rec_id)?;
+                    index.update(IndexOp::Remove { id: rec_id.clone() });
+                    let path = dir.join(rec_id.as_full_path());
+                    monitor_dirs
+                        .insert(path.parent().expect("path should have a parent").to_owned());
+                }
+                for monitor_dir in monitor_dirs {
+                    prune::remove_monitor_dir_if_empty(dir, &monitor_dir)?;
+                }
+                prune::remove_empty_dirs(dir)?;
+                if *disk_excess > 0 {
+                    prune::remove_oldest_day_without_recordings(dir)?;
+                }
+            }
+            Ok(())
+        })
+        .await
+        .expect("join")
     }
 }
 
-#[derive(Debug, Error)]
-pub(crate) enum PruneError {
-    #[error("usage: {0}")]
-    Usage(#[from] UsageError),
+// Converts index entries to responses. Also returns true if any
+// of the recordings are missing from disk.
+fn index_responses(
+    tiers: &Tiers,
+    recordings: Vec<(RecordingId, IndexEntry)>,
+    active_recordings: &HashSet<RecordingId>,
+    include_data: bool,
+    key: Option<&EncryptionKey>,
+) -> (Vec<RecordingResponse>, bool) {
+    let mut responses = Vec::new();
+    let mut missing = false;
+    for (id, entry) in recordings {
+        let locked = entry.locked;
+        if active_recordings.contains(&id) {
+            responses.push(RecordingResponse::Active(RecordingActive { id, locked }));
+            continue;
+        }
 
-    #[error("read dir: {0}")]
-    ReadDir(std::io::Error),
+        let path = tiers.recording_path(&id);
+        if !path.with_extension("meta").exists() {
+            missing = true;
+            continue;
+        }
 
-    #[error("dir entry: {0}")]
-    DirEntry(std::io::Error),
+        if entry.data.is_none() {
+            responses.push(RecordingResponse::Incomplete(RecordingIncomplete {
+                id,
+                locked,
+            }));
+            continue;
+        }
 
-    #[error("remove dir all: {0}")]
-    RemoveDirAll(std::io::Error),
+        let data = if include_data {
+            std::fs::read(path.with_extension("json"))
+                .ok()
+                .and_then(|raw| decrypt_file(raw, key).ok())
+                .and_then(|raw| serde_json::from_slice(&raw).ok())
+        } else {
+            None
+        };
+        responses.push(RecordingResponse::Finalized(RecordingFinalized {
+            id,
+            locked,
+            data,
+        }));
+    }
+    (responses, missing)
 }
 
 pub struct RecordingHandle {
-    active_recordings: Arc<std::sync::Mutex<HashSet<RecordingId>>>,
+    active_recordings: ActiveRecordings,
+    abort: CancellationToken,
+    _stopped: DropGuard,
+    index: Arc<Index>,
     id: RecordingId,
 
     path: PathBuf,
     open_files: Arc<std::sync::Mutex<HashSet<String>>>,
+    encryption_key: Option<EncryptionKey>,
+    hash_chains: Arc<HashChains>,
+    replication: Option<Arc<Replication>>,
 }
 
 #[derive(Debug, Error)]
@@ -416,32 +870,152 @@ pub enum OpenFileError {
     #[error("a file with this extension is already open")]
     AlreadyOpen,
 
+    #[error("encrypted files can't be reopened")]
+    Encrypted,
+
+    #[error("write encryption header: {0} {1}")]
+    WriteHeader(PathBuf, std::io::Error),
+
     #[error("open file: {0} {1}")]
     OpenFile(PathBuf, std::io::Error),
 }
 
+#[derive(Debug, Error)]
+pub enum SaveDataError {
+    #[error("serialize data: {0}")]
+    Serialize(#[from] serde_json::Error),
+
+    #[error("open file: {0}")]
+    OpenFile(#[from] OpenFileError),
+
+    #[error("write data file: {0}")]
+    Write(std::io::Error),
+
+    #[error("flush data file: {0}")]
+    Flush(std::io::Error),
+}
+
+#[derive(Debug, Error)]
+pub enum ReplaceFileError {
+    #[error("open file: {0}")]
+    OpenFile(#[from] OpenFileError),
+
+    #[error("write file: {0}")]
+    Write(std::io::Error),
+
+    #[error("flush file: {0}")]
+    Flush(std::io::Error),
+
+    #[error("rename file: {0}")]
+    Rename(std::io::Error),
+}
+
 impl RecordingHandle {
     #[must_use]
     pub fn id(&self) -> &RecordingId {
         &self.id
     }
 
+    // Returns true if the recording is being deleted. The
+    // recorder should stop and drop the handle without saving.
+    #[must_use]
+    pub fn is_aborted(&self) -> bool {
+        self.abort.is_cancelled()
+    }
+
+    // Completes when the recording is being deleted.
+    pub async fn aborted(&self) {
+        self.abort.cancelled().await;
+    }
+
+    // Writes the data file and finalizes the recording in the index.
+    pub async fn save_data(&self, data: &RecordingData) -> Result<(), SaveDataError> {
+        use SaveDataError::*;
+        let json = serde_json::to_vec_pretty(data)?;
+
+        let mut file = self.new_file("json").await?;
+        file.write_all(&json).await.map_err(Write)?;
+        file.flush().await.map_err(Flush)?;
+
+        let index = self.index.clone();
+        let id = self.id.clone();
+        let path = self.path.clone();
+        let data = DataSummary::new(data);
+        tokio::task::spawn_blocking(move || {
+            let size = recording_size(&path);
+            index.update(IndexOp::SetData { id, size, data });
+        })
+        .await
+        .expect("join");
+        Ok(())
+    }
+
+    // Should be called once all files have been written.
+    pub async fn append_to_hash_chain(&self) -> Result<(), AppendHashChainError> {
+        self.hash_chains.append(&self.id, self.path.clone()).await
+    }
+
+    // Queues the recording for off-site replication if enabled.
+    // Should be called once all files have been written.
+    pub async fn replicate(&self) -> Result<(), SaveReplicationQueueError> {
+        let Some(replication) = &self.replication else {
+            return Ok(());
+        };
+        replication.push(self.id.clone()).await
+    }
+
     pub async fn new_file(&self, ext: &str) -> Result<FileHandle, OpenFileError> {
         let mut options = OpenOptions::new();
         let options = options.create_new(true).write(true);
-        self.open_file_with_opts(ext, options).await
+        let file = self
+            .open_file_with_opts(ext, options, self.encryption_key.as_ref())
+            .await?;
+
+        // Recordings are listed once the meta file exists.
+        if ext == "meta" {
+            self.index.update(IndexOp::Insert {
+                id: self.id.clone(),
+                entry: IndexEntry::default(),
+            });
+        }
+        Ok(file)
     }
 
+    // Replaces the content of a file that may already exist. The data is written
+    // to a temporary file that is renamed over the old file once it's complete.
+    pub async fn replace_file(&self, ext: &str, data: &[u8]) -> Result<(), ReplaceFileError> {
+        use ReplaceFileError::*;
+        let tmp_ext = format!("{ext}.tmp");
+        let mut options = OpenOptions::new();
+        let options = options.create(true).truncate(true).write(true);
+        let mut file = self
+            .open_file_with_opts(&tmp_ext, options, self.encryption_key.as_ref())
+            .await?;
+        file.write_all(data).await.map_err(Write)?;
+        file.flush().await.map_err(Flush)?;
+
+        let mut path = self.path.clone();
+        path.set_extension(ext.to_lowercase());
+        tokio::fs::rename(file.path(), path).await.map_err(Rename)?;
+        Ok(())
+    }
+
+    // Opening existing files isn't supported when encryption is enabled.
     pub async fn open_file(&self, ext: &str) -> Result<FileHandle, OpenFileError> {
+        if self.encryption_key.is_some() {
+            return Err(OpenFileError::Encrypted);
+        }
         let mut options = OpenOptions::new();
         let options = options.write(true).read(true);
-        self.open_file_with_opts(ext, options).await
+        self.open_file_with_opts(ext, options, None).await
     }
 
-    pub async fn open_file_with_opts(
+    // The file must be new if `key` is set.
+    async fn open_file_with_opts(
         &self,
         ext: &str,
         options: &OpenOptions,
+        key: Option<&EncryptionKey>,
     ) -> Result<FileHandle, OpenFileError> {
         use OpenFileError::*;
         let ext = ext.to_lowercase();
@@ -452,6 +1026,14 @@ impl RecordingHandle {
             .open(&path)
             .await
             .map_err(|e| OpenFile(path.clone(), e))?;
+        let file = match key {
+            Some(key) => FileWriter::Encrypted(Box::new(
+                EncryptWriter::new(file, key)
+                    .await
+                    .map_err(|e| WriteHeader(path.clone(), e))?,
+            )),
+            None => FileWriter::Plain(file),
+        };
 
         {
             let mut open_files = self.open_files.lock().expect("not poisoned");
@@ -477,7 +1059,8 @@ impl Drop for RecordingHandle {
             self.active_recordings
                 .lock()
                 .expect("not poisoned")
-                .remove(&self.id),
+                .remove(&self.id)
+                .is_some(),
             "recording should be in hashset"
         );
     }
@@ -487,7 +1070,12 @@ pub struct FileHandle {
     open_files: Arc<std::sync::Mutex<HashSet<String>>>,
     ext: String,
     path: PathBuf,
-    file: File,
+    file: FileWriter,
+}
+
+enum FileWriter {
+    Plain(File),
+    Encrypted(Box<EncryptWriter<File>>),
 }
 
 impl FileHandle {
@@ -508,17 +1096,50 @@ impl Drop for FileHandle {
     }
 }
 
-impl Deref for FileHandle {
-    type Target = File;
+impl AsyncWrite for FileHandle {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        match &mut self.get_mut().file {
+            FileWriter::Plain(v) => Pin::new(v).poll_write(cx, buf),
+            FileWriter::Encrypted(v) => Pin::new(v).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), std::io::Error>> {
+        match &mut self.get_mut().file {
+            FileWriter::Plain(v) => Pin::new(v).poll_flush(cx),
+            FileWriter::Encrypted(v) => Pin::new(v).poll_flush(cx),
+        }
+    }
 
-    fn deref(&self) -> &Self::Target {
-        &self.file
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        match &mut self.get_mut().file {
+            FileWriter::Plain(v) => Pin::new(v).poll_shutdown(cx),
+            FileWriter::Encrypted(v) => Pin::new(v).poll_shutdown(cx),
+        }
     }
 }
 
-impl DerefMut for FileHandle {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.file
+// Encrypted files are write only.
+impl AsyncRead for FileHandle {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        match &mut self.get_mut().file {
+            FileWriter::Plain(v) => Pin::new(v).poll_read(cx, buf),
+            FileWriter::Encrypted(_) => Poll::Ready(Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "encrypted file is write only",
+            ))),
+        }
     }
 }
 
@@ -535,7 +1156,7 @@ mod tests {
 
     fn new_test_recdb(recordings_dir: &Path) -> RecDb {
         let disk = Disk::new(recordings_dir.to_path_buf(), ByteSize(0));
-        RecDb::new(DummyLogger::new(), recordings_dir.to_path_buf(), disk)
+        RecDb::new(DummyLogger::new(), recordings_dir.to_path_buf(), disk, None)
     }
 
     #[tokio::test]
@@ -591,6 +1212,31 @@ mod tests {
         recording.open_file("json").await.unwrap();
     }
 
+    #[tokio::test]
+    async fn test_replace_file() {
+        let temp_dir = TempDir::new().unwrap();
+
+        let rec_db = new_test_recdb(temp_dir.path());
+        let recording = rec_db.test_recording().await;
+
+        let mut file = recording.new_file("jpeg").await.unwrap();
+        file.write_all(b"a").await.unwrap();
+        file.flush().await.unwrap();
+        let path = file.path().to_path_buf();
+        drop(file);
+
+        recording.replace_file("jpeg", b"b").await.unwrap();
+        assert_eq!(b"b".to_vec(), std::fs::read(&path).unwrap());
+        assert!(!path.with_extension("jpeg.tmp").exists());
+
+        // The file doesn't have to exist.
+        recording.replace_file("json", b"c").await.unwrap();
+        assert_eq!(
+            b"c".to_vec(),
+            std::fs::read(path.with_extension("json")).unwrap()
+        );
+    }
+
     #[tokio::test]
     async fn test_delete_recording() {
         let recordings_dir = TempDir::new().unwrap();
@@ -625,6 +1271,300 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_delete_recording_locked() {
+        let recordings_dir = TempDir::new().unwrap();
+        let rec_dir = recordings_dir.path().join("2000/01/01/m1");
+        let rec_id: RecordingId = "2000-01-01_02-02-02_m1".to_owned().try_into().unwrap();
+        let files = vec![
+            "2000-01-01_02-02-02_m1.json".to_owned(),
+            "2000-01-01_02-02-02_m1.meta".to_owned(),
+        ];
+        std::fs::create_dir_all(&rec_dir).unwrap();
+        create_files(&rec_dir, &files);
+
+        let rec_db = new_test_recdb(recordings_dir.path());
+        rec_db.set_recording_locked(&rec_id, true).await.unwrap();
+        // Locking twice is a no-op.
+        rec_db.set_recording_locked(&rec_id, true).await.unwrap();
+        assert!(matches!(
+            rec_db.delete_recording(rec_id.clone()).await,
+            Err(DeleteRecordingError::Locked)
+        ));
+        assert_eq!(
+            vec![
+                "2000-01-01_02-02-02_m1.json".to_owned(),
+                "2000-01-01_02-02-02_m1.lock".to_owned(),
+                "2000-01-01_02-02-02_m1.meta".to_owned(),
+            ],
+            list_directory(&rec_dir)
+        );
+
+        rec_db.set_recording_locked(&rec_id, false).await.unwrap();
+        rec_db.set_recording_locked(&rec_id, false).await.unwrap();
+        rec_db.delete_recording(rec_id.clone()).await.unwrap();
+        assert!(list_directory(&rec_dir).is_empty());
+
+        assert!(matches!(
+            rec_db.set_recording_locked(&rec_id, true).await,
+            Err(LockRecordingError::NotExist)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_delete_active_recording() {
+        let temp_dir = TempDir::new().unwrap();
+        let rec_db = new_test_recdb(temp_dir.path());
+        let recording = rec_db.test_recording().await;
+        let rec_id = recording.id().clone();
+        let meta = recording.new_file("meta").await.unwrap();
+        recording.new_file("mdat").await.unwrap();
+
+        // Simulate the recorder.
+        let recorder = tokio::spawn(async move {
+            recording.aborted().await;
+            assert!(recording.is_aborted());
+            drop(meta);
+        });
+        rec_db.delete_recording(rec_id.clone()).await.unwrap();
+        recorder.await.unwrap();
+
+        let rec_dir = temp_dir.path().join("1970/01/01/test");
+        assert!(list_directory(&rec_dir).is_empty());
+        assert_eq!(0, rec_db.count_recordings().await);
+        assert!(matches!(
+            rec_db.delete_recording(rec_id).await,
+            Err(DeleteRecordingError::NotExist)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_recording() {
+        let temp_dir = TempDir::new().unwrap();
+        let disk = Disk::new(temp_dir.path().to_path_buf(), ByteSize(0));
+        let key = EncryptionKey::new([1; 32]);
+        let rec_db = RecDb::new(
+            DummyLogger::new(),
+            temp_dir.path().to_path_buf(),
+            disk,
+            Some(key),
+        );
+        let recording = rec_db.test_recording().await;
+        recording.new_file("meta").await.unwrap();
+        assert!(matches!(
+            recording.open_file("meta").await,
+            Err(OpenFileError::Encrypted)
+        ));
+
+        let data = RecordingData {
+            start: UnixNano::new(1),
+            end: UnixNano::new(2),
+            events: Vec::new(),
+        };
+        recording.save_data(&data).await.unwrap();
+        drop(recording);
+
+        let json_path = temp_dir
+            .path()
+            .join("1970/01/01/test/1970-01-01_00-00-00_test.json");
+        assert!(recording::is_encrypted(&std::fs::read(json_path).unwrap()));
+
+        let recordings = rec_db
+            .recordings_by_query(&RecDbQuery {
+                recording_id: "9999-01-01_00-00-00_x".to_owned().try_into().unwrap(),
+                end: None,
+                limit: NonZeroUsize::new(10).unwrap(),
+                reverse: false,
+                monitors: Vec::new(),
+                include_data: true,
+                filter: RecordingFilter::default(),
+            })
+            .await
+            .unwrap();
+        let [RecordingResponse::Finalized(rec)] = recordings.as_slice() else {
+            panic!("expected one finalized recording: {recordings:?}");
+        };
+        assert_eq!(Some(&data), rec.data());
+    }
+
+    #[tokio::test]
+    async fn test_append_to_hash_chain() {
+        let temp_dir = TempDir::new().unwrap();
+        let rec_db = new_test_recdb(temp_dir.path());
+
+        let recording = rec_db.test_recording().await;
+        let mut file = recording.new_file("meta").await.unwrap();
+        file.write_all(b"meta").await.unwrap();
+        drop(file);
+        let data = RecordingData {
+            start: UnixNano::new(1),
+            end: UnixNano::new(2),
+            events: Vec::new(),
+        };
+        recording.save_data(&data).await.unwrap();
+        recording.append_to_hash_chain().await.unwrap();
+        let rec_id = recording.id().clone();
+        drop(recording);
+
+        let monitor_id: MonitorId = "test".to_owned().try_into().unwrap();
+        let report = rec_db
+            .verify_hash_chain(monitor_id.clone(), Some(rec_id))
+            .await
+            .unwrap();
+        assert_eq!(1, report.verified);
+        assert!(report.is_ok());
+
+        std::fs::write(
+            temp_dir
+                .path()
+                .join("1970/01/01/test/1970-01-01_00-00-00_test.meta"),
+            b"xxxx",
+        )
+        .unwrap();
+        let report = rec_db.verify_hash_chain(monitor_id, None).await.unwrap();
+        assert_eq!(0, report.verified);
+        assert!(!report.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_recordings_by_filter() {
+        let temp_dir = TempDir::new().unwrap();
+        let rec_db = new_test_recdb(temp_dir.path());
+
+        // Existing recordings are indexed on the first query.
+        write_files(
+            temp_dir.path(),
+            &[
+                ("2000/01/01/m1/2000-01-01_00-00-00_m1.meta", 0),
+                ("2000/01/01/m1/2000-01-01_00-00-01_m1.meta", 0),
+            ],
+        );
+        std::fs::write(
+            temp_dir
+                .path()
+                .join("2000/01/01/m1/2000-01-01_00-00-00_m1.json"),
+            r#"{"start":1,"end":2,"events":[{"time":1,"duration":0,"detections":[
+                {"label":"person","score":90,"region":{"rectangle":null,"polygon":null}}
+            ]}]}"#,
+        )
+        .unwrap();
+        std::fs::write(
+            temp_dir
+                .path()
+                .join("2000/01/01/m1/2000-01-01_00-00-01_m1.json"),
+            r#"{"start":1,"end":2,"events":[]}"#,
+        )
+        .unwrap();
+
+        let query = RecDbQuery {
+            recording_id: "9999-01-01_00-00-00_x".to_owned().try_into().unwrap(),
+            end: None,
+            limit: NonZeroUsize::new(10).unwrap(),
+            reverse: false,
+            monitors: Vec::new(),
+            include_data: true,
+            filter: RecordingFilter {
+                labels: vec!["person".to_owned().try_into().unwrap()],
+                ..Default::default()
+            },
+        };
+        let (rec_db, query) = (&rec_db, &query);
+        let query_ids = move || async move {
+            rec_db
+                .recordings_by_query(query)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|rec| {
+                    let RecordingResponse::Finalized(rec) = rec else {
+                        panic!("expected finalized");
+                    };
+                    assert!(rec.data.is_some());
+                    rec.id.as_str().to_owned()
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec!["2000-01-01_00-00-00_m1"], query_ids().await);
+
+        // New recordings are indexed when the data is saved.
+        let recording = rec_db
+            .new_recording("m1".to_owned().try_into().unwrap(), UnixH264::new(1))
+            .await
+            .unwrap();
+        recording.new_file("meta").await.unwrap();
+        let data: RecordingData = serde_json::from_str(
+            r#"{"start":1,"end":2,"events":[{"time":1,"duration":0,"detections":[
+                {"label":"person","score":90,"region":{"rectangle":null,"polygon":null}}
+            ]}]}"#,
+        )
+        .unwrap();
+        recording.save_data(&data).await.unwrap();
+        drop(recording);
+        assert_eq!(
+            vec!["2000-01-01_00-00-00_m1", "1970-01-01_00-00-00_m1"],
+            query_ids().await
+        );
+
+        // Deleted recordings are removed from the index.
+        rec_db
+            .delete_recording("2000-01-01_00-00-00_m1".to_owned().try_into().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(vec!["1970-01-01_00-00-00_m1"], query_ids().await);
+    }
+
+    #[tokio::test]
+    async fn test_recording_index_missing_dir() {
+        let temp_dir = TempDir::new().unwrap();
+        let rec_db = new_test_recdb(&temp_dir.path().join("recordings"));
+        assert_eq!(0, rec_db.count_recordings().await);
+    }
+
+    #[tokio::test]
+    async fn test_recording_index_persistent() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        let rec_path = |name: &str| format!("2000/01/01/m1/2000-01-01_00-00-0{name}_m1.meta");
+        write_files(dir, &[(&rec_path("0"), 0)]);
+
+        let rec_db = new_test_recdb(dir);
+        assert_eq!(1, rec_db.count_recordings().await);
+        assert!(dir.join("index.jsonl").exists());
+
+        // The index file is loaded instead of crawling the filesystem.
+        write_files(dir, &[(&rec_path("1"), 0)]);
+        let rec_db = new_test_recdb(dir);
+        assert_eq!(1, rec_db.count_recordings().await);
+
+        // The index is rebuilt if an indexed recording is missing.
+        std::fs::remove_file(dir.join(rec_path("0"))).unwrap();
+        let recordings = rec_db
+            .recordings_by_query(&RecDbQuery {
+                recording_id: "9999-01-01_00-00-00_x".to_owned().try_into().unwrap(),
+                end: None,
+                limit: NonZeroUsize::new(10).unwrap(),
+                reverse: false,
+                monitors: Vec::new(),
+                include_data: false,
+                filter: RecordingFilter::default(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            vec!["2000-01-01_00-00-01_m1"],
+            recordings
+                .iter()
+                .map(|v| v.id().as_str())
+                .collect::<Vec<_>>()
+        );
+
+        // The index is rebuilt if the file is removed.
+        std::fs::remove_file(dir.join("index.jsonl")).unwrap();
+        write_files(dir, &[(&rec_path("2"), 0)]);
+        let rec_db = new_test_recdb(dir);
+        assert_eq!(2, rec_db.count_recordings().await);
+    }
+
     fn create_files(dir: &Path, files: &[String]) {
         for file in files {
             std::fs::OpenOptions::new()
@@ -648,28 +1588,28 @@ mod tests {
     #[test_case(&["recordings/2000"],    &["recordings"]; "no months")]
     #[test_case(&["recordings"],         &["recordings"]; "no years" )]
     #[test_case(
-        &["recordings/2000/01/01/x/x/x"],
+        &["recordings/2000/01/01/x"],
         &["recordings/2000/01"];
         "one day"
     )]
     #[test_case(
-        &["recordings/2000/01/01/x/x/x", "recordings/2000/01/02/x/x/x"],
-        &["recordings/2000/01/02/x/x/x"];
+        &["recordings/2000/01/01/x", "recordings/2000/01/02/x"],
+        &["recordings/2000/01/02/x"];
         "two days"
     )]
     #[test_case(
-        &["recordings/2000/01/01/x/x/x", "recordings/2000/02/01/x/x/x"],
-        &["recordings/2000/01", "recordings/2000/02/01/x/x/x"];
+        &["recordings/2000/01/01/x", "recordings/2000/02/01/x"],
+        &["recordings/2000/01", "recordings/2000/02/01/x"];
         "two months"
     )]
     #[test_case(
-        &["recordings/2000/01/01/x/x/x", "recordings/2001/01/01/x/x/x"],
-        &["recordings/2000/01", "recordings/2001/01/01/x/x/x"];
+        &["recordings/2000/01/01/x", "recordings/2001/01/01/x"],
+        &["recordings/2000/01", "recordings/2001/01/01/x"];
         "two years"
     )]
     #[test_case(
-        &["recordings/2000/01", "recordings/2001/01/01/x/x/x", "recordings/2002/01/01/x/x/x"],
-        &["recordings/2001/01", "recordings/2002/01/01/x/x/x"];
+        &["recordings/2000/01", "recordings/2001/01/01/x", "recordings/2002/01/01/x"],
+        &["recordings/2001/01", "recordings/2002/01/01/x"];
         "remove empty dirs"
     )]
     #[tokio::test]
@@ -682,7 +1622,7 @@ mod tests {
             ByteSize(GB),
             Box::new(StubDiskUsageBytes(1_000_000_000)),
         );
-        let recdb = RecDb::new(DummyLogger::new(), recordings_dir, disk);
+        let recdb = RecDb::new(DummyLogger::new(), recordings_dir, disk, None);
 
         write_empty_dirs(temp_dir.path(), before);
         assert_eq!(before, list_empty_dirs(temp_dir.path()));
@@ -691,18 +1631,280 @@ mod tests {
         assert_eq!(after, list_empty_dirs(temp_dir.path()));
     }
 
+    #[tokio::test]
+    async fn test_prune_disk_full() {
+        let temp_dir = TempDir::new().unwrap();
+        let recordings_dir = temp_dir.path().join("recordings");
+
+        // 20MB must be freed.
+        let disk = Disk::with_disk_usage(
+            recordings_dir.clone(),
+            ByteSize(GB),
+            Box::new(StubDiskUsageBytes(1_000_000_000)),
+        );
+        let recdb = RecDb::new(DummyLogger::new(), recordings_dir.clone(), disk, None);
+
+        write_files(
+            &recordings_dir,
+            &[
+                ("2000/01/01/m1/2000-01-01_00-00-00_m1.meta", 0),
+                ("2000/01/01/m1/2000-01-01_00-00-00_m1.mdat", 15_000_000),
+                ("2000/01/01/m1/events.jsonl", 0),
+                ("2000/01/01/m2/2000-01-01_00-00-01_m2.meta", 0),
+                ("2000/01/01/m2/2000-01-01_00-00-01_m2.mdat", 10_000_000),
+                ("2000/01/02/m1/2000-01-02_00-00-00_m1.meta", 0),
+                ("2000/01/02/m1/2000-01-02_00-00-00_m1.mdat", 10_000_000),
+            ],
+        );
+        recdb.prune().await.unwrap();
+
+        assert_eq!(
+            vec![
+                "2000/01/02/m1/2000-01-02_00-00-00_m1.mdat",
+                "2000/01/02/m1/2000-01-02_00-00-00_m1.meta",
+            ],
+            list_files(&recordings_dir)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_prune_retention() {
+        let temp_dir = TempDir::new().unwrap();
+        let recordings_dir = temp_dir.path().join("recordings");
+
+        let disk = Disk::with_disk_usage(
+            recordings_dir.clone(),
+            ByteSize(GB),
+            Box::new(StubDiskUsageBytes(0)),
+        );
+        let recdb = RecDb::new(DummyLogger::new(), recordings_dir.clone(), disk, None);
+        recdb.set_retention_policies(HashMap::from([
+            (
+                "m1".to_owned().try_into().unwrap(),
+                RetentionConfig {
+                    max_age: Some(1.0),
+                    event_max_age: Some(100_000.0),
+                    max_size: None,
+                },
+            ),
+            (
+                "m2".to_owned().try_into().unwrap(),
+                RetentionConfig {
+                    max_age: None,
+                    event_max_age: None,
+                    max_size: Some(0.01),
+                },
+            ),
+        ]));
+
+        write_files(
+            &recordings_dir,
+            &[
+                ("2000/01/01/m1/2000-01-01_00-00-00_m1.meta", 0),
+                ("2000/01/01/m1/2000-01-01_00-00-01_m1.meta", 0),
+                ("2000/01/01/m1/2000-01-01_00-00-02_m1.meta", 0),
+                ("2000/01/01/m2/2000-01-01_00-00-00_m2.meta", 0),
+                ("2000/01/01/m2/2000-01-01_00-00-00_m2.mdat", 6_000_000),
+                ("2000/01/01/m2/2000-01-01_00-00-01_m2.meta", 0),
+                ("2000/01/01/m2/2000-01-01_00-00-01_m2.mdat", 6_000_000),
+                ("2000/01/01/m3/2000-01-01_00-00-00_m3.meta", 0),
+            ],
+        );
+        recdb
+            .set_recording_locked(
+                &"2000-01-01_00-00-00_m1".to_owned().try_into().unwrap(),
+                true,
+            )
+            .await
+            .unwrap();
+        std::fs::write(
+            recordings_dir.join("2000/01/01/m1/2000-01-01_00-00-01_m1.json"),
+            r#"{"start":0,"end":0,"events":[{"time":0,"duration":0,"detections":[]}]}"#,
+        )
+        .unwrap();
+        recdb.prune().await.unwrap();
+
+        assert_eq!(
+            vec![
+                "2000/01/01/m1/2000-01-01_00-00-00_m1.lock",
+                "2000/01/01/m1/2000-01-01_00-00-00_m1.meta",
+                "2000/01/01/m1/2000-01-01_00-00-01_m1.json",
+                "2000/01/01/m1/2000-01-01_00-00-01_m1.meta",
+                "2000/01/01/m2/2000-01-01_00-00-01_m2.mdat",
+                "2000/01/01/m2/2000-01-01_00-00-01_m2.meta",
+                "2000/01/01/m3/2000-01-01_00-00-00_m3.meta",
+            ],
+            list_files(&recordings_dir)
+        );
+    }
+
+    fn new_test_archive(dir: &Path, used: u64) -> Archive {
+        let disk = Disk::with_disk_usage(
+            dir.to_path_buf(),
+            ByteSize(GB),
+            Box::new(StubDiskUsageBytes(used)),
+        );
+        Archive::new(dir.to_path_buf(), disk, Duration::from_hours(24))
+    }
+
+    #[tokio::test]
+    async fn test_archive() {
+        let temp_dir = TempDir::new().unwrap();
+        let recordings_dir = temp_dir.path().join("recordings");
+        let archive_dir = temp_dir.path().join("archive");
+        let rec_db =
+            new_test_recdb(&recordings_dir).with_archive(new_test_archive(&archive_dir, 0));
+
+        write_files(
+            &recordings_dir,
+            &[
+                ("2000/01/01/m1/2000-01-01_00-00-00_m1.meta", 0),
+                ("2000/01/01/m1/2000-01-01_00-00-00_m1.mdat", 0),
+                ("2000/01/01/m1/2000-01-01_00-00-00_m1.jpeg", 0),
+                ("2000/01/01/m1/events.jsonl", 0),
+            ],
+        );
+        let rec_id: RecordingId = "2000-01-01_00-00-00_m1".to_owned().try_into().unwrap();
+        rec_db.load_index().await.unwrap();
+
+        rec_db.archive().await.unwrap();
+        assert!(list_files(&recordings_dir).is_empty());
+        let archived = rec_db.index.map_all(|_, v| v.archived).await.unwrap();
+        assert_eq!(vec![true], archived);
+        assert_eq!(
+            vec![
+                "2000/01/01/m1/2000-01-01_00-00-00_m1.jpeg",
+                "2000/01/01/m1/2000-01-01_00-00-00_m1.mdat",
+                "2000/01/01/m1/2000-01-01_00-00-00_m1.meta",
+                "2000/01/01/m1/events.jsonl",
+            ],
+            list_files(&archive_dir)
+        );
+
+        // Both tiers are queried.
+        let query = RecDbQuery {
+            recording_id: "9999-01-01_00-00-00_x".to_owned().try_into().unwrap(),
+            end: None,
+            limit: NonZeroUsize::new(10).unwrap(),
+            reverse: false,
+            monitors: Vec::new(),
+            include_data: false,
+            filter: RecordingFilter::default(),
+        };
+        let ids: Vec<_> = rec_db
+            .recordings_by_query(&query)
+            .await
+            .unwrap()
+            .iter()
+            .map(|v| v.id().clone())
+            .collect();
+        assert_eq!(vec![rec_id.clone()], ids);
+
+        let path = rec_db.recording_file_by_ext(&rec_id, "jpeg").await.unwrap();
+        assert!(path.starts_with(archive_dir.canonicalize().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_prune_archive_disk_full() {
+        let temp_dir = TempDir::new().unwrap();
+        let recordings_dir = temp_dir.path().join("recordings");
+        let archive_dir = temp_dir.path().join("archive");
+
+        // 20MB must be freed from the archive.
+        let disk = Disk::with_disk_usage(
+            recordings_dir.clone(),
+            ByteSize(GB),
+            Box::new(StubDiskUsageBytes(0)),
+        );
+        let rec_db = RecDb::new(DummyLogger::new(), recordings_dir.clone(), disk, None)
+            .with_archive(new_test_archive(&archive_dir, 1_000_000_000));
+
+        write_files(
+            &recordings_dir,
+            &[
+                ("2000/01/03/m1/2000-01-03_00-00-00_m1.meta", 0),
+                ("2000/01/03/m1/2000-01-03_00-00-00_m1.mdat", 15_000_000),
+            ],
+        );
+        write_files(
+            &archive_dir,
+            &[
+                ("2000/01/01/m1/2000-01-01_00-00-00_m1.meta", 0),
+                ("2000/01/01/m1/2000-01-01_00-00-00_m1.mdat", 20_000_000),
+                ("2000/01/02/m1/2000-01-02_00-00-00_m1.meta", 0),
+                ("2000/01/02/m1/2000-01-02_00-00-00_m1.mdat", 10_000_000),
+            ],
+        );
+        rec_db.prune().await.unwrap();
+
+        assert_eq!(
+            vec![
+                "2000/01/03/m1/2000-01-03_00-00-00_m1.mdat",
+                "2000/01/03/m1/2000-01-03_00-00-00_m1.meta",
+            ],
+            list_files(&recordings_dir)
+        );
+        assert_eq!(
+            vec![
+                "2000/01/02/m1/2000-01-02_00-00-00_m1.mdat",
+                "2000/01/02/m1/2000-01-02_00-00-00_m1.meta",
+            ],
+            list_files(&archive_dir)
+        );
+    }
+
+    fn write_files(base: &Path, files: &[(&str, u64)]) {
+        for (path, size) in files {
+            let path = base.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::File::create(path).unwrap().set_len(*size).unwrap();
+        }
+    }
+
+    // The index file is excluded.
+    fn list_files(base: &Path) -> Vec<String> {
+        let mut list = Vec::new();
+        let mut dirs = vec![base.to_owned()];
+        while let Some(dir) = dirs.pop() {
+            for entry in std::fs::read_dir(&dir).unwrap() {
+                let entry = entry.unwrap();
+                if entry.file_name() == index::INDEX_FILE_NAME {
+                    continue;
+                }
+                if entry.metadata().unwrap().is_dir() {
+                    dirs.push(entry.path());
+                } else {
+                    list.push(
+                        entry
+                            .path()
+                            .strip_prefix(base)
+                            .unwrap()
+                            .to_string_lossy()
+                            .to_string(),
+                    );
+                }
+            }
+        }
+        list.sort();
+        list
+    }
+
     fn write_empty_dirs(base: &Path, paths: &[&str]) {
         for path in paths {
             std::fs::create_dir_all(base.join(path)).unwrap();
         }
     }
 
+    // Directories that only contain the index file are considered empty.
     fn list_empty_dirs(path: &Path) -> Vec<String> {
         let mut list = Vec::new();
 
         let mut dirs = vec![path.to_owned()];
         while let Some(dir) = dirs.pop() {
-            let entries: Vec<_> = std::fs::read_dir(&dir).unwrap().collect();
+            let entries: Vec<_> = std::fs::read_dir(&dir)
+                .unwrap()
+                .filter(|v| v.as_ref().unwrap().file_name() != index::INDEX_FILE_NAME)
+                .collect();
             if entries.is_empty() {
                 list.push(
                     dir.strip_prefix(path)
@@ -0,0 +1,769 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+// Authenticated encryption of recording files at rest.
+//
+// header: magic (8 bytes) | salt (16 bytes)
+// slot:   version (4 bytes) | ciphertext (max 4096 bytes) | tag (16 bytes)
+//
+// The plaintext is split into fixed size chunks that are sealed separately
+// with ChaCha20-Poly1305, any range can be read without decrypting the
+// whole file. Each file has its own key derived from the master key and
+// the salt. The nonce is the chunk index followed by the slot version,
+// the last chunk is resealed with the next version on every flush so
+// that active recordings can be read while they're being written.
+//
+// The last slot is sealed with a flag in the additional data and is never
+// full, an empty slot is appended when the last chunk is full. Files that
+// were truncated at a slot boundary fail authentication of the last slot.
+
+use common::EncryptionKey;
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305},
+    hkdf,
+    rand::{SecureRandom, SystemRandom},
+};
+use std::{
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+pub const ENCRYPTED_FILE_MAGIC: [u8; 8] = *b"SSENCv01";
+
+const SALT_SIZE: usize = 16;
+const HEADER_SIZE: u64 = 24;
+const CHUNK_SIZE: u64 = 4096;
+const VERSION_SIZE: u64 = 4;
+const TAG_SIZE: u64 = 16;
+const SLOT_SIZE: u64 = VERSION_SIZE + CHUNK_SIZE + TAG_SIZE;
+
+const HKDF_INFO: &[u8] = b"sentryshot recording";
+
+#[derive(Debug, Error)]
+pub enum DecryptError {
+    #[error("file is encrypted but no encryption key is configured")]
+    NoKey,
+
+    #[error("file is truncated")]
+    Truncated,
+
+    #[error("authentication of chunk {0} failed, wrong key or modified file")]
+    Authentication(u64),
+}
+
+impl From<DecryptError> for std::io::Error {
+    fn from(e: DecryptError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+    }
+}
+
+// Returns true if the data starts with the encrypted file header.
+#[must_use]
+pub fn is_encrypted(header: &[u8]) -> bool {
+    header.starts_with(&ENCRYPTED_FILE_MAGIC)
+}
+
+// Decrypts a whole file. Unencrypted files are returned unchanged.
+pub fn decrypt_file(raw: Vec<u8>, key: Option<&EncryptionKey>) -> Result<Vec<u8>, DecryptError> {
+    use DecryptError::*;
+    if !is_encrypted(&raw) {
+        return Ok(raw);
+    }
+    let key = key.ok_or(NoKey)?;
+    let file_size = to_u64(raw.len());
+    if file_size <= HEADER_SIZE {
+        return Err(Truncated);
+    }
+    let (header, body) = raw.split_at(to_usize(HEADER_SIZE));
+    let file_key = file_key(key, &header[ENCRYPTED_FILE_MAGIC.len()..]);
+
+    let last_index = last_chunk_index(file_size);
+    let mut plain = Vec::with_capacity(to_usize(plaintext_size(file_size)));
+    for (i, slot) in body.chunks(to_usize(SLOT_SIZE)).enumerate() {
+        let i = to_u64(i);
+        plain.extend_from_slice(&open_slot(&file_key, i, i == last_index, slot)?);
+    }
+    Ok(plain)
+}
+
+// Encrypts a whole file.
+pub fn encrypt_file(plain: &[u8], key: &EncryptionKey) -> Result<Vec<u8>, std::io::Error> {
+    let salt = new_salt()?;
+    let file_key = file_key(key, &salt);
+
+    let mut raw = new_header(&salt);
+    for (i, chunk) in plain.chunks(to_usize(CHUNK_SIZE)).enumerate() {
+        let last = to_u64(chunk.len()) < CHUNK_SIZE;
+        raw.extend_from_slice(&seal_slot(&file_key, to_u64(i), 0, last, chunk));
+    }
+    if to_u64(plain.len()) % CHUNK_SIZE == 0 {
+        let i = to_u64(plain.len()) / CHUNK_SIZE;
+        raw.extend_from_slice(&seal_slot(&file_key, i, 0, true, &[]));
+    }
+    Ok(raw)
+}
+
+// Reads a whole recording file and decrypts it if needed.
+pub async fn read_recording_file(
+    path: &Path,
+    key: Option<&EncryptionKey>,
+) -> Result<Vec<u8>, std::io::Error> {
+    let raw = tokio::fs::read(path).await?;
+    Ok(decrypt_file(raw, key)?)
+}
+
+// Size of the plaintext in an encrypted file of the given size.
+fn plaintext_size(file_size: u64) -> u64 {
+    let body = file_size.saturating_sub(HEADER_SIZE);
+    let last_slot = body % SLOT_SIZE;
+    (body / SLOT_SIZE) * CHUNK_SIZE + last_slot.saturating_sub(VERSION_SIZE + TAG_SIZE)
+}
+
+fn slot_offset(chunk_index: u64) -> u64 {
+    HEADER_SIZE + chunk_index * SLOT_SIZE
+}
+
+// Index of the last slot in an encrypted file of the given size.
+fn last_chunk_index(file_size: u64) -> u64 {
+    file_size.saturating_sub(HEADER_SIZE + 1) / SLOT_SIZE
+}
+
+fn file_key(key: &EncryptionKey, salt: &[u8]) -> LessSafeKey {
+    let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, salt).extract(key.as_bytes());
+    let okm = prk
+        .expand(&[HKDF_INFO], &CHACHA20_POLY1305)
+        .expect("key length should be valid");
+    LessSafeKey::new(UnboundKey::from(okm))
+}
+
+fn new_salt() -> Result<[u8; SALT_SIZE], std::io::Error> {
+    let mut salt = [0; SALT_SIZE];
+    SystemRandom::new()
+        .fill(&mut salt)
+        .map_err(|_| std::io::Error::other("generate salt"))?;
+    Ok(salt)
+}
+
+fn new_header(salt: &[u8; SALT_SIZE]) -> Vec<u8> {
+    let mut header = Vec::with_capacity(to_usize(HEADER_SIZE));
+    header.extend_from_slice(&ENCRYPTED_FILE_MAGIC);
+    header.extend_from_slice(salt);
+    header
+}
+
+fn nonce(chunk_index: u64, version: u32) -> Nonce {
+    let mut nonce = [0; 12];
+    nonce[..8].copy_from_slice(&chunk_index.to_be_bytes());
+    nonce[8..].copy_from_slice(&version.to_be_bytes());
+    Nonce::assume_unique_for_key(nonce)
+}
+
+fn aad(last: bool) -> Aad<[u8; 1]> {
+    Aad::from([u8::from(last)])
+}
+
+fn seal_slot(
+    key: &LessSafeKey,
+    chunk_index: u64,
+    version: u32,
+    last: bool,
+    chunk: &[u8],
+) -> Vec<u8> {
+    let mut slot = Vec::with_capacity(to_usize(SLOT_SIZE));
+    slot.extend_from_slice(&version.to_be_bytes());
+    slot.extend_from_slice(chunk);
+    let tag = key
+        .seal_in_place_separate_tag(
+            nonce(chunk_index, version),
+            aad(last),
+            &mut slot[to_usize(VERSION_SIZE)..],
+        )
+        .expect("chunk should be within size limit");
+    slot.extend_from_slice(tag.as_ref());
+    slot
+}
+
+fn open_slot(
+    key: &LessSafeKey,
+    chunk_index: u64,
+    last: bool,
+    slot: &[u8],
+) -> Result<Vec<u8>, DecryptError> {
+    use DecryptError::*;
+    if to_u64(slot.len()) < VERSION_SIZE + TAG_SIZE {
+        return Err(Truncated);
+    }
+    let (version, sealed) = slot.split_at(to_usize(VERSION_SIZE));
+    let version = u32::from_be_bytes(version.try_into().expect("4 bytes"));
+
+    let mut buf = sealed.to_vec();
+    let n = key
+        .open_in_place(nonce(chunk_index, version), aad(last), &mut buf)
+        .map_err(|_| Authentication(chunk_index))?
+        .len();
+    buf.truncate(n);
+    Ok(buf)
+}
+
+fn to_usize(v: u64) -> usize {
+    usize::try_from(v).expect("usize should fit u64")
+}
+
+fn to_u64(v: usize) -> u64 {
+    u64::try_from(v).expect("u64 should fit usize")
+}
+
+// Encrypts everything written to it. The last chunk is buffered until
+// it's full or the writer is flushed, the inner writer must be seekable
+// because the last slot is rewritten on each flush.
+pub struct EncryptWriter<W> {
+    inner: W,
+    key: LessSafeKey,
+
+    chunk_index: u64,
+    version: u32,
+    chunk: Vec<u8>,
+    dirty: bool,
+
+    // Sealed slot waiting to be written.
+    pending: Vec<u8>,
+    pending_offset: u64,
+    pending_written: usize,
+
+    // Position of the inner writer.
+    cursor: u64,
+    state: WriteState,
+}
+
+enum WriteState {
+    Idle,
+    Seeking,
+    Writing,
+}
+
+impl<W: AsyncWrite + AsyncSeek + Unpin> EncryptWriter<W> {
+    // Writes the header and an empty last slot, `inner` must be empty.
+    pub async fn new(mut inner: W, key: &EncryptionKey) -> Result<Self, std::io::Error> {
+        let salt = new_salt()?;
+        let key = file_key(key, &salt);
+        let mut header = new_header(&salt);
+        header.extend_from_slice(&seal_slot(&key, 0, 0, true, &[]));
+        inner.write_all(&header).await?;
+        Ok(Self {
+            inner,
+            key,
+            chunk_index: 0,
+            version: 1,
+            chunk: Vec::with_capacity(to_usize(CHUNK_SIZE)),
+            dirty: false,
+            pending: Vec::new(),
+            pending_offset: 0,
+            pending_written: 0,
+            cursor: to_u64(header.len()),
+            state: WriteState::Idle,
+        })
+    }
+
+    fn seal_chunk(&mut self) {
+        let full = to_u64(self.chunk.len()) == CHUNK_SIZE;
+        self.pending = seal_slot(
+            &self.key,
+            self.chunk_index,
+            self.version,
+            !full,
+            &self.chunk,
+        );
+        self.pending_offset = slot_offset(self.chunk_index);
+        self.pending_written = 0;
+        self.dirty = false;
+        if full {
+            // The empty last slot is written together with the full
+            // slot so that the file always ends with a last slot.
+            self.chunk_index += 1;
+            self.chunk.clear();
+            let empty = seal_slot(&self.key, self.chunk_index, 0, true, &[]);
+            self.pending.extend_from_slice(&empty);
+            self.version = 1;
+        } else {
+            self.version += 1;
+        }
+    }
+
+    fn poll_write_pending(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), std::io::Error>> {
+        loop {
+            match self.state {
+                WriteState::Idle => {
+                    if self.pending.is_empty() {
+                        return Poll::Ready(Ok(()));
+                    }
+                    if self.cursor == self.pending_offset {
+                        self.state = WriteState::Writing;
+                        continue;
+                    }
+                    // Previous writes must complete before seeking.
+                    ready!(Pin::new(&mut self.inner).poll_complete(cx))?;
+                    Pin::new(&mut self.inner).start_seek(SeekFrom::Start(self.pending_offset))?;
+                    self.state = WriteState::Seeking;
+                }
+                WriteState::Seeking => {
+                    ready!(Pin::new(&mut self.inner).poll_complete(cx))?;
+                    self.cursor = self.pending_offset;
+                    self.state = WriteState::Writing;
+                }
+                WriteState::Writing => {
+                    while self.pending_written < self.pending.len() {
+                        let n = ready!(Pin::new(&mut self.inner)
+                            .poll_write(cx, &self.pending[self.pending_written..]))?;
+                        if n == 0 {
+                            return Poll::Ready(Err(std::io::ErrorKind::WriteZero.into()));
+                        }
+                        self.pending_written += n;
+                        self.cursor += to_u64(n);
+                    }
+                    self.pending.clear();
+                    self.state = WriteState::Idle;
+                    return Poll::Ready(Ok(()));
+                }
+            }
+        }
+    }
+}
+
+impl<W: AsyncWrite + AsyncSeek + Unpin> AsyncWrite for EncryptWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_pending(cx))?;
+
+        let n = std::cmp::min(buf.len(), to_usize(CHUNK_SIZE) - this.chunk.len());
+        this.chunk.extend_from_slice(&buf[..n]);
+        this.dirty = true;
+        if to_u64(this.chunk.len()) == CHUNK_SIZE {
+            this.seal_chunk();
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), std::io::Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_pending(cx))?;
+        if this.dirty {
+            this.seal_chunk();
+            ready!(this.poll_write_pending(cx))?;
+        }
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+// Decrypts an encrypted file with random access.
+// The most recently read chunk is cached.
+pub struct DecryptReader<R> {
+    inner: R,
+    key: LessSafeKey,
+    file_size: u64,
+    size: u64,
+    pos: u64,
+
+    cached_index: Option<u64>,
+    cached_chunk: Vec<u8>,
+
+    slot: Vec<u8>,
+    slot_filled: usize,
+
+    // Position of the inner reader.
+    cursor: Option<u64>,
+    state: ReadState,
+}
+
+enum ReadState {
+    Idle,
+    Seeking(u64),
+    Reading(u64),
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin> DecryptReader<R> {
+    // The inner reader must be positioned after the header.
+    fn new(inner: R, key: LessSafeKey, file_size: u64) -> Self {
+        Self {
+            inner,
+            key,
+            file_size,
+            size: plaintext_size(file_size),
+            pos: 0,
+            cached_index: None,
+            cached_chunk: Vec::new(),
+            slot: Vec::new(),
+            slot_filled: 0,
+            cursor: Some(HEADER_SIZE),
+            state: ReadState::Idle,
+        }
+    }
+
+    fn start_reading(&mut self, chunk_index: u64) {
+        let offset = slot_offset(chunk_index);
+        let slot_size = std::cmp::min(SLOT_SIZE, self.file_size.saturating_sub(offset));
+        self.slot.resize(to_usize(slot_size), 0);
+        self.slot_filled = 0;
+        self.state = ReadState::Reading(chunk_index);
+    }
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin> AsyncRead for DecryptReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        let this = self.get_mut();
+        loop {
+            if this.pos >= this.size || buf.remaining() == 0 {
+                return Poll::Ready(Ok(()));
+            }
+            let chunk_index = this.pos / CHUNK_SIZE;
+            if this.cached_index == Some(chunk_index) {
+                let start = to_usize(this.pos - chunk_index * CHUNK_SIZE);
+                let n = std::cmp::min(
+                    buf.remaining(),
+                    this.cached_chunk.len().saturating_sub(start),
+                );
+                if n == 0 {
+                    return Poll::Ready(Err(DecryptError::Truncated.into()));
+                }
+                buf.put_slice(&this.cached_chunk[start..start + n]);
+                this.pos += to_u64(n);
+                return Poll::Ready(Ok(()));
+            }
+
+            match this.state {
+                ReadState::Idle => {
+                    let offset = slot_offset(chunk_index);
+                    if this.cursor == Some(offset) {
+                        this.start_reading(chunk_index);
+                        continue;
+                    }
+                    this.cursor = None;
+                    ready!(Pin::new(&mut this.inner).poll_complete(cx))?;
+                    Pin::new(&mut this.inner).start_seek(SeekFrom::Start(offset))?;
+                    this.state = ReadState::Seeking(chunk_index);
+                }
+                ReadState::Seeking(chunk_index) => {
+                    ready!(Pin::new(&mut this.inner).poll_complete(cx))?;
+                    this.cursor = Some(slot_offset(chunk_index));
+                    this.start_reading(chunk_index);
+                }
+                ReadState::Reading(chunk_index) => {
+                    while this.slot_filled < this.slot.len() {
+                        let mut slot_buf = ReadBuf::new(&mut this.slot[this.slot_filled..]);
+                        ready!(Pin::new(&mut this.inner).poll_read(cx, &mut slot_buf))?;
+                        let n = slot_buf.filled().len();
+                        if n == 0 {
+                            this.state = ReadState::Idle;
+                            this.cursor = None;
+                            return Poll::Ready(Err(DecryptError::Truncated.into()));
+                        }
+                        this.slot_filled += n;
+                        this.cursor = this.cursor.map(|v| v + to_u64(n));
+                    }
+                    this.state = ReadState::Idle;
+                    let last = chunk_index == last_chunk_index(this.file_size);
+                    this.cached_chunk = open_slot(&this.key, chunk_index, last, &this.slot)?;
+                    this.cached_index = Some(chunk_index);
+                }
+            }
+        }
+    }
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin> AsyncSeek for DecryptReader<R> {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> Result<(), std::io::Error> {
+        let this = self.get_mut();
+        let pos = match position {
+            SeekFrom::Start(v) => Some(v),
+            SeekFrom::End(v) => this.size.checked_add_signed(v),
+            SeekFrom::Current(v) => this.pos.checked_add_signed(v),
+        };
+        let Some(pos) = pos else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            ));
+        };
+        this.pos = pos;
+        Ok(())
+    }
+
+    fn poll_complete(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+    ) -> Poll<Result<u64, std::io::Error>> {
+        Poll::Ready(Ok(self.pos))
+    }
+}
+
+// Recording file opened for reading, encrypted
+// files are decrypted transparently.
+pub struct RecordingFile {
+    inner: RecordingFileInner,
+    size: u64,
+}
+
+impl std::fmt::Debug for RecordingFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let encrypted = matches!(self.inner, RecordingFileInner::Encrypted(_));
+        f.debug_struct("RecordingFile")
+            .field("encrypted", &encrypted)
+            .field("size", &self.size)
+            .finish()
+    }
+}
+
+enum RecordingFileInner {
+    Plain(tokio::fs::File),
+    Encrypted(Box<DecryptReader<tokio::fs::File>>),
+}
+
+impl RecordingFile {
+    pub async fn open(path: &Path, key: Option<&EncryptionKey>) -> Result<Self, std::io::Error> {
+        let path = path.to_owned();
+        let key = key.cloned();
+        tokio::task::spawn_blocking(move || {
+            Self::from_std(std::fs::File::open(path)?, key.as_ref())
+        })
+        .await
+        .expect("join")
+    }
+
+    // Blocking.
+    pub fn from_std(
+        mut file: std::fs::File,
+        key: Option<&EncryptionKey>,
+    ) -> Result<Self, std::io::Error> {
+        let file_size = file.metadata()?.len();
+        let mut header = Vec::with_capacity(to_usize(HEADER_SIZE));
+        (&mut file).take(HEADER_SIZE).read_to_end(&mut header)?;
+
+        if !is_encrypted(&header) {
+            file.seek(SeekFrom::Start(0))?;
+            return Ok(Self {
+                inner: RecordingFileInner::Plain(tokio::fs::File::from_std(file)),
+                size: file_size,
+            });
+        }
+        let key = key.ok_or(DecryptError::NoKey)?;
+        if file_size <= HEADER_SIZE {
+            return Err(DecryptError::Truncated.into());
+        }
+        let key = file_key(key, &header[ENCRYPTED_FILE_MAGIC.len()..]);
+
+        // Truncated files are rejected before anything is read.
+        let last_index = last_chunk_index(file_size);
+        let mut slot = Vec::with_capacity(to_usize(SLOT_SIZE));
+        file.seek(SeekFrom::Start(slot_offset(last_index)))?;
+        (&mut file).take(SLOT_SIZE).read_to_end(&mut slot)?;
+        open_slot(&key, last_index, true, &slot)?;
+        file.seek(SeekFrom::Start(HEADER_SIZE))?;
+
+        let reader = DecryptReader::new(tokio::fs::File::from_std(file), key, file_size);
+        Ok(Self {
+            size: reader.size,
+            inner: RecordingFileInner::Encrypted(Box::new(reader)),
+        })
+    }
+
+    // Size of the plaintext when the file was opened.
+    #[must_use]
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl AsyncRead for RecordingFile {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        match &mut self.get_mut().inner {
+            RecordingFileInner::Plain(v) => Pin::new(v).poll_read(cx, buf),
+            RecordingFileInner::Encrypted(v) => Pin::new(v).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncSeek for RecordingFile {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> Result<(), std::io::Error> {
+        match &mut self.get_mut().inner {
+            RecordingFileInner::Plain(v) => Pin::new(v).start_seek(position),
+            RecordingFileInner::Encrypted(v) => Pin::new(v).start_seek(position),
+        }
+    }
+
+    fn poll_complete(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<u64, std::io::Error>> {
+        match &mut self.get_mut().inner {
+            RecordingFileInner::Plain(v) => Pin::new(v).poll_complete(cx),
+            RecordingFileInner::Encrypted(v) => Pin::new(v).poll_complete(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tempfile::tempdir;
+    use test_case::test_case;
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+    fn test_key(v: u8) -> EncryptionKey {
+        EncryptionKey::new([v; 32])
+    }
+
+    fn test_data(n: usize) -> Vec<u8> {
+        (0..n).map(|i| u8::try_from(i % 251).unwrap()).collect()
+    }
+
+    #[test_case(0; "empty")]
+    #[test_case(1; "one")]
+    #[test_case(4096; "chunk")]
+    #[test_case(10000; "multiple")]
+    fn test_encrypt_decrypt_file(n: usize) {
+        let key = test_key(1);
+        let plain = test_data(n);
+        let raw = encrypt_file(&plain, &key).unwrap();
+        assert!(is_encrypted(&raw));
+        assert_eq!(plaintext_size(to_u64(raw.len())), to_u64(n));
+        assert_eq!(plain, decrypt_file(raw, Some(&key)).unwrap());
+    }
+
+    #[test]
+    fn test_decrypt_file_errors() {
+        let raw = encrypt_file(&test_data(100), &test_key(1)).unwrap();
+        assert!(matches!(
+            decrypt_file(raw.clone(), None),
+            Err(DecryptError::NoKey)
+        ));
+        assert!(matches!(
+            decrypt_file(raw.clone(), Some(&test_key(2))),
+            Err(DecryptError::Authentication(0))
+        ));
+
+        let mut modified = raw;
+        *modified.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            decrypt_file(modified, Some(&test_key(1))),
+            Err(DecryptError::Authentication(0))
+        ));
+
+        // Truncated at a slot boundary.
+        let mut truncated = encrypt_file(&test_data(10000), &test_key(1)).unwrap();
+        truncated.truncate(to_usize(slot_offset(2)));
+        assert!(matches!(
+            decrypt_file(truncated, Some(&test_key(1))),
+            Err(DecryptError::Authentication(1))
+        ));
+
+        // Unencrypted files are passed through.
+        let plain = b"{}".to_vec();
+        assert_eq!(plain, decrypt_file(plain.clone(), None).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_encrypt_writer() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("test.mdat");
+        let key = test_key(1);
+        let plain = test_data(10000);
+
+        let file = tokio::fs::File::create(&path).await.unwrap();
+        let mut writer = EncryptWriter::new(file, &key).await.unwrap();
+
+        // The file must be readable after each flush.
+        let mut written = 0;
+        for n in [1, 10, 4000, 85, 200, 5000, 704] {
+            writer
+                .write_all(&plain[written..written + n])
+                .await
+                .unwrap();
+            writer.flush().await.unwrap();
+            written += n;
+
+            let raw = std::fs::read(&path).unwrap();
+            assert_eq!(plain[..written], decrypt_file(raw, Some(&key)).unwrap());
+        }
+        assert_eq!(plain.len(), written);
+
+        // Flushing without new data doesn't reseal the last chunk.
+        let raw = std::fs::read(&path).unwrap();
+        writer.flush().await.unwrap();
+        assert_eq!(raw, std::fs::read(&path).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_recording_file() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("test.mdat");
+        let key = test_key(1);
+        let plain = test_data(10000);
+        std::fs::write(&path, encrypt_file(&plain, &key).unwrap()).unwrap();
+
+        let mut file = RecordingFile::open(&path, Some(&key)).await.unwrap();
+        assert_eq!(10000, file.size());
+
+        let mut got = Vec::new();
+        file.read_to_end(&mut got).await.unwrap();
+        assert_eq!(plain, got);
+
+        // Range across a chunk boundary.
+        file.seek(SeekFrom::Start(4090)).await.unwrap();
+        let mut buf = [0; 12];
+        file.read_exact(&mut buf).await.unwrap();
+        assert_eq!(plain[4090..4102], buf);
+
+        file.seek(SeekFrom::End(-3)).await.unwrap();
+        let mut got = Vec::new();
+        file.read_to_end(&mut got).await.unwrap();
+        assert_eq!(plain[9997..], got);
+
+        assert!(RecordingFile::open(&path, None).await.is_err());
+
+        // Truncated at a slot boundary.
+        let mut raw = std::fs::read(&path).unwrap();
+        raw.truncate(to_usize(slot_offset(2)));
+        std::fs::write(&path, raw).unwrap();
+        assert!(RecordingFile::open(&path, Some(&key)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_recording_file_plain() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("test.json");
+        std::fs::write(&path, b"abc").unwrap();
+
+        let mut file = RecordingFile::open(&path, Some(&test_key(1)))
+            .await
+            .unwrap();
+        assert_eq!(3, file.size());
+
+        let mut got = Vec::new();
+        file.read_to_end(&mut got).await.unwrap();
+        assert_eq!(b"abc".to_vec(), got);
+    }
+}
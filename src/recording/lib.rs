@@ -1,15 +1,20 @@
 // SPDX-License-Identifier: GPL-2.0-or-later
 
 mod cache;
+mod crypt;
 mod mp4_muxer;
 mod video;
 mod video_reader;
 
 pub use cache::VideoCache;
+pub use crypt::{
+    decrypt_file, encrypt_file, is_encrypted, read_recording_file, DecryptError, EncryptWriter,
+    RecordingFile,
+};
 pub use hls::VIDEO_TRACK_ID;
-pub use mp4_muxer::{generate_mp4, GenerateMp4Error, Mp4Muxer};
+pub use mp4_muxer::{generate_mp4, GenerateMp4Error, Mp4Generator, Mp4Muxer};
 pub use video::{
-    read_meta, CreateVideoWriterError, MetaHeader, MetaReader, ReadMetaError, Sample,
-    TrackParameters, VideoWriter, WriteSampleError,
+    migrate_meta_v0, read_meta, CreateVideoWriterError, MetaHeader, MetaReader, MigrateMetaError,
+    ReadMetaError, Sample, TrackParameters, VideoWriter, WriteSampleError, SAMPLE_SIZE,
 };
 pub use video_reader::{new_video_reader, CreateVideoReaderError};
@@ -2,8 +2,11 @@
 
 use crate::video::{Sample, TrackParameters};
 use async_trait::async_trait;
-use common::time::{DurationH264, UnixH264, H264_TIMESCALE};
-use hls::VIDEO_TRACK_ID;
+use common::{
+    time::{DurationH264, UnixH264, H264_TIMESCALE},
+    AudioSampleEntry, VideoCodec,
+};
+use hls::{AUDIO_TRACK_ID, VIDEO_TRACK_ID};
 use mp4::{FullBox, ImmutableBox, ImmutableBoxAsync, Mp4Error};
 use std::{num::TryFromIntError, sync::Arc};
 use thiserror::Error;
@@ -29,6 +32,9 @@ pub enum GenerateMp4Error {
     #[error("stsz length: {0} {1}")]
     StszLen(usize, TryFromIntError),
 
+    #[error("stsc: {0}")]
+    Stsc(TryFromIntError),
+
     #[error("generate trak: {0}")]
     GenerateTrak(#[from] GenerateTrakError),
 
@@ -49,64 +55,133 @@ pub struct Mp4Muxer {
     pub ctts: Vec<mp4::CttsEntryV1>,
     pub stsc: Vec<mp4::StscEntry>,
     pub stsz: Vec<u32>,
-    pub stco: Arc<std::sync::Mutex<Vec<u32>>>,
+    pub stco: Arc<std::sync::Mutex<Vec<u64>>>,
+
+    pub audio_stts: Vec<mp4::SttsEntry>,
+    pub audio_stsc: Vec<mp4::StscEntry>,
+    pub audio_stsz: Vec<u32>,
+    pub audio_stco: Arc<std::sync::Mutex<Vec<u64>>>,
+
+    // Write 64 bit chunk offsets, set if the file is larger than 4GB.
+    pub co64: bool,
 }
 
-#[allow(
-    clippy::items_after_statements,
-    clippy::similar_names,
-    clippy::too_many_lines
-)]
+// Consecutive samples from the same track are stored in a single chunk.
+// Audio samples are skipped if `params.audio` is None.
+// Returns the size of the mdat data.
 pub async fn generate_mp4<'a, S>(
     out: &'a mut (dyn AsyncWrite + Unpin + Send + Sync),
     start_time: UnixH264,
     samples: S,
     params: &'a TrackParameters,
-) -> Result<u32, GenerateMp4Error>
+) -> Result<u64, GenerateMp4Error>
 where
     S: Iterator<Item = &'a Sample>,
 {
-    use GenerateMp4Error::*;
+    let mut g = Mp4Generator::new(start_time, params.clone());
+    for sample in samples {
+        g.push(sample)?;
+    }
+    g.finish(out).await
+}
 
-    let mut m = Mp4Muxer {
-        stco: Arc::new(std::sync::Mutex::new(vec![0])),
-        ..Default::default()
-    };
-    let mut mdat_pos: u32 = 0;
-    let mut end_time = UnixH264::new(0);
-    let mut dts_shift = DurationH264::new(0);
+// Builds the sample tables one sample at a time, the
+// samples themselves don't have to be kept in memory.
+pub struct Mp4Generator {
+    start_time: UnixH264,
+    params: TrackParameters,
+
+    m: Mp4Muxer,
+    mdat_pos: u64,
+    end_time: UnixH264,
+    dts_shift: DurationH264,
+    audio_duration: DurationH264,
+
+    prev_sample_is_audio: Option<bool>,
+    video_chunks: Vec<u32>,
+    audio_chunks: Vec<u32>,
+}
+
+impl Mp4Generator {
+    #[must_use]
+    pub fn new(start_time: UnixH264, params: TrackParameters) -> Self {
+        Self {
+            start_time,
+            params,
+            m: Mp4Muxer::default(),
+            mdat_pos: 0,
+            end_time: UnixH264::new(0),
+            dts_shift: DurationH264::new(0),
+            audio_duration: DurationH264::new(0),
+            prev_sample_is_audio: None,
+            video_chunks: Vec::new(),
+            audio_chunks: Vec::new(),
+        }
+    }
+
+    // Samples must be pushed in the same order as their data is written.
+    #[allow(clippy::similar_names)]
+    pub fn push(&mut self, sample: &Sample) -> Result<(), GenerateMp4Error> {
+        use GenerateMp4Error::*;
+        let m = &mut self.m;
+
+        let new_chunk = self.prev_sample_is_audio != Some(sample.audio);
+        self.prev_sample_is_audio = Some(sample.audio);
+
+        if sample.audio {
+            if self.params.audio.is_some() {
+                if new_chunk {
+                    m.audio_stco
+                        .lock()
+                        .expect("not poisoned")
+                        .push(self.mdat_pos);
+                    self.audio_chunks.push(0);
+                }
+                *self.audio_chunks.last_mut().expect("chunk should exist") += 1;
+
+                let delta = sample
+                    .duration
+                    .as_u32()
+                    .map_err(|v| Delta(sample.duration, v))?;
+                push_stts(&mut m.audio_stts, delta);
+                m.audio_stsz.push(sample.data_size);
+                self.audio_duration = self
+                    .audio_duration
+                    .checked_add(sample.duration)
+                    .ok_or(Add)?;
+            }
+            self.mdat_pos += u64::from(sample.data_size);
+            return Ok(());
+        }
+
+        if new_chunk {
+            m.stco.lock().expect("not poisoned").push(self.mdat_pos);
+            self.video_chunks.push(0);
+        }
+        *self.video_chunks.last_mut().expect("chunk should exist") += 1;
 
-    for sample in samples {
         let delta = sample
             .duration
             .as_u32()
             .map_err(|v| Delta(sample.duration, v))?;
-        match m.stts.last_mut() {
-            Some(last) if last.sample_delta == delta => {
-                last.sample_count += 1;
-            }
-            _ => m.stts.push(mp4::SttsEntry {
-                sample_count: 1,
-                sample_delta: delta,
-            }),
-        }
+        push_stts(&mut m.stts, delta);
 
-        let pts = DurationH264::from(sample.pts.checked_sub(start_time).ok_or(Sub)?);
+        let pts = DurationH264::from(sample.pts.checked_sub(self.start_time).ok_or(Sub)?);
         let dts = DurationH264::from(
             sample
                 .dts()
                 .ok_or(Sub)?
-                .checked_sub(start_time)
+                .checked_sub(self.start_time)
                 .ok_or(Sub)?,
         );
 
         let first_sample = m.stsz.is_empty();
         if first_sample {
-            dts_shift = pts.checked_sub(dts).ok_or(Sub)?;
+            self.dts_shift = pts.checked_sub(dts).ok_or(Sub)?;
         }
 
         let cts = *pts
-            .checked_sub(dts.checked_add(dts_shift).ok_or(Add)?)
+            .checked_sub(dts.checked_add(self.dts_shift).ok_or(Add)?)
             .ok_or(Add)?;
         let cts = i32::try_from(cts).map_err(|v| Cts(cts, v))?;
         //cts := pts - (dts + m.dtsShift)
@@ -121,7 +196,7 @@ where
             }),
         }
 
-        mdat_pos += sample.data_size;
+        self.mdat_pos += u64::from(sample.data_size);
         m.stsz.push(sample.data_size);
 
         if sample.random_access_present {
@@ -129,82 +204,116 @@ where
                 .push(u32::try_from(m.stsz.len()).map_err(|v| StszLen(m.stts.len(), v))?);
         }
 
-        end_time = sample
+        self.end_time = sample
             .dts()
             .ok_or(Sub)?
             .checked_add(sample.duration.into())
             .ok_or(Add)?;
+        Ok(())
     }
 
-    m.stsc.push(mp4::StscEntry {
-        first_chunk: 1,
-        samples_per_chunk: u32::try_from(m.stsz.len()).map_err(|e| StszLen(m.stsz.len(), e))?,
-        sample_description_index: 1,
-    });
-
-    let duration = DurationH264::from(
-        end_time
-            .checked_sub(start_time)
-            .ok_or(GenerateMp4Error::Sub)?,
-    );
-    //duration := time.Duration(m.endTime - m.startTime)
-
-    let moov = mp4::BoxesAsync::new(mp4::Moov {}).with_children2(
-        // Mvhd.
-        mp4::BoxesAsync::new(mp4::Mvhd {
-            timescale: 1000,
-            version: mp4::MvhdVersion::V0(mp4::MvhdV0 {
-                duration: u32::try_from(duration.as_millis())
-                    .map_err(|v| MvhdDuration(duration.as_millis(), v))?,
-                ..Default::default()
-            }),
-            rate: 65536,
-            volume: 256,
-            matrix: [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000],
-            next_track_id: VIDEO_TRACK_ID + 1,
-            ..Default::default()
-        }),
-        // Trak.
-        m.generate_trak(duration, params)?,
-    );
-
-    const FTYP_SIZE: u32 = 20;
-    const MDAT_HEADER_SIZE: u32 = 8;
-    let mdat_offset: u32 = FTYP_SIZE
-        + u32::try_from(moov.size()).map_err(|v| MoovSize(moov.size(), v))?
-        + MDAT_HEADER_SIZE;
-
-    {
-        let mut stco = m.stco.lock().expect("not poisoned");
-        for i in 0..stco.len() {
-            stco[i] += mdat_offset;
+    // Writes ftyp, moov and the mdat header. Returns the size of the mdat data.
+    #[allow(clippy::items_after_statements)]
+    pub async fn finish(
+        mut self,
+        out: &mut (dyn AsyncWrite + Unpin + Send + Sync),
+    ) -> Result<u64, GenerateMp4Error> {
+        use GenerateMp4Error::*;
+        let m = &mut self.m;
+
+        m.stsc = stsc_entries(&self.video_chunks).map_err(Stsc)?;
+        m.audio_stsc = stsc_entries(&self.audio_chunks).map_err(Stsc)?;
+
+        let duration = DurationH264::from(
+            self.end_time
+                .checked_sub(self.start_time)
+                .ok_or(GenerateMp4Error::Sub)?,
+        );
+        //duration := time.Duration(m.endTime - m.startTime)
+
+        const FTYP_SIZE: u64 = 20;
+        // The 64 bit size field is used for files larger than 4GB.
+        let mdat_size = self.mdat_pos.checked_add(8).ok_or(Add)?;
+        let large_mdat = u32::try_from(mdat_size).is_err();
+        let mdat_header_size: u64 = if large_mdat { 16 } else { 8 };
+
+        let mut moov = m.generate_moov(duration, self.audio_duration, &self.params)?;
+        let mut mdat_offset =
+            FTYP_SIZE + u64::try_from(moov.size()).expect("u64 fit usize") + mdat_header_size;
+        if u32::try_from(mdat_offset + self.mdat_pos).is_err() {
+            m.co64 = true;
+            moov = m.generate_moov(duration, self.audio_duration, &self.params)?;
+            mdat_offset =
+                FTYP_SIZE + u64::try_from(moov.size()).expect("u64 fit usize") + mdat_header_size;
         }
-        drop(stco);
-    }
 
-    /*
-       ftyp
-       moov
-       - mvhd
-       - trak (video)
-       mdat
-    */
+        for stco in [&m.stco, &m.audio_stco] {
+            let mut stco = stco.lock().expect("not poisoned");
+            for i in 0..stco.len() {
+                stco[i] += mdat_offset;
+            }
+            drop(stco);
+        }
 
-    let ftyp = mp4::Ftyp {
-        major_brand: *b"iso4",
-        minor_version: 512,
-        compatible_brands: vec![mp4::CompatibleBrandElem(*b"iso4")],
-    };
+        /*
+           ftyp
+           moov
+           - mvhd
+           - trak (video)
+           - trak (audio)
+           mdat
+        */
+
+        let ftyp = mp4::Ftyp {
+            major_brand: *b"iso4",
+            minor_version: 512,
+            compatible_brands: vec![mp4::CompatibleBrandElem(*b"iso4")],
+        };
 
-    mp4::write_single_box2(out, &ftyp).await?;
+        mp4::write_single_box2(out, &ftyp).await?;
 
-    moov.marshal(out).await?;
+        moov.marshal(out).await?;
 
-    out.write_all(&(mdat_pos.checked_add(8).ok_or(GenerateMp4Error::Add)?).to_be_bytes())
-        .await?;
-    out.write_all(b"mdat").await?;
+        if large_mdat {
+            out.write_all(&1_u32.to_be_bytes()).await?;
+            out.write_all(b"mdat").await?;
+            out.write_all(&(mdat_size + 8).to_be_bytes()).await?;
+        } else {
+            out.write_all(&u32::try_from(mdat_size).expect("checked").to_be_bytes())
+                .await?;
+            out.write_all(b"mdat").await?;
+        }
+
+        Ok(self.mdat_pos)
+    }
+}
 
-    Ok(mdat_pos)
+fn push_stts(stts: &mut Vec<mp4::SttsEntry>, delta: u32) {
+    match stts.last_mut() {
+        Some(last) if last.sample_delta == delta => {
+            last.sample_count += 1;
+        }
+        _ => stts.push(mp4::SttsEntry {
+            sample_count: 1,
+            sample_delta: delta,
+        }),
+    }
+}
+
+// Run-length encodes the number of samples in each chunk.
+fn stsc_entries(samples_per_chunk: &[u32]) -> Result<Vec<mp4::StscEntry>, TryFromIntError> {
+    let mut entries: Vec<mp4::StscEntry> = Vec::new();
+    for (i, n) in samples_per_chunk.iter().enumerate() {
+        if entries.last().is_some_and(|v| v.samples_per_chunk == *n) {
+            continue;
+        }
+        entries.push(mp4::StscEntry {
+            first_chunk: u32::try_from(i + 1)?,
+            samples_per_chunk: *n,
+            sample_description_index: 1,
+        });
+    }
+    Ok(entries)
 }
 
 #[derive(Debug, Error)]
@@ -220,6 +329,43 @@ pub enum GenerateTrakError {
 }
 
 impl Mp4Muxer {
+    fn generate_moov(
+        &self,
+        duration: DurationH264,
+        audio_duration: DurationH264,
+        params: &TrackParameters,
+    ) -> Result<mp4::BoxesAsync, GenerateMp4Error> {
+        let next_track_id = if params.audio.is_some() {
+            AUDIO_TRACK_ID + 1
+        } else {
+            VIDEO_TRACK_ID + 1
+        };
+
+        let mut moov = mp4::BoxesAsync::new(mp4::Moov {}).with_children2(
+            // Mvhd.
+            mp4::BoxesAsync::new(mp4::Mvhd {
+                timescale: 1000,
+                version: mp4::MvhdVersion::V0(mp4::MvhdV0 {
+                    duration: u32::try_from(duration.as_millis())
+                        .map_err(|v| GenerateMp4Error::MvhdDuration(duration.as_millis(), v))?,
+                    ..Default::default()
+                }),
+                rate: 65536,
+                volume: 256,
+                matrix: [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000],
+                next_track_id,
+                ..Default::default()
+            }),
+            // Trak.
+            self.generate_trak(duration, params)?,
+        );
+        if let Some(audio) = &params.audio {
+            moov.children
+                .push(self.generate_audio_trak(audio_duration, audio)?);
+        }
+        Ok(moov)
+    }
+
     #[allow(clippy::let_and_return)]
     pub fn generate_trak(
         &self,
@@ -257,10 +403,7 @@ impl Mp4Muxer {
                 mp4::BoxesAsync::new(mp4::Mdhd {
                     timescale: H264_TIMESCALE,
                     language: *b"und",
-                    version: mp4::MdhdVersion::V0(mp4::MdhdV0 {
-                        duration: duration.as_u32().map_err(|v| MdhdDuration(duration, v))?,
-                        ..Default::default()
-                    }),
+                    version: mdhd_version(duration)?,
                     ..Default::default()
                 }),
                 // Hdlr.
@@ -333,6 +476,7 @@ impl Mp4Muxer {
             mp4::BoxesAsync::new(MyStco {
                 full_box: mp4::FullBox::default(),
                 chunk_offsets: self.stco.clone(),
+                co64: self.co64,
             }),
         );
 
@@ -340,108 +484,260 @@ impl Mp4Muxer {
             // Vmhd.
             mp4::BoxesAsync::new(mp4::Vmhd::default()),
             // Dinf.
-            mp4::BoxesAsync::new(mp4::Dinf).with_child(
-                // Dref.
-                mp4::BoxesAsync::new(mp4::Dref {
-                    full_box: mp4::FullBox::default(),
-                    entry_count: 1,
-                })
-                .with_child(
-                    // Url.
-                    mp4::BoxesAsync::new(mp4::Url {
-                        full_box: mp4::FullBox {
-                            version: 0,
-                            flags: [0, 0, 1],
-                        },
-                        location: String::new(),
-                    }),
-                ),
-            ),
+            generate_dinf(),
             // Stbl.
             stbl,
         );
 
         Ok(minf)
     }
+
+    pub fn generate_audio_trak(
+        &self,
+        duration: DurationH264,
+        sample_entry: &AudioSampleEntry,
+    ) -> Result<mp4::BoxesAsync, GenerateTrakError> {
+        use GenerateTrakError::*;
+        /*
+           trak
+           - tkhd
+           - mdia
+             - mdhd
+             - hdlr
+             - minf
+               - smhd
+               - dinf
+                 - dref
+                   - url
+               - stbl
+                 - stsd
+                   - mp4a or other sample entry
+                 - stts
+                 - stsc
+                 - stsz
+                 - stco
+        */
+
+        let stbl = mp4::BoxesAsync::new(mp4::Stbl {}).with_children5(
+            // Stsd.
+            mp4::BoxesAsync::new(mp4::Stsd {
+                full_box: mp4::FullBox::default(),
+                entry_count: 1,
+            })
+            .with_child(mp4::BoxesAsync::new(MyRawBox {
+                box_type: sample_entry.box_type,
+                data: sample_entry.data.clone(),
+            })),
+            // Stts.
+            mp4::BoxesAsync::new(mp4::Stts {
+                full_box: mp4::FullBox::default(),
+                entries: self.audio_stts.clone(),
+            }),
+            // Stsc.
+            mp4::BoxesAsync::new(mp4::Stsc {
+                full_box: mp4::FullBox::default(),
+                entries: self.audio_stsc.clone(),
+            }),
+            // Stsz.
+            mp4::BoxesAsync::new(mp4::Stsz {
+                full_box: mp4::FullBox::default(),
+                sample_size: 0,
+                sample_count: u32::try_from(self.audio_stsz.len())
+                    .map_err(|v| StszLen(self.audio_stsz.len(), v))?,
+                entry_sizes: self.audio_stsz.clone(),
+            }),
+            // Stco.
+            mp4::BoxesAsync::new(MyStco {
+                full_box: mp4::FullBox::default(),
+                chunk_offsets: self.audio_stco.clone(),
+                co64: self.co64,
+            }),
+        );
+
+        let minf = mp4::BoxesAsync::new(mp4::Minf).with_children3(
+            // Smhd.
+            mp4::BoxesAsync::new(mp4::Smhd::default()),
+            // Dinf.
+            generate_dinf(),
+            // Stbl.
+            stbl,
+        );
+
+        let trak = mp4::BoxesAsync::new(mp4::Trak).with_children2(
+            // Tkhd.
+            mp4::BoxesAsync::new(mp4::Tkhd {
+                flags: [0, 0, 3],
+                track_id: AUDIO_TRACK_ID,
+                version: mp4::TkhdVersion::V0(mp4::TkhdV0 {
+                    duration: u32::try_from(duration.as_millis())
+                        .map_err(|v| TkhdDuration(duration.as_millis(), v))?,
+                    ..Default::default()
+                }),
+                alternate_group: 1,
+                volume: 256,
+                matrix: [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000],
+                ..Default::default()
+            }),
+            // Mdia.
+            mp4::BoxesAsync::new(mp4::Mdia).with_children3(
+                // Mdhd.
+                mp4::BoxesAsync::new(mp4::Mdhd {
+                    timescale: H264_TIMESCALE,
+                    language: *b"und",
+                    version: mdhd_version(duration)?,
+                    ..Default::default()
+                }),
+                // Hdlr.
+                mp4::BoxesAsync::new(mp4::Hdlr {
+                    handler_type: *b"soun",
+                    name: "SoundHandler".to_owned(),
+                    ..Default::default()
+                }),
+                // Minf.
+                minf,
+            ),
+        );
+
+        Ok(trak)
+    }
+}
+
+// Version 1 is only used if the duration doesn't fit in
+// 32 bits, which happens after 13 hours at 90kHz.
+fn mdhd_version(duration: DurationH264) -> Result<mp4::MdhdVersion, GenerateTrakError> {
+    if let Ok(v) = duration.as_u32() {
+        return Ok(mp4::MdhdVersion::V0(mp4::MdhdV0 {
+            duration: v,
+            ..Default::default()
+        }));
+    }
+    Ok(mp4::MdhdVersion::V1(mp4::MdhdV1 {
+        creation_time: 0,
+        modification_time: 0,
+        duration: u64::try_from(*duration)
+            .map_err(|v| GenerateTrakError::MdhdDuration(duration, v))?,
+    }))
+}
+
+fn generate_dinf() -> mp4::BoxesAsync {
+    mp4::BoxesAsync::new(mp4::Dinf).with_child(
+        // Dref.
+        mp4::BoxesAsync::new(mp4::Dref {
+            full_box: mp4::FullBox::default(),
+            entry_count: 1,
+        })
+        .with_child(
+            // Url.
+            mp4::BoxesAsync::new(mp4::Url {
+                full_box: mp4::FullBox {
+                    version: 0,
+                    flags: [0, 0, 1],
+                },
+                location: String::new(),
+            }),
+        ),
+    )
 }
 
 #[allow(clippy::let_and_return)]
 fn generate_stsd(params: &TrackParameters) -> mp4::BoxesAsync {
     /*
        - stsd
-         - avc1
-           - avcC
+         - avc1 or hvc1
+           - avcC or hvcC
     */
 
+    let visual_sample_entry = mp4::Avc1 {
+        sample_entry: mp4::SampleEntry {
+            data_reference_index: 1,
+            ..Default::default()
+        },
+        width: params.width,
+        height: params.height,
+        horiz_resolution: 4_718_592,
+        vert_resolution: 4_718_592,
+        frame_count: 1,
+        depth: 24,
+        pre_defined3: -1,
+        ..Default::default()
+    };
+    let (sample_entry, config_type) = match params.video_codec {
+        // Avc1.
+        VideoCodec::H264 => (mp4::BoxesAsync::new(visual_sample_entry), mp4::TYPE_AVCC),
+        // Hvc1.
+        VideoCodec::H265 => (
+            mp4::BoxesAsync::new(mp4::Hvc1(visual_sample_entry)),
+            mp4::TYPE_HVCC,
+        ),
+    };
+
     let stsd = mp4::BoxesAsync::new(mp4::Stsd {
         full_box: mp4::FullBox::default(),
         entry_count: 1,
     })
-    .with_child(
-        // Avc1.
-        mp4::BoxesAsync::new(mp4::Avc1 {
-            sample_entry: mp4::SampleEntry {
-                data_reference_index: 1,
-                ..Default::default()
-            },
-            width: params.width,
-            height: params.height,
-            horiz_resolution: 4_718_592,
-            vert_resolution: 4_718_592,
-            frame_count: 1,
-            depth: 24,
-            pre_defined3: -1,
-            ..Default::default()
-        })
-        .with_child(
-            // AvcC.
-            mp4::BoxesAsync::new(MyAvcC(params.extra_data.clone())),
-        ),
-    );
+    .with_child(sample_entry.with_child(
+        // AvcC or HvcC.
+        mp4::BoxesAsync::new(MyRawBox {
+            box_type: config_type,
+            data: params.extra_data.clone(),
+        }),
+    ));
 
     stsd
 }
 
-struct MyAvcC(Vec<u8>);
+// Box with content from the camera, like the decoder configuration record.
+struct MyRawBox {
+    box_type: mp4::BoxType,
+    data: Vec<u8>,
+}
 
-impl ImmutableBox for MyAvcC {
+impl ImmutableBox for MyRawBox {
     fn box_type(&self) -> mp4::BoxType {
-        mp4::TYPE_AVCC
+        self.box_type
     }
 
     fn size(&self) -> usize {
-        self.0.len()
+        self.data.len()
     }
 }
 #[async_trait]
-impl ImmutableBoxAsync for MyAvcC {
+impl ImmutableBoxAsync for MyRawBox {
     async fn marshal(
         &self,
         w: &mut (dyn AsyncWrite + Unpin + Send + Sync),
     ) -> Result<(), Mp4Error> {
-        w.write_all(&self.0).await?;
+        w.write_all(&self.data).await?;
         Ok(())
     }
 }
 
-impl From<MyAvcC> for Box<dyn ImmutableBoxAsync> {
-    fn from(value: MyAvcC) -> Self {
+impl From<MyRawBox> for Box<dyn ImmutableBoxAsync> {
+    fn from(value: MyRawBox) -> Self {
         Box::new(value)
     }
 }
 
 pub struct MyStco {
     pub full_box: FullBox,
-    pub chunk_offsets: Arc<std::sync::Mutex<Vec<u32>>>,
+    pub chunk_offsets: Arc<std::sync::Mutex<Vec<u64>>>,
+
+    // Write a co64 box instead.
+    pub co64: bool,
 }
 
 impl ImmutableBox for MyStco {
     fn box_type(&self) -> mp4::BoxType {
-        mp4::TYPE_STCO
+        if self.co64 {
+            *b"co64"
+        } else {
+            mp4::TYPE_STCO
+        }
     }
 
     fn size(&self) -> usize {
-        8 + (self.chunk_offsets.lock().expect("not poisoned").len()) * 4
+        let entry_size = if self.co64 { 8 } else { 4 };
+        8 + (self.chunk_offsets.lock().expect("not poisoned").len()) * entry_size
     }
 }
 
@@ -460,7 +756,13 @@ impl ImmutableBoxAsync for MyStco {
         )
         .await?;
         for offset in chunk_offsets {
-            w.write_all(&offset.to_be_bytes()).await?;
+            if self.co64 {
+                w.write_all(&offset.to_be_bytes()).await?;
+            } else {
+                let offset =
+                    u32::try_from(offset).map_err(|e| Mp4Error::FromInt("stco".to_owned(), e))?;
+                w.write_all(&offset.to_be_bytes()).await?;
+            }
         }
         Ok(())
     }
@@ -493,6 +795,7 @@ mod tests {
                 duration: DurationH264::new(9),
                 data_size: 2,
                 data_offset: 0,
+                audio: false,
             },
             Sample {
                 // VideoSample2. P-Frame.
@@ -502,6 +805,7 @@ mod tests {
                 duration: DurationH264::new(9),
                 data_size: 2,
                 data_offset: 0,
+                audio: false,
             },
             Sample {
                 // VideoSample1. I-Frame.
@@ -511,6 +815,7 @@ mod tests {
                 duration: DurationH264::new(9),
                 data_size: 2,
                 data_offset: 0,
+                audio: false,
             },
         ];
 
@@ -519,6 +824,7 @@ mod tests {
         let params = TrackParameters {
             width: 650,
             height: 450,
+            video_codec: VideoCodec::H264,
             extra_data: vec![
                 1,    // Configuration version.
                 0x64, // Profile.
@@ -536,6 +842,7 @@ mod tests {
                 1,    // Reserved N sequence parameters.
                 0, 0, // Length.
             ],
+            audio: None,
         };
 
         let start_time = UnixH264::new(1);
@@ -700,4 +1007,194 @@ mod tests {
 
         assert_eq!(pretty_hex(&want), pretty_hex(&buf.into_inner()));
     }
+
+    #[tokio::test]
+    async fn test_generate_stsd_h265() {
+        let params = TrackParameters {
+            width: 650,
+            height: 450,
+            video_codec: VideoCodec::H265,
+            extra_data: vec![1, 2, 3],
+            audio: None,
+        };
+
+        let mut buf = Cursor::new(Vec::new());
+        generate_stsd(&params).marshal(&mut buf).await.unwrap();
+        let buf = buf.into_inner();
+
+        #[rustfmt::skip]
+        let want_start = vec![
+            0, 0, 0, 0x71, b's', b't', b's', b'd', //
+            0, 0, 0, 0, // FullBox.
+            0, 0, 0, 1, // Entry count.
+            0, 0, 0, 0x61, b'h', b'v', b'c', b'1', //
+        ];
+        #[rustfmt::skip]
+        let want_end = vec![
+            0, 0, 0, 0x0b, b'h', b'v', b'c', b'C', //
+            1, 2, 3, // Decoder configuration record.
+        ];
+        assert_eq!(want_start.as_slice(), &buf[..want_start.len()]);
+        assert_eq!(want_end.as_slice(), &buf[buf.len() - want_end.len()..]);
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack.windows(needle.len()).any(|v| v == needle)
+    }
+
+    #[tokio::test]
+    async fn test_generate_mp4_audio() {
+        let samples = vec![
+            Sample {
+                random_access_present: true,
+                pts: UnixH264::new(0),
+                dts_offset: DtsOffset::new(0),
+                duration: DurationH264::new(9),
+                data_size: 2,
+                data_offset: 0,
+                audio: false,
+            },
+            Sample {
+                random_access_present: true,
+                pts: UnixH264::new(0),
+                dts_offset: DtsOffset::new(0),
+                duration: DurationH264::new(7),
+                data_size: 3,
+                data_offset: 2,
+                audio: true,
+            },
+            Sample {
+                random_access_present: false,
+                pts: UnixH264::new(9),
+                dts_offset: DtsOffset::new(0),
+                duration: DurationH264::new(9),
+                data_size: 2,
+                data_offset: 5,
+                audio: false,
+            },
+        ];
+        let params = TrackParameters {
+            width: 650,
+            height: 450,
+            video_codec: VideoCodec::H264,
+            extra_data: vec![1, 2, 3],
+            audio: Some(AudioSampleEntry {
+                box_type: *b"mp4a",
+                data: vec![4, 5],
+            }),
+        };
+
+        let mut buf = Cursor::new(Vec::new());
+        let mdat_size = generate_mp4(&mut buf, UnixH264::new(0), samples.iter(), &params)
+            .await
+            .unwrap();
+        assert_eq!(7, mdat_size);
+        let buf = buf.into_inner();
+        let offset = u32::try_from(buf.len()).unwrap();
+
+        #[rustfmt::skip]
+        let want_video_stco = [
+            &[
+                0, 0, 0, 0x18, b's', b't', b'c', b'o', //
+                0, 0, 0, 0, // FullBox.
+                0, 0, 0, 2, // Entry count.
+            ][..],
+            &offset.to_be_bytes(), // Chunk offset1.
+            &(offset + 5).to_be_bytes(), // Chunk offset2.
+        ].concat();
+        #[rustfmt::skip]
+        let want_audio_stco = [
+            &[
+                0, 0, 0, 0x14, b's', b't', b'c', b'o', //
+                0, 0, 0, 0, // FullBox.
+                0, 0, 0, 1, // Entry count.
+            ][..],
+            &(offset + 2).to_be_bytes(), // Chunk offset1.
+        ].concat();
+        #[rustfmt::skip]
+        let want_stsc = [
+            0, 0, 0, 0x1c, b's', b't', b's', b'c', //
+            0, 0, 0, 0, // FullBox.
+            0, 0, 0, 1, // Entry count.
+            0, 0, 0, 1, // First chunk.
+            0, 0, 0, 1, // Samples per chunk.
+            0, 0, 0, 1, // Sample description index.
+        ];
+        #[rustfmt::skip]
+        let want_sample_entry = [
+            0, 0, 0, 0x0a, b'm', b'p', b'4', b'a', 4, 5,
+        ];
+        #[rustfmt::skip]
+        let want_audio_stts = [
+            0, 0, 0, 0x18, b's', b't', b't', b's', //
+            0, 0, 0, 0, // FullBox.
+            0, 0, 0, 1, // Entry count.
+            0, 0, 0, 1, // Sample count.
+            0, 0, 0, 7, // Sample delta.
+        ];
+        assert!(contains(&buf, &want_video_stco));
+        assert!(contains(&buf, &want_audio_stco));
+        assert!(contains(&buf, &want_stsc));
+        assert!(contains(&buf, &want_sample_entry));
+        assert!(contains(&buf, &want_audio_stts));
+        assert!(contains(&buf, b"soun"));
+    }
+
+    #[test]
+    fn test_stsc_entries() {
+        let got: Vec<_> = stsc_entries(&[2, 2, 1, 1])
+            .unwrap()
+            .iter()
+            .map(|v| {
+                (
+                    v.first_chunk,
+                    v.samples_per_chunk,
+                    v.sample_description_index,
+                )
+            })
+            .collect();
+        assert_eq!(vec![(1, 2, 1), (3, 1, 1)], got);
+    }
+
+    #[tokio::test]
+    async fn test_co64() {
+        let stco = MyStco {
+            full_box: FullBox::default(),
+            chunk_offsets: Arc::new(std::sync::Mutex::new(vec![1, 0x1_0000_0002])),
+            co64: true,
+        };
+        assert_eq!(*b"co64", stco.box_type());
+        assert_eq!(24, stco.size());
+
+        let mut buf = Vec::new();
+        ImmutableBoxAsync::marshal(&stco, &mut buf).await.unwrap();
+        #[rustfmt::skip]
+        let want = vec![
+            0, 0, 0, 0, // FullBox.
+            0, 0, 0, 2, // Entry count.
+            0, 0, 0, 0, 0, 0, 0, 1, // Chunk offset1.
+            0, 0, 0, 1, 0, 0, 0, 2, // Chunk offset2.
+        ];
+        assert_eq!(want, buf);
+    }
+
+    #[test]
+    fn test_mdhd_version() {
+        let hour = DurationH264::new(3600 * i64::from(H264_TIMESCALE));
+        assert!(matches!(
+            mdhd_version(hour).unwrap(),
+            mp4::MdhdVersion::V0(mp4::MdhdV0 {
+                duration: 324_000_000,
+                ..
+            })
+        ));
+        let day = DurationH264::new(24 * 3600 * i64::from(H264_TIMESCALE));
+        assert!(matches!(
+            mdhd_version(day).unwrap(),
+            mp4::MdhdVersion::V1(mp4::MdhdV1 {
+                duration: 7_776_000_000,
+                ..
+            })
+        ));
+    }
 }
@@ -1,8 +1,8 @@
 // SPDX-License-Identifier: GPL-2.0-or-later
 
 use common::{
-    time::{DtsOffset, DurationH264, UnixH264},
-    PartFinalized, VideoSample,
+    time::{DtsOffset, DurationH264, UnixH264, UnixNano},
+    AudioSample, AudioSampleEntry, PartFinalized, VideoCodec, VideoSample,
 };
 use std::sync::Arc;
 use thiserror::Error;
@@ -10,6 +10,7 @@ use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncWrite, AsyncWriteExt};
 
 // Sample flags.
 const FLAG_RANDOM_ACCESS_PRESENT: u8 = 0b1000_0000;
+const FLAG_AUDIO: u8 = 0b0100_0000;
 
 const SAMPLE_SIZE_U8: u8 = 25;
 #[allow(clippy::as_conversions)]
@@ -19,6 +20,7 @@ pub const SAMPLE_SIZE: usize = SAMPLE_SIZE_U8 as usize;
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub struct Sample {
     pub random_access_present: bool,
+    pub audio: bool, // Audio sample, video otherwise.
 
     pub pts: UnixH264,         // Presentation time in 90khz since the unix epoch.
     pub dts_offset: DtsOffset, // Display time offset.
@@ -33,6 +35,7 @@ impl Sample {
         let flags = b[0];
         Self {
             random_access_present: flags & FLAG_RANDOM_ACCESS_PRESENT != 0,
+            audio: flags & FLAG_AUDIO != 0,
             pts: UnixH264::new(i64::from_be_bytes([
                 b[1], b[2], b[3], b[4], b[5], b[6], b[7], b[8],
             ])),
@@ -56,6 +59,9 @@ impl Sample {
         if self.random_access_present {
             flags |= FLAG_RANDOM_ACCESS_PRESENT;
         }
+        if self.audio {
+            flags |= FLAG_AUDIO;
+        }
 
         let mut out = Vec::with_capacity(SAMPLE_SIZE);
 
@@ -128,6 +134,7 @@ impl<'a, W: AsyncWrite + Unpin> VideoWriter<'a, W> {
     }
 
     // Writes HLS parts in the custom format to the output files.
+    // The audio samples of each part are written after its video samples.
     pub async fn write_parts(
         &mut self,
         parts: &Vec<Arc<PartFinalized>>,
@@ -138,6 +145,9 @@ impl<'a, W: AsyncWrite + Unpin> VideoWriter<'a, W> {
             for sample in part.video_samples.iter() {
                 self.write_sample(sample).await?;
             }
+            for sample in part.audio_samples.iter() {
+                self.write_audio_sample(sample).await?;
+            }
         }
         self.mdat.flush().await.map_err(Flush)?;
         self.meta.flush().await.map_err(Flush)?;
@@ -150,6 +160,7 @@ impl<'a, W: AsyncWrite + Unpin> VideoWriter<'a, W> {
 
         let s = Sample {
             random_access_present: sample.random_access_present,
+            audio: false,
             pts: sample.pts,
             dts_offset: sample.dts_offset,
             duration: sample.duration,
@@ -164,6 +175,31 @@ impl<'a, W: AsyncWrite + Unpin> VideoWriter<'a, W> {
 
         Ok(())
     }
+
+    // Writes a single audio sample in the custom format to the output files.
+    pub async fn write_audio_sample(
+        &mut self,
+        sample: &AudioSample,
+    ) -> Result<(), WriteSampleError> {
+        use WriteSampleError::*;
+
+        let s = Sample {
+            random_access_present: true,
+            audio: true,
+            pts: sample.pts,
+            dts_offset: DtsOffset::new(0),
+            duration: sample.duration,
+            data_offset: self.mdat_pos,
+            data_size: u32::try_from(sample.data.len())?,
+        };
+
+        self.mdat.write_all(&sample.data).await.map_err(Write)?;
+        self.mdat_pos += u32::try_from(sample.data.len())?;
+
+        self.meta.write_all(&s.encode()?).await.map_err(Write)?;
+
+        Ok(())
+    }
 }
 
 // Reads a single meta file.
@@ -252,17 +288,37 @@ pub struct MetaHeader {
     pub start_time: UnixH264,
     pub width: u16,
     pub height: u16,
+    pub video_codec: VideoCodec,
     pub extra_data: Vec<u8>,
+    pub audio: Option<AudioSampleEntry>,
 }
 
+// Version 0 has the same layout as version 1 but all times are
+// in nanoseconds. Version 1 is implicitly H.264, version 2 adds the
+// codec after the version byte and version 3 adds the audio sample
+// entry at the end.
+const API_VERSION_V0: u8 = 0;
+const API_VERSION_H264: u8 = 1;
+const API_VERSION_CODEC: u8 = 2;
+const API_VERSION_AUDIO: u8 = 3;
+
+const CODEC_H264: u8 = 0;
+const CODEC_H265: u8 = 1;
+
 #[derive(Debug, Error)]
 pub enum HeaderFromReaderError {
-    #[error("old version, upgrade with this script: TODO")]
+    #[error("old version, upgrade with `sentryshot migrate-recordings`")]
     OldVersion,
 
     #[error("unsupported version")]
     UnsupportedVersion,
 
+    #[error("unsupported codec: {0}")]
+    UnsupportedCodec(u8),
+
+    #[error("invalid audio sample entry")]
+    InvalidAudioSampleEntry,
+
     #[error("read: {0}")]
     Read(#[from] std::io::Error),
 
@@ -276,11 +332,21 @@ impl MetaHeader {
     ) -> Result<Self, HeaderFromReaderError> {
         let mut api_version = [0];
         r.read_exact(&mut api_version).await?;
-        if api_version[0] == 0 {
-            return Err(HeaderFromReaderError::OldVersion);
-        } else if api_version[0] != 1 {
-            return Err(HeaderFromReaderError::UnsupportedVersion);
-        }
+        let api_version = api_version[0];
+        let video_codec = match api_version {
+            API_VERSION_V0 => return Err(HeaderFromReaderError::OldVersion),
+            API_VERSION_H264 => VideoCodec::H264,
+            API_VERSION_CODEC | API_VERSION_AUDIO => {
+                let mut codec = [0];
+                r.read_exact(&mut codec).await?;
+                match codec[0] {
+                    CODEC_H264 => VideoCodec::H264,
+                    CODEC_H265 => VideoCodec::H265,
+                    v => return Err(HeaderFromReaderError::UnsupportedCodec(v)),
+                }
+            }
+            _ => return Err(HeaderFromReaderError::UnsupportedVersion),
+        };
 
         // Start time.
         let mut start_time = [0; 8];
@@ -306,26 +372,72 @@ impl MetaHeader {
         let mut extra_data = vec![0; size.into()];
         r.read_exact(&mut extra_data).await?;
 
+        // Audio sample entry.
+        let audio = if api_version == API_VERSION_AUDIO {
+            let mut size_buf = [0; 2];
+            r.read_exact(&mut size_buf).await?;
+            let size = u16::from_be_bytes(size_buf);
+
+            if size == 0 {
+                None
+            } else {
+                let mut sample_entry = vec![0; size.into()];
+                r.read_exact(&mut sample_entry).await?;
+                Some(
+                    AudioSampleEntry::from_box(&sample_entry)
+                        .ok_or(HeaderFromReaderError::InvalidAudioSampleEntry)?,
+                )
+            }
+        } else {
+            None
+        };
+
         Ok(MetaHeader {
             start_time,
             width,
             height,
+            video_codec,
             extra_data,
+            audio,
         })
     }
 
+    // Recordings use the lowest version that can represent them
+    // so they remain readable by older versions.
+    fn api_version(&self) -> u8 {
+        if self.audio.is_some() {
+            API_VERSION_AUDIO
+        } else if self.video_codec == VideoCodec::H264 {
+            API_VERSION_H264
+        } else {
+            API_VERSION_CODEC
+        }
+    }
+
     // Marshaled size.
     #[must_use]
     pub fn size(&self) -> usize {
-        15 + self.extra_data.len()
+        match self.api_version() {
+            API_VERSION_H264 => 15 + self.extra_data.len(),
+            API_VERSION_CODEC => 16 + self.extra_data.len(),
+            _ => {
+                let audio_size = self.audio.as_ref().map_or(0, |v| v.data.len() + 8);
+                18 + self.extra_data.len() + audio_size
+            }
+        }
     }
 
     pub fn marshal(&self) -> Result<Vec<u8>, std::num::TryFromIntError> {
-        const API_VERSION: u8 = 1;
-
         let mut out = Vec::with_capacity(self.size());
 
-        out.push(API_VERSION);
+        let api_version = self.api_version();
+        out.push(api_version);
+        if api_version != API_VERSION_H264 {
+            match self.video_codec {
+                VideoCodec::H264 => out.push(CODEC_H264),
+                VideoCodec::H265 => out.push(CODEC_H265),
+            }
+        }
 
         // Start time.
         out.extend_from_slice(&self.start_time.to_be_bytes());
@@ -341,6 +453,13 @@ impl MetaHeader {
         out.extend_from_slice(&extra_data_size.to_be_bytes());
         out.extend_from_slice(&self.extra_data);
 
+        // Audio sample entry.
+        if let Some(audio) = &self.audio {
+            let sample_entry = audio.to_box()?;
+            out.extend_from_slice(&u16::try_from(sample_entry.len())?.to_be_bytes());
+            out.extend_from_slice(&sample_entry);
+        }
+
         Ok(out)
     }
 
@@ -349,16 +468,81 @@ impl MetaHeader {
         TrackParameters {
             width: self.width,
             height: self.height,
+            video_codec: self.video_codec,
             extra_data: self.extra_data.clone(),
+            audio: self.audio.clone(),
         }
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Error)]
+pub enum MigrateMetaError {
+    #[error("not a version 0 meta file")]
+    NotV0,
+
+    #[error("header is truncated")]
+    Truncated,
+
+    #[error("sample time overflow")]
+    Overflow,
+
+    #[error("{0}")]
+    TryFromInt(#[from] std::num::TryFromIntError),
+}
+
+// Converts a version 0 meta file into version 1. The mdat file
+// is unchanged. An incomplete sample at the end is dropped.
+pub fn migrate_meta_v0(raw: &[u8]) -> Result<Vec<u8>, MigrateMetaError> {
+    use MigrateMetaError::*;
+    if raw.first() != Some(&API_VERSION_V0) {
+        return Err(NotV0);
+    }
+    let u16_at = |pos: usize| -> Result<u16, MigrateMetaError> {
+        let b = raw.get(pos..pos + 2).ok_or(Truncated)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    };
+    let start_time = raw.get(1..9).ok_or(Truncated)?;
+    let start_time = i64::from_be_bytes(start_time.try_into().expect("8 bytes"));
+    let width = u16_at(9)?;
+    let height = u16_at(11)?;
+    let header_size = 15 + usize::from(u16_at(13)?);
+    let extra_data = raw.get(15..header_size).ok_or(Truncated)?.to_vec();
+
+    let header = MetaHeader {
+        start_time: UnixH264::from(UnixNano::new(start_time)),
+        width,
+        height,
+        video_codec: VideoCodec::H264,
+        extra_data,
+        audio: None,
+    };
+    let mut out = header.marshal()?;
+
+    for b in raw[header_size..].chunks_exact(SAMPLE_SIZE) {
+        let mut sample = Sample::from_bytes(b.try_into().expect("sample size"));
+        let pts = UnixNano::new(*sample.pts);
+        let end = pts
+            .checked_add(UnixNano::new(*sample.duration))
+            .ok_or(Overflow)?;
+
+        // Convert the end time instead of the duration to avoid rounding gaps.
+        sample.pts = UnixH264::from(pts);
+        let end = UnixH264::from(end);
+        sample.duration = DurationH264::new(*end - *sample.pts);
+        let dts_offset = *DurationH264::from(UnixNano::new(i64::from(*sample.dts_offset)));
+        sample.dts_offset = DtsOffset::new(i32::try_from(dts_offset)?);
+        out.extend_from_slice(&sample.encode()?);
+    }
+    Ok(out)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct TrackParameters {
     pub width: u16,
     pub height: u16,
+    pub video_codec: VideoCodec,
     pub extra_data: Vec<u8>,
+    pub audio: Option<AudioSampleEntry>,
 }
 
 #[allow(clippy::unwrap_used)]
@@ -370,6 +554,7 @@ mod tests {
     use pretty_hex::pretty_hex;
     use sentryshot_padded_bytes::PaddedBytes;
     use std::{io::Cursor, sync::Arc};
+    use test_case::test_case;
 
     #[tokio::test]
     async fn test_video() {
@@ -380,7 +565,9 @@ mod tests {
             start_time: UnixH264::new(1_000_000_000),
             width: 1920,
             height: 1080,
+            video_codec: VideoCodec::H264,
             extra_data: vec![0, 1],
+            audio: None,
         };
 
         let mut w = VideoWriter::new(&mut meta, &mut mdat, test_header.clone())
@@ -448,6 +635,7 @@ mod tests {
         let want_samples = &[
             Sample {
                 random_access_present: true,
+                audio: false,
                 pts: UnixH264::new(100_000_000_000_000_000),
                 dts_offset: DtsOffset::new(-1_000_000_000),
                 duration: DurationH264::new(1_000_000_000),
@@ -456,6 +644,7 @@ mod tests {
             },
             Sample {
                 random_access_present: false,
+                audio: false,
                 pts: UnixH264::new(300_000_000_000_000_000),
                 dts_offset: DtsOffset::new(-1_000_000_000),
                 duration: DurationH264::new(1_000_000_000),
@@ -466,4 +655,185 @@ mod tests {
 
         assert_eq!(want_samples, samples.as_slice());
     }
+
+    #[tokio::test]
+    async fn test_meta_header_h265() {
+        let header = MetaHeader {
+            start_time: UnixH264::new(1_000_000_000),
+            width: 1920,
+            height: 1080,
+            video_codec: VideoCodec::H265,
+            extra_data: vec![0, 1],
+            audio: None,
+        };
+
+        #[rustfmt::skip]
+        let want = vec![
+            2, // Version.
+            1, // Codec.
+            0, 0, 0, 0, 0x3b, 0x9a, 0xca, 0, // Start time.
+            7, 0x80, // Width.
+            4, 0x38, // Height.
+            0, 2, // Extra data size.
+            0, 1, // Extra data.
+        ];
+        let got = header.marshal().unwrap();
+        assert_eq!(want, got);
+        assert_eq!(want.len(), header.size());
+
+        let got = MetaHeader::from_reader(&mut Cursor::new(want))
+            .await
+            .unwrap();
+        assert_eq!(header, got);
+    }
+
+    #[tokio::test]
+    async fn test_video_audio() {
+        let mut meta = Vec::new();
+        let mut mdat = Vec::new();
+
+        let test_header = MetaHeader {
+            start_time: UnixH264::new(1_000_000_000),
+            width: 1920,
+            height: 1080,
+            video_codec: VideoCodec::H264,
+            extra_data: vec![0, 1],
+            audio: Some(AudioSampleEntry {
+                box_type: *b"mp4a",
+                data: vec![2, 3],
+            }),
+        };
+
+        let mut w = VideoWriter::new(&mut meta, &mut mdat, test_header.clone())
+            .await
+            .unwrap();
+
+        let parts = vec![Arc::new(PartFinalized {
+            video_samples: Arc::new(vec![VideoSample {
+                pts: UnixH264::new(1),
+                random_access_present: true,
+                avcc: Arc::new(PaddedBytes::new(vec![4, 5])),
+                duration: DurationH264::new(2),
+                ..Default::default()
+            }]),
+            audio_samples: Arc::new(vec![AudioSample {
+                pts: UnixH264::new(3),
+                data: vec![6_u8].into(),
+                duration: DurationH264::new(4),
+            }]),
+            ..Default::default()
+        })];
+        w.write_parts(&parts).await.unwrap();
+
+        #[rustfmt::skip]
+        let want_meta = &[
+            3, // Version.
+            0, // Codec.
+            0, 0, 0, 0, 0x3b, 0x9a, 0xca, 0, // Start time.
+            7, 0x80, // Width.
+            4, 0x38, // Height.
+            0, 2, // Extra data size.
+            0, 1, // Extra data.
+            0, 0x0a, // Audio sample entry size.
+            0, 0, 0, 0x0a, b'm', b'p', b'4', b'a', 2, 3, // Audio sample entry.
+            //
+            // Sample 1.
+            0b1000_0000, // Flags.
+            0, 0, 0, 0, 0, 0, 0, 1, // PTS.
+            0, 0, 0, 0, // DTS offset.
+            0, 0, 0, 2, // Duration.
+            0, 0, 0, 0, // Offset.
+            0, 0, 0, 2, // Size.
+            //
+            // Sample 2.
+            0b1100_0000, // Flags.
+            0, 0, 0, 0, 0, 0, 0, 3, // PTS.
+            0, 0, 0, 0, // Dts offset.
+            0, 0, 0, 4, // Duration.
+            0, 0, 0, 2, // Offset.
+            0, 0, 0, 1, // Size.
+        ];
+        let want_meta_len = u64::try_from(want_meta.len()).unwrap();
+
+        assert_eq!(pretty_hex(&want_meta), pretty_hex(&meta));
+        assert_eq!(vec![4, 5, 6], mdat);
+        assert_eq!(test_header.size() + 2 * SAMPLE_SIZE, want_meta.len());
+
+        let (header, samples) = read_meta(Cursor::new(want_meta), want_meta_len)
+            .await
+            .unwrap();
+        assert_eq!(test_header, header);
+        assert_eq!(2, samples.len());
+        assert!(!samples[0].audio);
+        assert!(samples[1].audio);
+    }
+
+    #[test_case(2; "codec")]
+    #[test_case(3; "audio")]
+    #[tokio::test]
+    async fn test_meta_header_unsupported_codec(version: u8) {
+        let mut r = Cursor::new(vec![version, 9]);
+        assert!(matches!(
+            MetaHeader::from_reader(&mut r).await,
+            Err(HeaderFromReaderError::UnsupportedCodec(9))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_migrate_meta_v0() {
+        #[rustfmt::skip]
+        let v0 = vec![
+            0, // Version.
+            0, 0, 0, 0, 0x3b, 0x9a, 0xca, 0, // Start time.
+            2, 128, // Width.
+            1, 224, // Height.
+            0, 2, 7, 8, // Extra data.
+            //
+            0b1000_0000, // Flags.
+            0, 0, 0, 0, 0x77, 0x35, 0x94, 0, // PTS.
+            0x05, 0xf5, 0xe1, 0, // DTS offset.
+            0x01, 0xfc, 0xa0, 0x55, // Duration.
+            0, 0, 0, 0, // Offset.
+            0, 0, 0, 5, // Size.
+            //
+            0b1000_0000, 1, 2, // Incomplete sample.
+        ];
+        assert!(matches!(
+            MetaHeader::from_reader(&mut Cursor::new(v0.clone())).await,
+            Err(HeaderFromReaderError::OldVersion)
+        ));
+
+        let v1 = migrate_meta_v0(&v0).unwrap();
+        let v1_len = u64::try_from(v1.len()).unwrap();
+        let (header, samples) = read_meta(Cursor::new(v1), v1_len).await.unwrap();
+        let want_header = MetaHeader {
+            start_time: UnixH264::new(90000),
+            width: 640,
+            height: 480,
+            video_codec: VideoCodec::H264,
+            extra_data: vec![7, 8],
+            audio: None,
+        };
+        assert_eq!(want_header, header);
+
+        let want_samples = vec![Sample {
+            random_access_present: true,
+            audio: false,
+            pts: UnixH264::new(180_000),
+            dts_offset: DtsOffset::new(9000),
+            duration: DurationH264::new(2999),
+            data_size: 5,
+            data_offset: 0,
+        }];
+        assert_eq!(want_samples, samples);
+
+        assert!(matches!(
+            migrate_meta_v0(&[1, 0]),
+            Err(MigrateMetaError::NotV0)
+        ));
+        assert!(matches!(
+            migrate_meta_v0(&v0[..16]),
+            Err(MigrateMetaError::Truncated)
+        ));
+    }
 }
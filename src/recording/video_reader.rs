@@ -1,10 +1,12 @@
 // SPDX-License-Identifier: GPL-2.0-or-later
 
 use crate::{
+    crypt::RecordingFile,
     mp4_muxer::{generate_mp4, GenerateMp4Error},
     video::{read_meta, ReadMetaError},
     VideoCache,
 };
+use common::EncryptionKey;
 use pin_project::pin_project;
 use std::{
     io::{self, SeekFrom},
@@ -70,7 +72,8 @@ pub async fn new_video_reader(
     recording_path: PathBuf,
     cache_id: u32,
     cache: &Option<Arc<Mutex<VideoCache>>>,
-) -> Result<VideoReader<MetaCursor, tokio::fs::File>, CreateVideoReaderError> {
+    key: Option<&EncryptionKey>,
+) -> Result<VideoReader<MetaCursor, RecordingFile>, CreateVideoReaderError> {
     use CreateVideoReaderError::*;
     let mut meta_path = recording_path.clone();
     meta_path.set_extension("meta");
@@ -84,25 +87,23 @@ pub async fn new_video_reader(
             if let Some(v) = cache.get((&recording_path, cache_id)) {
                 v
             } else {
-                let meta = Arc::new(read_video_metadata(&meta_path).await?);
+                let meta = Arc::new(read_video_metadata(&meta_path, key).await?);
                 cache.add((recording_path, cache_id), meta.clone());
                 meta
             }
         } else {
-            Arc::new(read_video_metadata(&meta_path).await?)
+            Arc::new(read_video_metadata(&meta_path, key).await?)
         }
     };
 
-    let mdat = tokio::fs::OpenOptions::new()
-        .read(true)
-        .open(mdat_path)
+    let mdat = RecordingFile::open(&mdat_path, key)
         .await
         .map_err(OpenMdat)?;
 
     Ok(VideoReader {
         mdat,
         meta_size: meta.buf.len(),
-        mdat_size: usize::try_from(meta.mdat_size).expect("usize fit u32"),
+        mdat_size: usize::try_from(meta.mdat_size).expect("usize fit u64"),
         pos: 0,
         last_modified: meta.last_modified,
         meta: new_meta_cursor(meta),
@@ -357,22 +358,22 @@ pub enum ReadVideoMetadataError {
     GenerateMp4(#[from] GenerateMp4Error),
 }
 
-async fn read_video_metadata(meta_path: &Path) -> Result<VideoMetadata, ReadVideoMetadataError> {
+async fn read_video_metadata(
+    meta_path: &Path,
+    key: Option<&EncryptionKey>,
+) -> Result<VideoMetadata, ReadVideoMetadataError> {
     use ReadVideoMetadataError::*;
     let metadata = tokio::fs::metadata(meta_path).await.map_err(Metadata)?;
 
-    let meta_size = metadata.len();
     let last_modified = metadata.modified().map_err(LastModified)?;
     //let mod_time = metadata.modified();
     //modTime := metaStat.ModTime()
 
-    let mut meta = BufReader::new(
-        tokio::fs::OpenOptions::new()
-            .read(true)
-            .open(meta_path)
-            .await
-            .map_err(OpenFile)?,
-    );
+    let meta_file = RecordingFile::open(meta_path, key)
+        .await
+        .map_err(OpenFile)?;
+    let meta_size = meta_file.size();
+    let mut meta = BufReader::new(meta_file);
 
     let (header, samples) = read_meta(&mut meta, meta_size).await?;
     let params = header.params();
@@ -400,7 +401,7 @@ pub struct MetaCursor {
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub(crate) struct VideoMetadata {
     pub(crate) buf: Vec<u8>,
-    pub(crate) mdat_size: u32,
+    pub(crate) mdat_size: u64,
     pub(crate) last_modified: std::time::SystemTime,
 }
 
@@ -529,7 +530,7 @@ mod tests {
         std::fs::write(meta_path, test_meta).unwrap();
         std::fs::write(mdat_path, [0, 0, 0, 0]).unwrap();
 
-        let mut video = new_video_reader(path, 0, &None).await.unwrap();
+        let mut video = new_video_reader(path, 0, &None, None).await.unwrap();
 
         // Read 1000 bytes.
         let mut buf = vec![0; 100];
@@ -539,6 +540,61 @@ mod tests {
         //require.Greater(t, n, int64(1000))*/
     }
 
+    #[tokio::test]
+    async fn test_new_video_reader_encrypted() {
+        let temp_dir = tempdir().unwrap();
+        let key = EncryptionKey::new([1; 32]);
+
+        let test_meta = &[
+            1, // Version.
+            0, 0, 0, 0, 0, 0, 0, 0, // Start time.
+            7, 0x80, // Width.
+            4, 0x38, // Height.
+            0, 2, // Extra data size.
+            0, 1, // Extra data.
+            //
+            // Sample.
+            0, // Flags.
+            0, 0, 0, 0, 0, 0, 0, 0x0, // PTS.
+            0, 0, 0, 0, 0, 0, 0, 0, // DTS.
+            0, 0, 0, 0, 0, 0, 0, 0, // Next dts.
+            0, 0, 0, 0, // Offset.
+            0, 0, 0, 4, // Size.
+        ];
+        let test_mdat = [1, 2, 3, 4];
+
+        let plain_path = temp_dir.path().join("plain");
+        std::fs::write(plain_path.with_extension("meta"), test_meta).unwrap();
+        std::fs::write(plain_path.with_extension("mdat"), test_mdat).unwrap();
+
+        let encrypted_path = temp_dir.path().join("encrypted");
+        std::fs::write(
+            encrypted_path.with_extension("meta"),
+            crate::encrypt_file(test_meta, &key).unwrap(),
+        )
+        .unwrap();
+        std::fs::write(
+            encrypted_path.with_extension("mdat"),
+            crate::encrypt_file(&test_mdat, &key).unwrap(),
+        )
+        .unwrap();
+
+        let mut want = Vec::new();
+        let mut video = new_video_reader(plain_path, 0, &None, None).await.unwrap();
+        video.read_to_end(&mut want).await.unwrap();
+
+        let mut got = Vec::new();
+        let mut video = new_video_reader(encrypted_path.clone(), 0, &None, Some(&key))
+            .await
+            .unwrap();
+        video.read_to_end(&mut got).await.unwrap();
+        assert_eq!(want, got);
+
+        assert!(new_video_reader(encrypted_path, 0, &None, None)
+            .await
+            .is_err());
+    }
+
     #[tokio::test]
     async fn test_video_reader() {
         let mut r = VideoReader {
@@ -20,7 +20,7 @@ use plugin::{
     types::{admin, csrf, user, NewAuthError},
     Application, PluginManager, PreLoadPluginsError, PreLoadedPlugins,
 };
-use recdb::{Disk, RecDb};
+use recdb::{Archive, Disk, RecDb};
 use recording::VideoCache;
 use rust_embed::RustEmbed;
 use std::{
@@ -39,7 +39,7 @@ use tokio::{
 };
 use tokio_util::sync::CancellationToken;
 use tower::ServiceBuilder;
-use vod::VodCache;
+use vod::{ExportManager, VodCache};
 use web::Templater;
 
 #[allow(clippy::wildcard_imports)]
@@ -103,6 +103,7 @@ pub struct App {
     hls_server: Arc<HlsServer>,
     monitor_manager: MonitorManager,
     recdb: Arc<RecDb>,
+    export_manager: Arc<ExportManager>,
     router: Router,
 }
 
@@ -146,10 +147,31 @@ impl App {
         let new_auth = pre_loaded_plugins.new_auth_fn();
         let auth = new_auth(rt_handle.clone(), env.config_dir(), logger.clone())?;
 
-        let rec_db = Arc::new(RecDb::new(
+        // Exports expire on their own and aren't pruned.
+        let exports_dir = env.storage_dir().join("exports");
+        let mut rec_db = RecDb::new(
             logger.clone(),
             env.recordings_dir().to_path_buf(),
-            Disk::new(env.storage_dir().to_path_buf(), env.max_disk_usage()),
+            Disk::new(env.storage_dir().to_path_buf(), env.max_disk_usage())
+                .with_excluded_dir(exports_dir.clone()),
+            env.encryption_key().cloned(),
+        );
+        if let Some(archive) = env.archive() {
+            rec_db = rec_db.with_archive(Archive::new(
+                archive.recordings_dir().to_path_buf(),
+                Disk::new(archive.dir().to_path_buf(), archive.max_disk_usage()),
+                archive.archive_after(),
+            ));
+        }
+        if let Some(replication) = env.replication() {
+            rec_db = rec_db.with_replication(replication.clone());
+        }
+        let rec_db = Arc::new(rec_db);
+
+        let export_manager = Arc::new(ExportManager::new(
+            logger.clone(),
+            rec_db.clone(),
+            exports_dir,
         ));
 
         let hls_server = Arc::new(HlsServer::new(token.clone(), logger.clone()));
@@ -177,6 +199,7 @@ impl App {
                 hls_server,
                 monitor_manager,
                 recdb: rec_db,
+                export_manager,
                 router,
             },
             pre_loaded_plugins,
@@ -217,6 +240,11 @@ impl App {
             templater: templater.clone(),
             auth: self.auth.clone(),
         };
+        let vod_handler_state = VodHandlerState {
+            logger: self.logger.clone(),
+            recdb: self.recdb.clone(),
+            cache: VodCache::new(),
+        };
 
         let router = self
             .router
@@ -287,11 +315,14 @@ impl App {
             .route(
                 "/vod/vod.mp4",
                 get(vod_handler)
-                    .with_state(VodHandlerState {
-                        logger: self.logger.clone(),
-                        recdb: self.recdb.clone(),
-                        cache: VodCache::new(),
-                    })
+                    .with_state(vod_handler_state.clone())
+                    .layer(middleware::from_fn_with_state(self.auth.clone(), user))
+                    .with_state(self.auth.clone()),
+            )
+            .route(
+                "/vod/hls/:file",
+                get(vod_hls_handler)
+                    .with_state(vod_handler_state)
                     .layer(middleware::from_fn_with_state(self.auth.clone(), user))
                     .with_state(self.auth.clone()),
             )
@@ -340,6 +371,17 @@ impl App {
                     .route_layer(middleware::from_fn_with_state(self.auth.clone(), user))
                     .with_state(self.auth.clone()),
             )
+            // Event query.
+            .route(
+                "/api/event/query",
+                get(event_query_handler)
+                    .with_state(RecordingQueryHandlerState {
+                        logger: self.logger.clone(),
+                        rec_db: self.recdb.clone(),
+                    })
+                    .route_layer(middleware::from_fn_with_state(self.auth.clone(), user))
+                    .with_state(self.auth.clone()),
+            )
             // Log WebSocket feed.
             .route(
                 "/api/log/feed",
@@ -400,6 +442,86 @@ impl App {
                     .route_layer(middleware::from_fn_with_state(self.auth.clone(), user))
                     .with_state(self.auth.clone()),
             )
+            // Recording lock.
+            .route(
+                "/api/recording/lock/*id",
+                post(recording_lock_handler)
+                    .delete(recording_lock_handler)
+                    .with_state(self.recdb.clone())
+                    .route_layer(
+                        ServiceBuilder::new()
+                            .layer(middleware::from_fn_with_state(self.auth.clone(), admin))
+                            .layer(middleware::from_fn_with_state(self.auth.clone(), csrf)),
+                    )
+                    .with_state(self.auth.clone()),
+            )
+            // Recording hash chain.
+            .route(
+                "/api/recording/verify/*id",
+                get(recording_verify_handler)
+                    .with_state(self.recdb.clone())
+                    .route_layer(middleware::from_fn_with_state(self.auth.clone(), user))
+                    .with_state(self.auth.clone()),
+            )
+            .route(
+                "/api/monitor/verify/:id",
+                get(monitor_verify_handler)
+                    .with_state(self.recdb.clone())
+                    .route_layer(middleware::from_fn_with_state(self.auth.clone(), user))
+                    .with_state(self.auth.clone()),
+            )
+            // Off-site replication.
+            .route(
+                "/api/replication/status",
+                get(replication_status_handler)
+                    .with_state(self.recdb.clone())
+                    .route_layer(middleware::from_fn_with_state(self.auth.clone(), user))
+                    .with_state(self.auth.clone()),
+            )
+            // Clip export.
+            .route(
+                "/api/export",
+                post(export_create_handler)
+                    .with_state(self.export_manager.clone())
+                    .route_layer(
+                        ServiceBuilder::new()
+                            .layer(middleware::from_fn_with_state(self.auth.clone(), user))
+                            .layer(middleware::from_fn_with_state(self.auth.clone(), csrf)),
+                    )
+                    .with_state(self.auth.clone()),
+            )
+            .route(
+                "/api/exports",
+                get(exports_handler)
+                    .with_state(self.export_manager.clone())
+                    .route_layer(middleware::from_fn_with_state(self.auth.clone(), user))
+                    .with_state(self.auth.clone()),
+            )
+            .route(
+                "/api/export/:id",
+                get(export_handler)
+                    .with_state(self.export_manager.clone())
+                    .route_layer(middleware::from_fn_with_state(self.auth.clone(), user))
+                    .with_state(self.auth.clone()),
+            )
+            .route(
+                "/api/export/:id",
+                delete(export_delete_handler)
+                    .with_state(self.export_manager.clone())
+                    .route_layer(
+                        ServiceBuilder::new()
+                            .layer(middleware::from_fn_with_state(self.auth.clone(), user))
+                            .layer(middleware::from_fn_with_state(self.auth.clone(), csrf)),
+                    )
+                    .with_state(self.auth.clone()),
+            )
+            .route(
+                "/api/export/:id/download",
+                get(export_download_handler)
+                    .with_state(self.export_manager.clone())
+                    .route_layer(middleware::from_fn_with_state(self.auth.clone(), user))
+                    .with_state(self.auth.clone()),
+            )
             // Recording thumbnail.
             .route(
                 "/api/recording/thumbnail/*id",
@@ -408,6 +530,21 @@ impl App {
                     .route_layer(middleware::from_fn_with_state(self.auth.clone(), user))
                     .with_state(self.auth.clone()),
             )
+            // Recording timeline sprite sheet and its WebVTT index.
+            .route(
+                "/api/recording/sprite/*id",
+                get(recording_sprite_handler)
+                    .with_state(self.recdb.clone())
+                    .route_layer(middleware::from_fn_with_state(self.auth.clone(), user))
+                    .with_state(self.auth.clone()),
+            )
+            .route(
+                "/api/recording/sprite-vtt/*id",
+                get(recording_sprite_vtt_handler)
+                    .with_state(self.recdb.clone())
+                    .route_layer(middleware::from_fn_with_state(self.auth.clone(), user))
+                    .with_state(self.auth.clone()),
+            )
             // Recording video.
             .route(
                 "/api/recording/video/*id",
@@ -450,6 +587,32 @@ impl App {
                 .await;
         });
 
+        let rec_db = self.recdb.clone();
+        let token2 = self.token.clone();
+        tokio::spawn(async move {
+            rec_db.replication_loop(token2).await;
+        });
+
+        let export_manager = self.export_manager.clone();
+        let token2 = self.token.clone();
+        tokio::spawn(async move {
+            export_manager.expire_loop(token2).await;
+        });
+
+        // Load the recording index before the first query.
+        let rec_db = self.recdb.clone();
+        let logger = self.logger.clone();
+        tokio::spawn(async move {
+            if let Err(e) = rec_db.load_index().await {
+                logger.log(LogEntry::new(
+                    LogLevel::Error,
+                    "app",
+                    None,
+                    format!("load recording index: {e}"),
+                ));
+            }
+        });
+
         self.logger.log(LogEntry {
             level: LogLevel::Info,
             source: "app".try_into().expect("valid"),
@@ -520,6 +683,9 @@ impl Application for App {
     fn shutdown_complete_tx(&self) -> mpsc::Sender<()> {
         self.shutdown_complete_tx.clone()
     }
+    fn token(&self) -> CancellationToken {
+        self.token.clone()
+    }
     fn logger(&self) -> common::DynLogger {
         self.logger.clone()
     }
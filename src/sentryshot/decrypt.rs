@@ -0,0 +1,153 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+use common::{EncryptionKey, EnvConfig};
+use env::{EnvConf, EnvConfigNewError};
+use recording::RecordingFile;
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+use tokio::io::AsyncWriteExt;
+
+// Files that are encrypted when encryption is enabled.
+const RECORDING_FILE_EXTENSIONS: [&str; 6] = ["meta", "mdat", "json", "jpeg", "sprite", "vtt"];
+
+#[derive(Debug, Error)]
+pub enum DecryptRecordingsError {
+    #[error("{0}")]
+    EnvConfig(#[from] EnvConfigNewError),
+
+    #[error("no encryption key in config")]
+    NoKey,
+
+    #[error("read dir: {0}")]
+    ReadDir(std::io::Error),
+
+    #[error("entry: {0}")]
+    Entry(std::io::Error),
+
+    #[error("metadata: {0}")]
+    Metadata(std::io::Error),
+}
+
+// Decrypts the recording files in `path` into `output`, the directory
+// structure is kept. Used to hand over recordings to someone without
+// the key. Unencrypted files are copied as is.
+pub async fn decrypt_recordings(
+    config_path: &PathBuf,
+    path: PathBuf,
+    output: PathBuf,
+) -> Result<(), DecryptRecordingsError> {
+    let env = EnvConf::new(config_path)?;
+    let key = env.encryption_key().ok_or(DecryptRecordingsError::NoKey)?;
+
+    let files = tokio::task::spawn_blocking(move || find_recording_files(path))
+        .await
+        .expect("join")?;
+
+    let n_files = files.len();
+    println!("Found {n_files} files to decrypt");
+    let mut n_failed = 0;
+    for (i, (src, rel_path)) in files.iter().enumerate() {
+        let i = i + 1;
+        let dst = output.join(rel_path);
+        let src_str = src.to_string_lossy();
+        if let Err(e) = decrypt_file(key, src, &dst).await {
+            println!("[{i}/{n_files}] [ERR] {src_str} {e}");
+            n_failed += 1;
+            continue;
+        }
+        println!("[{i}/{n_files}] [OK] {src_str}");
+    }
+    if n_failed != 0 {
+        println!("{n_failed} files failed to decrypt");
+    }
+    Ok(())
+}
+
+// Returns the full and relative path of each recording file. The
+// path can be a single file or a directory that's searched recursively.
+fn find_recording_files(path: PathBuf) -> Result<Vec<(PathBuf, PathBuf)>, DecryptRecordingsError> {
+    use DecryptRecordingsError::*;
+    let is_recording_file = |path: &Path| {
+        path.extension()
+            .and_then(|v| v.to_str())
+            .is_some_and(|ext| RECORDING_FILE_EXTENSIONS.contains(&ext))
+    };
+
+    if !std::fs::metadata(&path).map_err(Metadata)?.is_dir() {
+        let Some(name) = path.file_name() else {
+            return Ok(Vec::new());
+        };
+        let name = PathBuf::from(name);
+        return Ok(if is_recording_file(&path) {
+            vec![(path, name)]
+        } else {
+            Vec::new()
+        });
+    }
+
+    let mut files = Vec::new();
+    let mut dirs_to_visit = VecDeque::from([path.clone()]);
+    while let Some(dir) = dirs_to_visit.pop_front() {
+        for entry in std::fs::read_dir(dir).map_err(ReadDir)? {
+            let entry = entry.map_err(Entry)?;
+            let entry_path = entry.path();
+            if entry.metadata().map_err(Metadata)?.is_dir() {
+                dirs_to_visit.push_back(entry_path);
+                continue;
+            }
+            if !is_recording_file(&entry_path) {
+                continue;
+            }
+            let rel_path = entry_path
+                .strip_prefix(&path)
+                .expect("entry should be in path")
+                .to_path_buf();
+            files.push((entry_path, rel_path));
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+#[derive(Debug, Error)]
+enum DecryptFileError {
+    #[error("open file: {0}")]
+    OpenFile(std::io::Error),
+
+    #[error("create dir: {0}")]
+    CreateDir(std::io::Error),
+
+    #[error("create file: {0}")]
+    CreateFile(std::io::Error),
+
+    #[error("copy: {0}")]
+    Copy(std::io::Error),
+
+    #[error("sync: {0}")]
+    Sync(std::io::Error),
+}
+
+// Existing files are never overwritten.
+async fn decrypt_file(key: &EncryptionKey, src: &Path, dst: &Path) -> Result<(), DecryptFileError> {
+    use DecryptFileError::*;
+    let mut src = RecordingFile::open(src, Some(key))
+        .await
+        .map_err(OpenFile)?;
+
+    if let Some(parent) = dst.parent() {
+        tokio::fs::create_dir_all(parent).await.map_err(CreateDir)?;
+    }
+    let mut dst = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(dst)
+        .await
+        .map_err(CreateFile)?;
+
+    tokio::io::copy(&mut src, &mut dst).await.map_err(Copy)?;
+    dst.flush().await.map_err(Sync)?;
+    dst.sync_all().await.map_err(Sync)
+}
@@ -0,0 +1,57 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+use monitor::thumbnail_from_avcc;
+use recdb::{check_recording, list_fsck_files, ListFsckFilesError, INDEX_FILE_NAME};
+use std::path::PathBuf;
+
+// Checks every recording in the recordings directory and repairs them
+// unless `dry_run` is set. Sentryshot should be stopped while this runs.
+pub async fn fsck(recordings_dir: PathBuf, dry_run: bool) -> Result<(), ListFsckFilesError> {
+    let files = list_fsck_files(&recordings_dir)?;
+
+    let generate_thumbnail =
+        |avcc, extradata| thumbnail_from_avcc(avcc, extradata).map_err(|e| e.to_string());
+
+    let n_recordings = files.recordings.len();
+    println!("Checking {n_recordings} recordings");
+    let mut n_issues = 0;
+    let mut n_failed = 0;
+    for (i, rec_path) in files.recordings.iter().enumerate() {
+        let i = i + 1;
+        let path = rec_path.to_string_lossy();
+        match check_recording(&recordings_dir, rec_path, !dry_run, &generate_thumbnail).await {
+            Ok(issues) if issues.is_empty() => {}
+            Ok(issues) => {
+                let status = if dry_run { "ISSUE" } else { "FIXED" };
+                println!(
+                    "[{i}/{n_recordings}] [{status}] {path}: {}",
+                    issues.join(", ")
+                );
+                n_issues += 1;
+            }
+            Err(e) => {
+                println!("[{i}/{n_recordings}] [ERR] {path}: {e}");
+                n_failed += 1;
+            }
+        }
+    }
+
+    for path in &files.orphans {
+        println!("[ORPHAN] {}", path.to_string_lossy());
+    }
+
+    let action = if dry_run { "with issues" } else { "repaired" };
+    println!(
+        "{n_recordings} recordings checked, {n_issues} {action}, {n_failed} failed, {} orphaned files",
+        files.orphans.len()
+    );
+
+    // The index is rebuilt on the next start.
+    if !dry_run && n_issues != 0 {
+        let index_path = recordings_dir.join(INDEX_FILE_NAME);
+        if std::fs::remove_file(index_path).is_ok() {
+            println!("Removed recording index");
+        }
+    }
+    Ok(())
+}
@@ -1,12 +1,22 @@
 // SPDX-License-Identifier: GPL-2.0-or-later
 
 mod app;
+mod decrypt;
+mod fsck;
+mod migrate;
 mod rec2mp4;
+mod timelapse;
+mod verify;
 
 use app::run;
+pub use decrypt::decrypt_recordings;
+pub use fsck::fsck;
+pub use migrate::migrate_recordings;
 pub use rec2mp4::rec_to_mp4;
+pub use timelapse::timelapse;
+pub use verify::verify;
 
-use std::{path::PathBuf, process::ExitCode};
+use std::{num::NonZeroU32, path::PathBuf, process::ExitCode};
 
 #[tokio::main]
 async fn main() -> ExitCode {
@@ -61,6 +71,119 @@ async fn main() -> ExitCode {
                 return ExitCode::FAILURE;
             }
         }
+        "migrate-recordings" => {
+            if pargs.contains(["-h", "--help"]) {
+                print!("{HELP_MIGRATE_RECORDINGS}");
+                return ExitCode::SUCCESS;
+            }
+            let dry_run = pargs.contains("--dry-run");
+            let Ok(path) = pargs.free_from_str() else {
+                println!("missing path");
+                return ExitCode::FAILURE;
+            };
+            if let Err(e) = migrate_recordings(path, dry_run) {
+                eprintln!("error: {e}");
+                return ExitCode::FAILURE;
+            }
+        }
+        "fsck" => {
+            if pargs.contains(["-h", "--help"]) {
+                print!("{HELP_FSCK}");
+                return ExitCode::SUCCESS;
+            }
+            let dry_run = pargs.contains("--dry-run");
+            let Ok(path) = pargs.free_from_str() else {
+                println!("missing path");
+                return ExitCode::FAILURE;
+            };
+            if let Err(e) = fsck(path, dry_run).await {
+                eprintln!("error: {e}");
+                return ExitCode::FAILURE;
+            }
+        }
+        "decrypt" => {
+            if pargs.contains(["-h", "--help"]) {
+                print!("{HELP_DECRYPT}");
+                return ExitCode::SUCCESS;
+            }
+            let config = pargs
+                .value_from_str("--config")
+                .unwrap_or_else(|_| PathBuf::from(DEFAULT_CONFIG_PATH));
+            let Ok(path) = pargs.free_from_str() else {
+                println!("missing path");
+                return ExitCode::FAILURE;
+            };
+            let Ok(output) = pargs.free_from_str() else {
+                println!("missing output path");
+                return ExitCode::FAILURE;
+            };
+            if let Err(e) = decrypt_recordings(&config, path, output).await {
+                eprintln!("error: {e}");
+                return ExitCode::FAILURE;
+            }
+        }
+        "verify" => {
+            if pargs.contains(["-h", "--help"]) {
+                print!("{HELP_VERIFY}");
+                return ExitCode::SUCCESS;
+            }
+            let config = pargs
+                .value_from_str("--config")
+                .unwrap_or_else(|_| PathBuf::from(DEFAULT_CONFIG_PATH));
+            let Ok(id) = pargs.free_from_str() else {
+                println!("missing id");
+                return ExitCode::FAILURE;
+            };
+            match verify(&config, id).await {
+                Ok(true) => {}
+                Ok(false) => return ExitCode::FAILURE,
+                Err(e) => {
+                    eprintln!("error: {e}");
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+        "timelapse" => {
+            if pargs.contains(["-h", "--help"]) {
+                print!("{HELP_TIMELAPSE}");
+                return ExitCode::SUCCESS;
+            }
+            let config = pargs
+                .value_from_str("--config")
+                .unwrap_or_else(|_| PathBuf::from(DEFAULT_CONFIG_PATH));
+            let Ok(interval) = pargs.opt_value_from_str("--interval") else {
+                println!("invalid interval");
+                return ExitCode::FAILURE;
+            };
+            let Ok(fps) = pargs.opt_value_from_str("--fps") else {
+                println!("invalid fps");
+                return ExitCode::FAILURE;
+            };
+            let Ok(monitor_id) = pargs.free_from_str() else {
+                println!("missing monitor id");
+                return ExitCode::FAILURE;
+            };
+            let Ok(start) = pargs.free_from_str::<String>() else {
+                println!("missing start time");
+                return ExitCode::FAILURE;
+            };
+            let Ok(end) = pargs.free_from_str::<String>() else {
+                println!("missing end time");
+                return ExitCode::FAILURE;
+            };
+            let Ok(output) = pargs.free_from_str::<PathBuf>() else {
+                println!("missing output path");
+                return ExitCode::FAILURE;
+            };
+            let interval = interval.unwrap_or(NonZeroU32::new(60).expect("nonzero"));
+            let fps = fps.unwrap_or(NonZeroU32::new(30).expect("nonzero"));
+            if let Err(e) =
+                timelapse(&config, monitor_id, &start, &end, interval, fps, &output).await
+            {
+                eprintln!("error: {e}");
+                return ExitCode::FAILURE;
+            }
+        }
         v => {
             println!("invalid subcommand '{v}'");
             return ExitCode::FAILURE;
@@ -75,9 +198,14 @@ const HELP: &str = "\
 Usage: sentryshot [OPTIONS] <COMMAND>
 
 Commands:
-  run      Run the program
-  rec2mp4  Convert recordings into mp4 videos
-  help     Print this message or the help of the given subcommand(s)
+  run                 Run the program
+  rec2mp4             Convert recordings into mp4 videos
+  migrate-recordings  Upgrade recordings from old versions
+  fsck                Check and repair recordings
+  decrypt             Decrypt recordings for export
+  verify              Verify the recording hash chain
+  timelapse           Generate a timelapse from recordings
+  help                Print this message or the help of the given subcommand(s)
 
 Options:
       --config <CONFIG>  [default: ./configs/sentryshot.toml]
@@ -106,3 +234,90 @@ Arguments:
 Options:
   -h, --help  Print help
 ";
+
+const HELP_MIGRATE_RECORDINGS: &str = "\
+Upgrade recordings from old versions in place
+
+Usage: sentryshot migrate-recordings [OPTIONS] <PATH>
+
+Arguments:
+  <PATH>  Recordings directory
+
+Options:
+      --dry-run  List the recordings without changing them
+  -h, --help     Print help
+";
+
+const HELP_FSCK: &str = "\
+Check and repair recordings
+
+Truncates recordings to the last complete sample, regenerates missing data
+files and thumbnails and reports orphaned files. Stop the program first.
+
+Usage: sentryshot fsck [OPTIONS] <PATH>
+
+Arguments:
+  <PATH>  Recordings directory
+
+Options:
+      --dry-run  Report issues without repairing them
+  -h, --help     Print help
+";
+
+const HELP_DECRYPT: &str = "\
+Decrypt recordings for export
+
+Decrypts the recording files in PATH into OUTPUT using the key from the
+config, the directory structure is kept. Unencrypted files are copied.
+
+Usage: sentryshot decrypt [OPTIONS] <PATH> <OUTPUT>
+
+Arguments:
+  <PATH>    Recording file or directory
+  <OUTPUT>  Output directory
+
+Options:
+      --config <CONFIG>  [default: ./configs/sentryshot.toml]
+  -h, --help             Print help
+";
+
+const HELP_VERIFY: &str = "\
+Verify the recording hash chain
+
+Verifies the hash chain of a monitor and the files of every recording in it.
+If ID is a recording id, the chain is verified up to that recording and only
+its files are checked. Prints the first broken link and exits with a non-zero
+status if the chain is broken.
+
+Usage: sentryshot verify [OPTIONS] <ID>
+
+Arguments:
+  <ID>  Monitor or recording id
+
+Options:
+      --config <CONFIG>  [default: ./configs/sentryshot.toml]
+  -h, --help             Print help
+";
+
+const HELP_TIMELAPSE: &str = "\
+Generate a timelapse from recordings
+
+Samples one keyframe every INTERVAL seconds from the recordings of a monitor
+between START and END and writes them to OUTPUT as a MP4 file played back at
+FPS frames per second. The video isn't re-encoded. Times are in UTC, the max
+duration is 31 days.
+
+Usage: sentryshot timelapse [OPTIONS] <MONITOR_ID> <START> <END> <OUTPUT>
+
+Arguments:
+  <MONITOR_ID>  Monitor id
+  <START>       Start time, YYYY-MM-DD_hh-mm-ss
+  <END>         End time, YYYY-MM-DD_hh-mm-ss
+  <OUTPUT>      Output file
+
+Options:
+      --config <CONFIG>      [default: ./configs/sentryshot.toml]
+      --interval <INTERVAL>  Seconds between frames [default: 60]
+      --fps <FPS>            Playback frame rate [default: 30]
+  -h, --help                 Print help
+";
@@ -0,0 +1,125 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+use recording::{migrate_meta_v0, MigrateMetaError};
+use std::{
+    collections::VecDeque,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum MigrateRecordingsError {
+    #[error("read dir: {0}")]
+    ReadDir(std::io::Error),
+
+    #[error("entry: {0}")]
+    Entry(std::io::Error),
+
+    #[error("metadata: {0}")]
+    Metadata(std::io::Error),
+
+    #[error("read version: {0} {1}")]
+    ReadVersion(PathBuf, std::io::Error),
+}
+
+// Rewrites version 0 meta files into the current format in place.
+pub fn migrate_recordings(path: PathBuf, dry_run: bool) -> Result<(), MigrateRecordingsError> {
+    let meta_paths = find_v0_meta_files(path)?;
+
+    let n_files = meta_paths.len();
+    println!("Found {n_files} recordings to migrate");
+    if dry_run {
+        for path in meta_paths {
+            println!("{}", path.to_string_lossy());
+        }
+        return Ok(());
+    }
+
+    let mut n_failed = 0;
+    for (i, path) in meta_paths.iter().enumerate() {
+        let i = i + 1;
+        let path_str = path.to_string_lossy();
+        if let Err(e) = migrate_file(path) {
+            println!("[{i}/{n_files}] [ERR] {path_str} {e}");
+            n_failed += 1;
+            continue;
+        }
+        println!("[{i}/{n_files}] [OK] {path_str}");
+    }
+    if n_failed != 0 {
+        println!("{n_failed} recordings failed to migrate");
+    }
+    Ok(())
+}
+
+fn find_v0_meta_files(path: PathBuf) -> Result<Vec<PathBuf>, MigrateRecordingsError> {
+    use MigrateRecordingsError::*;
+    let mut meta_paths = Vec::new();
+    let mut dirs_to_visit = VecDeque::new();
+    dirs_to_visit.push_back(path);
+
+    while let Some(dir) = dirs_to_visit.pop_front() {
+        let entries = std::fs::read_dir(dir).map_err(ReadDir)?;
+        for entry in entries {
+            let entry = entry.map_err(Entry)?;
+            let metadata = entry.metadata().map_err(Metadata)?;
+            if metadata.is_dir() {
+                dirs_to_visit.push_back(entry.path());
+                continue;
+            }
+
+            let path = entry.path();
+            let is_meta_file = path
+                .extension()
+                .map_or(false, |ext| ext.eq_ignore_ascii_case("meta"));
+            if !is_meta_file {
+                continue;
+            }
+            if read_version(&path).map_err(|e| ReadVersion(path.clone(), e))? == Some(0) {
+                meta_paths.push(path);
+            }
+        }
+    }
+    meta_paths.sort();
+    Ok(meta_paths)
+}
+
+// Returns None if the file is empty.
+fn read_version(path: &Path) -> Result<Option<u8>, std::io::Error> {
+    let mut buf = [0];
+    let n = std::fs::File::open(path)?.read(&mut buf)?;
+    Ok((n != 0).then_some(buf[0]))
+}
+
+#[derive(Debug, Error)]
+enum MigrateFileError {
+    #[error("read file: {0}")]
+    ReadFile(std::io::Error),
+
+    #[error("migrate: {0}")]
+    Migrate(#[from] MigrateMetaError),
+
+    #[error("write file: {0}")]
+    WriteFile(std::io::Error),
+
+    #[error("rename file: {0}")]
+    RenameFile(std::io::Error),
+}
+
+// The new file is written next to the old one and then
+// renamed to replace it, the old file is never left half-written.
+fn migrate_file(path: &Path) -> Result<(), MigrateFileError> {
+    use MigrateFileError::*;
+    let raw = std::fs::read(path).map_err(ReadFile)?;
+    let migrated = migrate_meta_v0(&raw)?;
+
+    let mut tmp_path = path.to_path_buf();
+    tmp_path.set_extension("meta.tmp");
+    {
+        let mut file = std::fs::File::create(&tmp_path).map_err(WriteFile)?;
+        file.write_all(&migrated).map_err(WriteFile)?;
+        file.sync_all().map_err(WriteFile)?;
+    }
+    std::fs::rename(&tmp_path, path).map_err(RenameFile)
+}
@@ -0,0 +1,94 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+use common::{
+    recording::{RecordingId, RecordingIdError},
+    time::UnixNano,
+    DummyLogger, EnvConfig, MonitorId, ParseMonitorIdError,
+};
+use env::{EnvConf, EnvConfigNewError};
+use recdb::{Archive, Disk, RecDb};
+use std::{
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+use tokio::fs::File;
+use vod::{write_timelapse, TimelapseError, TimelapseOptions};
+
+#[derive(Debug, Error)]
+pub enum TimelapseCmdError {
+    #[error("{0}")]
+    EnvConfig(#[from] EnvConfigNewError),
+
+    #[error("invalid monitor id: {0}")]
+    ParseMonitorId(#[from] ParseMonitorIdError),
+
+    #[error("invalid time '{0}': {1}")]
+    ParseTime(String, RecordingIdError),
+
+    #[error("invalid time '{0}'")]
+    InvalidTime(String),
+
+    #[error("create file: {0}")]
+    CreateFile(std::io::Error),
+
+    #[error("{0}")]
+    Timelapse(#[from] TimelapseError),
+
+    #[error("no recordings found")]
+    NoRecordings,
+}
+
+// Writes a timelapse of the monitor's recordings between `start` and `end`
+// to `output`. The times are in the recording id format in UTC.
+#[allow(clippy::module_name_repetitions)]
+pub async fn timelapse(
+    config_path: &PathBuf,
+    monitor_id: String,
+    start: &str,
+    end: &str,
+    interval: NonZeroU32,
+    fps: NonZeroU32,
+    output: &Path,
+) -> Result<(), TimelapseCmdError> {
+    use TimelapseCmdError::*;
+    let env = EnvConf::new(config_path)?;
+    let monitor_id = MonitorId::try_from(monitor_id)?;
+    let start = parse_time(start, &monitor_id)?;
+    let end = parse_time(end, &monitor_id)?;
+    let opts = TimelapseOptions { interval, fps };
+    opts.validate(start, end)?;
+
+    let mut rec_db = RecDb::new(
+        DummyLogger::new(),
+        env.recordings_dir().to_path_buf(),
+        Disk::new(env.storage_dir().to_path_buf(), env.max_disk_usage()),
+        env.encryption_key().cloned(),
+    );
+    if let Some(archive) = env.archive() {
+        rec_db = rec_db.with_archive(Archive::new(
+            archive.recordings_dir().to_path_buf(),
+            Disk::new(archive.dir().to_path_buf(), archive.max_disk_usage()),
+            archive.archive_after(),
+        ));
+    }
+
+    let mut file = File::create(output).await.map_err(CreateFile)?;
+    let Some(size) = write_timelapse(&rec_db, &monitor_id, start, end, &opts, &mut file).await?
+    else {
+        drop(file);
+        _ = tokio::fs::remove_file(output).await;
+        return Err(NoRecordings);
+    };
+    println!("wrote {size} bytes to {}", output.display());
+    Ok(())
+}
+
+// `YYYY-MM-DD_hh-mm-ss`
+fn parse_time(s: &str, monitor_id: &MonitorId) -> Result<UnixNano, TimelapseCmdError> {
+    let rec_id = RecordingId::try_from(format!("{s}_{monitor_id}"))
+        .map_err(|e| TimelapseCmdError::ParseTime(s.to_owned(), e))?;
+    rec_id
+        .as_nanos()
+        .ok_or_else(|| TimelapseCmdError::InvalidTime(s.to_owned()))
+}
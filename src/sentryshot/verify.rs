@@ -0,0 +1,61 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+use common::{recording::RecordingId, EnvConfig, MonitorId, ParseMonitorIdError};
+use env::{EnvConf, EnvConfigNewError};
+use recdb::{verify_hash_chain, VerifyHashChainError};
+use std::path::PathBuf;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum VerifyError {
+    #[error("{0}")]
+    EnvConfig(#[from] EnvConfigNewError),
+
+    #[error("invalid monitor or recording id: {0}")]
+    ParseId(#[from] ParseMonitorIdError),
+
+    #[error("{0}")]
+    Verify(#[from] VerifyHashChainError),
+}
+
+// Verifies the hash chain of a monitor, or the chain up to a recording
+// if `id` is a recording id. Returns false if the chain is broken.
+pub async fn verify(config_path: &PathBuf, id: String) -> Result<bool, VerifyError> {
+    let env = EnvConf::new(config_path)?;
+
+    let (monitor_id, rec_id) = match RecordingId::try_from(id.clone()) {
+        Ok(rec_id) => (rec_id.monitor().clone(), Some(rec_id)),
+        Err(_) => (MonitorId::try_from(id)?, None),
+    };
+
+    let report = verify_hash_chain(
+        env.recordings_dir().to_path_buf(),
+        env.archive().map(|v| v.recordings_dir().to_path_buf()),
+        env.encryption_key(),
+        monitor_id,
+        rec_id,
+    )
+    .await?;
+
+    for rec_id in &report.deleted_recordings {
+        println!("[DELETED] {}", rec_id.as_str());
+    }
+    println!("{} entries verified", report.verified);
+    if env.encryption_key().is_none() {
+        println!("No encryption key in config, signatures were not checked");
+    }
+
+    let Some(link) = report.first_broken_link else {
+        println!("OK");
+        return Ok(true);
+    };
+    let rec_id = link
+        .recording_id
+        .as_ref()
+        .map_or("unknown", RecordingId::as_str);
+    println!(
+        "[BROKEN] line {} recording {rec_id}: {}",
+        link.line, link.reason
+    );
+    Ok(false)
+}
@@ -1,28 +1,37 @@
-// SPDX-License-Identifier: GPL-2.0-or-later
-
-use crate::QueryResult;
-use crate::VodQuery;
+use crate::{playlist::PlaylistResult, QueryResult, VodQuery};
 use std::{collections::HashMap, sync::Arc};
 use tokio::sync::Mutex;
 
-// Caches the n most recent vod readers.
+// Caches the n most recent vod readers and playlists. The total size is
+// limited separately because long queries have large sample tables.
 #[derive(Clone)]
 #[allow(clippy::module_name_repetitions)]
-pub struct VodCache(Arc<Mutex<State>>);
+pub struct VodCache {
+    readers: Arc<Mutex<State<QueryResult>>>,
+    playlists: Arc<Mutex<State<PlaylistResult>>>,
+}
 
-struct State {
-    items: HashMap<VodQuery, CacheItem>,
+// Approximate memory usage.
+pub(crate) trait MemSize {
+    fn mem_size(&self) -> usize;
+}
+
+struct State<T> {
+    items: HashMap<VodQuery, CacheItem<T>>,
     age: usize,
+    bytes: usize,
 
     max_size: usize,
+    max_bytes: usize,
 }
 
-struct CacheItem {
+struct CacheItem<T> {
     age: usize,
-    data: Arc<QueryResult>,
+    data: Arc<T>,
 }
 
 const VOD_CACHE_SIZE: usize = 10;
+const VOD_CACHE_MAX_BYTES: usize = 128 * 1024 * 1024;
 
 impl VodCache {
     #[must_use]
@@ -31,19 +40,30 @@ impl VodCache {
     }
 
     fn with_capacity(max_size: usize) -> Self {
-        Self(Arc::new(Mutex::new(State {
-            items: HashMap::new(),
-            age: 0,
-            max_size,
-        })))
+        Self::with_limits(max_size, VOD_CACHE_MAX_BYTES)
+    }
+
+    fn with_limits(max_size: usize, max_bytes: usize) -> Self {
+        Self {
+            readers: Arc::new(Mutex::new(State::new(max_size, max_bytes))),
+            playlists: Arc::new(Mutex::new(State::new(max_size, max_bytes))),
+        }
     }
 
     pub(crate) async fn add(&self, key: VodQuery, res: Arc<QueryResult>) {
-        self.0.lock().await.add(key, res);
+        self.readers.lock().await.add(key, res);
     }
 
     pub(crate) async fn get(&self, key: &VodQuery) -> Option<Arc<QueryResult>> {
-        self.0.lock().await.get(key)
+        self.readers.lock().await.get(key)
+    }
+
+    pub(crate) async fn add_playlist(&self, key: VodQuery, res: Arc<PlaylistResult>) {
+        self.playlists.lock().await.add(key, res);
+    }
+
+    pub(crate) async fn get_playlist(&self, key: &VodQuery) -> Option<Arc<PlaylistResult>> {
+        self.playlists.lock().await.get(key)
     }
 }
 
@@ -53,24 +73,41 @@ impl Default for VodCache {
     }
 }
 
-impl State {
-    fn add(&mut self, key: VodQuery, res: Arc<QueryResult>) {
+impl<T: MemSize> State<T> {
+    fn new(max_size: usize, max_bytes: usize) -> Self {
+        Self {
+            items: HashMap::new(),
+            age: 0,
+            bytes: 0,
+            max_size,
+            max_bytes,
+        }
+    }
+
+    fn add(&mut self, key: VodQuery, res: Arc<T>) {
         // Ignore duplicate keys.
         if self.items.contains_key(&key) {
             return;
         }
+        let size = res.mem_size();
+        if size > self.max_bytes {
+            return;
+        }
 
         self.age += 1;
 
-        if self.items.len() >= self.max_size {
+        while self.items.len() >= self.max_size || self.bytes + size > self.max_bytes {
             // Delete the oldest item.
             let (key, _) = self
                 .items
                 .iter()
                 .min_by_key(|(_, v)| v.age)
-                .expect("len > max_size");
-            self.items.remove(&key.to_owned());
+                .expect("cache should not be empty");
+            let key = key.to_owned();
+            let item = self.items.remove(&key).expect("item should exist");
+            self.bytes -= item.data.mem_size();
         }
+        self.bytes += size;
 
         self.items.insert(
             key,
@@ -81,7 +118,7 @@ impl State {
         );
     }
 
-    fn get(&mut self, key: &VodQuery) -> Option<Arc<QueryResult>> {
+    fn get(&mut self, key: &VodQuery) -> Option<Arc<T>> {
         for (item_key, item) in &mut self.items {
             if item_key == key {
                 self.age += 1;
@@ -101,7 +138,7 @@ mod tests {
 
     fn key(v: u32) -> VodQuery {
         VodQuery {
-            monitor_id: "x".to_owned().try_into().unwrap(),
+            monitor_ids: vec!["x".to_owned().try_into().unwrap()],
             start: UnixNano::new(0),
             end: UnixNano::new(0),
             cache_id: v,
@@ -156,4 +193,31 @@ mod tests {
         let e2 = cache.get(&key(5)).await.unwrap();
         assert_eq!(e, e2);
     }
+
+    fn sized(meta_size: usize) -> Arc<QueryResult> {
+        Arc::new(QueryResult {
+            meta: vec![0; meta_size],
+            meta_size,
+            size: 0,
+            recs: Vec::new(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_video_reader_cache_max_bytes() {
+        let cache = VodCache::with_limits(10, 250);
+
+        cache.add(key(1), sized(100)).await;
+        cache.add(key(2), sized(100)).await;
+
+        // Add item and check if 1 was removed to make room.
+        cache.add(key(3), sized(100)).await;
+        assert!(cache.get(&key(1)).await.is_none());
+        assert!(cache.get(&key(2)).await.is_some());
+
+        // Items larger than the cache aren't cached.
+        cache.add(key(4), sized(300)).await;
+        assert!(cache.get(&key(4)).await.is_none());
+        assert!(cache.get(&key(3)).await.is_some());
+    }
 }
@@ -0,0 +1,743 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+use crate::{
+    write_timelapse, zip::ZipWriter, CreateVodReaderError, TimelapseError, TimelapseOptions,
+    VodCache, VodQuery, VodReader,
+};
+use common::{
+    recording::{RecordingId, RecordingIdError},
+    time::{UnixNano, DAY, HOUR},
+    DynLogger, LogEntry, LogLevel, MonitorId,
+};
+use recdb::{QueryRecordingsError, RecDb, RecDbQuery, RecordingFilter, RecordingResponse};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+    time::UNIX_EPOCH,
+};
+use thiserror::Error;
+use tokio::{fs::File, io::AsyncWriteExt, task::AbortHandle};
+use tokio_util::sync::CancellationToken;
+
+// Zip exports contain one MP4 file per monitor and hour.
+const EXPORT_PART_DURATION: i64 = HOUR;
+const MAX_EXPORT_DURATION: i64 = 7 * DAY;
+
+// Finished exports are deleted after this duration.
+const EXPORT_TTL: i64 = DAY;
+const EXPIRE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
+// Running exports are written to a temporary file
+// that's renamed once the export is done.
+const PART_EXTENSION: &str = ".part";
+
+const MAX_RUNNING_EXPORTS: usize = 2;
+
+#[allow(clippy::module_name_repetitions)]
+#[derive(Clone, Debug, Deserialize)]
+pub struct ExportRequest {
+    pub monitors: Vec<MonitorId>,
+    pub start: UnixNano,
+    pub end: UnixNano,
+
+    // Include the data files of the exported recordings.
+    #[serde(default, rename = "includeEvents")]
+    pub include_events: bool,
+
+    // Export a keyframe-only timelapse instead of the full recordings.
+    #[serde(default)]
+    pub timelapse: Option<TimelapseOptions>,
+}
+
+impl ExportRequest {
+    // A single part from a single monitor or a timelapse is exported
+    // as a plain MP4 file, everything else is bundled in a zip.
+    fn is_single_file(&self, n_parts: usize) -> bool {
+        self.timelapse.is_some()
+            || (self.monitors.len() == 1 && n_parts == 1 && !self.include_events)
+    }
+}
+
+#[allow(clippy::module_name_repetitions)]
+pub type ExportId = u32;
+
+#[allow(clippy::module_name_repetitions)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportState {
+    Running,
+    Done,
+    Failed,
+}
+
+#[allow(clippy::module_name_repetitions)]
+#[derive(Clone, Debug, Serialize)]
+pub struct ExportStatus {
+    pub id: ExportId,
+    pub state: ExportState,
+
+    #[serde(rename = "partsDone")]
+    pub parts_done: usize,
+    #[serde(rename = "partsTotal")]
+    pub parts_total: usize,
+
+    #[serde(rename = "fileName")]
+    pub file_name: String,
+
+    // Size of the finished file in bytes.
+    pub size: Option<u64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    // The export is deleted after this time.
+    pub expires: Option<UnixNano>,
+}
+
+#[derive(Debug, Error)]
+pub enum CreateExportError {
+    #[error("no monitors selected")]
+    NoMonitors,
+
+    #[error("end must be after start")]
+    NegativeDuration,
+
+    #[error("max export duration is 7 days")]
+    MaxDuration,
+
+    #[error("invalid start time")]
+    InvalidTime,
+
+    #[error("too many running exports, max {MAX_RUNNING_EXPORTS}")]
+    TooManyExports,
+
+    #[error("timelapse requires exactly one monitor")]
+    TimelapseMonitors,
+
+    #[error("timelapse: {0}")]
+    Timelapse(#[from] TimelapseError),
+}
+
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Error)]
+pub enum ExportError {
+    #[error("no recordings found")]
+    NoRecordings,
+
+    #[error("create vod reader: {0}")]
+    CreateVodReader(#[from] CreateVodReaderError),
+
+    #[error("query recordings: {0}")]
+    QueryRecordings(#[from] QueryRecordingsError),
+
+    #[error("recording id: {0}")]
+    RecordingId(#[from] RecordingIdError),
+
+    #[error("serialize data: {0}")]
+    Serialize(#[from] serde_json::Error),
+
+    #[error("create file: {0}")]
+    CreateFile(std::io::Error),
+
+    #[error("write file: {0}")]
+    WriteFile(std::io::Error),
+
+    #[error("rename file: {0}")]
+    RenameFile(std::io::Error),
+
+    #[error("timelapse: {0}")]
+    Timelapse(#[from] TimelapseError),
+}
+
+struct Job {
+    status: ExportStatus,
+    path: PathBuf,
+
+    // Set while the export is running.
+    abort: Option<AbortHandle>,
+}
+
+struct State {
+    jobs: HashMap<ExportId, Job>,
+    next_id: ExportId,
+}
+
+// Runs export jobs in the background. The jobs are kept in memory,
+// finished exports are restored from their files on startup.
+#[allow(clippy::module_name_repetitions)]
+pub struct ExportManager {
+    logger: DynLogger,
+    recdb: Arc<RecDb>,
+    dir: PathBuf,
+    state: Mutex<State>,
+}
+
+impl ExportManager {
+    #[must_use]
+    pub fn new(logger: DynLogger, recdb: Arc<RecDb>, dir: PathBuf) -> Self {
+        let jobs = restore_jobs(&dir);
+        let next_id = jobs.keys().max().map_or(1, |v| v.wrapping_add(1));
+        Self {
+            logger,
+            recdb,
+            dir,
+            state: Mutex::new(State { jobs, next_id }),
+        }
+    }
+
+    // Removes expired exports until the token is cancelled.
+    pub async fn expire_loop(&self, token: CancellationToken) {
+        loop {
+            tokio::select! {
+                () = token.cancelled() => return,
+                () = tokio::time::sleep(EXPIRE_INTERVAL) => drop(self.state()),
+            }
+        }
+    }
+
+    // Expired exports are removed whenever the state is accessed.
+    fn state(&self) -> MutexGuard<'_, State> {
+        let mut state = self.state.lock().expect("not poisoned");
+        let now = UnixNano::now();
+        state.jobs.retain(|_, job| {
+            let expired = job.status.expires.is_some_and(|v| v.before(now));
+            if expired {
+                _ = std::fs::remove_file(&job.path);
+            }
+            !expired
+        });
+        state
+    }
+
+    // Validates the request and starts the export in the background.
+    pub fn create(self: &Arc<Self>, req: ExportRequest) -> Result<ExportStatus, CreateExportError> {
+        use CreateExportError::*;
+        if req.monitors.is_empty() {
+            return Err(NoMonitors);
+        }
+        if let Some(opts) = &req.timelapse {
+            if req.monitors.len() != 1 {
+                return Err(TimelapseMonitors);
+            }
+            opts.validate(req.start, req.end)?;
+        } else {
+            if !req.start.before(req.end) {
+                return Err(NegativeDuration);
+            }
+            if (*req.end)
+                .checked_sub(*req.start)
+                .map_or(true, |v| v > MAX_EXPORT_DURATION)
+            {
+                return Err(MaxDuration);
+            }
+        }
+        let start_time = req.start.as_chrono().ok_or(InvalidTime)?;
+
+        let mut state = self.state();
+        let running = state
+            .jobs
+            .values()
+            .filter(|v| v.status.state == ExportState::Running)
+            .count();
+        if running >= MAX_RUNNING_EXPORTS {
+            return Err(TooManyExports);
+        }
+        let id = state.next_id;
+        state.next_id = state.next_id.wrapping_add(1);
+
+        // The timelapse is written in one pass.
+        let parts = if req.timelapse.is_some() {
+            vec![(req.start, req.end)]
+        } else {
+            split_range(req.start, req.end)
+        };
+        let time = start_time.format("%Y-%m-%d_%H-%M-%S");
+        let file_name = if req.timelapse.is_some() {
+            format!("{}_{time}_timelapse.mp4", req.monitors[0])
+        } else if req.is_single_file(parts.len()) {
+            format!("{}_{time}.mp4", req.monitors[0])
+        } else {
+            format!("export_{time}.zip")
+        };
+        let path = self.dir.join(format!("{id}_{file_name}"));
+
+        let status = ExportStatus {
+            id,
+            state: ExportState::Running,
+            parts_done: 0,
+            parts_total: parts.len() * req.monitors.len(),
+            file_name,
+            size: None,
+            error: None,
+            expires: None,
+        };
+
+        let manager = self.clone();
+        let path2 = path.clone();
+        let task = tokio::spawn(async move { manager.run(id, req, parts, path2).await });
+        state.jobs.insert(
+            id,
+            Job {
+                status: status.clone(),
+                path,
+                abort: Some(task.abort_handle()),
+            },
+        );
+        Ok(status)
+    }
+
+    async fn run(
+        self: Arc<Self>,
+        id: ExportId,
+        req: ExportRequest,
+        parts: Vec<(UnixNano, UnixNano)>,
+        path: PathBuf,
+    ) {
+        let part_path = part_path(&path);
+        let res = match self.export(id, &req, &parts, &part_path).await {
+            Ok(size) => tokio::fs::rename(&part_path, &path)
+                .await
+                .map(|()| size)
+                .map_err(ExportError::RenameFile),
+            Err(e) => Err(e),
+        };
+
+        let mut state = self.state();
+        // The export was deleted.
+        let Some(job) = state.jobs.get_mut(&id) else {
+            return;
+        };
+        job.abort = None;
+        job.status.expires = Some(UnixNano::now() + UnixNano::new(EXPORT_TTL));
+        match res {
+            Ok(size) => {
+                job.status.state = ExportState::Done;
+                job.status.size = Some(size);
+            }
+            Err(e) => {
+                self.logger.log(LogEntry::new(
+                    LogLevel::Error,
+                    "app",
+                    None,
+                    format!("export {id}: {e}"),
+                ));
+                job.status.state = ExportState::Failed;
+                job.status.error = Some(e.to_string());
+                _ = std::fs::remove_file(&part_path);
+            }
+        }
+    }
+
+    // Returns the size of the exported file.
+    async fn export(
+        &self,
+        id: ExportId,
+        req: &ExportRequest,
+        parts: &[(UnixNano, UnixNano)],
+        path: &Path,
+    ) -> Result<u64, ExportError> {
+        use ExportError::*;
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .map_err(CreateFile)?;
+        let mut file = File::create(path).await.map_err(CreateFile)?;
+
+        if let Some(opts) = &req.timelapse {
+            let Some(size) = write_timelapse(
+                &self.recdb,
+                &req.monitors[0],
+                req.start,
+                req.end,
+                opts,
+                &mut file,
+            )
+            .await?
+            else {
+                return Err(NoRecordings);
+            };
+            self.part_done(id);
+            return Ok(size);
+        }
+
+        if req.is_single_file(parts.len()) {
+            let (start, end) = parts[0];
+            let Some(mut reader) = self.vod_reader(&req.monitors[0], start, end).await? else {
+                return Err(NoRecordings);
+            };
+            let size = tokio::io::copy(&mut reader, &mut file)
+                .await
+                .map_err(WriteFile)?;
+            file.flush().await.map_err(WriteFile)?;
+            self.part_done(id);
+            return Ok(size);
+        }
+
+        let mut zip = ZipWriter::new(file);
+        let mut found = false;
+        for monitor_id in &req.monitors {
+            for (start, end) in parts {
+                if let Some(reader) = self.vod_reader(monitor_id, *start, *end).await? {
+                    let name = format!("{monitor_id}/{}.mp4", format_time(*start));
+                    zip.add_file(&name, reader).await.map_err(WriteFile)?;
+                    found = true;
+                }
+                self.part_done(id);
+            }
+            if req.include_events {
+                for (rec_id, data) in self.recording_data(monitor_id, req.start, req.end).await? {
+                    let name = format!("{monitor_id}/{}.json", rec_id.as_str());
+                    zip.add_file(&name, &data[..]).await.map_err(WriteFile)?;
+                }
+            }
+        }
+        if !found {
+            return Err(NoRecordings);
+        }
+        zip.finish().await.map_err(WriteFile)
+    }
+
+    async fn vod_reader(
+        &self,
+        monitor_id: &MonitorId,
+        start: UnixNano,
+        end: UnixNano,
+    ) -> Result<Option<VodReader>, CreateVodReaderError> {
+        let query = VodQuery {
+            monitor_ids: vec![monitor_id.clone()],
+            start,
+            end,
+            cache_id: 0,
+        };
+        VodReader::new(&self.recdb, &VodCache::new(), query).await
+    }
+
+    // Returns the data files of the recordings that overlap the time range.
+    async fn recording_data(
+        &self,
+        monitor_id: &MonitorId,
+        start: UnixNano,
+        end: UnixNano,
+    ) -> Result<Vec<(RecordingId, Vec<u8>)>, ExportError> {
+        let recordings = self
+            .recdb
+            .recordings_by_query(&RecDbQuery {
+                recording_id: RecordingId::from_nanos(end, monitor_id)?,
+                end: None,
+                limit: NonZeroUsize::new(10_000).expect("nonzero"),
+                reverse: false,
+                monitors: vec![monitor_id.to_string()],
+                include_data: true,
+                filter: RecordingFilter {
+                    time_from: Some(start),
+                    time_to: Some(end),
+                    ..Default::default()
+                },
+            })
+            .await?;
+
+        let mut files = Vec::new();
+        // Oldest first.
+        for rec in recordings.iter().rev() {
+            let RecordingResponse::Finalized(rec) = rec else {
+                continue;
+            };
+            let Some(data) = rec.data() else {
+                continue;
+            };
+            files.push((rec.id.clone(), serde_json::to_vec_pretty(data)?));
+        }
+        Ok(files)
+    }
+
+    fn part_done(&self, id: ExportId) {
+        if let Some(job) = self.state().jobs.get_mut(&id) {
+            job.status.parts_done += 1;
+        }
+    }
+
+    #[must_use]
+    pub fn status(&self, id: ExportId) -> Option<ExportStatus> {
+        self.state().jobs.get(&id).map(|v| v.status.clone())
+    }
+
+    #[must_use]
+    pub fn list(&self) -> Vec<ExportStatus> {
+        let mut list: Vec<_> = self
+            .state()
+            .jobs
+            .values()
+            .map(|v| v.status.clone())
+            .collect();
+        list.sort_by_key(|v| v.id);
+        list
+    }
+
+    // Returns the path and file name of a finished export.
+    #[must_use]
+    pub fn file(&self, id: ExportId) -> Option<(PathBuf, String)> {
+        let state = self.state();
+        let job = state.jobs.get(&id)?;
+        if job.status.state != ExportState::Done {
+            return None;
+        }
+        Some((job.path.clone(), job.status.file_name.clone()))
+    }
+
+    // Cancels the export if it's running and deletes the file.
+    // Returns false if the export doesn't exist.
+    pub fn delete(&self, id: ExportId) -> bool {
+        let Some(job) = self.state().jobs.remove(&id) else {
+            return false;
+        };
+        if let Some(abort) = job.abort {
+            abort.abort();
+            _ = std::fs::remove_file(part_path(&job.path));
+        }
+        _ = std::fs::remove_file(job.path);
+        true
+    }
+}
+
+fn part_path(path: &Path) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(PART_EXTENSION);
+    PathBuf::from(path)
+}
+
+// Restores the finished exports from before a restart,
+// files from unfinished exports are removed.
+fn restore_jobs(dir: &Path) -> HashMap<ExportId, Job> {
+    let mut jobs = HashMap::new();
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return jobs;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if let Some(job) = restore_job(&path) {
+            jobs.insert(job.status.id, job);
+        } else {
+            _ = std::fs::remove_file(&path);
+        }
+    }
+    jobs
+}
+
+// Finished exports are named `<ID>_<FILE_NAME>`.
+fn restore_job(path: &Path) -> Option<Job> {
+    let name = path.file_name()?.to_str()?;
+    if name.ends_with(PART_EXTENSION) {
+        return None;
+    }
+    let (id, file_name) = name.split_once('_')?;
+    let id: ExportId = id.parse().ok()?;
+
+    let metadata = std::fs::metadata(path).ok()?;
+    if !metadata.is_file() {
+        return None;
+    }
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    let modified = UnixNano::new(i64::try_from(modified.as_nanos()).ok()?);
+    Some(Job {
+        status: ExportStatus {
+            id,
+            state: ExportState::Done,
+            // The number of parts isn't stored.
+            parts_done: 1,
+            parts_total: 1,
+            file_name: file_name.to_owned(),
+            size: Some(metadata.len()),
+            error: None,
+            expires: modified.checked_add(UnixNano::new(EXPORT_TTL)),
+        },
+        path: path.to_owned(),
+        abort: None,
+    })
+}
+
+fn split_range(start: UnixNano, end: UnixNano) -> Vec<(UnixNano, UnixNano)> {
+    let mut parts = Vec::new();
+    let mut part_start = *start;
+    while part_start < *end {
+        let part_end = std::cmp::min(part_start.saturating_add(EXPORT_PART_DURATION), *end);
+        parts.push((UnixNano::new(part_start), UnixNano::new(part_end)));
+        part_start = part_end;
+    }
+    parts
+}
+
+fn format_time(time: UnixNano) -> String {
+    time.as_chrono().map_or_else(
+        || time.to_string(),
+        |v| v.format("%Y-%m-%d_%H-%M-%S").to_string(),
+    )
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use bytesize::ByteSize;
+    use common::{time::MINUTE, DummyLogger};
+    use pretty_assertions::assert_eq;
+    use recdb::Disk;
+    use std::num::NonZeroU32;
+    use tempfile::TempDir;
+    use test_case::test_case;
+
+    fn new_test_manager(dir: &Path) -> Arc<ExportManager> {
+        let recordings_dir = dir.join("recordings");
+        let recdb = Arc::new(RecDb::new(
+            DummyLogger::new(),
+            recordings_dir.clone(),
+            Disk::new(recordings_dir, ByteSize(0)),
+            None,
+        ));
+        Arc::new(ExportManager::new(
+            DummyLogger::new(),
+            recdb,
+            dir.join("exports"),
+        ))
+    }
+
+    fn test_request(start: i64, end: i64) -> ExportRequest {
+        ExportRequest {
+            monitors: vec!["m1".to_owned().try_into().unwrap()],
+            start: UnixNano::new(start),
+            end: UnixNano::new(end),
+            include_events: false,
+            timelapse: None,
+        }
+    }
+
+    fn test_timelapse_request(start: i64, end: i64) -> ExportRequest {
+        ExportRequest {
+            timelapse: Some(TimelapseOptions {
+                interval: NonZeroU32::new(60).unwrap(),
+                fps: NonZeroU32::new(30).unwrap(),
+            }),
+            ..test_request(start, end)
+        }
+    }
+
+    #[test]
+    fn test_split_range() {
+        let parts: Vec<_> = split_range(UnixNano::new(MINUTE), UnixNano::new(2 * HOUR))
+            .into_iter()
+            .map(|(start, end)| (*start, *end))
+            .collect();
+        assert_eq!(
+            vec![(MINUTE, HOUR + MINUTE), (HOUR + MINUTE, 2 * HOUR)],
+            parts
+        );
+    }
+
+    #[test_case(ExportRequest{ monitors: Vec::new(), ..test_request(0, 1) }, "no monitors selected"; "no monitors")]
+    #[test_case(test_request(1, 1), "end must be after start"; "negative duration")]
+    #[test_case(test_request(0, 8 * DAY), "max export duration is 7 days"; "max duration")]
+    #[test_case(ExportRequest{ monitors: Vec::new(), ..test_timelapse_request(0, 1) }, "no monitors selected"; "timelapse no monitors")]
+    #[test_case(
+        ExportRequest{
+            monitors: vec!["m1".to_owned().try_into().unwrap(), "m2".to_owned().try_into().unwrap()],
+            ..test_timelapse_request(0, 1)
+        },
+        "timelapse requires exactly one monitor";
+        "timelapse monitors"
+    )]
+    #[test_case(test_timelapse_request(0, 32 * DAY), "timelapse: max timelapse duration is 31 days"; "timelapse max duration")]
+    #[tokio::test]
+    async fn test_create_export_error(req: ExportRequest, want: &str) {
+        let temp_dir = TempDir::new().unwrap();
+        let manager = new_test_manager(temp_dir.path());
+        assert_eq!(want, manager.create(req).unwrap_err().to_string());
+    }
+
+    #[tokio::test]
+    async fn test_export_no_recordings() {
+        let temp_dir = TempDir::new().unwrap();
+        let manager = new_test_manager(temp_dir.path());
+
+        let mut req = test_request(0, 2 * HOUR);
+        req.monitors.push("m2".to_owned().try_into().unwrap());
+        let status = manager.create(req).unwrap();
+        assert_eq!(ExportState::Running, status.state);
+        assert_eq!(4, status.parts_total);
+        assert_eq!("export_1970-01-01_00-00-00.zip", status.file_name);
+
+        let status = loop {
+            let status = manager.status(status.id).unwrap();
+            if status.state != ExportState::Running {
+                break status;
+            }
+            tokio::task::yield_now().await;
+        };
+        assert_eq!(ExportState::Failed, status.state);
+        assert_eq!(Some("no recordings found".to_owned()), status.error);
+        assert_eq!(4, status.parts_done);
+        assert!(status.expires.is_some());
+        assert!(manager.file(status.id).is_none());
+
+        assert!(manager.delete(status.id));
+        assert!(manager.list().is_empty());
+        assert!(!manager.delete(status.id));
+    }
+
+    #[tokio::test]
+    async fn test_restore_exports() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path().join("exports");
+        std::fs::create_dir(&dir).unwrap();
+        std::fs::write(dir.join("3_m1_2000-01-01_00-00-00.mp4"), [0; 5]).unwrap();
+        std::fs::write(dir.join("4_export_2000-01-01_00-00-00.zip.part"), [0; 5]).unwrap();
+        std::fs::write(dir.join("x"), [0; 5]).unwrap();
+
+        let manager = new_test_manager(temp_dir.path());
+        let list = manager.list();
+        assert_eq!(1, list.len());
+        assert_eq!(3, list[0].id);
+        assert_eq!(ExportState::Done, list[0].state);
+        assert_eq!("m1_2000-01-01_00-00-00.mp4", list[0].file_name);
+        assert_eq!(Some(5), list[0].size);
+        assert!(list[0].expires.unwrap().after(UnixNano::now()));
+        assert_eq!(
+            Some((
+                dir.join("3_m1_2000-01-01_00-00-00.mp4"),
+                "m1_2000-01-01_00-00-00.mp4".to_owned()
+            )),
+            manager.file(3)
+        );
+
+        // Unfinished exports and unknown files are removed.
+        let mut files: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|v| v.unwrap().file_name())
+            .collect();
+        files.sort();
+        assert_eq!(vec!["3_m1_2000-01-01_00-00-00.mp4"], files);
+
+        let status = manager.create(test_request(0, 1)).unwrap();
+        assert_eq!(4, status.id);
+    }
+
+    #[tokio::test]
+    async fn test_export_timelapse_no_recordings() {
+        let temp_dir = TempDir::new().unwrap();
+        let manager = new_test_manager(temp_dir.path());
+
+        let status = manager.create(test_timelapse_request(0, 8 * DAY)).unwrap();
+        assert_eq!(1, status.parts_total);
+        assert_eq!("m1_1970-01-01_00-00-00_timelapse.mp4", status.file_name);
+
+        let status = loop {
+            let status = manager.status(status.id).unwrap();
+            if status.state != ExportState::Running {
+                break status;
+            }
+            tokio::task::yield_now().await;
+        };
+        assert_eq!(ExportState::Failed, status.state);
+        assert_eq!(Some("no recordings found".to_owned()), status.error);
+    }
+}
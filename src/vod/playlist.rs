@@ -0,0 +1,571 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+use crate::{
+    cache::MemSize, clamp_duration, last_video_sample, pad_durations, query_samples,
+    CreateVodReaderError, RecordingSink, SelectedRecording, VodCache, VodQuery,
+};
+use bytes::Bytes;
+use common::{
+    time::{DurationH264, UnixH264, UnixNano, H264_SECOND, SECOND},
+    AudioParameters, AudioSample, EncryptionKey, PaddedBytes, VideoSample,
+};
+use hls::{generate_init, generate_part, GenerateInitError, GeneratePartError};
+use recdb::RecDb;
+use recording::{read_meta, ReadMetaError, RecordingFile, Sample, TrackParameters};
+use std::{
+    fmt::Write,
+    io::SeekFrom,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, BufReader};
+
+// Segments are split on the first keyframe after this duration.
+const SEGMENT_DURATION: DurationH264 = DurationH264::new(4 * H264_SECOND);
+
+// HLS playlist over the recordings in the query. Only the segment index
+// is kept in memory, the segments are generated when they're requested.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug)]
+pub struct VodPlaylist {
+    p: Arc<PlaylistResult>,
+    encryption_key: Option<EncryptionKey>,
+}
+
+#[derive(Debug)]
+pub(crate) struct PlaylistResult {
+    start: UnixH264,
+    params: common::TrackParameters,
+    recs: Vec<PlaylistRec>,
+    segments: Vec<Segment>,
+}
+
+impl MemSize for PlaylistResult {
+    fn mem_size(&self) -> usize {
+        self.recs.len() * std::mem::size_of::<PlaylistRec>()
+            + self.segments.len() * std::mem::size_of::<Segment>()
+    }
+}
+
+#[derive(Debug)]
+struct PlaylistRec {
+    meta_path: PathBuf,
+    mdat_path: PathBuf,
+
+    // Selected range of the sample table.
+    samples: Range<usize>,
+
+    // The first video sample is moved to the start of the playlist.
+    is_first: bool,
+
+    // Includes the gap to the next recording.
+    last_duration: DurationH264,
+}
+
+#[derive(Debug)]
+struct Segment {
+    rec: usize,
+
+    // Range of the recording's selected samples.
+    samples: Range<usize>,
+
+    // Decode time relative to the start of the playlist.
+    start: DurationH264,
+    duration: DurationH264,
+}
+
+#[derive(Debug, Error)]
+pub enum VodSegmentError {
+    #[error("open file: {0}")]
+    OpenFile(std::io::Error),
+
+    #[error("read meta: {0}")]
+    ReadMeta(#[from] ReadMetaError),
+
+    #[error("sample table changed")]
+    SampleTableChanged,
+
+    #[error("retime samples: {0}")]
+    Retime(#[from] CreateVodReaderError),
+
+    #[error("dts")]
+    Dts,
+
+    #[error("read samples: {0}")]
+    ReadSamples(std::io::Error),
+
+    #[error("generate part: {0}")]
+    GeneratePart(#[from] GeneratePartError),
+}
+
+impl VodPlaylist {
+    pub async fn new(
+        recdb: &RecDb,
+        cache: &VodCache,
+        q: VodQuery,
+    ) -> Result<Option<Self>, CreateVodReaderError> {
+        let p = {
+            if let Some(p) = cache.get_playlist(&q).await {
+                p
+            } else {
+                let Some(builder) = query_samples::<PlaylistBuilder>(recdb, &q).await? else {
+                    return Ok(None);
+                };
+                let p = Arc::new(builder.finish(q.end)?);
+                cache.add_playlist(q, p.clone()).await;
+                p
+            }
+        };
+
+        Ok(Some(Self {
+            p,
+            encryption_key: recdb.encryption_key().cloned(),
+        }))
+    }
+
+    // Media playlist, `query` is appended to the segment URIs.
+    #[must_use]
+    pub fn playlist(&self, query: &str) -> String {
+        let target_duration = self
+            .p
+            .segments
+            .iter()
+            .map(|v| div_up(v.duration.as_nanos(), SECOND))
+            .max()
+            .unwrap_or(0);
+
+        let mut cnt = "#EXTM3U\n".to_owned();
+        cnt += "#EXT-X-VERSION:7\n";
+        writeln!(cnt, "#EXT-X-TARGETDURATION:{target_duration}")
+            .expect("writing to string should not fail");
+        cnt += "#EXT-X-MEDIA-SEQUENCE:0\n";
+        cnt += "#EXT-X-PLAYLIST-TYPE:VOD\n";
+        cnt += "#EXT-X-INDEPENDENT-SEGMENTS\n";
+        writeln!(cnt, "#EXT-X-MAP:URI=\"init.mp4?{query}\"")
+            .expect("writing to string should not fail");
+        for (i, seg) in self.p.segments.iter().enumerate() {
+            write!(
+                cnt,
+                "#EXTINF:{0:.5},\n{i}.mp4?{query}\n",
+                seg.duration.as_secs_f64()
+            )
+            .expect("writing to string should not fail");
+        }
+        cnt += "#EXT-X-ENDLIST\n";
+        cnt
+    }
+
+    pub fn init(&self) -> Result<Bytes, GenerateInitError> {
+        generate_init(&self.p.params)
+    }
+
+    // Reads the segment's samples from disk and muxes them into a fMP4
+    // fragment. Returns None if the index is out of range.
+    pub async fn segment(&self, index: usize) -> Result<Option<Bytes>, VodSegmentError> {
+        use VodSegmentError::*;
+        let Some(seg) = self.p.segments.get(index) else {
+            return Ok(None);
+        };
+        let rec = &self.p.recs[seg.rec];
+
+        let meta = RecordingFile::open(&rec.meta_path, self.encryption_key.as_ref())
+            .await
+            .map_err(OpenFile)?;
+        let meta_size = meta.size();
+        let (_, mut samples) = read_meta(BufReader::new(meta), meta_size).await?;
+
+        // Retime the samples the same way as when the playlist was built.
+        let samples = samples
+            .get_mut(rec.samples.clone())
+            .ok_or(SampleTableChanged)?;
+        pad_durations(samples, rec.is_first.then_some(self.p.start))?;
+        last_video_sample(samples)?.duration = rec.last_duration;
+        let samples = samples.get(seg.samples.clone()).ok_or(SampleTableChanged)?;
+
+        let data = read_sample_data(&rec.mdat_path, self.encryption_key.as_ref(), samples).await?;
+        let (video, audio) = split_samples(self.p.start, seg.start, samples, &data)?;
+        Ok(Some(generate_part(
+            self.p.start,
+            Arc::new(video),
+            Arc::new(audio),
+        )?))
+    }
+}
+
+// Samples are stored contiguously in the mdat file.
+async fn read_sample_data(
+    mdat_path: &Path,
+    encryption_key: Option<&EncryptionKey>,
+    samples: &[Sample],
+) -> Result<Bytes, VodSegmentError> {
+    use VodSegmentError::*;
+    let (Some(first), Some(last)) = (samples.first(), samples.last()) else {
+        return Ok(Bytes::new());
+    };
+    let start = u64::from(first.data_offset);
+    let end = u64::from(last.data_offset) + u64::from(last.data_size);
+    let size = usize::try_from(end.saturating_sub(start)).expect("usize fit u64");
+
+    let mut file = RecordingFile::open(mdat_path, encryption_key)
+        .await
+        .map_err(OpenFile)?;
+    file.seek(SeekFrom::Start(start))
+        .await
+        .map_err(ReadSamples)?;
+    let mut buf = vec![0; size];
+    file.read_exact(&mut buf).await.map_err(ReadSamples)?;
+    Ok(Bytes::from(buf))
+}
+
+// Shifts the samples so that the first video sample is decoded at
+// `seg_start` and splits them into video and audio samples.
+fn split_samples(
+    playlist_start: UnixH264,
+    seg_start: DurationH264,
+    samples: &[Sample],
+    data: &Bytes,
+) -> Result<(Vec<VideoSample>, Vec<AudioSample>), VodSegmentError> {
+    use VodSegmentError::*;
+    let first_dts = samples
+        .iter()
+        .find(|v| !v.audio)
+        .ok_or(Dts)?
+        .dts()
+        .ok_or(Dts)?;
+    let shift = playlist_start + UnixH264::from(seg_start) - first_dts;
+    let data_start = samples.first().map_or(0, |v| v.data_offset);
+
+    let mut video = Vec::new();
+    let mut audio = Vec::new();
+    for sample in samples {
+        let offset = usize::try_from(sample.data_offset - data_start).expect("usize fit u32");
+        let size = usize::try_from(sample.data_size).expect("usize fit u32");
+        let pts = sample.pts + shift;
+        if sample.audio {
+            // Audio before the start of the playlist can't be represented.
+            if pts < playlist_start {
+                continue;
+            }
+            audio.push(AudioSample {
+                pts,
+                data: data.slice(offset..offset + size),
+                duration: sample.duration,
+            });
+        } else {
+            video.push(VideoSample {
+                pts,
+                dts_offset: sample.dts_offset,
+                avcc: Arc::new(PaddedBytes::new(data[offset..offset + size].to_vec())),
+                random_access_present: sample.random_access_present,
+                duration: sample.duration,
+            });
+        }
+    }
+    Ok((video, audio))
+}
+
+fn div_up(a: i64, b: i64) -> i64 {
+    (a + (b - 1)) / b
+}
+
+// Builds the segment index one recording at a time. The duration of the
+// last video sample in each recording is known once the next one is pushed.
+struct PlaylistBuilder {
+    start: UnixH264,
+    params: TrackParameters,
+    recs: Vec<PlaylistRec>,
+    segments: Vec<Segment>,
+
+    // Decode time of the last video sample.
+    duration: DurationH264,
+    last_dts: UnixH264,
+    last_pts: UnixH264,
+}
+
+impl RecordingSink for PlaylistBuilder {
+    fn new(start: UnixNano, params: TrackParameters) -> Self {
+        Self {
+            start: start.into(),
+            params,
+            recs: Vec::new(),
+            segments: Vec::new(),
+            duration: DurationH264::new(0),
+            last_dts: start.into(),
+            last_pts: start.into(),
+        }
+    }
+
+    fn params(&self) -> &TrackParameters {
+        &self.params
+    }
+
+    fn last_dts(&self) -> UnixH264 {
+        self.last_dts
+    }
+
+    fn push_recording(
+        &mut self,
+        rec: SelectedRecording,
+        mut samples: Vec<Sample>,
+    ) -> Result<(), CreateVodReaderError> {
+        use CreateVodReaderError::*;
+        let is_first = self.recs.is_empty();
+        let (first_dts, last_dts) = pad_durations(&mut samples, is_first.then_some(self.start))?;
+        if !is_first {
+            self.end_recording(clamp_duration(first_dts - self.last_dts))?;
+        }
+
+        let rec_index = self.recs.len();
+        let last_video = samples
+            .iter()
+            .rposition(|v| !v.audio)
+            .ok_or(NoVideoSample)?;
+        let mut seg: Option<Segment> = None;
+        for (i, sample) in samples.iter().enumerate() {
+            let split = !sample.audio
+                && sample.random_access_present
+                && seg
+                    .as_ref()
+                    .map_or(true, |v| v.duration >= SEGMENT_DURATION);
+            if split {
+                self.segments.extend(seg.take());
+                seg = Some(Segment {
+                    rec: rec_index,
+                    samples: i..i,
+                    start: self.duration,
+                    duration: DurationH264::new(0),
+                });
+            }
+            // Audio before the first keyframe is dropped.
+            let Some(seg) = seg.as_mut() else {
+                continue;
+            };
+            seg.samples.end = i + 1;
+
+            // The last duration is added by `end_recording`.
+            if !sample.audio && i != last_video {
+                seg.duration = seg.duration.checked_add(sample.duration).ok_or(Add)?;
+                self.duration = self.duration.checked_add(sample.duration).ok_or(Add)?;
+            }
+        }
+        self.segments.extend(seg);
+
+        self.last_dts = last_dts;
+        self.last_pts = samples[last_video].pts;
+        self.recs.push(PlaylistRec {
+            meta_path: rec.meta_path,
+            mdat_path: rec.mdat_path,
+            samples: rec.samples,
+            is_first,
+            last_duration: DurationH264::new(0),
+        });
+        Ok(())
+    }
+}
+
+impl PlaylistBuilder {
+    fn end_recording(&mut self, last_duration: DurationH264) -> Result<(), CreateVodReaderError> {
+        use CreateVodReaderError::*;
+        let rec = self.recs.last_mut().expect("should have a recording");
+        rec.last_duration = last_duration;
+        let seg = self.segments.last_mut().ok_or(NoVideoSample)?;
+        seg.duration = seg.duration.checked_add(last_duration).ok_or(Add)?;
+        self.duration = self.duration.checked_add(last_duration).ok_or(Add)?;
+        Ok(())
+    }
+
+    fn finish(mut self, end: UnixNano) -> Result<PlaylistResult, CreateVodReaderError> {
+        self.end_recording(clamp_duration(UnixH264::from(end) - self.last_pts))?;
+        let params = common::TrackParameters {
+            width: self.params.width,
+            height: self.params.height,
+            video_codec: self.params.video_codec,
+            // The codec strings are only used in multivariant playlists.
+            codec: String::new(),
+            extra_data: self.params.extra_data,
+            audio: self.params.audio.map(|sample_entry| AudioParameters {
+                codec: String::new(),
+                sample_entry,
+            }),
+        };
+        Ok(PlaylistResult {
+            start: self.start,
+            params,
+            recs: self.recs,
+            segments: self.segments,
+        })
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use common::time::DtsOffset;
+    use pretty_assertions::assert_eq;
+
+    fn video(pts: i64, duration: i64, random_access_present: bool) -> Sample {
+        Sample {
+            random_access_present,
+            audio: false,
+            pts: UnixH264::new(pts),
+            dts_offset: DtsOffset::new(0),
+            duration: DurationH264::new(duration),
+            data_size: 1,
+            data_offset: 0,
+        }
+    }
+
+    fn selected(samples: usize) -> SelectedRecording {
+        SelectedRecording {
+            meta_path: PathBuf::new(),
+            mdat_path: PathBuf::new(),
+            samples: 0..samples,
+        }
+    }
+
+    fn segments(p: &PlaylistResult) -> Vec<(usize, Range<usize>, i64, i64)> {
+        p.segments
+            .iter()
+            .map(|v| (v.rec, v.samples.clone(), *v.start, *v.duration))
+            .collect()
+    }
+
+    #[test]
+    fn test_playlist_builder() {
+        let s = H264_SECOND;
+        let params = TrackParameters {
+            width: 0,
+            height: 0,
+            video_codec: common::VideoCodec::H264,
+            extra_data: Vec::new(),
+            audio: None,
+        };
+        let mut b = PlaylistBuilder::new(UnixNano::new(0), params);
+        let rec1 = vec![
+            video(0, s, true),
+            video(s, s, false),
+            video(2 * s, s, false),
+            video(3 * s, s, false),
+            video(4 * s, s, true),
+            video(5 * s, s, true),
+        ];
+        b.push_recording(selected(rec1.len()), rec1).unwrap();
+        // Gap of 4 seconds.
+        let rec2 = vec![video(10 * s, s, true), video(11 * s, s, false)];
+        b.push_recording(selected(rec2.len()), rec2).unwrap();
+
+        let p = b.finish(UnixNano::new(13 * SECOND)).unwrap();
+        let want = vec![
+            (0, 0..4, 0, 4 * s),
+            (0, 4..6, 4 * s, 6 * s),
+            (1, 0..2, 10 * s, 3 * s),
+        ];
+        assert_eq!(want, segments(&p));
+        assert_eq!(DurationH264::new(5 * s), p.recs[0].last_duration);
+        assert_eq!(DurationH264::new(2 * s), p.recs[1].last_duration);
+    }
+
+    #[test]
+    fn test_playlist_builder_audio() {
+        let s = H264_SECOND;
+        let params = TrackParameters {
+            width: 0,
+            height: 0,
+            video_codec: common::VideoCodec::H264,
+            extra_data: Vec::new(),
+            audio: None,
+        };
+        let audio = |pts| Sample {
+            audio: true,
+            ..video(pts, s, false)
+        };
+
+        // Audio before the first keyframe isn't part of any segment.
+        let mut b = PlaylistBuilder::new(UnixNano::new(0), params.clone());
+        let rec = vec![audio(0), video(0, s, true), audio(s), video(s, s, false)];
+        b.push_recording(selected(rec.len()), rec).unwrap();
+        let p = b.finish(UnixNano::new(2 * SECOND)).unwrap();
+        assert_eq!(vec![(0, 1..4, 0, 2 * s)], segments(&p));
+
+        let mut b = PlaylistBuilder::new(UnixNano::new(0), params);
+        let rec = vec![audio(0), audio(s)];
+        assert!(matches!(
+            b.push_recording(selected(rec.len()), rec),
+            Err(CreateVodReaderError::NoVideoSample)
+        ));
+    }
+
+    #[test]
+    fn test_playlist() {
+        let s = H264_SECOND;
+        let playlist = VodPlaylist {
+            p: Arc::new(PlaylistResult {
+                start: UnixH264::new(0),
+                params: common::TrackParameters {
+                    width: 0,
+                    height: 0,
+                    video_codec: common::VideoCodec::H264,
+                    codec: String::new(),
+                    extra_data: Vec::new(),
+                    audio: None,
+                },
+                recs: Vec::new(),
+                segments: vec![
+                    Segment {
+                        rec: 0,
+                        samples: 0..0,
+                        start: DurationH264::new(0),
+                        duration: DurationH264::new(4 * s),
+                    },
+                    Segment {
+                        rec: 0,
+                        samples: 0..0,
+                        start: DurationH264::new(4 * s),
+                        duration: DurationH264::new(4 * s + s / 2),
+                    },
+                ],
+            }),
+            encryption_key: None,
+        };
+        let want = "#EXTM3U
+#EXT-X-VERSION:7
+#EXT-X-TARGETDURATION:5
+#EXT-X-MEDIA-SEQUENCE:0
+#EXT-X-PLAYLIST-TYPE:VOD
+#EXT-X-INDEPENDENT-SEGMENTS
+#EXT-X-MAP:URI=\"init.mp4?a=b\"
+#EXTINF:4.00000,
+0.mp4?a=b
+#EXTINF:4.50000,
+1.mp4?a=b
+#EXT-X-ENDLIST
+";
+        assert_eq!(want, playlist.playlist("a=b"));
+    }
+
+    #[test]
+    fn test_split_samples() {
+        let mut audio = video(2000, 10, false);
+        audio.audio = true;
+        audio.data_offset = 1;
+        let mut early_audio = audio.clone();
+        early_audio.pts = UnixH264::new(500);
+        early_audio.data_offset = 2;
+        let samples = vec![video(1000, 10, true), audio, early_audio];
+
+        let data = Bytes::from(vec![7, 8, 9]);
+        let (video, audio) =
+            split_samples(UnixH264::new(100), DurationH264::new(50), &samples, &data).unwrap();
+
+        assert_eq!(1, video.len());
+        assert_eq!(UnixH264::new(150), video[0].pts);
+        assert_eq!(7, video[0].avcc[0]);
+        // The early audio sample is dropped.
+        assert_eq!(1, audio.len());
+        assert_eq!(UnixH264::new(1150), audio[0].pts);
+        assert_eq!(Bytes::from(vec![8]), audio[0].data);
+    }
+}
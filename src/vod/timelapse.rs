@@ -0,0 +1,301 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+use common::{
+    recording::{RecordingId, RecordingIdError},
+    time::{DtsOffset, DurationH264, UnixH264, UnixNano, DAY, H264_TIMESCALE, SECOND},
+    MonitorId,
+};
+use recdb::{QueryRecordingsError, RecDb, RecDbQuery, RecordingFilter, RecordingResponse};
+use recording::{
+    generate_mp4, read_meta, GenerateMp4Error, ReadMetaError, RecordingFile, Sample,
+    TrackParameters,
+};
+use serde::Deserialize;
+use std::{
+    io::SeekFrom,
+    num::{NonZeroU32, NonZeroUsize},
+    path::PathBuf,
+};
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt, BufReader};
+
+pub const MAX_TIMELAPSE_DURATION: i64 = 31 * DAY;
+const MAX_FPS: u32 = 120;
+
+// Keeps the file size reasonable.
+const MAX_TIMELAPSE_SIZE: u64 = 3 * 1024 * 1024 * 1024;
+
+fn default_fps() -> NonZeroU32 {
+    NonZeroU32::new(30).expect("nonzero")
+}
+
+// A keyframe is sampled every `interval` seconds and played back at `fps`.
+// A day sampled every 24 seconds at 30 fps results in a two minute video.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Clone, Debug, Deserialize)]
+pub struct TimelapseOptions {
+    pub interval: NonZeroU32,
+
+    #[serde(default = "default_fps")]
+    pub fps: NonZeroU32,
+}
+
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Error)]
+pub enum TimelapseError {
+    #[error("end must be after start")]
+    NegativeDuration,
+
+    #[error("max timelapse duration is 31 days")]
+    MaxDuration,
+
+    #[error("max fps is {MAX_FPS}")]
+    MaxFps,
+
+    #[error("timelapse is larger than 3GB, increase the interval")]
+    TooLarge,
+
+    #[error("query recordings: {0}")]
+    QueryRecordings(#[from] QueryRecordingsError),
+
+    #[error("recording id: {0}")]
+    RecordingId(#[from] RecordingIdError),
+
+    #[error("open file: {0}")]
+    OpenFile(std::io::Error),
+
+    #[error("read meta: {0}")]
+    ReadMeta(#[from] ReadMetaError),
+
+    #[error("dts")]
+    Dts,
+
+    #[error("generate mp4: {0}")]
+    GenerateMp4(#[from] GenerateMp4Error),
+
+    #[error("read sample: {0}")]
+    ReadSample(std::io::Error),
+
+    #[error("write: {0}")]
+    Write(std::io::Error),
+}
+
+impl TimelapseOptions {
+    pub fn validate(&self, start: UnixNano, end: UnixNano) -> Result<(), TimelapseError> {
+        use TimelapseError::*;
+        if !start.before(end) {
+            return Err(NegativeDuration);
+        }
+        if (*end)
+            .checked_sub(*start)
+            .map_or(true, |v| v > MAX_TIMELAPSE_DURATION)
+        {
+            return Err(MaxDuration);
+        }
+        if self.fps.get() > MAX_FPS {
+            return Err(MaxFps);
+        }
+        Ok(())
+    }
+
+    fn frame_duration(&self) -> DurationH264 {
+        DurationH264::new(i64::from(H264_TIMESCALE / self.fps.get()))
+    }
+}
+
+// Keyframe from the mdat file at index `file` in the file list.
+struct Frame {
+    file: usize,
+    sample: Sample,
+}
+
+// Samples keyframes from the monitor's recordings and writes them as a
+// MP4 file without re-encoding. Recordings with different video parameters
+// than the first recording are skipped. Returns the number of bytes
+// written or None if there aren't any keyframes in the time range.
+pub async fn write_timelapse(
+    recdb: &RecDb,
+    monitor_id: &MonitorId,
+    start: UnixNano,
+    end: UnixNano,
+    opts: &TimelapseOptions,
+    out: &mut (dyn AsyncWrite + Unpin + Send + Sync),
+) -> Result<Option<u64>, TimelapseError> {
+    use TimelapseError::*;
+    opts.validate(start, end)?;
+
+    let Some((params, mdat_paths, frames)) =
+        sample_keyframes(recdb, monitor_id, start, end, opts).await?
+    else {
+        return Ok(None);
+    };
+
+    let data_size: u64 = frames.iter().map(|v| u64::from(v.sample.data_size)).sum();
+    if data_size > MAX_TIMELAPSE_SIZE {
+        return Err(TooLarge);
+    }
+
+    // Retime the keyframes to play back at `fps`.
+    let frame_duration = opts.frame_duration();
+    let start_time = UnixH264::from(start);
+    let samples: Vec<Sample> = frames
+        .iter()
+        .enumerate()
+        .map(|(i, frame)| Sample {
+            random_access_present: true,
+            audio: false,
+            pts: UnixH264::new(
+                *start_time + *frame_duration * i64::try_from(i).expect("i64 fit usize"),
+            ),
+            dts_offset: DtsOffset::new(0),
+            duration: frame_duration,
+            data_size: frame.sample.data_size,
+            data_offset: 0,
+        })
+        .collect();
+
+    let mut meta = Vec::new();
+    generate_mp4(&mut meta, start_time, samples.iter(), &params).await?;
+    out.write_all(&meta).await.map_err(Write)?;
+
+    let mut buf = Vec::new();
+    let mut open_file: Option<(usize, RecordingFile)> = None;
+    for frame in &frames {
+        if open_file.as_ref().map_or(true, |(i, _)| *i != frame.file) {
+            let file = RecordingFile::open(&mdat_paths[frame.file], recdb.encryption_key())
+                .await
+                .map_err(OpenFile)?;
+            open_file = Some((frame.file, file));
+        }
+        let (_, file) = open_file.as_mut().expect("file should be open");
+        let data_size = usize::try_from(frame.sample.data_size).expect("u32 fit usize");
+        buf.resize(data_size, 0);
+        file.seek(SeekFrom::Start(frame.sample.data_offset.into()))
+            .await
+            .map_err(ReadSample)?;
+        file.read_exact(&mut buf).await.map_err(ReadSample)?;
+        out.write_all(&buf).await.map_err(Write)?;
+    }
+    out.flush().await.map_err(Write)?;
+
+    let meta_size = u64::try_from(meta.len()).expect("u64 fit usize");
+    Ok(Some(meta_size + data_size))
+}
+
+type Keyframes = (TrackParameters, Vec<PathBuf>, Vec<Frame>);
+
+async fn sample_keyframes(
+    recdb: &RecDb,
+    monitor_id: &MonitorId,
+    start: UnixNano,
+    end: UnixNano,
+    opts: &TimelapseOptions,
+) -> Result<Option<Keyframes>, TimelapseError> {
+    let recordings = recdb
+        .recordings_by_query(&RecDbQuery {
+            recording_id: RecordingId::from_nanos(end, monitor_id)?,
+            end: None,
+            limit: NonZeroUsize::new(100_000).expect("nonzero"),
+            reverse: false,
+            monitors: vec![monitor_id.to_string()],
+            include_data: false,
+            filter: RecordingFilter {
+                time_from: Some(start),
+                time_to: Some(end),
+                ..Default::default()
+            },
+        })
+        .await?;
+
+    let interval = i64::from(opts.interval.get()) * SECOND;
+    let mut next = *start;
+    let mut params: Option<TrackParameters> = None;
+    let mut mdat_paths = Vec::new();
+    let mut frames = Vec::new();
+
+    // Oldest first.
+    for rec in recordings.iter().rev() {
+        let RecordingResponse::Finalized(rec) = rec else {
+            continue;
+        };
+        let Some(meta_path) = recdb.recording_file_by_ext(&rec.id, "meta").await else {
+            continue;
+        };
+        let Some(mdat_path) = recdb.recording_file_by_ext(&rec.id, "mdat").await else {
+            continue;
+        };
+
+        let meta = RecordingFile::open(&meta_path, recdb.encryption_key())
+            .await
+            .map_err(TimelapseError::OpenFile)?;
+        let meta_size = meta.size();
+        let (header, samples) = read_meta(BufReader::new(meta), meta_size).await?;
+
+        let mut rec_params = header.params();
+        rec_params.audio = None;
+        // The keyframes are decoded with the parameters of the first recording.
+        if params.as_ref().is_some_and(|v| *v != rec_params) {
+            continue;
+        }
+
+        let file = mdat_paths.len();
+        let mut rec_frames = Vec::new();
+        for sample in samples {
+            if sample.audio || !sample.random_access_present {
+                continue;
+            }
+            let dts = UnixNano::from(sample.dts().ok_or(TimelapseError::Dts)?);
+            if *dts < next || !dts.before(end) {
+                continue;
+            }
+            // Align to the interval to avoid drift.
+            next = *start + ((*dts - *start) / interval + 1) * interval;
+            rec_frames.push(Frame { file, sample });
+        }
+        if rec_frames.is_empty() {
+            continue;
+        }
+        params = Some(rec_params);
+        mdat_paths.push(mdat_path);
+        frames.extend(rec_frames);
+    }
+
+    let Some(params) = params else {
+        return Ok(None);
+    };
+    Ok(Some((params, mdat_paths, frames)))
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use common::time::HOUR;
+    use pretty_assertions::assert_eq;
+    use test_case::test_case;
+
+    fn opts(interval: u32, fps: u32) -> TimelapseOptions {
+        TimelapseOptions {
+            interval: NonZeroU32::new(interval).unwrap(),
+            fps: NonZeroU32::new(fps).unwrap(),
+        }
+    }
+
+    #[test_case(0, HOUR, 30, None; "ok")]
+    #[test_case(HOUR, HOUR, 30, Some("end must be after start"); "negative duration")]
+    #[test_case(0, 32 * DAY, 30, Some("max timelapse duration is 31 days"); "max duration")]
+    #[test_case(0, HOUR, 121, Some("max fps is 120"); "max fps")]
+    fn test_validate(start: i64, end: i64, fps: u32, want: Option<&str>) {
+        let got = opts(1, fps)
+            .validate(UnixNano::new(start), UnixNano::new(end))
+            .err()
+            .map(|e| e.to_string());
+        assert_eq!(want.map(ToOwned::to_owned), got);
+    }
+
+    #[test]
+    fn test_frame_duration() {
+        assert_eq!(DurationH264::new(3000), opts(1, 30).frame_duration());
+        assert_eq!(DurationH264::new(3600), opts(1, 25).frame_duration());
+    }
+}
@@ -1,36 +1,58 @@
 // SPDX-License-Identifier: GPL-2.0-or-later
 
 mod cache;
+mod export;
+mod playlist;
+mod timelapse;
+mod zip;
 
+use cache::MemSize;
 pub use cache::VodCache;
 use common::{
     recording::{RecordingId, RecordingIdError},
-    time::{Duration, UnixH264, UnixNano, HOUR},
-    MonitorId,
+    time::{Duration, DurationH264, UnixH264, UnixNano, DAY},
+    EncryptionKey, MonitorId,
+};
+use csv::deserialize_csv_option;
+pub use export::{
+    CreateExportError, ExportError, ExportId, ExportManager, ExportRequest, ExportState,
+    ExportStatus,
 };
 use pin_project::pin_project;
-use recdb::{CrawlerError, RecDb, RecDbQuery, RecordingResponse};
-use recording::{generate_mp4, read_meta, GenerateMp4Error, ReadMetaError, Sample};
+pub use playlist::{VodPlaylist, VodSegmentError};
+use recdb::{QueryRecordingsError, RecDb, RecDbQuery, RecordingFilter, RecordingResponse};
+use recording::{
+    read_meta, GenerateMp4Error, Mp4Generator, ReadMetaError, RecordingFile, Sample,
+    TrackParameters,
+};
 use serde::Deserialize;
 use std::{
     future::Future,
     io::SeekFrom,
     num::NonZeroUsize,
+    ops::Range,
     path::PathBuf,
-    pin::{pin, Pin},
+    pin::Pin,
     sync::Arc,
     task::{Context, Poll},
 };
 use thiserror::Error;
+pub use timelapse::{write_timelapse, TimelapseError, TimelapseOptions, MAX_TIMELAPSE_DURATION};
 use tokio::{
     io::{AsyncRead, AsyncSeek, BufReader},
     task::JoinHandle,
 };
 
+const MAX_VOD_DURATION: i64 = 7 * DAY;
+
+// Largest duration that fits in the 32 bit sample duration.
+const MAX_SAMPLE_DURATION: DurationH264 = DurationH264::new(4_294_967_295);
+
 #[derive(Clone, Deserialize, Hash, PartialEq, Eq)]
 pub struct VodQuery {
-    #[serde(rename = "monitor-id")]
-    pub monitor_id: MonitorId,
+    // Recordings from multiple monitors are played back in order.
+    #[serde(rename = "monitor-id", deserialize_with = "deserialize_csv_option")]
+    pub monitor_ids: Vec<MonitorId>,
     pub start: UnixNano,
     pub end: UnixNano,
 
@@ -46,10 +68,17 @@ struct QueryResult {
     recs: Vec<Rec>,
 }
 
+impl MemSize for QueryResult {
+    fn mem_size(&self) -> usize {
+        self.meta.len() + self.recs.len() * std::mem::size_of::<Rec>()
+    }
+}
+
 #[pin_project]
 #[derive(Debug)]
 pub struct VodReader {
     r: Arc<QueryResult>,
+    encryption_key: Option<EncryptionKey>,
 
     #[pin]
     file_state: FileState,
@@ -58,14 +87,17 @@ pub struct VodReader {
 
 #[derive(Debug, Error)]
 pub enum CreateVodReaderError {
+    #[error("no monitors selected")]
+    NoMonitors,
+
     #[error("duration is negative")]
     NegativeDuration,
 
-    #[error("max duration is 12 hours, it's easy to extend if anyone wants it")]
+    #[error("max duration is 7 days")]
     MaxDuration,
 
     #[error("query recordings: {0}")]
-    QueryRecordings(#[from] CrawlerError),
+    QueryRecordings(#[from] QueryRecordingsError),
 
     #[error("sub")]
     Sub,
@@ -76,14 +108,11 @@ pub enum CreateVodReaderError {
     #[error("parse recording id: {0}")]
     ParseRecordingId(#[from] RecordingIdError),
 
-    #[error("read metadata: {0}")]
-    Metadata(std::io::Error),
-
     #[error("open file: {0}")]
     OpenFile(std::io::Error),
 
-    #[error("mismatched params")]
-    MismatchedParams,
+    #[error("recording {} has different track parameters than the previous recordings", .0.as_str())]
+    MismatchedParams(RecordingId),
 
     #[error("read meta: {0}")]
     ReadMeta(#[from] ReadMetaError),
@@ -94,6 +123,9 @@ pub enum CreateVodReaderError {
     #[error("end")]
     End,
 
+    #[error("no video sample")]
+    NoVideoSample,
+
     #[error("generate mp4: {0}")]
     GenerateMp4(#[from] GenerateMp4Error),
 }
@@ -118,6 +150,7 @@ impl VodReader {
 
         Ok(Some(Self {
             r,
+            encryption_key: recdb.encryption_key().cloned(),
             file_state: FileState::Close(CloseReadState::State1),
             pos: 0,
         }))
@@ -128,177 +161,336 @@ impl VodReader {
     }
 }
 
-#[allow(clippy::too_many_lines)]
 async fn execute_query(
     recdb: &RecDb,
     q: &VodQuery,
 ) -> Result<Option<Arc<QueryResult>>, CreateVodReaderError> {
+    let Some(builder) = query_samples::<ResultBuilder>(recdb, q).await? else {
+        return Ok(None);
+    };
+    Ok(Some(Arc::new(builder.finish(q.end).await?)))
+}
+
+// Recording with the range of its sample table that matched the query.
+struct SelectedRecording {
+    meta_path: PathBuf,
+    mdat_path: PathBuf,
+    samples: Range<usize>,
+}
+
+// Receives the selected samples of each recording in order.
+trait RecordingSink: Sized {
+    fn new(start: UnixNano, params: TrackParameters) -> Self;
+
+    fn params(&self) -> &TrackParameters;
+
+    // DTS of the last video sample.
+    fn last_dts(&self) -> UnixH264;
+
+    // The samples start with a video sample.
+    fn push_recording(
+        &mut self,
+        rec: SelectedRecording,
+        samples: Vec<Sample>,
+    ) -> Result<(), CreateVodReaderError>;
+}
+
+// Returns None if there aren't any samples in the time range.
+async fn query_samples<S: RecordingSink>(
+    recdb: &RecDb,
+    q: &VodQuery,
+) -> Result<Option<S>, CreateVodReaderError> {
     use CreateVodReaderError::*;
 
+    if q.monitor_ids.is_empty() {
+        return Err(NoMonitors);
+    }
     let duration = q.end - q.start;
     if duration.is_negative() {
         return Err(NegativeDuration);
     }
-    if duration > UnixNano::new(HOUR * 12) {
+    if duration > UnixNano::new(MAX_VOD_DURATION) {
         return Err(MaxDuration);
     }
 
-    // Find first recording by seeking backwards.
-    let end_minus_1 = q
-        .start
+    let mut recordings = Vec::new();
+    for monitor_id in &q.monitor_ids {
+        recordings.extend(query_recordings(recdb, monitor_id, q.start, q.end).await?);
+    }
+    // Oldest first, recordings from different monitors are interleaved.
+    recordings.sort_by(|a, b| a.id().as_str().cmp(b.id().as_str()));
+
+    let mut sink: Option<S> = None;
+    for rec in &recordings {
+        let RecordingResponse::Finalized(rec) = rec else {
+            continue;
+        };
+
+        let Some(meta_path) = recdb.recording_file_by_ext(&rec.id, "meta").await else {
+            continue;
+        };
+        let Some(mdat_path) = recdb.recording_file_by_ext(&rec.id, "mdat").await else {
+            continue;
+        };
+
+        let meta = RecordingFile::open(&meta_path, recdb.encryption_key())
+            .await
+            .map_err(OpenFile)?;
+        let meta_size = meta.size();
+        let mut meta = BufReader::new(meta);
+
+        let (header, mut samples) = read_meta(&mut meta, meta_size).await?;
+        // Recordings with different track parameters can't be stitched together.
+        if sink
+            .as_ref()
+            .is_some_and(|v| *v.params() != header.params())
+        {
+            return Err(MismatchedParams(rec.id.clone()));
+        }
+
+        // Samples that overlap the previous recording are skipped,
+        // this can only happen if there are multiple monitors.
+        let after = sink.as_ref().map(RecordingSink::last_dts);
+        let range = select_samples(&samples, q, after)?;
+        if range.is_empty() {
+            continue;
+        }
+        samples.truncate(range.end);
+        samples.drain(..range.start);
+
+        let rec = SelectedRecording {
+            meta_path,
+            mdat_path,
+            samples: range,
+        };
+        sink.get_or_insert_with(|| S::new(q.start, header.params()))
+            .push_recording(rec, samples)?;
+    }
+    Ok(sink)
+}
+
+// Returns the recordings that overlap the time range.
+async fn query_recordings(
+    recdb: &RecDb,
+    monitor_id: &MonitorId,
+    start: UnixNano,
+    end: UnixNano,
+) -> Result<Vec<RecordingResponse>, CreateVodReaderError> {
+    use CreateVodReaderError::*;
+
+    // Find first recording by seeking backwards. Recording ids
+    // can't be before the epoch.
+    let end_minus_1 = start
         .checked_sub(Duration::from_secs(1).into())
-        .ok_or(Sub)?;
+        .ok_or(Sub)?
+        .max(UnixNano::new(0));
     let mut recordings = recdb
         .recordings_by_query(&RecDbQuery {
-            recording_id: RecordingId::from_nanos(end_minus_1, &q.monitor_id)?,
+            recording_id: RecordingId::from_nanos(end_minus_1, monitor_id)?,
             end: None,
             limit: NonZeroUsize::new(1).expect("nonzero"),
             reverse: false,
-            monitors: vec![q.monitor_id.to_string()],
+            monitors: vec![monitor_id.to_string()],
             include_data: false,
+            filter: RecordingFilter::default(),
         })
         .await?;
 
     let first_rec_id = match recordings.first() {
         Some(v) => v.id().clone(),
-        None => RecordingId::zero(&q.monitor_id),
+        None => RecordingId::zero(monitor_id),
     };
 
     // Find all matching recorings by querying from the first recording.
-    let end_plus_1 = q
-        .end
-        .checked_add(Duration::from_secs(1).into())
-        .ok_or(Add)?;
+    let end_plus_1 = end.checked_add(Duration::from_secs(1).into()).ok_or(Add)?;
     recordings.extend(
         recdb
             .recordings_by_query(&RecDbQuery {
                 recording_id: first_rec_id.clone(),
-                end: Some(RecordingId::from_nanos(end_plus_1, &q.monitor_id)?),
+                end: Some(RecordingId::from_nanos(end_plus_1, monitor_id)?),
                 limit: NonZeroUsize::new(100_000).expect("nonzero"),
                 reverse: true,
-                monitors: vec![q.monitor_id.to_string()],
+                monitors: vec![monitor_id.to_string()],
                 include_data: false,
+                filter: RecordingFilter::default(),
             })
             .await?,
     );
+    Ok(recordings)
+}
 
-    if recordings.is_empty() {
-        return Ok(None);
+// Returns the range from the first IDR to the last video sample within
+// the query, audio samples in between are included to keep the data
+// contiguous. Samples with a DTS before or equal to `after` are excluded.
+fn select_samples(
+    samples: &[Sample],
+    q: &VodQuery,
+    after: Option<UnixH264>,
+) -> Result<Range<usize>, CreateVodReaderError> {
+    use CreateVodReaderError::*;
+    let keep = samples
+        .iter()
+        .map(|s| {
+            let dts = s.dts().ok_or(Dts)?;
+            let end = UnixNano::from(s.end().ok_or(End)?);
+            Ok(q.start <= UnixNano::from(dts)
+                && end <= q.end
+                && after.map_or(true, |after| after < dts))
+        })
+        // I don't like fallible iterators.
+        .collect::<Result<Vec<bool>, CreateVodReaderError>>()?;
+
+    let first = samples
+        .iter()
+        .zip(&keep)
+        .position(|(v, keep)| *keep && !v.audio && v.random_access_present);
+    let last = samples
+        .iter()
+        .zip(&keep)
+        .rposition(|(v, keep)| *keep && !v.audio);
+    Ok(match (first, last) {
+        (Some(first), Some(last)) if first <= last => first..last + 1,
+        _ => 0..0,
+    })
+}
+
+// Moves the first video sample to `start` if set and pads the video
+// sample durations to fill any gaps. The duration of the last video sample
+// is left unchanged. Returns the DTS of the first and last video samples.
+fn pad_durations(
+    samples: &mut [Sample],
+    start: Option<UnixH264>,
+) -> Result<(UnixH264, UnixH264), CreateVodReaderError> {
+    use CreateVodReaderError::*;
+    let mut video: Vec<_> = samples.iter_mut().filter(|v| !v.audio).collect();
+    if let Some(start) = start {
+        let first = video.first_mut().ok_or(NoVideoSample)?;
+        first.pts = start;
+    }
+    for i in 1..video.len() {
+        let s0_dts = video[i - 1].dts().ok_or(Dts)?;
+        let s1_dts = video[i].dts().ok_or(Dts)?;
+        video[i - 1].duration = clamp_duration(s1_dts - s0_dts);
     }
+    let first_dts = video.first().ok_or(NoVideoSample)?.dts().ok_or(Dts)?;
+    let last_dts = video.last().ok_or(NoVideoSample)?.dts().ok_or(Dts)?;
+    Ok((first_dts, last_dts))
+}
 
-    let mut recs = Vec::new();
-    let mut params = None;
+fn last_video_sample(samples: &mut [Sample]) -> Result<&mut Sample, CreateVodReaderError> {
+    samples
+        .iter_mut()
+        .rfind(|v| !v.audio)
+        .ok_or(CreateVodReaderError::NoVideoSample)
+}
 
-    for rec in &recordings {
-        let RecordingResponse::Finalized(rec) = rec else {
-            continue;
-        };
+// Builds the query result one recording at a time, only the sample
+// tables are kept in memory. The latest recording is held back until
+// the duration of its last video sample is known.
+struct ResultBuilder {
+    start: UnixH264,
+    params: TrackParameters,
+    mp4: Mp4Generator,
+    recs: Vec<Rec>,
+    pending: Option<(Rec, Vec<Sample>)>,
 
-        let Some(meta_path) = recdb.recording_file_by_ext(&rec.id, "meta").await else {
-            continue;
-        };
-        let Some(mdat_path) = recdb.recording_file_by_ext(&rec.id, "mdat").await else {
-            continue;
-        };
+    // DTS of the last video sample.
+    last_dts: UnixH264,
+}
 
-        let meta_size = tokio::fs::metadata(&meta_path)
-            .await
-            .map_err(Metadata)?
-            .len();
-
-        let mut meta = BufReader::new(
-            tokio::fs::OpenOptions::new()
-                .read(true)
-                .open(meta_path)
-                .await
-                .map_err(OpenFile)?,
-        );
+impl RecordingSink for ResultBuilder {
+    fn new(start: UnixNano, params: TrackParameters) -> Self {
+        Self {
+            start: start.into(),
+            mp4: Mp4Generator::new(start.into(), params.clone()),
+            params,
+            recs: Vec::new(),
+            pending: None,
+            last_dts: start.into(),
+        }
+    }
 
-        let (header, samples) = read_meta(&mut meta, meta_size).await?;
-        params = Some(header.params());
+    fn params(&self) -> &TrackParameters {
+        &self.params
+    }
 
-        let samples: Vec<_> = samples
-            .into_iter()
-            .map(|s| {
-                let dts = UnixNano::from(s.dts().ok_or(Dts)?);
-                let end = UnixNano::from(s.end().ok_or(End)?);
-                Ok((s, q.start <= dts && end <= q.end))
-            })
-            // I don't like fallible iterators.
-            .collect::<Result<Vec<(Sample, bool)>, CreateVodReaderError>>()?
-            .into_iter()
-            .filter_map(|(v, keep)| keep.then_some(v))
-            // Skip until first IDR.
-            .skip_while(|v| !v.random_access_present)
-            .collect();
-
-        if let Some(first) = samples.first() {
-            let data_start = usize::try_from(first.data_offset).expect("usize fit u32");
-            let data_size: usize = samples
-                .iter()
-                .map(|v| usize::try_from(v.data_size).expect("u32 fit usize"))
-                .sum();
-
-            recs.push(RecPartWithSamples {
-                rec: Rec {
-                    mdat_path: mdat_path.clone(),
-                    data_start,
-                    size: data_size,
-                    start: 0,
-                    end: 0,
-                },
-                samples: samples.clone(),
-            });
-        }
+    fn last_dts(&self) -> UnixH264 {
+        self.last_dts
     }
 
-    let mut samples: Vec<_> = recs.iter_mut().flat_map(|v| &mut v.samples).collect();
+    fn push_recording(
+        &mut self,
+        rec: SelectedRecording,
+        mut samples: Vec<Sample>,
+    ) -> Result<(), CreateVodReaderError> {
+        use CreateVodReaderError::*;
+        // Shift first video sample to start time.
+        let start = self.pending.is_none().then_some(self.start);
+        let (first_dts, last_dts) = pad_durations(&mut samples, start)?;
+        self.last_dts = last_dts;
+
+        let data_start = usize::try_from(samples[0].data_offset).expect("usize fit u32");
+        let data_size: usize = samples
+            .iter()
+            .map(|v| usize::try_from(v.data_size).expect("u32 fit usize"))
+            .sum();
+        let rec = Rec {
+            mdat_path: rec.mdat_path,
+            data_start,
+            size: data_size,
+            start: 0,
+            end: 0,
+        };
+
+        if let Some((prev_rec, mut prev_samples)) = self.pending.take() {
+            let last = last_video_sample(&mut prev_samples)?;
+            last.duration = clamp_duration(first_dts - last.dts().ok_or(Dts)?);
+            self.push_samples(prev_rec, &prev_samples)?;
+        }
+        self.pending = Some((rec, samples));
+        Ok(())
+    }
+}
 
-    // Shift first sample to start time.
-    let Some(first) = samples.first_mut() else {
-        return Ok(None);
-    };
-    first.pts = q.start.into();
-
-    // Pad durations to fill any gaps.
-    for i in 1..samples.len() {
-        let s0_dts = samples[i - 1].dts().ok_or(Dts)?;
-        let s1_dts = samples[i].dts().ok_or(Dts)?;
-        let diff = s1_dts - s0_dts;
-        samples[i - 1].duration = diff.into();
+impl ResultBuilder {
+    fn push_samples(&mut self, rec: Rec, samples: &[Sample]) -> Result<(), GenerateMp4Error> {
+        for sample in samples {
+            self.mp4.push(sample)?;
+        }
+        self.recs.push(rec);
+        Ok(())
     }
-    let last = samples.last_mut().expect("should exist");
-    last.duration = (UnixH264::from(q.end) - last.pts).into();
-    assert_eq!(last.end().ok_or(End)?, q.end.into());
-
-    let mut meta = Vec::new();
-    let mdat_size = usize::try_from(
-        generate_mp4(
-            &mut meta,
-            q.start.into(),
-            recs.iter().flat_map(|v| &v.samples),
-            &params.expect("should be Some"),
-        )
-        .await?,
-    )
-    .expect("u32 fit usize");
-    let meta_size = meta.len();
-
-    // Calculate recordings offsets.
-    let mut pos = meta_size;
-    let mut recs: Vec<_> = recs.into_iter().map(|v| v.rec).collect();
-    for rec in &mut recs {
-        rec.start = pos;
-        rec.end = pos + rec.size;
-        pos += rec.size;
+
+    async fn finish(mut self, end: UnixNano) -> Result<QueryResult, CreateVodReaderError> {
+        let (rec, mut samples) = self.pending.take().expect("should have a recording");
+        let last = last_video_sample(&mut samples)?;
+        last.duration = clamp_duration(UnixH264::from(end) - last.pts);
+        self.push_samples(rec, &samples)?;
+
+        let mut meta = Vec::new();
+        let mdat_size = usize::try_from(self.mp4.finish(&mut meta).await?).expect("usize fit u64");
+        let meta_size = meta.len();
+
+        // Calculate recordings offsets.
+        let mut pos = meta_size;
+        for rec in &mut self.recs {
+            rec.start = pos;
+            rec.end = pos + rec.size;
+            pos += rec.size;
+        }
+
+        Ok(QueryResult {
+            meta,
+            meta_size,
+            size: meta_size + mdat_size,
+            recs: self.recs,
+        })
     }
+}
 
-    Ok(Some(Arc::new(QueryResult {
-        meta: meta.clone(),
-        meta_size: meta.len(),
-        size: meta_size + mdat_size,
-        recs,
-    })))
+// Sample durations are 32 bit, gaps longer than 13 hours are shortened.
+fn clamp_duration(v: UnixH264) -> DurationH264 {
+    DurationH264::from(v).min(MAX_SAMPLE_DURATION)
 }
 
 #[allow(clippy::too_many_lines)]
@@ -324,10 +516,11 @@ impl AsyncRead for VodReader {
                         }
 
                         // Find recording at the position.
-                        let Some(i) = this.r.recs.iter().position(|rec| *this.pos < rec.end) else {
+                        let i = this.r.recs.partition_point(|rec| rec.end <= *this.pos);
+                        if i == this.r.recs.len() {
                             // EOF.
                             return Poll::Ready(Ok(()));
-                        };
+                        }
                         let rec = &this.r.recs[i];
                         assert!(rec.start <= *this.pos);
 
@@ -340,8 +533,10 @@ impl AsyncRead for VodReader {
                     }
                     CloseReadStateProj::State4(i, file_pos, amt) => {
                         let mdat_path = this.r.recs[*i].mdat_path.clone();
+                        let key = this.encryption_key.clone();
                         let open_fut = tokio::task::spawn_blocking(move || {
-                            std::fs::OpenOptions::new().read(true).open(mdat_path)
+                            let file = std::fs::OpenOptions::new().read(true).open(mdat_path)?;
+                            RecordingFile::from_std(file, key.as_ref())
                         });
                         *read_state = CloseReadState::State5(open_fut, *i, *file_pos, *amt);
                         continue;
@@ -350,7 +545,7 @@ impl AsyncRead for VodReader {
                         //
                         match open_fut.poll(cx) {
                             Poll::Ready(res) => {
-                                let file = tokio::fs::File::from_std(res??);
+                                let file = res??;
                                 if *file_pos != 0 {
                                     let state = OpenReadState::State6(*file_pos, *amt);
                                     *this.file_state = FileState::Open(state, file, 0, *i);
@@ -379,11 +574,11 @@ impl AsyncRead for VodReader {
                             }
 
                             // Find recording at the position.
-                            let Some(i) = this.r.recs.iter().position(|rec| *this.pos < rec.end)
-                            else {
+                            let i = this.r.recs.partition_point(|rec| rec.end <= *this.pos);
+                            if i == this.r.recs.len() {
                                 // EOF.
                                 return Poll::Ready(Ok(()));
-                            };
+                            }
                             let rec = &this.r.recs[i];
                             assert!(rec.start <= *this.pos);
 
@@ -465,7 +660,7 @@ impl AsyncRead for VodReader {
 #[pin_project(project = FileStateProj)]
 enum FileState {
     Close(#[pin] CloseReadState),
-    Open(#[pin] OpenReadState, #[pin] tokio::fs::File, usize, usize),
+    Open(#[pin] OpenReadState, #[pin] RecordingFile, usize, usize),
 }
 
 #[derive(Debug)]
@@ -486,7 +681,7 @@ enum OpenReadState {
     State7(usize),
 }
 
-type OpenFut = JoinHandle<Result<std::fs::File, std::io::Error>>;
+type OpenFut = JoinHandle<Result<RecordingFile, std::io::Error>>;
 
 impl AsyncSeek for VodReader {
     fn start_seek(mut self: Pin<&mut Self>, position: SeekFrom) -> std::io::Result<()> {
@@ -580,11 +775,6 @@ impl Seek for VodReader {
 }
 */
 
-struct RecPartWithSamples {
-    rec: Rec,
-    samples: Vec<Sample>,
-}
-
 #[derive(Debug, PartialEq, Eq)]
 struct Rec {
     mdat_path: PathBuf,
@@ -601,8 +791,8 @@ mod tests {
     use bytesize::ByteSize;
     use common::{
         recording::RecordingData,
-        time::{DtsOffset, DurationH264, UnixH264, UnixNano, HOUR, MINUTE, SECOND},
-        DummyLogger, PaddedBytes, VideoSample,
+        time::{DtsOffset, DurationH264, UnixH264, UnixNano, MINUTE, SECOND},
+        DummyLogger, PaddedBytes, VideoCodec, VideoSample,
     };
     use pretty_assertions::assert_eq;
     use pretty_hex::pretty_hex;
@@ -620,7 +810,7 @@ mod tests {
         let (_tmp_dir, rec_db) = single_recording(start_time).await;
 
         let query = VodQuery {
-            monitor_id: "x".to_owned().try_into().unwrap(),
+            monitor_ids: vec!["x".to_owned().try_into().unwrap()],
             start: start_time.into(),
             end: UnixNano::from(start_time + UnixH264::new(7)) + UnixNano::new(1),
             cache_id: 0,
@@ -690,7 +880,7 @@ mod tests {
         let (_tmp_dir, rec_db) = single_recording(start_time).await;
 
         let query = VodQuery {
-            monitor_id: "x".to_owned().try_into().unwrap(),
+            monitor_ids: vec!["x".to_owned().try_into().unwrap()],
             start: (start_time + UnixH264::new(4)).into(), // Second sample.
             end: UnixNano::from(start_time + UnixH264::new(7)) + UnixNano::new(1),
             cache_id: 0,
@@ -757,7 +947,7 @@ mod tests {
         let (_tmp_dir, rec_db) = single_recording(start_time).await;
 
         let query = VodQuery {
-            monitor_id: "x".to_owned().try_into().unwrap(),
+            monitor_ids: vec!["x".to_owned().try_into().unwrap()],
             start: (start_time + UnixH264::new(5)).into(), // Third sample.
             end: (start_time + UnixH264::new(1_000_000)).into(),
             cache_id: 0,
@@ -824,7 +1014,7 @@ mod tests {
         let (_tmp_dir, rec_db) = single_recording(start_time).await;
 
         let query = VodQuery {
-            monitor_id: "x".to_owned().try_into().unwrap(),
+            monitor_ids: vec!["x".to_owned().try_into().unwrap()],
             start: (start_time + UnixH264::new(6)).into(), // Last sample.
             end: (start_time + UnixH264::new(1_000_000)).into(),
             cache_id: 0,
@@ -843,7 +1033,7 @@ mod tests {
         let (_tmp_dir, rec_db) = single_recording(start_time + UnixH264::new(90000)).await;
 
         let query = VodQuery {
-            monitor_id: "x".to_owned().try_into().unwrap(),
+            monitor_ids: vec!["x".to_owned().try_into().unwrap()],
             start: start_time.into(),
             end: (start_time + UnixH264::new(SECOND)).into(),
             cache_id: 0,
@@ -915,7 +1105,7 @@ mod tests {
         let (_tmp_dir, rec_db) = single_recording(start_time).await;
 
         let query = VodQuery {
-            monitor_id: "x".to_owned().try_into().unwrap(),
+            monitor_ids: vec!["x".to_owned().try_into().unwrap()],
             start: start_time.into(),
             end: UnixNano::from(start_time + UnixH264::new(6)) + UnixNano::new(1), // Third sample.
             cache_id: 0,
@@ -984,7 +1174,7 @@ mod tests {
         let (_tmp_dir, rec_db) = single_recording(start_time).await;
 
         let query = VodQuery {
-            monitor_id: "x".to_owned().try_into().unwrap(),
+            monitor_ids: vec!["x".to_owned().try_into().unwrap()],
             start: (start_time + UnixH264::new(20)).into(),
             end: start_time.into(),
             cache_id: 0,
@@ -1002,15 +1192,63 @@ mod tests {
         let (_tmp_dir, rec_db) = single_recording(start_time).await;
 
         let query = VodQuery {
-            monitor_id: "x".to_owned().try_into().unwrap(),
+            monitor_ids: vec!["x".to_owned().try_into().unwrap()],
             start: start_time.into(),
-            end: UnixNano::from(start_time) + UnixNano::new(HOUR * 13),
+            end: UnixNano::from(start_time) + UnixNano::new(DAY * 8),
             cache_id: 0,
         };
         let result = VodReader::new(&rec_db, &VodCache::new(), query).await;
         assert!(matches!(result, Err(CreateVodReaderError::MaxDuration)));
     }
 
+    #[tokio::test]
+    async fn test_vod_mismatched_params() {
+        let year_2000: UnixH264 = UnixNano::new(946_684_800 * SECOND).into();
+        let start_time: UnixH264 = year_2000 + UnixNano::new(10 * MINUTE).into();
+
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        let mut rec_db = RecDb::new(
+            DummyLogger::new(),
+            path.clone(),
+            Disk::new(path, ByteSize(0)),
+            None,
+        );
+        for (monitor_id, width, offset) in [("x", 640, 0), ("y", 1280, 2)] {
+            let start = start_time + UnixH264::new(offset);
+            save_monitor_recording(
+                &mut rec_db,
+                monitor_id,
+                width,
+                start,
+                start + UnixH264::new(1),
+                vec![VideoSample {
+                    pts: start,
+                    avcc: Arc::new(PaddedBytes::new(vec![0x1])),
+                    random_access_present: true,
+                    duration: DurationH264::new(1),
+                    ..Default::default()
+                }],
+            )
+            .await;
+        }
+
+        let query = VodQuery {
+            monitor_ids: vec![
+                "x".to_owned().try_into().unwrap(),
+                "y".to_owned().try_into().unwrap(),
+            ],
+            start: start_time.into(),
+            end: (start_time + UnixH264::new(4)).into(),
+            cache_id: 0,
+        };
+        let result = VodReader::new(&rec_db, &VodCache::new(), query).await;
+        assert!(matches!(
+            result,
+            Err(CreateVodReaderError::MismatchedParams(_))
+        ));
+    }
+
     async fn single_recording(start_time: UnixH264) -> (TempDir, RecDb) {
         let temp_dir = TempDir::new().unwrap();
         let path = temp_dir.path().to_path_buf();
@@ -1018,6 +1256,7 @@ mod tests {
             DummyLogger::new(),
             path.clone(),
             Disk::new(path, ByteSize(0)),
+            None,
         );
 
         save_recording(
@@ -1065,7 +1304,7 @@ mod tests {
         let (_tmp_dir, rec_db) = two_recordings(start_time).await;
 
         let query = VodQuery {
-            monitor_id: "x".to_owned().try_into().unwrap(),
+            monitor_ids: vec!["x".to_owned().try_into().unwrap()],
             start: start_time.into(),
             end: (start_time + UnixH264::new(16)).into(),
             cache_id: 0,
@@ -1136,6 +1375,7 @@ mod tests {
             DummyLogger::new(),
             path.clone(),
             Disk::new(path, ByteSize(0)),
+            None,
         );
 
         save_recording(
@@ -1195,6 +1435,7 @@ mod tests {
             DummyLogger::new(),
             path.clone(),
             Disk::new(path, ByteSize(0)),
+            None,
         );
         save_recording(
             &mut rec_db,
@@ -1240,7 +1481,7 @@ mod tests {
         .await;
 
         let query = VodQuery {
-            monitor_id: "x".to_owned().try_into().unwrap(),
+            monitor_ids: vec!["x".to_owned().try_into().unwrap()],
             start: start_time.into(),
             end: (start_time + UnixH264::new(16)).into(),
             cache_id: 0,
@@ -1313,7 +1554,7 @@ mod tests {
 
         let (_tmp_dir, rec_db) = multiple_recordings(start_time).await;
         let query = VodQuery {
-            monitor_id: "x".to_owned().try_into().unwrap(),
+            monitor_ids: vec!["x".to_owned().try_into().unwrap()],
             start: (start_time + UnixNano::new(SECOND * 10).into()).into(),
             end: UnixNano::from(start_time + UnixNano::new(SECOND * 10).into() + UnixH264::new(1))
                 + UnixNano::new(1),
@@ -1377,7 +1618,7 @@ mod tests {
 
         let (_tmp_dir, rec_db) = multiple_recordings(start_time).await;
         let query = VodQuery {
-            monitor_id: "x".to_owned().try_into().unwrap(),
+            monitor_ids: vec!["x".to_owned().try_into().unwrap()],
             start: (start_time + UnixNano::new(SECOND * 9).into()).into(),
             end: (start_time + UnixNano::new(SECOND * 11).into()).into(),
             cache_id: 0,
@@ -1440,7 +1681,7 @@ mod tests {
 
         let (_tmp_dir, rec_db) = multiple_recordings(start_time).await;
         let query = VodQuery {
-            monitor_id: "x".to_owned().try_into().unwrap(),
+            monitor_ids: vec!["x".to_owned().try_into().unwrap()],
             start: (start_time + UnixNano::new(SECOND * 8).into()).into(),
             end: (start_time + UnixNano::new(SECOND * 12).into()).into(),
             cache_id: 0,
@@ -1503,6 +1744,7 @@ mod tests {
             DummyLogger::new(),
             path.clone(),
             Disk::new(path, ByteSize(0)),
+            None,
         );
 
         let rec1 = start_time;
@@ -1562,6 +1804,7 @@ mod tests {
             DummyLogger::new(),
             path.clone(),
             Disk::new(path, ByteSize(0)),
+            None,
         );
         save_recording(
             &mut rec_db,
@@ -1586,7 +1829,7 @@ mod tests {
         .await;
 
         let query = VodQuery {
-            monitor_id: "x".to_owned().try_into().unwrap(),
+            monitor_ids: vec!["x".to_owned().try_into().unwrap()],
             start: start_time.into(),
             end: UnixNano::from(start_time + UnixH264::new(12)) + UnixNano::new(1),
             cache_id: 0,
@@ -1650,9 +1893,20 @@ mod tests {
         start_time: UnixH264,
         end_time: UnixH264,
         samples: Vec<VideoSample>,
+    ) {
+        save_monitor_recording(rec_db, "x", 640, start_time, end_time, samples).await;
+    }
+
+    async fn save_monitor_recording(
+        rec_db: &mut RecDb,
+        monitor_id: &str,
+        width: u16,
+        start_time: UnixH264,
+        end_time: UnixH264,
+        samples: Vec<VideoSample>,
     ) {
         let rec = rec_db
-            .new_recording("x".to_owned().try_into().unwrap(), start_time)
+            .new_recording(monitor_id.to_owned().try_into().unwrap(), start_time)
             .await
             .unwrap();
 
@@ -1660,12 +1914,14 @@ mod tests {
         let mut mdat = rec.new_file("mdat").await.unwrap();
         let header = MetaHeader {
             start_time,
-            width: 640,
+            width,
             height: 480,
+            video_codec: VideoCodec::H264,
             extra_data: vec![0x33],
+            audio: None,
         };
 
-        let mut w = VideoWriter::new(&mut *meta, &mut *mdat, header)
+        let mut w = VideoWriter::new(&mut meta, &mut mdat, header)
             .await
             .unwrap();
         for sample in samples {
@@ -0,0 +1,300 @@
+// SPDX-License-Identifier: GPL-2.0-or-later
+
+use std::io::SeekFrom;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+
+// Minimal zip writer. Files are stored without compression since
+// the content is already compressed video. The zip64 extensions are
+// always used because exports can be larger than 4GB.
+pub(crate) struct ZipWriter<W> {
+    w: W,
+    pos: u64,
+    entries: Vec<ZipEntry>,
+}
+
+struct ZipEntry {
+    name: String,
+    crc: u32,
+    size: u64,
+    offset: u64,
+}
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x0403_4b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x0201_4b50;
+const ZIP64_END_SIGNATURE: u32 = 0x0606_4b50;
+const ZIP64_LOCATOR_SIGNATURE: u32 = 0x0706_4b50;
+const END_SIGNATURE: u32 = 0x0605_4b50;
+
+const VERSION: u16 = 45;
+const FLAG_UTF8: u16 = 0x0800;
+// 1980-01-01 00:00:00.
+const DOS_TIME: u16 = 0;
+const DOS_DATE: u16 = 0x21;
+const ZIP64_EXTRA_ID: u16 = 0x0001;
+
+impl<W: AsyncWrite + AsyncSeek + Unpin> ZipWriter<W> {
+    pub(crate) fn new(w: W) -> Self {
+        Self {
+            w,
+            pos: 0,
+            entries: Vec::new(),
+        }
+    }
+
+    // Copies the reader into a new file in the archive.
+    pub(crate) async fn add_file<R: AsyncRead + Unpin>(
+        &mut self,
+        name: &str,
+        mut r: R,
+    ) -> Result<(), std::io::Error> {
+        let offset = self.pos;
+        let header = local_header(name, 0, 0);
+        self.w.write_all(&header).await?;
+        self.pos += u64_from_usize(header.len());
+
+        let mut crc = Crc32::new();
+        let mut size = 0;
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let n = r.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            crc.update(&buf[..n]);
+            self.w.write_all(&buf[..n]).await?;
+            size += u64_from_usize(n);
+        }
+        self.pos += size;
+        let crc = crc.finish();
+
+        // Rewrite the header now that the size and checksum are known.
+        self.w.seek(SeekFrom::Start(offset)).await?;
+        self.w.write_all(&local_header(name, crc, size)).await?;
+        self.w.seek(SeekFrom::Start(self.pos)).await?;
+
+        self.entries.push(ZipEntry {
+            name: name.to_owned(),
+            crc,
+            size,
+            offset,
+        });
+        Ok(())
+    }
+
+    // Writes the central directory and returns the size of the archive.
+    pub(crate) async fn finish(mut self) -> Result<u64, std::io::Error> {
+        let central_dir_offset = self.pos;
+        let mut buf = Vec::new();
+        for entry in &self.entries {
+            put_u32(&mut buf, CENTRAL_HEADER_SIGNATURE);
+            put_u16(&mut buf, VERSION); // Version made by.
+            put_u16(&mut buf, VERSION); // Version needed.
+            put_u16(&mut buf, FLAG_UTF8);
+            put_u16(&mut buf, 0); // Stored.
+            put_u16(&mut buf, DOS_TIME);
+            put_u16(&mut buf, DOS_DATE);
+            put_u32(&mut buf, entry.crc);
+            put_u32(&mut buf, u32::MAX); // Compressed size.
+            put_u32(&mut buf, u32::MAX); // Uncompressed size.
+            put_u16(&mut buf, name_len(&entry.name));
+            put_u16(&mut buf, 28); // Extra field length.
+            put_u16(&mut buf, 0); // Comment length.
+            put_u16(&mut buf, 0); // Disk number.
+            put_u16(&mut buf, 0); // Internal attributes.
+            put_u32(&mut buf, 0); // External attributes.
+            put_u32(&mut buf, u32::MAX); // Local header offset.
+            buf.extend_from_slice(entry.name.as_bytes());
+            put_u16(&mut buf, ZIP64_EXTRA_ID);
+            put_u16(&mut buf, 24);
+            put_u64(&mut buf, entry.size);
+            put_u64(&mut buf, entry.size);
+            put_u64(&mut buf, entry.offset);
+        }
+        let central_dir_size = u64_from_usize(buf.len());
+        let zip64_end_offset = central_dir_offset + central_dir_size;
+        let n_entries = u64_from_usize(self.entries.len());
+
+        put_u32(&mut buf, ZIP64_END_SIGNATURE);
+        put_u64(&mut buf, 44); // Size of the remaining record.
+        put_u16(&mut buf, VERSION);
+        put_u16(&mut buf, VERSION);
+        put_u32(&mut buf, 0); // Disk number.
+        put_u32(&mut buf, 0); // Disk with central directory.
+        put_u64(&mut buf, n_entries);
+        put_u64(&mut buf, n_entries);
+        put_u64(&mut buf, central_dir_size);
+        put_u64(&mut buf, central_dir_offset);
+
+        put_u32(&mut buf, ZIP64_LOCATOR_SIGNATURE);
+        put_u32(&mut buf, 0); // Disk with zip64 end record.
+        put_u64(&mut buf, zip64_end_offset);
+        put_u32(&mut buf, 1); // Total number of disks.
+
+        put_u32(&mut buf, END_SIGNATURE);
+        put_u16(&mut buf, 0); // Disk number.
+        put_u16(&mut buf, 0); // Disk with central directory.
+        put_u16(&mut buf, u16::MAX);
+        put_u16(&mut buf, u16::MAX);
+        put_u32(&mut buf, u32::MAX);
+        put_u32(&mut buf, u32::MAX);
+        put_u16(&mut buf, 0); // Comment length.
+
+        self.w.write_all(&buf).await?;
+        self.w.flush().await?;
+        Ok(self.pos + u64_from_usize(buf.len()))
+    }
+}
+
+fn local_header(name: &str, crc: u32, size: u64) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(50 + name.len());
+    put_u32(&mut buf, LOCAL_HEADER_SIGNATURE);
+    put_u16(&mut buf, VERSION);
+    put_u16(&mut buf, FLAG_UTF8);
+    put_u16(&mut buf, 0); // Stored.
+    put_u16(&mut buf, DOS_TIME);
+    put_u16(&mut buf, DOS_DATE);
+    put_u32(&mut buf, crc);
+    put_u32(&mut buf, u32::MAX); // Compressed size.
+    put_u32(&mut buf, u32::MAX); // Uncompressed size.
+    put_u16(&mut buf, name_len(name));
+    put_u16(&mut buf, 20); // Extra field length.
+    buf.extend_from_slice(name.as_bytes());
+    put_u16(&mut buf, ZIP64_EXTRA_ID);
+    put_u16(&mut buf, 16);
+    put_u64(&mut buf, size);
+    put_u64(&mut buf, size);
+    buf
+}
+
+fn name_len(name: &str) -> u16 {
+    u16::try_from(name.len()).expect("file names should be short")
+}
+
+fn u64_from_usize(v: usize) -> u64 {
+    u64::try_from(v).expect("u64 fit usize")
+}
+
+fn put_u16(buf: &mut Vec<u8>, v: u16) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_u32(buf: &mut Vec<u8>, v: u32) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_u64(buf: &mut Vec<u8>, v: u64) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+// CRC-32 with the IEEE polynomial.
+struct Crc32(u32);
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        #[allow(clippy::as_conversions, clippy::cast_possible_truncation)]
+        let mut v = i as u32;
+        let mut j = 0;
+        while j < 8 {
+            v = if v & 1 == 1 {
+                0xedb8_8320 ^ (v >> 1)
+            } else {
+                v >> 1
+            };
+            j += 1;
+        }
+        table[i] = v;
+        i += 1;
+    }
+    table
+}
+
+impl Crc32 {
+    fn new() -> Self {
+        Self(u32::MAX)
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        for b in data {
+            let i = usize::from(self.0.to_le_bytes()[0] ^ b);
+            self.0 = CRC32_TABLE[i] ^ (self.0 >> 8);
+        }
+    }
+
+    fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_crc32() {
+        let mut crc = Crc32::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(0xcbf4_3926, crc.finish());
+    }
+
+    fn read_u16(buf: &[u8], pos: usize) -> u16 {
+        u16::from_le_bytes(buf[pos..pos + 2].try_into().unwrap())
+    }
+
+    fn read_u32(buf: &[u8], pos: usize) -> u32 {
+        u32::from_le_bytes(buf[pos..pos + 4].try_into().unwrap())
+    }
+
+    fn read_u64(buf: &[u8], pos: usize) -> u64 {
+        u64::from_le_bytes(buf[pos..pos + 8].try_into().unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_zip_writer() {
+        let mut cursor = Cursor::new(Vec::new());
+        let mut zip = ZipWriter::new(&mut cursor);
+        zip.add_file("a.txt", &b"abc"[..]).await.unwrap();
+        zip.add_file("b/c.txt", &b"de"[..]).await.unwrap();
+        let size = zip.finish().await.unwrap();
+        let buf = cursor.into_inner();
+        assert_eq!(375, size);
+        assert_eq!(375, buf.len());
+
+        // First local header.
+        assert_eq!(LOCAL_HEADER_SIGNATURE, read_u32(&buf, 0));
+        let mut crc = Crc32::new();
+        crc.update(b"abc");
+        assert_eq!(crc.finish(), read_u32(&buf, 14));
+        assert_eq!(5, read_u16(&buf, 26));
+        assert_eq!(b"a.txt", &buf[30..35]);
+        assert_eq!(3, read_u64(&buf, 39));
+        assert_eq!(b"abc", &buf[55..58]);
+
+        // Second local header.
+        assert_eq!(LOCAL_HEADER_SIGNATURE, read_u32(&buf, 58));
+        assert_eq!(b"b/c.txt", &buf[88..95]);
+        assert_eq!(2, read_u64(&buf, 99));
+        assert_eq!(b"de", &buf[115..117]);
+
+        // Central directory.
+        assert_eq!(CENTRAL_HEADER_SIGNATURE, read_u32(&buf, 117));
+        assert_eq!(0, read_u64(&buf, 117 + 46 + 5 + 20));
+        assert_eq!(CENTRAL_HEADER_SIGNATURE, read_u32(&buf, 196));
+        assert_eq!(58, read_u64(&buf, 196 + 46 + 7 + 20));
+
+        // Zip64 end record, locator and end record.
+        assert_eq!(ZIP64_END_SIGNATURE, read_u32(&buf, 277));
+        assert_eq!(2, read_u64(&buf, 277 + 24));
+        assert_eq!(117, read_u64(&buf, 277 + 48));
+        assert_eq!(ZIP64_LOCATOR_SIGNATURE, read_u32(&buf, 333));
+        assert_eq!(277, read_u64(&buf, 341));
+        assert_eq!(END_SIGNATURE, read_u32(&buf, 353));
+    }
+}
@@ -264,15 +264,17 @@ const fieldTemplate = {
 	 * @param {string} label
 	 * @param {string} placeholder
 	 * @param {string} initial
+	 * @param {number=} max
 	 * @return {Field<number>}
 	 */
-	number(label, placeholder, initial = "0") {
+	number(label, placeholder, initial = "0", max = undefined) {
 		return newNumberField(
 			[inputRules.notEmpty, inputRules.noSpaces],
 			{
 				errorField: true,
 				input: "number",
 				min: 0,
+				max: max,
 			},
 			{
 				label: label,
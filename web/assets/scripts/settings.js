@@ -939,6 +939,7 @@ function newSourceField(options, getField) {
  * @property {Field<string>} mainStream
  * @property {Field<string>} subStream
  * @property {Field<string>} onvifUrl
+ * @property {Field<boolean>} audio
  */
 
 /** @returns {Field<string>} */
@@ -976,10 +977,65 @@ function newSourceRTSP() {
 				placeholder: "http://x.x.x.x/onvif/device_service",
 			}
 		),
+		audio: fieldTemplate.toggle("Audio", false),
 	};
+	return newSource("RTSP source", fields);
+}
+
+/** @returns {Field<string>} */
+function newSourceFile() {
+	const fields = {
+		path: newField(
+			[inputRules.notEmpty],
+			{
+				input: "text",
+				errorField: true,
+			},
+			{
+				label: "Path",
+				placeholder: "/path/to/video.mp4",
+			}
+		),
+	};
+	return newSource("File source", fields);
+}
+
+/** @returns {Field<string>} */
+function newSourceHTTP() {
+	const fields = {
+		mainStream: newField(
+			[inputRules.notEmpty],
+			{
+				input: "text",
+				errorField: true,
+			},
+			{
+				label: "Main stream",
+				placeholder: "http://x.x.x.x/main.m3u8",
+			}
+		),
+		subStream: newField(
+			[],
+			{
+				input: "text",
+			},
+			{
+				label: "Sub stream",
+				placeholder: "http://x.x.x.x/sub.m3u8 (optional)",
+			}
+		),
+	};
+	return newSource("HTTP source", fields);
+}
 
+/**
+ * @param {string} title
+ * @param {Fields<any>} fields
+ * @returns {Field<string>}
+ */
+function newSource(title, fields) {
 	const form = newForm(fields);
-	const modal = newModal("RTSP source", form.html());
+	const modal = newModal(title, form.html());
 
 	let value = {};
 
@@ -1033,7 +1089,7 @@ function newSourceRTSP() {
 			}
 			const err = form.validate();
 			if (err !== undefined) {
-				return "RTSP source: " + err;
+				return `${title}: ${err}`;
 			}
 			return;
 		},
@@ -1078,14 +1134,24 @@ function init() {
 		);
 		monitorFields.name = fieldTemplate.text("Name", "my_monitor");
 		monitorFields.enable = fieldTemplate.toggle("Enable monitor", true);
-		monitorFields.source = newSourceField(["rtsp"], getMonitorField);
+		monitorFields.source = newSourceField(
+			["rtsp", "file", "http"],
+			getMonitorField
+		);
 		monitorFields.sourcertsp = newSourceRTSP();
+		monitorFields.sourcefile = newSourceFile();
+		monitorFields.sourcehttp = newSourceHTTP();
 		monitorFields.alwaysRecord = fieldTemplate.toggle("Always record", false);
 		monitorFields.videoLength = fieldTemplate.number(
 			"Video length (min)",
 			"15",
 			"15"
 		);
+		monitorFields.preRoll = fieldTemplate.number("Pre-roll (sec)", "0", "0", 30);
+		monitorFields.thumbnailDetections = fieldTemplate.toggle(
+			"Thumbnail detections",
+			false
+		);
 		//timestampOffset: fieldTemplate.integer("Timestamp offset (ms)", "500", "500"),
 		/* SETTINGS_LAST_MONITOR_FIELD */
 